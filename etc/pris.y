@@ -13,23 +13,50 @@
 
 %%
 
+document: statements | version statements;
+
 statements: statement | statements statement;
 
+/* The version can only be declared at the start of the document. */
+version: "pris_version" STRING;
+
 statement
   : import
+  | infix
   | assign
+  | update
   | return
   | block
   | put
+  | repeat
+  | with
+  | section
   ;
 
 import: "import" idents;
 
+/* Precedence declarations are resolved by the parser as it goes, so for the
+ * grammar all infix calls have the same precedence. */
+infix: "infix" NUMBER idents;
+
 idents: IDENT | idents '.' IDENT;
 
 assign: IDENT '=' expr;
 
-expr: expr_infix;
+update
+  : IDENT "+=" expr
+  | IDENT "-=" expr
+  | IDENT "*=" expr
+  ;
+
+expr: expr_or;
+
+/* A default binds less tightly than anything else, and associates to the
+ * right. */
+expr_or
+  : expr_infix
+  | expr_infix "or" expr_or
+  ;
 
 expr_infix
   : expr_add
@@ -37,8 +64,9 @@ expr_infix
    * makes the grammar ambiguous in the case of a parser with one token
    * lookahead, because the IDENT here could be either an infix call, or it
    * could be the left-hand side of an assignment. But by looking ahead two
-   * tokens we can tell them apart: if the IDENT is followed by '=' then it is
-   * part of an assignment, otherwise it is an infix call. An other way to
+   * tokens we can tell them apart: if the IDENT is followed by '=' or an
+   * update operator such as "+=", then it is part of an assignment or update,
+   * otherwise it is an infix call. An other way to
    * resolve the ambiguity, without resorting to an extra token of lookahead,
    * would have been to terminate statements with semicolons.
    */
@@ -73,6 +101,7 @@ term
   | fn_def
   | block
   | list
+  | match
   | '(' expr ')'
   ;
 
@@ -103,10 +132,25 @@ fn_def_args: IDENT | fn_def_args ',' IDENT;
 list
   : '[' ']'
   | '[' list_elems ']'
-  | '[' list_elems ';' ']' /* Allow but do not require a trailing semicolon. */
+  | '[' list_elems list_sep ']' /* Allow but do not require a trailing separator. */
   ;
 
-list_elems : expr | list_elems ';' expr;
+list_elems : expr | list_elems list_sep expr;
+
+list_sep: ';' | ',';
+
+match
+  : "match" expr '{' '}'
+  | "match" expr '{' match_arms '}'
+  | "match" expr '{' match_arms ',' '}'
+  | "match" expr '{' match_else '}'
+  | "match" expr '{' match_arms ',' match_else '}'
+  ;
+
+match_arms: STRING "->" expr | match_arms ',' STRING "->" expr;
+
+/* The else arm must be the last one, a trailing comma is allowed. */
+match_else: "else" "->" expr | "else" "->" expr ',';
 
 block
   : '{' '}'
@@ -116,3 +160,15 @@ block
 return: "return" expr;
 
 put: "put" expr;
+
+repeat: "repeat" expr block;
+
+/* The first block of a with statement can only assign variables. */
+with
+  : "with" '{' '}' block
+  | "with" '{' assigns '}' block
+  ;
+
+assigns: assign | assigns assign;
+
+section: "section" STRING;
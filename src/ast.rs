@@ -5,37 +5,80 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
+use std::fmt;
+
 use pretty::{Formatter, Print};
+use symbol::Symbol;
 
 pub struct Document<'a>(pub Vec<Stmt<'a>>);
 
 #[derive(PartialEq)]
 pub enum Stmt<'a> {
-    Import(Import<'a>),
+    Import(Import),
+    Infix(InfixDecl),
     Assign(Assign<'a>),
+    Update(Update<'a>),
     Return(Return<'a>),
     Block(Block<'a>),
     Put(Put<'a>),
+    Repeat(Repeat<'a>),
+    With(With<'a>),
+    Section(Section),
+    Version(Version),
+    Comment(Comment<'a>),
 }
 
 #[derive(PartialEq, Eq)]
-pub struct Import<'a>(pub Idents<'a>);
+pub struct Import(pub Idents);
+
+/// A comment, preserved only when parsing with `parse_with_comments`.
+#[derive(PartialEq, Eq)]
+pub struct Comment<'a> {
+    /// The comment text, including the leading "//".
+    pub text: &'a str,
+    /// Whether the comment is on the same line as the preceding statement.
+    pub trailing: bool,
+}
+
+/// Declaration of the precedence of a function when called infix.
+#[derive(PartialEq, Eq)]
+pub struct InfixDecl(pub u32, pub Idents);
+
+/// Start of a section, which groups the slides that follow.
+#[derive(PartialEq, Eq)]
+pub struct Section(pub String);
+
+/// The version of the language that a document is written for, declared with
+/// `pris_version "major.minor"` at the start of the document.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(pub u32, pub u32);
 
 #[derive(PartialEq, Eq)]
-pub struct Idents<'a>(pub Vec<&'a str>);
+pub struct Idents(pub Vec<Symbol>);
 
 #[derive(PartialEq)]
 pub struct List<'a>(pub Vec<Term<'a>>);
 
+/// Assignment of a term to a variable, with the doc comment that precedes it.
 #[derive(PartialEq)]
-pub struct Assign<'a>(pub &'a str, pub Term<'a>);
+pub struct Assign<'a>(pub Symbol, pub Term<'a>, pub Doc<'a>);
+
+/// Update of a variable with `+=`, `-=`, or `*=`.
+///
+/// `x += 1` is short for `x = x + 1`, the operator is `Add`, `Sub`, or `Mul`.
+#[derive(PartialEq)]
+pub struct Update<'a>(pub Symbol, pub BinOp, pub Term<'a>);
+
+/// The lines of a doc comment, including the leading "///".
+#[derive(PartialEq, Eq)]
+pub struct Doc<'a>(pub Vec<&'a str>);
 
 #[derive(PartialEq)]
 pub enum Term<'a> {
     String(String),
     Number(Num),
     Color(Color),
-    Idents(Idents<'a>),
+    Idents(Idents),
     Coord(Box<Coord<'a>>),
     BinOp(Box<BinTerm<'a>>),
     UnOp(Box<UnTerm<'a>>),
@@ -43,6 +86,7 @@ pub enum Term<'a> {
     FnDef(FnDef<'a>),
     Block(Block<'a>),
     List(List<'a>),
+    Match(Box<Match<'a>>),
 }
 
 impl<'a> Term<'a> {
@@ -76,20 +120,23 @@ pub enum Unit {
   H,
   Em,
   Pt,
+  Pw,
+  Ph,
 }
 
+/// A color literal: red, green, blue, and alpha, which is 255 when omitted.
 #[derive(PartialEq, Eq)]
-pub struct Color(pub u8, pub u8, pub u8);
+pub struct Color(pub u8, pub u8, pub u8, pub u8);
 
 #[derive(PartialEq)]
 pub struct Coord<'a>(pub Term<'a>, pub Term<'a>);
 
 /// A binary operation applied to two terms.
 #[derive(PartialEq)]
-pub struct BinTerm<'a>(pub Term<'a>, pub BinOp<'a>, pub Term<'a>);
+pub struct BinTerm<'a>(pub Term<'a>, pub BinOp, pub Term<'a>);
 
 #[derive(PartialEq, Eq)]
-pub enum BinOp<'a> {
+pub enum BinOp {
     /// Adjoin, `~`.
     Adj,
     /// Add, `+`.
@@ -103,7 +150,9 @@ pub enum BinOp<'a> {
     /// Exponentiate, `^`.
     Exp,
     /// Infix function call.
-    Infix(Idents<'a>),
+    Infix(Idents),
+    /// Default for a value that is not set, `or`.
+    Or,
 }
 
 /// A unary operation applied to a term.
@@ -120,25 +169,160 @@ pub enum UnOp {
 pub struct FnCall<'a>(pub Term<'a>, pub Vec<Term<'a>>);
 
 #[derive(PartialEq)]
-pub struct FnDef<'a>(pub Vec<&'a str>, pub Block<'a>);
+pub struct FnDef<'a>(pub Vec<Symbol>, pub Block<'a>);
 
 #[derive(PartialEq)]
 pub struct Block<'a>(pub Vec<Stmt<'a>>);
 
+/// Selection of a result by a string, `match x { "a" -> 1, else -> 2 }`.
+///
+/// The arms are tried in order. The optional last term is the `else` arm.
+#[derive(PartialEq)]
+pub struct Match<'a>(pub Term<'a>, pub Vec<MatchArm<'a>>, pub Option<Term<'a>>);
+
+/// An arm of a match, the string to compare with and the result.
+#[derive(PartialEq)]
+pub struct MatchArm<'a>(pub String, pub Term<'a>);
+
 #[derive(PartialEq)]
 pub struct Return<'a>(pub Term<'a>);
 
 #[derive(PartialEq)]
 pub struct Put<'a>(pub Term<'a>);
 
+/// Execute the statements in the block a number of times, `repeat n { ... }`.
+#[derive(PartialEq)]
+pub struct Repeat<'a>(pub Term<'a>, pub Block<'a>);
+
+/// Execute the body with variables overridden, `with { x = 1 } { ... }`.
+///
+/// The first block contains only assignments and comments.
+#[derive(PartialEq)]
+pub struct With<'a>(pub Block<'a>, pub Block<'a>);
+
+impl<'a> Document<'a> {
+    /// Return the number of sections in the document.
+    pub fn count_sections(&self) -> usize {
+        self.0.iter().filter(|stmt| match **stmt {
+            Stmt::Section(..) => true,
+            _ => false,
+        }).count()
+    }
+
+    /// Return the names of the top-level sections, in order.
+    pub fn sections(&self) -> Vec<&str> {
+        self.0.iter().filter_map(|stmt| match *stmt {
+            Stmt::Section(ref section) => Some(&section.0[..]),
+            _ => None,
+        }).collect()
+    }
+
+    /// Return the language version that the document declares, if any.
+    pub fn version(&self) -> Option<Version> {
+        self.0.iter().filter_map(|stmt| match *stmt {
+            Stmt::Version(version) => Some(version),
+            _ => None,
+        }).next()
+    }
+
+    /// Return the top-level assignments that have a doc comment.
+    pub fn documented(&self) -> Vec<&Assign<'a>> {
+        self.0.iter().filter_map(|stmt| match *stmt {
+            Stmt::Assign(ref assign) if !assign.2.is_empty() => Some(assign),
+            _ => None,
+        }).collect()
+    }
+}
+
+impl Version {
+    /// The newest version of the language that this build implements.
+    pub const CURRENT: Version = Version(0, 2);
+
+    /// The version that introduced `pris_version`, older versions cannot be
+    /// declared.
+    pub const FIRST: Version = Version(0, 2);
+
+    /// Parse a version of the form "major.minor".
+    pub fn parse(version: &str) -> Option<Version> {
+        let mut parts = version.splitn(2, '.');
+        let major = parts.next().and_then(|p| p.parse().ok());
+        let minor = parts.next().and_then(|p| p.parse().ok());
+        match (major, minor) {
+            (Some(major), Some(minor)) => Some(Version(major, minor)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.0, self.1)
+    }
+}
+
+impl fmt::Display for Idents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, symbol) in self.0.iter().enumerate() {
+            if i > 0 { f.write_str(".")?; }
+            write!(f, "{}", symbol)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Doc<'a> {
+    pub fn none() -> Doc<'a> {
+        Doc(Vec::new())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Return the text of the comment, without the leading slashes.
+    ///
+    /// A single space after the slashes is stripped too, so `/// Foo` becomes
+    /// `Foo`. Lines are separated by newlines.
+    pub fn text(&self) -> String {
+        let lines: Vec<&str> = self.0.iter().map(|line| {
+            let line = &line[3..];
+            if line.starts_with(' ') { &line[1..] } else { line }
+        }).collect();
+        lines.join("\n")
+    }
+}
+
 // Pretty-printers.
 
+/// Print statements one per line, with trailing comments on the line of the
+/// statement that they follow.
+fn print_statements<'a>(statements: &[Stmt<'a>], f: &mut Formatter) {
+    for (i, stmt) in statements.iter().enumerate() {
+        match *stmt {
+            Stmt::Comment(ref c) if c.trailing && i > 0 => continue,
+            Stmt::Assign(ref a) => {
+                for line in &(a.2).0 {
+                    f.println(*line);
+                    f.print("\n");
+                }
+                f.println(stmt);
+            }
+            _ => f.println(stmt),
+        }
+        match statements.get(i + 1) {
+            Some(&Stmt::Comment(ref c)) if c.trailing => {
+                f.print("  ");
+                f.print(c);
+            }
+            _ => {}
+        }
+        f.print("\n");
+    }
+}
+
 impl<'a> Print for Document<'a> {
     fn print(&self, f: &mut Formatter) {
-        for item in &self.0 {
-            f.println(item);
-            f.print("\n");
-        }
+        print_statements(&self.0, f);
     }
 }
 
@@ -146,22 +330,70 @@ impl<'a> Print for Stmt<'a> {
     fn print(&self, f: &mut Formatter) {
         match *self {
             Stmt::Import(ref i) => f.print(i),
+            Stmt::Infix(ref i) => f.print(i),
             Stmt::Assign(ref a) => f.print(a),
+            Stmt::Update(ref u) => f.print(u),
             Stmt::Return(ref r) => f.print(r),
             Stmt::Block(ref bk) => f.print(bk),
             Stmt::Put(ref put)  => f.print(put),
+            Stmt::Repeat(ref r) => f.print(r),
+            Stmt::With(ref w) => f.print(w),
+            Stmt::Section(ref s) => f.print(s),
+            Stmt::Version(ref v) => f.print(v),
+            Stmt::Comment(ref c) => f.print(c),
         }
     }
 }
 
-impl<'a> Print for Import<'a> {
+impl<'a> Print for Comment<'a> {
+    fn print(&self, f: &mut Formatter) {
+        f.print(self.text);
+    }
+}
+
+impl Print for Import {
     fn print(&self, f: &mut Formatter) {
         f.print("import ");
         f.print(&self.0);
     }
 }
 
-impl<'a> Print for Idents<'a> {
+impl Print for InfixDecl {
+    fn print(&self, f: &mut Formatter) {
+        f.print("infix ");
+        f.print(self.0);
+        f.print(" ");
+        f.print(&self.1);
+    }
+}
+
+/// Print a string as a string literal, with quotes and escape sequences.
+fn print_string_literal(string: &str, f: &mut Formatter) {
+    let escaped = string
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    f.print("\"");
+    f.print(&escaped[..]);
+    f.print("\"");
+}
+
+impl Print for Section {
+    fn print(&self, f: &mut Formatter) {
+        f.print("section ");
+        print_string_literal(&self.0, f);
+    }
+}
+
+impl Print for Version {
+    fn print(&self, f: &mut Formatter) {
+        f.print("pris_version \"");
+        f.print(&self.to_string()[..]);
+        f.print("\"");
+    }
+}
+
+impl Print for Idents {
     fn print(&self, f: &mut Formatter) {
         assert!(self.0.len() > 0);
         let mut parts = self.0.iter();
@@ -181,6 +413,16 @@ impl<'a> Print for Assign<'a> {
     }
 }
 
+impl<'a> Print for Update<'a> {
+    fn print(&self, f: &mut Formatter) {
+        f.print(&self.0);
+        f.print(" ");
+        f.print(&self.1);
+        f.print("= ");
+        f.print(&self.2);
+    }
+}
+
 impl<'a> Print for Term<'a> {
     fn print(&self, f: &mut Formatter) {
         match *self {
@@ -196,6 +438,7 @@ impl<'a> Print for Term<'a> {
             Term::FnDef(ref fdf) => f.print(fdf),
             Term::Block(ref blk) => f.print(blk),
             Term::List(ref list) => f.print(list),
+            Term::Match(ref m) => f.print(&**m),
         }
     }
 }
@@ -216,6 +459,8 @@ impl Print for Unit {
             Unit::H => f.print("h"),
             Unit::Em => f.print("em"),
             Unit::Pt => f.print("pt"),
+            Unit::Pw => f.print("pw"),
+            Unit::Ph => f.print("ph"),
         }
     }
 }
@@ -226,6 +471,9 @@ impl Print for Color {
         f.print_hex_byte(self.0);
         f.print_hex_byte(self.1);
         f.print_hex_byte(self.2);
+        if self.3 != 0xff {
+            f.print_hex_byte(self.3);
+        }
     }
 }
 
@@ -251,7 +499,7 @@ impl<'a> Print for BinTerm<'a> {
     }
 }
 
-impl<'a> Print for BinOp<'a> {
+impl<'a> Print for BinOp {
     fn print(&self, f: &mut Formatter) {
         match *self {
             BinOp::Adj => f.print("~"),
@@ -261,6 +509,7 @@ impl<'a> Print for BinOp<'a> {
             BinOp::Div => f.print("/"),
             BinOp::Exp => f.print("^"),
             BinOp::Infix(ref op) => op.print(f),
+            BinOp::Or => f.print("or"),
         }
     }
 }
@@ -315,10 +564,7 @@ impl<'a> Print for Block<'a> {
         f.println("\n");
         f.println("{\n");
         f.indent_more();
-        for stmt in &self.0 {
-            f.println(stmt);
-            f.print("\n");
-        }
+        print_statements(&self.0, f);
         f.indent_less();
         f.println("}");
     }
@@ -354,3 +600,39 @@ impl<'a> Print for Put<'a> {
         f.print(&self.0);
     }
 }
+
+impl<'a> Print for Match<'a> {
+    fn print(&self, f: &mut Formatter) {
+        f.print("match ");
+        f.print(&self.0);
+        f.print(" { ");
+        for arm in &self.1 {
+            print_string_literal(&arm.0, f);
+            f.print(" -> ");
+            f.print(&arm.1);
+            f.print(", ");
+        }
+        if let Some(ref default) = self.2 {
+            f.print("else -> ");
+            f.print(default);
+            f.print(" ");
+        }
+        f.print("}");
+    }
+}
+
+impl<'a> Print for Repeat<'a> {
+    fn print(&self, f: &mut Formatter) {
+        f.print("repeat ");
+        f.print(&self.0);
+        f.print(&self.1);
+    }
+}
+
+impl<'a> Print for With<'a> {
+    fn print(&self, f: &mut Formatter) {
+        f.print("with");
+        f.print(&self.0);
+        f.print(&self.1);
+    }
+}
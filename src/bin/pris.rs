@@ -8,116 +8,1784 @@
 extern crate pris;
 
 use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::env;
 use std::fs::File;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
 use std::io::BufReader;
 use std::io::Read;
+use std::io::Write;
 use std::io;
+use std::os::raw::c_int;
+use std::os::unix::fs::DirBuilderExt;
 use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{ATOMIC_BOOL_INIT, AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use pris::ast;
 use pris::cairo;
+use pris::compile::{self, Slides};
+use pris::diff;
 use pris::driver;
-use pris::error::Error;
-use pris::interpreter;
+use pris::error::{Error, ErrorKind, Result};
+use pris::external::Tools;
 use pris::lexer;
+use pris::limits::Limits;
+use pris::lint;
+use pris::logger::{Level, Logger, StderrLogger, StdoutLogger};
+use pris::module;
+use pris::output::{ImageFormat, OutputPattern};
 use pris::parser;
+use pris::pdf;
+use pris::profile::Profile;
 use pris::runtime;
+use pris::scene;
+use pris::simulation::Simulation;
+use pris::spellcheck;
+use pris::symbol::Symbol;
+use pris::timing;
+use pris::todo::Todo;
+use pris::transcript;
 
 const USAGE: &'static str = "
 Pris, a language for designing slides.
 
 Usage:
-  pris [--] <infile> [<outfile>]
+  pris [--define <key>=<value>]... [--path <dir>]... [--allow-getenv]
+       [--tool <name>=<command>]... [--warn-shadowing] [--warn-contrast] [--spellcheck <dict>] [--release-check]
+       [--quiet | --verbose] [--profile] [--watch] [--speaker-pdf <file>]
+       [--contact-sheet <file>] [--transcript <file>] [--emit-scene <file>]
+       [--max-statements <n>] [--max-elements <n>] [--max-memory <bytes>]
+       [--] <infile> [<outfile>]
+  pris [options] [--pdfa | <encryption>...] [--] <infile> (-o | --output) <outfile>
+  pris [options] --output-pattern <pattern> [--pixel-snap]
+       [--antialias <mode>] [--hinting <style>] [--simulate <mode>]
+       [--] <infile>
+  pris merge [options] [--] <outfile> <infile>...
+  pris info [--define <key>=<value>]... [--path <dir>]... [--allow-getenv]
+       [--tool <name>=<command>]... [--] <infile>
+  pris styles [--define <key>=<value>]... [--path <dir>]... [--allow-getenv]
+       [--tool <name>=<command>]... [--] <infile>
+  pris grep [--define <key>=<value>]... [--path <dir>]... [--allow-getenv]
+       [--tool <name>=<command>]... [--] <infile> <text>
+  pris diff [--define <key>=<value>]... [--path <dir>]... [--allow-getenv]
+       [--tool <name>=<command>]... [--output-pattern <pattern>] [--] <old> <new>
+  pris --lint [--define <key>=<value>]... [--path <dir>]... [--] <infile>
+  pris doc [--path <dir>]... [--] (<infile> | <module>)
   pris (-h | --help)
 
 Options:
-  -h --help  Show this screen.
+  -h --help                 Show this screen.
+  -o --output <outfile>     Write the output to <outfile>, like the second
+                            positional argument.
+  --define <key>=<value>    Set the variable <key> to the string <value>.
+  --path <dir>              Look for imported packages in <dir>, before the
+                            directories in PRIS_PATH.
+  --allow-getenv            Allow reading environment variables with getenv.
+  --tool <name>=<command>   Let external_svg run the shell command <command>
+                            under the name <name>.
+  --warn-shadowing          Warn about variables that shadow builtin functions.
+  --warn-contrast           Warn about text that has too little contrast with
+                            the color beneath it to be readable.
+  --release-check           Fail if the document contains todo() placeholders.
+  --spellcheck <dict>       Warn about misspelled words in text, according to
+                            the Hunspell dictionary <dict>, such as 'en_US'.
+  -q --quiet                Do not report progress.
+  -v --verbose              Report the time spent on every slide, and on every
+                            phase of compilation.
+  --profile                 Report the time spent on the slowest top-level
+                            statements, and on every builtin function.
+  --watch                   Render again whenever the input, a module that it
+                            imports, or an image on a slide changes.
+  --lint                    Check the document for unused and undefined
+                            variables, and for lists that mix separators,
+                            without rendering it.
+  --speaker-pdf <file>      Also write a pdf for presenting, with the notes
+                            of every slide and the next page beside it.
+  --contact-sheet <file>    Also write a pdf with thumbnails of all slides,
+                            twelve per page.
+  --transcript <file>       Also write the text on every slide in reading
+                            order, as Markdown if <file> ends in '.md'.
+  --emit-scene <file>       Also write the elements on every slide, after
+                            layout, as JSON.
+  --pdfa                    Write archival pdf, conforming to PDF/A-2b.
+  --password-file <file>    Encrypt the pdf with the user password on the first
+                            line of <file>, and the owner password on the
+                            second line.
+  --user-password <pw>      Encrypt the pdf, and require <pw> to open it.
+  --owner-password <pw>     Encrypt the pdf, and require <pw> to change the
+                            permissions.
+  --no-print                Encrypt the pdf, and do not allow printing it.
+  --no-copy                 Encrypt the pdf, and do not allow copying text.
+  --output-pattern <pattern>
+                            Write every page to a separate png or svg file,
+                            named after <pattern>, such as 'out/{deck}-{:03}.png'.
+  --pixel-snap              Round straight lines and rectangles to whole pixels
+                            in png output, so hairlines do not blur.
+  --antialias <mode>        Rasterize text in png output with antialiasing mode
+                            'none', 'gray', 'subpixel', or 'default'.
+  --hinting <style>         Fit text in png output to the pixel grid with hint
+                            style 'none', 'slight', 'medium', 'full', or 'default'.
+  --simulate <mode>         Transform the colors of png output, to check that
+                            slides are legible for everybody. Mode is
+                            'colorblind=deuteranopia', 'colorblind=protanopia',
+                            'colorblind=tritanopia', or 'grayscale'.
+  --max-statements <n>      Fail when evaluation executes more than <n>
+                            statements.
+  --max-elements <n>        Fail when a slide has more than <n> elements,
+                            summed over its steps.
+  --max-memory <bytes>      Fail when the slides take more than an estimated
+                            <bytes> bytes of memory.
 
 If the output file is not specified, it defaults to the input file, with
 the extension replaced with '.pdf'. The input file name can optionally
 be '-' to read from stdin. In that case the output file is mandatory.
+The output file name can be '-' to write to stdout. In that case, messages
+are printed to stderr instead of stdout.
+
+The merge command renders several documents into a single pdf. Slides are
+numbered across all documents, and the outline has an entry for every
+document, with its sections. The documents must have the same canvas size,
+bleed, and crop marks.
+
+The info command evaluates the document without rendering it, and prints the
+number of slides and pages, the canvas size, the sections, the fonts, and the
+images, one 'key: value' pair per line.
+
+The styles command evaluates the document without rendering it, and prints
+the fonts, with their size in pt, and the colors on every slide, prefixed with
+the slide number. Then it lists fonts and colors that differ only slightly,
+which are likely unintended.
+
+The grep command evaluates the document without rendering it, and prints the
+lines of text on slides that contain <text>, prefixed with the slide number.
+
+The doc command lists the functions and variables that have a doc comment,
+with their documentation, without rendering anything. It reads <infile>, or
+if there is no such file, the module <module>, such as 'std.colors'. Modules
+are found like 'import' finds them.
+
+The diff command renders two versions of a document, and prints the slides
+that were changed, removed, or added. With --output-pattern, it writes a png
+for every changed slide, with the changed pixels highlighted.
+
+With --output-pattern, '{deck}' in the pattern is replaced with the name of
+the input file without extension, and '{}' with the page number. '{:03}'
+pads the page number with zeros to three digits. The extension determines
+the format. Directories in the pattern are created when they do not exist.
+
+'import a.b' evaluates the module 'a/b.pris' next to the input file. If there
+is no such file, 'a' must be a package: a directory with a 'pris.package'
+manifest, in a directory passed with --path or listed in PRIS_PATH. Then the
+import evaluates 'b.pris' in the package, and 'import a' evaluates 'main.pris'.
+The standard library is the package 'std', which is built into Pris, so
+'import std.colors' works without installing anything.
+
+With --watch, Pris keeps running after rendering, and renders the document
+again when one of the files that it reads changes. Stop it with Ctrl+C.
+
+Top-level assignments to a variable set with --define are ignored, so the
+document can assign a default value that --define overrides.
+
+external_svg('<name>', input) runs the command of --tool <name>=<command>
+with the input on stdin, and embeds the svg image that it prints, for example
+with '--tool plantuml=\"plantuml -tsvg -pipe\"'. Documents cannot run other
+commands.
+Images are cached in $XDG_CACHE_HOME/pris, or in ~/.cache/pris, so a tool
+runs again only when its command or input changes.
+
+When the output is a terminal, a progress bar is shown while rendering,
+unless --quiet is passed.
+
+The system defaults for antialiasing and hinting vary between machines and
+FreeType versions. Set both to get the same png output everywhere, for example
+'--antialias gray --hinting none'.
+
+Spell checking uses the hunspell program, and is only available when Pris was
+built with the 'spellcheck' feature. It checks string literals passed to t().
+
+Encryption uses the qpdf program, and is only available when Pris was built
+with the 'encryption' feature. Restricting printing or copying requires an
+owner password, because without one anybody could lift the restrictions.
+Passwords are read from --password-file, or else from the PRIS_USER_PASSWORD
+and PRIS_OWNER_PASSWORD environment variables. --user-password and
+--owner-password are a fallback: other users can see the command line of a
+running program, including the passwords on it.
+
+By default there are no limits. They are useful when rendering documents
+that are not trusted, which could otherwise run indefinitely.
+
+Exit status:
+  0  Success.
+  1  --lint found problems, grep found no matches, or diff found changes.
+  2  The command line is invalid.
+  3  The document contains a syntax error.
+  4  Evaluating the document failed.
+  5  A font, image, or other resource is missing or invalid.
+  6  Reading the input or writing the output failed.
 ";
 
+const EXIT_LINT: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_PARSE: i32 = 3;
+const EXIT_EVAL: i32 = 4;
+const EXIT_RESOURCE: i32 = 5;
+const EXIT_IO: i32 = 6;
+
+fn exit_code(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::Parse => EXIT_PARSE,
+        ErrorKind::Eval => EXIT_EVAL,
+        ErrorKind::Resource => EXIT_RESOURCE,
+        ErrorKind::Io => EXIT_IO,
+        // The command-line program does not cancel, but the library can.
+        ErrorKind::Cancelled => EXIT_EVAL,
+    }
+}
+
+/// Whether messages go to stderr, because the output goes to stdout.
+static MESSAGES_TO_STDERR: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Return the stream to print messages to.
+fn messages() -> Box<Write> {
+    if MESSAGES_TO_STDERR.load(Ordering::Relaxed) {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+/// Print the error, and exit with the exit code for its kind.
+fn exit_with_error(error: Error) -> ! {
+    error.write_to(&mut messages()).ok();
+    std::process::exit(exit_code(error.kind()))
+}
+
 fn print_help_and_exit(code: i32) {
     println!("{}", USAGE);
     std::process::exit(code);
 }
 
+/// Parse a `key=value` definition, where the key must be an identifier.
+fn parse_define(definition: &str) -> Option<(String, String)> {
+    let mut parts = definition.splitn(2, '=');
+    let key = parts.next()?;
+    let value = parts.next()?;
+    match lexer::lex(key.as_bytes()) {
+        Ok(ref tokens) if tokens.len() == 1 => match tokens[0].0 {
+            lexer::Token::Ident(..) => Some((key.to_string(), value.to_string())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Return the directory where `external_svg` caches the images of tools.
+fn tools_cache_dir() -> PathBuf {
+    match (env::var_os("XDG_CACHE_HOME"), env::var_os("HOME")) {
+        (Some(ref dir), _) if !dir.is_empty() => PathBuf::from(dir).join("pris"),
+        (_, Some(home)) => PathBuf::from(home).join(".cache").join("pris"),
+        (_, None) => env::temp_dir().join("pris"),
+    }
+}
+
+/// Parse the value of `--antialias` or `--hinting` with `from_name`, or exit
+/// with a message that lists the accepted `names`.
+fn parse_font_option<T, F>(flag: &str, value: Option<String>, names: &str, from_name: F) -> T
+where F: Fn(&str) -> Option<T> {
+    match value.as_ref().and_then(|v| from_name(v)) {
+        Some(option) => option,
+        None => {
+            println!("Expected '{} <value>', where <value> is one of {}.", flag, names);
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// Parse the value of a limit flag, or exit with a message.
+fn parse_limit(flag: &str, value: Option<String>) -> Option<u64> {
+    match value.as_ref().and_then(|v| v.parse().ok()) {
+        Some(n) => Some(n),
+        None => {
+            println!("Expected '{} <n>', where <n> is a nonnegative integer.", flag);
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+extern "C" {
+    fn isatty(fd: c_int) -> c_int;
+}
+
+/// Return whether messages are printed to a terminal.
+fn messages_is_tty() -> bool {
+    let fd = if MESSAGES_TO_STDERR.load(Ordering::Relaxed) { 2 } else { 1 };
+    unsafe { isatty(fd) == 1 }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+/// Return the duration in milliseconds, for printing.
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1e3 + duration.subsec_nanos() as f64 * 1e-6
+}
+
+/// Time spent in every phase of compilation.
+struct Timings {
+    lex: Duration,
+    parse: Duration,
+    eval: Duration,
+    render: Duration,
+}
+
+impl Timings {
+    fn print(&self) {
+        let mut out = messages();
+        writeln!(out, "Lexing took     {:8.1} ms.", millis(self.lex)).ok();
+        writeln!(out, "Parsing took    {:8.1} ms.", millis(self.parse)).ok();
+        writeln!(out, "Evaluation took {:8.1} ms.", millis(self.eval)).ok();
+        writeln!(out, "Rendering took  {:8.1} ms.", millis(self.render)).ok();
+    }
+}
+
+/// Reports progress while rendering slides.
+struct Progress {
+    verbosity: Verbosity,
+    /// Whether to draw a bar that is updated in place.
+    is_tty: bool,
+    num_slides: usize,
+}
+
+impl Progress {
+    fn new(verbosity: Verbosity, num_slides: usize) -> Progress {
+        Progress {
+            verbosity: verbosity,
+            is_tty: messages_is_tty(),
+            num_slides: num_slides,
+        }
+    }
+
+    /// Report that slide `i` (starting at 1) has been rendered.
+    fn slide_done(&self, i: usize, eval_time: Duration, render_time: Duration) {
+        match self.verbosity {
+            Verbosity::Quiet => {}
+            Verbosity::Verbose => {
+                writeln!(
+                    messages(),
+                    "[{}/{}] Slide {} took {:.1} ms to evaluate, {:.1} ms to render.",
+                    i, self.num_slides, i, millis(eval_time), millis(render_time)
+                ).ok();
+            }
+            Verbosity::Normal if self.is_tty => {
+                const BAR_WIDTH: usize = 30;
+                let filled = BAR_WIDTH * i / self.num_slides;
+                let bar: String = (0..BAR_WIDTH)
+                    .map(|j| if j < filled { '=' } else { ' ' })
+                    .collect();
+                let mut out = messages();
+                write!(out, "\r[{}] {}/{} slides", bar, i, self.num_slides).ok();
+                if i == self.num_slides { write!(out, "\n").ok(); }
+                out.flush().ok();
+            }
+            // Without a terminal, a line per slide would only add noise to logs.
+            Verbosity::Normal => {}
+        }
+    }
+}
+
+/// The optional checks to run on every document.
+struct Checks<'a> {
+    warn_shadowing: bool,
+    warn_contrast: bool,
+    release_check: bool,
+    /// The Hunspell dictionary to check spelling with.
+    spellcheck: Option<&'a str>,
+}
+
+/// The files to write for the rendered decks, and how to render them.
+struct Outputs<'a> {
+    /// The pdf to write, unless there is a pattern for images.
+    outfile: &'a Path,
+    to_stdout: bool,
+    pattern: Option<&'a OutputPattern<'a>>,
+    pixel_snap: bool,
+    font_options: Option<cairo::FontOptions>,
+    simulation: Option<Simulation>,
+    pdfa: bool,
+    encryption: Option<&'a pdf::Encryption>,
+    speaker_pdf: Option<&'a Path>,
+    contact_sheet: Option<&'a Path>,
+    transcript: Option<&'a Path>,
+    emit_scene: Option<&'a Path>,
+}
+
+/// The slides of one of the documents that are rendered into a pdf.
+struct Deck<'a> {
+    /// The name of the input file without extension, for the outline.
+    name: String,
+    slides: Slides<'a>,
+}
+
+/// Read the user and owner password from a password file.
+fn read_password_file(fname: &Path) -> Result<(Option<String>, Option<String>)> {
+    let contents = fs::read_to_string(fname)
+        .map_err(|e| Error::io(fname.display().to_string(), e.to_string()))?;
+    Ok(pdf::parse_password_file(&contents))
+}
+
+/// Return the password in the environment variable `name`, if it is set and not empty.
+fn password_from_env(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|password| !password.is_empty())
+}
+
+/// Return the directory of the input file, `None` when reading from stdin.
+fn document_dir(fname: &str) -> Option<&Path> {
+    if fname == "-" {
+        None
+    } else {
+        Some(Path::new(fname).parent().unwrap_or(Path::new("")))
+    }
+}
+
+/// Return the name of the input file without extension.
+fn deck_name(fname: &str) -> String {
+    match Path::new(fname).file_stem().and_then(|s| s.to_str()) {
+        Some(stem) if fname != "-" => stem.to_string(),
+        _ => "stdin".to_string(),
+    }
+}
+
+/// Print the slowest top-level statements, and the time per builtin.
+fn print_profile(profile: &Profile) {
+    const MAX_STATEMENTS: usize = 10;
+    let mut statements = profile.statements();
+    statements.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let mut out = messages();
+    writeln!(out, "Slowest top-level statements:").ok();
+    writeln!(out, "{:>10}  statement", "ms").ok();
+    for &(ref label, time) in statements.iter().take(MAX_STATEMENTS) {
+        writeln!(out, "{:10.1}  {}", millis(time), label).ok();
+    }
+    writeln!(out, "Builtin functions:").ok();
+    writeln!(out, "{:>10}  {:>8}  builtin", "ms", "calls").ok();
+    for (name, timing) in profile.builtins() {
+        writeln!(out, "{:10.1}  {:8}  {}", millis(timing.time), timing.count, name).ok();
+    }
+}
+
 fn main() {
     let mut fnames = Vec::new();
+    let mut defines = Vec::new();
+    let mut search_dirs = Vec::new();
+    let mut allow_getenv = false;
+    let mut tools = Tools::new(tools_cache_dir());
+    let mut warn_shadowing = false;
+    let mut warn_contrast = false;
+    let mut spellcheck = None;
+    let mut release_check = false;
+    let mut lint_only = false;
+    let mut verbosity = Verbosity::Normal;
+    let mut profile = false;
+    let mut watch = false;
+    let mut output_pattern = None;
+    let mut pixel_snap = false;
+    let mut pdfa = false;
+    let mut user_password = None;
+    let mut owner_password = None;
+    let mut password_file = None;
+    let mut allow_print = true;
+    let mut allow_copy = true;
+    let mut font_options = None;
+    let mut simulation = None;
+    let mut speaker_pdf = None;
+    let mut contact_sheet = None;
+    let mut transcript = None;
+    let mut emit_scene = None;
+    let mut output = None;
+    let mut limits = Limits::default();
+
+    let mut args = std::env::args().skip(1).peekable();
+    let merge = args.peek().map_or(false, |arg| arg == "merge");
+    let info = args.peek().map_or(false, |arg| arg == "info");
+    let styles = args.peek().map_or(false, |arg| arg == "styles");
+    let grep = args.peek().map_or(false, |arg| arg == "grep");
+    let diff = args.peek().map_or(false, |arg| arg == "diff");
+    let doc = args.peek().map_or(false, |arg| arg == "doc");
+    if merge || info || styles || grep || diff || doc {
+        args.next();
+    }
 
-    for arg in std::env::args().skip(1) {
+    while let Some(arg) = args.next() {
         match &arg[..] {
             "--" => continue,
             "-h" | "--help" => print_help_and_exit(0),
+            "--allow-getenv" => { allow_getenv = true; continue }
+            "--warn-shadowing" => { warn_shadowing = true; continue }
+            "--warn-contrast" => { warn_contrast = true; continue }
+            "--release-check" => { release_check = true; continue }
+            "--lint" => { lint_only = true; continue }
+            "-q" | "--quiet" => { verbosity = Verbosity::Quiet; continue }
+            "-v" | "--verbose" => { verbosity = Verbosity::Verbose; continue }
+            "--profile" => { profile = true; continue }
+            "--watch" => { watch = true; continue }
+            "--pixel-snap" => { pixel_snap = true; continue }
+            "--pdfa" => { pdfa = true; continue }
+            "--no-print" => { allow_print = false; continue }
+            "--no-copy" => { allow_copy = false; continue }
+            "--user-password" => {
+                match args.next() {
+                    Some(password) => user_password = Some(password),
+                    None => print_help_and_exit(EXIT_USAGE),
+                }
+                continue
+            }
+            "--owner-password" => {
+                match args.next() {
+                    Some(password) => owner_password = Some(password),
+                    None => print_help_and_exit(EXIT_USAGE),
+                }
+                continue
+            }
+            "--password-file" => {
+                match args.next() {
+                    Some(fname) => password_file = Some(PathBuf::from(fname)),
+                    None => print_help_and_exit(EXIT_USAGE),
+                }
+                continue
+            }
+            "--antialias" => {
+                let names = "'none', 'gray', 'subpixel', 'default'";
+                let antialias = parse_font_option(&arg, args.next(), names, cairo::Antialias::from_name);
+                let options = font_options.get_or_insert(cairo::FontOptions::default());
+                options.antialias = antialias;
+                continue
+            }
+            "--hinting" => {
+                let names = "'none', 'slight', 'medium', 'full', 'default'";
+                let hint_style = parse_font_option(&arg, args.next(), names, cairo::HintStyle::from_name);
+                let options = font_options.get_or_insert(cairo::FontOptions::default());
+                options.hint_style = hint_style;
+                continue
+            }
+            "--simulate" => {
+                match args.next().as_ref().and_then(|m| Simulation::from_name(m)) {
+                    Some(sim) => simulation = Some(sim),
+                    None => {
+                        println!("Expected '--simulate <mode>', where <mode> is \
+                                  'colorblind=deuteranopia', 'colorblind=protanopia', \
+                                  'colorblind=tritanopia', or 'grayscale'.");
+                        std::process::exit(EXIT_USAGE);
+                    }
+                }
+                continue
+            }
+            "--spellcheck" => {
+                match args.next() {
+                    Some(dictionary) => spellcheck = Some(dictionary),
+                    None => print_help_and_exit(EXIT_USAGE),
+                }
+                continue
+            }
+            "--max-statements" => {
+                limits.max_statements = parse_limit(&arg, args.next());
+                continue
+            }
+            "--max-elements" => {
+                limits.max_elements = parse_limit(&arg, args.next());
+                continue
+            }
+            "--max-memory" => {
+                limits.max_memory = parse_limit(&arg, args.next());
+                continue
+            }
+            "-o" | "--output" => {
+                match args.next() {
+                    Some(outfile) => output = Some(outfile),
+                    None => print_help_and_exit(EXIT_USAGE),
+                }
+                continue
+            }
+            "--output-pattern" => {
+                match args.next() {
+                    Some(pattern) => output_pattern = Some(pattern),
+                    None => print_help_and_exit(EXIT_USAGE),
+                }
+                continue
+            }
+            "--speaker-pdf" => {
+                match args.next() {
+                    Some(fname) => speaker_pdf = Some(PathBuf::from(fname)),
+                    None => print_help_and_exit(EXIT_USAGE),
+                }
+                continue
+            }
+            "--contact-sheet" => {
+                match args.next() {
+                    Some(fname) => contact_sheet = Some(PathBuf::from(fname)),
+                    None => print_help_and_exit(EXIT_USAGE),
+                }
+                continue
+            }
+            "--transcript" => {
+                match args.next() {
+                    Some(fname) => transcript = Some(PathBuf::from(fname)),
+                    None => print_help_and_exit(EXIT_USAGE),
+                }
+                continue
+            }
+            "--emit-scene" => {
+                match args.next() {
+                    Some(fname) => emit_scene = Some(PathBuf::from(fname)),
+                    None => print_help_and_exit(EXIT_USAGE),
+                }
+                continue
+            }
+            "--path" => {
+                match args.next() {
+                    Some(dir) => search_dirs.push(PathBuf::from(dir)),
+                    None => print_help_and_exit(EXIT_USAGE),
+                }
+                continue
+            }
+            "--tool" => {
+                match args.next().as_ref().and_then(|d| parse_define(d)) {
+                    Some((name, command)) => tools.add(name, command),
+                    None => {
+                        println!("Expected '--tool <name>=<command>', where <name> \
+                                  is a name for the command, like a variable name.");
+                        std::process::exit(EXIT_USAGE);
+                    }
+                }
+                continue
+            }
+            "--define" => {
+                match args.next().as_ref().and_then(|d| parse_define(d)) {
+                    Some(define) => defines.push(define),
+                    None => {
+                        println!("Expected '--define <key>=<value>', where <key> \
+                                  is a variable name.");
+                        std::process::exit(EXIT_USAGE);
+                    }
+                }
+                continue
+            }
             _ => {},
         }
         fnames.push(arg);
     }
 
-    if fnames.len() < 1 || fnames.len() > 2 {
-        print_help_and_exit(1);
+    if merge || info || styles || grep || diff || doc {
+        let min_fnames = if merge || grep || diff { 2 } else { 1 };
+        let max_fnames = if merge { fnames.len() } else { min_fnames };
+        if output.is_some() || (output_pattern.is_some() && !diff) || lint_only || watch
+            || ((speaker_pdf.is_some() || contact_sheet.is_some() || transcript.is_some()
+                 || emit_scene.is_some()) && !merge)
+            || fnames.len() < min_fnames || fnames.len() > max_fnames {
+            print_help_and_exit(EXIT_USAGE);
+        }
+    }
+
+    if let Some(outfile) = output {
+        if fnames.len() != 1 || output_pattern.is_some() {
+            print_help_and_exit(EXIT_USAGE);
+        }
+        fnames.push(outfile);
+    }
+
+    if spellcheck.is_some() && !cfg!(feature = "spellcheck") {
+        println!("Pris was compiled without spell checking support, \
+                  enable the 'spellcheck' feature to check spelling.");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    if pixel_snap && output_pattern.is_none() {
+        println!("--pixel-snap requires --output-pattern, pdf output is not rasterized.");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    if simulation.is_some() && output_pattern.is_none() {
+        println!("--simulate requires --output-pattern, pdf output is not rasterized.");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    if pdfa && output_pattern.is_some() {
+        println!("--pdfa cannot be combined with --output-pattern, which does not write pdf.");
+        std::process::exit(EXIT_USAGE);
     }
 
-    if fnames.len() == 1 && fnames[0] == "-" {
+    // Other users can see the command line of a running program, so
+    // passwords on it are only used when there is no safer source.
+    let (file_user_password, file_owner_password) = match password_file {
+        Some(ref fname) => read_password_file(fname).unwrap_or_else(|e| exit_with_error(e)),
+        None => (None, None),
+    };
+    let user_password = file_user_password
+        .or_else(|| password_from_env("PRIS_USER_PASSWORD"))
+        .or(user_password);
+    let owner_password = file_owner_password
+        .or_else(|| password_from_env("PRIS_OWNER_PASSWORD"))
+        .or(owner_password);
+
+    let restricted = !allow_print || !allow_copy;
+    let encryption = if user_password.is_some() || owner_password.is_some() || restricted {
+        if !cfg!(feature = "encryption") {
+            println!("Pris was compiled without encryption support, \
+                      enable the 'encryption' feature to encrypt pdf output.");
+            std::process::exit(EXIT_USAGE);
+        }
+        if output_pattern.is_some() || pdfa {
+            println!("Encryption is not possible with --output-pattern or --pdfa.");
+            std::process::exit(EXIT_USAGE);
+        }
+        if restricted && owner_password.is_none() {
+            println!("--no-print and --no-copy require an owner password.");
+            std::process::exit(EXIT_USAGE);
+        }
+        let user_password = user_password.unwrap_or_default();
+        Some(pdf::Encryption {
+            // Opening the file with the owner password grants all permissions,
+            // so without restrictions the user password can serve as both.
+            owner_password: owner_password.unwrap_or_else(|| user_password.clone()),
+            user_password: user_password,
+            allow_print: allow_print,
+            allow_copy: allow_copy,
+        })
+    } else {
+        None
+    };
+
+    if font_options.is_some() && output_pattern.is_none() {
+        println!("--antialias and --hinting require --output-pattern, \
+                  pdf output is not rasterized.");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let max_fnames = if output_pattern.is_some() { 1 } else { 2 };
+    if !merge && !diff && (fnames.len() < 1 || fnames.len() > max_fnames) {
+        print_help_and_exit(EXIT_USAGE);
+    }
+
+    let pattern = match output_pattern.as_ref().map(|p| OutputPattern::parse(p)) {
+        Some(Ok(pattern)) => Some(pattern),
+        Some(Err(e)) => { e.print(); std::process::exit(EXIT_USAGE) }
+        None => None,
+    };
+
+    // For grep, the text to search for follows the input file.
+    let needle = if grep { fnames.pop() } else { None };
+
+    match pattern {
+        Some(ref pattern) if diff && pattern.format() != ImageFormat::Png => {
+            println!("The diff command writes png images, the pattern must end in '.png'.");
+            std::process::exit(EXIT_USAGE);
+        }
+        _ => {}
+    }
+
+    if fnames.len() == 1 && fnames[0] == "-" && pattern.is_none() && !info && !styles && !grep && !doc {
         println!("Specifiying an output file is required when reading from stdin.");
-        std::process::exit(1);
+        std::process::exit(EXIT_USAGE);
     }
 
-    let infile = Path::new(&fnames[0]);
-    let outfile = if fnames.len() == 2 {
-        PathBuf::from(&fnames[1])
+    // For merge, the output file comes first, followed by the input files.
+    let (infiles, outfile) = if merge {
+        (&fnames[1..], PathBuf::from(&fnames[0]))
+    } else if diff {
+        (&fnames[..], PathBuf::new())
+    } else if fnames.len() == 2 {
+        (&fnames[..1], PathBuf::from(&fnames[1]))
     } else {
-        infile.with_extension("pdf")
+        (&fnames[..1], Path::new(&fnames[0]).with_extension("pdf"))
     };
 
-    let mut input = Vec::new();
+    let to_stdout = pattern.is_none() && outfile == Path::new("-");
+    if to_stdout {
+        MESSAGES_TO_STDERR.store(true, Ordering::Relaxed);
+    }
 
-    // Allow reading from stdin by passing "-" as the input filename.
-    if fnames[0] == "-" {
-        io::stdin().read_to_end(&mut input).unwrap();
+    // Relative links in the pdf are resolved against its directory.
+    let output_dir = if to_stdout || pattern.is_some() || diff {
+        None
     } else {
-        let f = File::open(infile)
-            .expect("Failed to open input file");
-        BufReader::new(f).read_to_end(&mut input)
-            .expect("Failed to read input file");
+        Some(outfile.parent().unwrap_or(Path::new("")))
+    };
+
+    let checks = Checks {
+        warn_shadowing: warn_shadowing,
+        warn_contrast: warn_contrast,
+        release_check: release_check,
+        spellcheck: spellcheck.as_ref().map(|dictionary| &dictionary[..]),
+    };
+    let outputs = Outputs {
+        outfile: &outfile,
+        to_stdout: to_stdout,
+        pattern: pattern.as_ref(),
+        pixel_snap: pixel_snap,
+        font_options: font_options,
+        simulation: simulation,
+        pdfa: pdfa,
+        encryption: encryption.as_ref(),
+        speaker_pdf: speaker_pdf.as_ref().map(|fname| fname.as_path()),
+        contact_sheet: contact_sheet.as_ref().map(|fname| fname.as_path()),
+        transcript: transcript.as_ref().map(|fname| fname.as_path()),
+        emit_scene: emit_scene.as_ref().map(|fname| fname.as_path()),
+    };
+
+    let logger: Rc<Logger> = if to_stdout {
+        Rc::new(StderrLogger)
+    } else {
+        Rc::new(StdoutLogger)
+    };
+    let options = compile::Options {
+        defines: defines,
+        allow_getenv: allow_getenv,
+        tools: Rc::new(tools),
+        profile: profile,
+        limits: limits,
+        logger: logger.clone(),
+        search_path: module::SearchPath::with_env(search_dirs),
+        output_dir: output_dir.map(|dir| dir.to_path_buf()),
+    };
+
+    if doc {
+        print_module_doc(&fnames[0], &options.search_path).unwrap_or_else(|e| exit_with_error(e));
+        std::process::exit(0);
+    }
+
+    if watch {
+        if lint_only {
+            print_help_and_exit(EXIT_USAGE);
+        }
+        if infiles[0] == "-" || to_stdout {
+            println!("--watch requires an input file and an output file, not stdin or stdout.");
+            std::process::exit(EXIT_USAGE);
+        }
+        watch_and_render(&infiles[0], &options, &checks, &outputs, verbosity);
+    }
+
+    let inputs: Vec<Vec<u8>> = infiles
+        .iter()
+        .map(|fname| read_input(fname).unwrap_or_else(|e| exit_with_error(e)))
+        .collect();
+
+    let lex_start = Instant::now();
+    let tokens: Vec<_> = inputs.iter().map(|input| lex_or_abort(input)).collect();
+    let parse_start = Instant::now();
+    let docs: Vec<_> = inputs
+        .iter()
+        .zip(&tokens)
+        .map(|(input, tokens)| parse_or_abort(input, tokens))
+        .collect();
+
+    // Imported modules are resolved relative to the directory of the input.
+    let sources: Vec<Vec<module::Source>> = infiles
+        .iter()
+        .zip(&docs)
+        .map(|(fname, doc)| {
+            let base_dir = Path::new(fname).parent().unwrap_or(Path::new(""));
+            module::load_sources(doc, base_dir, &options.search_path)
+                .unwrap_or_else(|e| exit_with_error(e))
+        })
+        .collect();
+    let modules: Vec<module::Modules> = sources
+        .iter()
+        .map(|s| module::Modules::parse(s).unwrap_or_else(|e| exit_with_error(e)))
+        .collect();
+    let eval_start = Instant::now();
+
+    if lint_only {
+        let mut defined: Vec<Symbol> = options.defines.iter().map(|&(ref key, _)| Symbol::intern(key)).collect();
+        defined.extend(modules[0].assigned_names());
+        let num_problems = lint(&inputs[0], &tokens[0], &docs[0], &defined);
+        std::process::exit(if num_problems > 0 { EXIT_LINT } else { 0 });
+    }
+
+    for ((fname, input), doc) in infiles.iter().zip(&inputs).zip(&docs) {
+        let file = if docs.len() > 1 { Some(&fname[..]) } else { None };
+        check_source(&*logger, &checks, file, input, doc)
+            .unwrap_or_else(|e| exit_with_error(e));
+    }
+
+    let mut fm = runtime::FontMap::new();
+    let mut decks = Vec::with_capacity(docs.len());
+    let mut page_setup: Option<driver::PageSetup> = None;
+    for ((fname, doc), modules) in infiles.iter().zip(&docs).zip(&modules) {
+        let (slides, page) = compile::evaluate(&mut fm, doc, modules, document_dir(fname), &options)
+            .unwrap_or_else(|e| exit_with_error(e));
+        check_slides(&*logger, &checks, &slides, &page)
+            .unwrap_or_else(|e| exit_with_error(e));
+        let deck = Deck {
+            name: deck_name(fname),
+            slides: slides,
+        };
+        // All pages of a pdf share the page boxes, so merged documents must
+        // have the same page setup. Compared documents need it to compare
+        // their pixels.
+        match page_setup {
+            Some(ref first) if *first != page => {
+                let msg = format!(
+                    "'{}' has a different canvas size, bleed, or crop marks than '{}', \
+                     but {} documents must have the same page setup.",
+                    fname, infiles[0], if diff { "compared" } else { "merged" },
+                );
+                exit_with_error(Error::value(msg));
+            }
+            Some(..) => {}
+            None => page_setup = Some(page),
+        }
+        decks.push(deck);
+    }
+    let page = page_setup.unwrap();
+
+    if info {
+        print_info(&docs[0], &decks[0].slides, &page);
+        std::process::exit(0);
+    }
+
+    if styles {
+        print_styles(&decks[0].slides, &page);
+        std::process::exit(0);
+    }
+
+    if diff {
+        let num_changes = print_diff(&mut fm, &*logger, &decks[0], &decks[1], &page, pattern.as_ref())
+            .unwrap_or_else(|e| exit_with_error(e));
+        std::process::exit(if num_changes > 0 { EXIT_LINT } else { 0 });
+    }
+
+    if let Some(ref needle) = needle {
+        let num_matches = print_matches(&decks[0].slides, needle);
+        std::process::exit(if num_matches > 0 { 0 } else { EXIT_LINT });
+    }
+
+    let render_start = Instant::now();
+
+    let num_slides = decks.iter().map(|deck| deck.slides.frames.len()).sum();
+    let progress = Progress::new(verbosity, num_slides);
+    let mut cache = driver::RenderCache::new();
+    if let Err(e) = write_outputs(&mut fm, &mut cache, None, &*logger, &decks, &page, &outputs, &progress) {
+        exit_with_error(e);
+    }
+
+    if verbosity == Verbosity::Verbose {
+        let timings = Timings {
+            lex: parse_start - lex_start,
+            parse: eval_start - parse_start,
+            eval: render_start - eval_start,
+            render: render_start.elapsed(),
+        };
+        timings.print();
+    }
+
+    if profile {
+        for deck in &decks {
+            if merge {
+                writeln!(messages(), "Profile of '{}':", deck.name).ok();
+            }
+            print_profile(&deck.slides.profile);
+        }
     }
+}
+
+/// How often to check the watched files for changes.
+const WATCH_INTERVAL_MILLIS: u64 = 200;
+
+/// Render the document once for watch mode, and add the files that it reads to `files`.
+///
+/// These are the input, the modules that it imports, and the images on its
+/// slides. When rendering fails, `files` holds the files found so far, so a
+/// fix to the input triggers the next render.
+fn render_watched(fm: &mut runtime::FontMap,
+                  cache: &mut driver::RenderCache,
+                  slide_cache: &mut driver::SlideCache,
+                  fname: &str,
+                  options: &compile::Options,
+                  checks: &Checks,
+                  outputs: &Outputs,
+                  verbosity: Verbosity,
+                  files: &mut Vec<PathBuf>)
+                  -> Result<()> {
+    files.push(PathBuf::from(fname));
+    let input = read_input(fname)?;
+    let report = |e: Error| { report_parse_location(&input, &e); e };
+    let tokens = lexer::lex(&input).map_err(&report)?;
+    let doc = parser::parse(&tokens).map_err(&report)?;
+    let base_dir = Path::new(fname).parent().unwrap_or(Path::new(""));
+    let sources = module::load_sources(&doc, base_dir, &options.search_path)?;
+    files.extend(sources.iter().filter_map(|source| source.path.clone()));
+    let modules = module::Modules::parse(&sources)?;
+    let logger = &*options.logger;
+    check_source(logger, checks, None, &input, &doc)?;
+
+    let (slides, page) = compile::evaluate(fm, &doc, &modules, Some(base_dir), options)?;
+    let mut resources = driver::Resources::new();
+    for frame in &slides.frames {
+        resources.collect(&driver::Slide::from_frame(frame)?);
+    }
+    files.extend(resources.images);
+    check_slides(logger, checks, &slides, &page)?;
 
-    let doc = parse_or_abort(&input);
+    let progress = Progress::new(verbosity, slides.frames.len());
+    let decks = [Deck { name: deck_name(fname), slides: slides }];
+    write_outputs(fm, cache, Some(slide_cache), logger, &decks, &page, outputs, &progress)?;
+    if options.profile {
+        print_profile(&decks[0].slides.profile);
+    }
+    Ok(())
+}
 
-    let mut frames = Vec::new();
+/// Return the modification time of every file, or none if it does not exist.
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Render the document, and render it again whenever a file that it reads changes.
+///
+/// The fonts and the render caches are kept between renders, so svg images
+/// that did not change are not loaded again, and slides that did not change
+/// are painted from their recording rather than drawn again. A failed render reports its
+/// error like a normal run, without stopping the watch.
+fn watch_and_render(fname: &str,
+                    options: &compile::Options,
+                    checks: &Checks,
+                    outputs: &Outputs,
+                    verbosity: Verbosity)
+                    -> ! {
+    let interval = Duration::from_millis(WATCH_INTERVAL_MILLIS);
     let mut fm = runtime::FontMap::new();
-    let canvas_size: pris::Vec2;
-
-    {
-        let mut stmt_interpreter = interpreter::StmtInterpreter::new(&mut fm);
-        for statement in &doc.0 {
-            let result = match stmt_interpreter.eval_statement(statement) {
-                Ok(x) => x,
-                Err(e) => { e.print(); panic!("Abort after error.") }
+    let mut cache = driver::RenderCache::new();
+    let mut slide_cache = driver::SlideCache::new();
+    let mut changed = Vec::new();
+    loop {
+        cache.start_document(&changed);
+        slide_cache.forget_images(&changed);
+        let mut files = Vec::new();
+        let result = render_watched(&mut fm, &mut cache, &mut slide_cache, fname, options,
+                                    checks, outputs, verbosity, &mut files);
+        if let Err(e) = result {
+            e.write_to(&mut messages()).ok();
+        }
+
+        if verbosity != Verbosity::Quiet {
+            let noun = if files.len() == 1 { "file" } else { "files" };
+            writeln!(messages(), "Watching {} {} for changes, press Ctrl+C to stop.", files.len(), noun).ok();
+        }
+        let rendered_times = modification_times(&files);
+        let mut times = rendered_times.clone();
+        while times == rendered_times {
+            thread::sleep(interval);
+            times = modification_times(&files);
+        }
+        // Editors may write a file in several steps, wait until it settles.
+        loop {
+            thread::sleep(interval);
+            let next_times = modification_times(&files);
+            if next_times == times {
+                break
+            }
+            times = next_times;
+        }
+        changed = files
+            .iter()
+            .zip(rendered_times.iter().zip(&times))
+            .filter(|&(_, (before, after))| before != after)
+            .map(|(file, _)| file.clone())
+            .collect();
+    }
+}
+
+/// Write the main output, and the other files that the outputs ask for.
+///
+/// The main output is rendered with `cache`, which watch mode keeps between
+/// renders of the document. Watch mode passes a `slide_cache` too, to reuse
+/// the slides that did not change when the output is a pdf.
+fn write_outputs(fm: &mut runtime::FontMap,
+                 cache: &mut driver::RenderCache,
+                 slide_cache: Option<&mut driver::SlideCache>,
+                 logger: &Logger,
+                 decks: &[Deck],
+                 page: &driver::PageSetup,
+                 outputs: &Outputs,
+                 progress: &Progress)
+                 -> Result<()> {
+    match outputs.pattern {
+        Some(pattern) => {
+            let deck = &decks[0];
+            render_images(fm, cache, logger, &deck.slides, page, pattern, &deck.name,
+                          outputs.pixel_snap, outputs.font_options, outputs.simulation, progress)?
+        }
+        None if outputs.to_stdout => {
+            render_pdf_to_stdout(fm, cache, slide_cache, logger, decks, page, outputs.pdfa,
+                                 outputs.encryption, progress)?
+        }
+        None => render_pdf(fm, cache, slide_cache, logger, decks, page, outputs.outfile, outputs.pdfa,
+                           outputs.encryption, progress)?,
+    }
+
+    if let Some(fname) = outputs.speaker_pdf {
+        render_overview_pdf(fm, logger, decks, page, fname, driver::render_speaker_view)?;
+    }
+
+    if let Some(fname) = outputs.contact_sheet {
+        render_overview_pdf(fm, logger, decks, page, fname, driver::render_contact_sheet)?;
+    }
+
+    if let Some(fname) = outputs.transcript {
+        write_transcript(decks, fname)?;
+    }
+
+    if let Some(fname) = outputs.emit_scene {
+        write_scene(decks, page, fname)?;
+    }
+
+    Ok(())
+}
+
+/// Render the slides of all decks to a single pdf file.
+///
+/// When there is more than one deck, the outline gets an item for every deck,
+/// with the entries of its table of contents nested below it.
+fn render_pdf(fm: &mut runtime::FontMap,
+              cache: &mut driver::RenderCache,
+              slide_cache: Option<&mut driver::SlideCache>,
+              logger: &Logger,
+              decks: &[Deck],
+              page: &driver::PageSetup,
+              outfile: &Path,
+              pdfa: bool,
+              encryption: Option<&pdf::Encryption>,
+              progress: &Progress)
+              -> Result<()> {
+    let bytes = render_pdf_bytes(fm, cache, slide_cache, logger, decks, page, pdfa, progress)?;
+    fs::write(outfile, &bytes)
+        .map_err(|e| Error::io(outfile.display().to_string(), e.to_string()))?;
+
+    if let Some(encryption) = encryption {
+        pdf::encrypt(outfile, encryption)?;
+    }
+
+    Ok(())
+}
+
+/// Render the decks to a pdf held in memory, like `render_pdf` without encryption.
+fn render_pdf_bytes(fm: &mut runtime::FontMap,
+                    cache: &mut driver::RenderCache,
+                    slide_cache: Option<&mut driver::SlideCache>,
+                    logger: &Logger,
+                    decks: &[Deck],
+                    page: &driver::PageSetup,
+                    pdfa: bool,
+                    progress: &Progress)
+                    -> Result<Vec<u8>> {
+    let mut docs = Vec::with_capacity(decks.len());
+    let mut eval_times = Vec::new();
+    for deck in decks {
+        let mut doc = compile_deck(deck, page)?;
+        doc.set_pdfa(pdfa);
+        docs.push(doc);
+        eval_times.extend_from_slice(&deck.slides.eval_times);
+    }
+    driver::render_pdf_merged(fm, cache, logger, &docs, slide_cache, &mut |i, render_time| {
+        progress.slide_done(i, eval_times[i - 1], render_time)
+    })
+}
+
+/// Collect the slides of the deck into a document to render.
+fn compile_deck(deck: &Deck, page: &driver::PageSetup) -> Result<driver::CompiledDoc> {
+    let mut doc = deck.slides.compile(page.clone())?;
+    doc.name = deck.name.clone();
+    Ok(doc)
+}
+
+/// The signature of functions that render a view of all slides, such as a speaker view.
+type RenderView = fn(&mut runtime::FontMap,
+                     &mut driver::RenderCache,
+                     &Logger,
+                     &mut cairo::Cairo,
+                     &driver::PageSetup,
+                     &[driver::Slide])
+                     -> Result<()>;
+
+/// Render a view of the slides of all decks to a pdf file with `render`.
+fn render_overview_pdf(fm: &mut runtime::FontMap,
+                       logger: &Logger,
+                       decks: &[Deck],
+                       page: &driver::PageSetup,
+                       outfile: &Path,
+                       render: RenderView)
+                       -> Result<()> {
+    let page_size = page.page_size();
+    let surf = cairo::Surface::new_pdf(outfile, page_size.x, page_size.y)?;
+    let mut cr = cairo::Cairo::new(surf)?;
+    let slides: Vec<_> = decks
+        .iter()
+        .flat_map(|deck| deck.slides.frames.iter().map(|frame| driver::Slide::from_frame(frame)))
+        .collect::<Result<_>>()?;
+    let mut cache = driver::RenderCache::new();
+    render(fm, &mut cache, logger, &mut cr, page, &slides)
+}
+
+/// Write the text on all slides of the decks to a transcript file.
+fn write_transcript(decks: &[Deck], outfile: &Path) -> Result<()> {
+    let slides: Vec<_> = decks
+        .iter()
+        .flat_map(|deck| deck.slides.frames.iter().map(|frame| driver::Slide::from_frame(frame)))
+        .collect::<Result<_>>()?;
+    let format = transcript::Format::from_path(outfile);
+    File::create(outfile)
+        .map(io::BufWriter::new)
+        .and_then(|mut f| transcript::write(&mut f, &slides, format).and_then(|()| f.flush()))
+        .map_err(|e| Error::io(outfile.display().to_string(), e.to_string()))
+}
+
+/// Write the elements on all slides of the decks to a JSON file.
+fn write_scene(decks: &[Deck], page: &driver::PageSetup, outfile: &Path) -> Result<()> {
+    let slides: Vec<_> = decks
+        .iter()
+        .flat_map(|deck| deck.slides.frames.iter().map(|frame| driver::Slide::from_frame(frame)))
+        .collect::<Result<_>>()?;
+    File::create(outfile)
+        .map(io::BufWriter::new)
+        .and_then(|mut f| scene::write(&mut f, &slides, page).and_then(|()| f.flush()))
+        .map_err(|e| Error::io(outfile.display().to_string(), e.to_string()))
+}
+
+/// Render all slides to a pdf, and write it to stdout.
+///
+/// The pdf is rendered in memory. Only encryption needs a file, because qpdf
+/// reads and writes files. That file goes in a new directory that only the
+/// current user can access, so other users cannot read or replace it.
+fn render_pdf_to_stdout(fm: &mut runtime::FontMap,
+                        cache: &mut driver::RenderCache,
+                        slide_cache: Option<&mut driver::SlideCache>,
+                        logger: &Logger,
+                        decks: &[Deck],
+                        page: &driver::PageSetup,
+                        pdfa: bool,
+                        encryption: Option<&pdf::Encryption>,
+                        progress: &Progress)
+                        -> Result<()> {
+    let bytes = match encryption {
+        None => render_pdf_bytes(fm, cache, slide_cache, logger, decks, page, pdfa, progress)?,
+        Some(encryption) => {
+            let tmp_dir = create_private_temp_dir()?;
+            let tmpfile = tmp_dir.join("output.pdf");
+            let result = render_pdf(fm, cache, slide_cache, logger, decks, page, &tmpfile, pdfa, Some(encryption), progress)
+                .and_then(|()| {
+                    fs::read(&tmpfile).map_err(|e| Error::io(tmpfile.display().to_string(), e.to_string()))
+                });
+            fs::remove_dir_all(&tmp_dir).ok();
+            result?
+        }
+    };
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    out.write_all(&bytes)
+        .and_then(|()| out.flush())
+        .map_err(|e| Error::io("stdout".to_string(), e.to_string()))
+}
+
+/// Create a new directory in the temp directory that only the current user
+/// can access.
+///
+/// The name has a random suffix, and creating the directory fails when it
+/// exists already, so another user cannot prepare it in advance.
+fn create_private_temp_dir() -> Result<PathBuf> {
+    let mut builder = fs::DirBuilder::new();
+    builder.mode(0o700);
+    let mut last_path = None;
+    for _ in 0..16 {
+        // Every `RandomState` has fresh random keys.
+        let suffix = RandomState::new().build_hasher().finish();
+        let path = env::temp_dir().join(format!("pris-{}-{:016x}", process::id(), suffix));
+        match builder.create(&path) {
+            Ok(()) => return Ok(path),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(Error::io(path.display().to_string(), e.to_string())),
+        }
+        last_path = Some(path);
+    }
+    let path = last_path.unwrap().display().to_string();
+    Err(Error::io(path, "failed to create a temporary directory.".to_string()))
+}
+
+/// Render every page (every step of every slide) to a separate image file.
+fn render_images(fm: &mut runtime::FontMap,
+                 cache: &mut driver::RenderCache,
+                 logger: &Logger,
+                 slides: &Slides,
+                 page: &driver::PageSetup,
+                 pattern: &OutputPattern,
+                 deck: &str,
+                 pixel_snap: bool,
+                 font_options: Option<cairo::FontOptions>,
+                 simulation: Option<Simulation>,
+                 progress: &Progress)
+                 -> Result<()> {
+    let page_size = page.page_size();
+    let mut page_number = 0;
+    if pattern.format() == ImageFormat::Png {
+        cache.set_pixel_snap(pixel_snap);
+        if let Some(options) = font_options {
+            cache.set_font_options(options);
+        }
+    }
+
+    for (i, frame) in slides.frames.iter().enumerate() {
+        let slide_start = Instant::now();
+        let slide = driver::Slide::from_frame(frame)?;
+        for j in 0..slide.num_steps() {
+            page_number += 1;
+            let fname = PathBuf::from(pattern.file_name(deck, page_number));
+            if let Some(dir) = fname.parent() {
+                fs::create_dir_all(dir).map_err(|e| {
+                    Error::io(dir.display().to_string(), e.to_string())
+                })?;
+            }
+
+            let surf = match pattern.format() {
+                ImageFormat::Png => cairo::Surface::new_image(page_size.x, page_size.y)?,
+                ImageFormat::Svg => cairo::Surface::new_svg(&fname, page_size.x, page_size.y)?,
             };
-            if let Some(frame) = result { frames.push(frame); }
+            let mut cr = cairo::Cairo::new(surf.clone())?;
+            driver::render_step(fm, cache, logger, &mut cr, page, &slide, j)?;
+
+            // For svg, destroying the context and surface writes the file.
+            drop(cr);
+            if pattern.format() == ImageFormat::Png {
+                if let Some(simulation) = simulation {
+                    surf.modify_image_data(|data| simulation.apply_argb32(data))?;
+                }
+                surf.write_to_png(&fname)?;
+            }
+        }
+        progress.slide_done(i + 1, slides.eval_times[i], slide_start.elapsed());
+    }
+
+    Ok(())
+}
+
+/// Print a summary of the evaluated document, one `key: value` pair per line.
+fn print_info(doc: &ast::Document, slides: &Slides, page: &driver::PageSetup) {
+    let times = timing::collect(&slides.frames).unwrap_or_else(|err| exit_with_error(err));
+    let mut resources = driver::Resources::new();
+    let mut num_pages = 0;
+    for frame in &slides.frames {
+        let slide = driver::Slide::from_frame(frame).unwrap_or_else(|err| exit_with_error(err));
+        num_pages += slide.num_steps();
+        resources.collect(&slide);
+    }
+
+    println!("slides: {}", slides.frames.len());
+    println!("pages: {}", num_pages);
+    println!("canvas_size: {} {}", page.canvas_size.x, page.canvas_size.y);
+    for section in doc.sections() {
+        println!("section: {}", section);
+    }
+    for &(ref family, ref style) in &resources.fonts {
+        println!("font: {}, {}", family, style);
+    }
+    for path in &resources.images {
+        println!("image: {}", path.display());
+    }
+
+    // Only documents that set `minutes` get a time estimate.
+    if times.iter().any(|t| t.timed_slides > 0) {
+        for time in &times {
+            match time.section {
+                Some(ref section) => println!("time: {} min, {}", time.minutes, section),
+                None => println!("time: {} min", time.minutes),
+            }
+        }
+        let total: f64 = times.iter().map(|t| t.minutes).sum();
+        let untimed: usize = times.iter().map(|t| t.slides - t.timed_slides).sum();
+        println!("total_time: {} min", total);
+        println!("untimed_slides: {}", untimed);
+    }
+}
+
+/// Render the final step of a slide to an image the size of a page.
+///
+/// A slide without content has no steps, it renders as an empty image.
+fn render_final_step(fm: &mut runtime::FontMap,
+                     cache: &mut driver::RenderCache,
+                     logger: &Logger,
+                     page: &driver::PageSetup,
+                     slide: &driver::Slide)
+                     -> Result<cairo::Surface> {
+    let page_size = page.page_size();
+    let surf = cairo::Surface::new_image(page_size.x, page_size.y)?;
+    if slide.num_steps() > 0 {
+        let mut cr = cairo::Cairo::new(surf.clone())?;
+        driver::render_step(fm, cache, logger, &mut cr, page, slide, slide.num_steps() - 1)?;
+    }
+    Ok(surf)
+}
+
+/// Print the slides that differ between two versions of a deck, return how many.
+///
+/// With a pattern, write an image of every changed slide with the changed
+/// pixels highlighted, numbered after the slide in the new deck.
+fn print_diff(fm: &mut runtime::FontMap,
+              logger: &Logger,
+              old: &Deck,
+              new: &Deck,
+              page: &driver::PageSetup,
+              pattern: Option<&OutputPattern>)
+              -> Result<usize> {
+    let old_slides: Vec<_> = old.slides.frames.iter().map(|f| driver::Slide::from_frame(f)).collect::<Result<_>>()?;
+    let new_slides: Vec<_> = new.slides.frames.iter().map(|f| driver::Slide::from_frame(f)).collect::<Result<_>>()?;
+    let old_hashes: Vec<u64> = old_slides.iter().map(|s| s.content_hash()).collect();
+    let new_hashes: Vec<u64> = new_slides.iter().map(|s| s.content_hash()).collect();
+
+    let mut cache = driver::RenderCache::new();
+    let mut num_changes = 0;
+    for change in diff::align(&old_hashes, &new_hashes) {
+        match change {
+            diff::Change::Same(..) => continue,
+            diff::Change::Removed(i) => println!("removed: {}", i + 1),
+            diff::Change::Added(j) => println!("added: {}", j + 1),
+            diff::Change::Changed(i, j) => {
+                let before = render_final_step(fm, &mut cache, logger, page, &old_slides[i])?;
+                let after = render_final_step(fm, &mut cache, logger, page, &new_slides[j])?;
+                let mut old_pixels = Vec::new();
+                before.modify_image_data(|data| old_pixels.extend_from_slice(data))?;
+                let mut num_pixels = 0;
+                let mut num_changed = 0;
+                after.modify_image_data(|data| {
+                    num_pixels = data.len() / 4;
+                    num_changed = diff::count_changed_pixels(&old_pixels, data);
+                    if num_changed > 0 && pattern.is_some() {
+                        diff::highlight_changes(&old_pixels, data);
+                    }
+                })?;
+
+                // Slides with a different content hash can still look the
+                // same, for example when elements are drawn in another order.
+                if num_changed == 0 {
+                    continue
+                }
+                let percentage = 100.0 * num_changed as f64 / num_pixels as f64;
+                println!("changed: {} -> {}, {:.1}% of pixels", i + 1, j + 1, percentage);
+
+                if let Some(pattern) = pattern {
+                    let fname = PathBuf::from(pattern.file_name(&new.name, j + 1));
+                    if let Some(dir) = fname.parent() {
+                        fs::create_dir_all(dir).map_err(|e| {
+                            Error::io(dir.display().to_string(), e.to_string())
+                        })?;
+                    }
+                    after.write_to_png(&fname)?;
+                }
+            }
+        }
+        num_changes += 1;
+    }
+
+    Ok(num_changes)
+}
+
+/// Record that `slide` uses `key`, return whether it was not recorded yet.
+///
+/// Slides are visited in order, so a use is recorded already if it is the
+/// last one of the key.
+fn record_use<T: PartialEq>(uses: &mut Vec<(T, Vec<usize>)>, key: T, slide: usize) -> bool {
+    match uses.iter().position(|u| u.0 == key) {
+        Some(i) if uses[i].1.last() == Some(&slide) => false,
+        Some(i) => { uses[i].1.push(slide); true }
+        None => { uses.push((key, vec![slide])); true }
+    }
+}
+
+/// Format slide numbers as 'slide 1' or 'slides 1, 2'.
+fn format_slides(slides: &[usize]) -> String {
+    let numbers: Vec<String> = slides.iter().map(|i| i.to_string()).collect();
+    let noun = if slides.len() == 1 { "slide" } else { "slides" };
+    format!("{} {}", noun, numbers.join(", "))
+}
+
+/// Format a color as `#rrggbb`, or as `#rrggbbaa` if it is not opaque.
+fn format_color(rgba: [u8; 4]) -> String {
+    match rgba[3] {
+        0xff => format!("#{:02x}{:02x}{:02x}", rgba[0], rgba[1], rgba[2]),
+        _ => format!("#{:02x}{:02x}{:02x}{:02x}", rgba[0], rgba[1], rgba[2], rgba[3]),
+    }
+}
+
+/// Print the fonts and colors on every slide, and the ones that look alike.
+///
+/// Sizes are rounded to 0.1 pt, so sizes that print the same count as the same.
+fn print_styles(slides: &Slides, page: &driver::PageSetup) {
+    // Every distinct font and color, with the slides that use it.
+    let mut fonts: Vec<((String, String, f64), Vec<usize>)> = Vec::new();
+    let mut colors: Vec<([u8; 4], Vec<usize>)> = Vec::new();
+    for (i, frame) in slides.frames.iter().enumerate() {
+        let slide = driver::Slide::from_frame(frame).unwrap_or_else(|err| exit_with_error(err));
+        let styles = slide.styles();
+        for (family, style, size) in styles.fonts {
+            // A pt is 1/1080 of the canvas height.
+            let size_pt = (size * 1080.0 / page.canvas_size.y * 10.0).round() / 10.0;
+            if record_use(&mut fonts, (family.clone(), style.clone(), size_pt), i + 1) {
+                println!("{}: font: {}, {}, {}pt", i + 1, family, style, size_pt);
+            }
+        }
+        for rgba in styles.colors {
+            if record_use(&mut colors, rgba, i + 1) {
+                println!("{}: color: {}", i + 1, format_color(rgba));
+            }
+        }
+    }
+
+    // Sizes within 10% of each other, in the same font, look alike.
+    for (j, &(ref b, ref b_slides)) in fonts.iter().enumerate() {
+        for &(ref a, ref a_slides) in &fonts[..j] {
+            if a.0 == b.0 && a.1 == b.1 && (a.2 - b.2).abs() <= 0.1 * a.2.max(b.2) {
+                println!(
+                    "similar font sizes: {}, {}, {}pt ({}) and {}pt ({})",
+                    a.0, a.1, a.2, format_slides(a_slides), b.2, format_slides(b_slides),
+                );
+            }
+        }
+    }
+
+    // Colors whose components all differ by a few steps look alike.
+    for (j, &(b, ref b_slides)) in colors.iter().enumerate() {
+        for &(a, ref a_slides) in &colors[..j] {
+            if (0..4).all(|k| (a[k] as i32 - b[k] as i32).abs() <= 12) {
+                println!(
+                    "similar colors: {} ({}) and {} ({})",
+                    format_color(a), format_slides(a_slides),
+                    format_color(b), format_slides(b_slides),
+                );
+            }
+        }
+    }
+}
+
+/// Print the lines of text on slides that contain `needle`, return how many.
+fn print_matches(slides: &Slides, needle: &str) -> usize {
+    let mut num_matches = 0;
+    for (i, frame) in slides.frames.iter().enumerate() {
+        let slide = driver::Slide::from_frame(frame).unwrap_or_else(|err| exit_with_error(err));
+        for text in slide.texts() {
+            for line in text.lines().filter(|line| line.contains(needle)) {
+                println!("{}: {}", i + 1, line);
+                num_matches += 1;
+            }
+        }
+    }
+    num_matches
+}
+
+/// Print the documented functions and variables of a file, or of a module
+/// such as 'std.colors' when there is no file with that name.
+fn print_module_doc(name: &str, search_path: &module::SearchPath) -> Result<()> {
+    let input = if name == "-" || Path::new(name).is_file() {
+        read_input(name)?
+    } else {
+        let parts: Vec<&str> = name.split('.').collect();
+        match search_path.resolve(Path::new(""), &parts)? {
+            module::Location::File(path) => {
+                fs::read(&path).map_err(|e| Error::io(path.display().to_string(), e.to_string()))?
+            }
+            module::Location::Std(bytes) => bytes.to_vec(),
+        }
+    };
+    let tokens = lex_or_abort(&input);
+    let doc = parse_or_abort(&input, &tokens);
+    print_doc(&doc);
+    Ok(())
+}
+
+/// Print the top-level assignments that have a doc comment, with their doc.
+fn print_doc(doc: &ast::Document) {
+    for (i, assign) in doc.documented().iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        match assign.1 {
+            ast::Term::FnDef(ref fdf) => {
+                let args: Vec<String> = fdf.0.iter().map(|arg| arg.to_string()).collect();
+                println!("{}({})", assign.0, args.join(", "));
+            }
+            _ => println!("{}", assign.0),
+        }
+        for line in assign.2.text().lines() {
+            if line.is_empty() {
+                println!();
+            } else {
+                println!("    {}", line);
+            }
+        }
+    }
+}
+
+/// Run the checks that look at the source of a document.
+///
+/// The file name is included in messages if it is given.
+fn check_source(logger: &Logger,
+                checks: &Checks,
+                fname: Option<&str>,
+                input: &[u8],
+                doc: &ast::Document)
+                -> Result<()> {
+    if checks.warn_shadowing {
+        for name in lint::shadowed_builtins(doc) {
+            let msg = format!("variable '{}' shadows a builtin function.", name);
+            logger.log(Level::Warning, &msg);
+        }
+    }
+    if let Some(dictionary) = checks.spellcheck {
+        warn_misspellings(logger, dictionary, fname, input, doc)?;
+    }
+    Ok(())
+}
+
+/// Run the checks that look at the evaluated slides of a document.
+fn check_slides(logger: &Logger,
+                checks: &Checks,
+                slides: &Slides,
+                page: &driver::PageSetup)
+                -> Result<()> {
+    if checks.warn_contrast {
+        warn_low_contrast(logger, slides, page)?;
+    }
+    if checks.release_check && !slides.todos.is_empty() {
+        return Err(todo_error(&slides.todos.list()));
+    }
+    Ok(())
+}
+
+/// Return the error that lists the `todo()` placeholders in a release build.
+fn todo_error(todos: &[Todo]) -> Error {
+    let mut msg = format!(
+        "The document contains {} todo() placeholder{}, but --release-check \
+         does not allow any:",
+        todos.len(), if todos.len() == 1 { "" } else { "s" },
+    );
+    for todo in todos {
+        match todo.slide {
+            Some(n) => msg.push_str(&format!("\n  slide {}: {}", n, todo.message)),
+            None => msg.push_str(&format!("\n  outside of a slide: {}", todo.message)),
         }
+    }
+    Error::value(msg)
+}
 
-        canvas_size = match stmt_interpreter
-            .env()
-            .lookup_coord_num(&ast::Idents(vec!["canvas_size"]))
-        {
-            Ok(sz) => sz,
-            Err(e) => { e.print(); panic!("Abort after error.") }
+/// Warn about text that is hard to read against its background.
+fn warn_low_contrast(logger: &Logger, slides: &Slides, page: &driver::PageSetup) -> Result<()> {
+    for (i, frame) in slides.frames.iter().enumerate() {
+        let slide = driver::Slide::from_frame(frame)?;
+        for problem in slide.low_contrast_text(page.canvas_size) {
+            let what = if problem.text.is_empty() {
+                "text".to_string()
+            } else {
+                format!("text '{}'", problem.text)
+            };
+            let msg = format!(
+                "slide {}: {} has a contrast ratio of {:.1}:1 with its background, \
+                 which is less than the {}:1 needed to read it.",
+                i + 1, what, problem.ratio, problem.required,
+            );
+            logger.log(Level::Warning, &msg);
         }
     }
+    Ok(())
+}
 
-    let surf = cairo::Surface::new_pdf(&outfile, canvas_size.x, canvas_size.y);
-    let mut cr = cairo::Cairo::new(surf);
-    cr.set_source_rgb(0.0, 0.0, 0.0);
-    cr.set_line_width(6.0);
+/// Warn about misspelled words in text, with their location in `input`.
+///
+/// The file name is included in the message if it is given.
+fn warn_misspellings(
+    logger: &Logger,
+    dictionary: &str,
+    fname: Option<&str>,
+    input: &[u8],
+    doc: &ast::Document,
+) -> Result<()> {
+    let literals = spellcheck::text_literals(doc, input);
+    let texts: Vec<&str> = literals.iter().map(|literal| literal.text).collect();
+    let misspellings = spellcheck::check(dictionary, &texts)?;
 
-    for frame in frames.iter() {
-        driver::render_frame(&mut fm, &mut cr, canvas_size, frame);
+    for (literal, words) in literals.iter().zip(misspellings) {
+        for misspelling in words {
+            // Point at the word in the source if we can find it, or else at
+            // the call to t() that it is typeset with.
+            let offset = input[literal.offset..]
+                .windows(misspelling.word.len())
+                .position(|w| w == misspelling.word.as_bytes())
+                .map_or(literal.offset, |i| literal.offset + i);
+            let (line, column) = line_column(input, offset);
+            let mut msg = match fname {
+                Some(fname) => format!("{}, line {}, column {}", fname, line, column),
+                None => format!("line {}, column {}", line, column),
+            };
+            if let Some(slide) = literal.slide {
+                msg.push_str(&format!(" (slide {})", slide));
+            }
+            msg.push_str(&format!(": '{}' may be misspelled", misspelling.word));
+            match misspelling.suggestions.first() {
+                Some(suggestion) => msg.push_str(&format!(", did you mean '{}'?", suggestion)),
+                None => msg.push('.'),
+            }
+            logger.log(Level::Warning, &msg);
+        }
     }
 
-    drop(cr);
+    Ok(())
+}
+
+/// Return the line and column of the byte at `offset`, both starting at 1.
+fn line_column(input: &[u8], offset: usize) -> (usize, usize) {
+    let before = &input[..offset];
+    let line = before.iter().filter(|&&c| c == b'\n').count() + 1;
+    let start = before.iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1);
+    let column = String::from_utf8_lossy(&before[start..]).chars().count() + 1;
+    (line, column)
+}
+
+/// Print the problems that static checks find, return how many there are.
+fn lint(input: &[u8],
+        tokens: &[(lexer::Token, lexer::Span)],
+        doc: &ast::Document,
+        defined: &[Symbol])
+        -> usize {
+    let mut num_problems = 0;
+    for name in lint::undefined_variables(doc, defined) {
+        println!("Warning: variable '{}' is used, but never assigned.", name);
+        num_problems += 1;
+    }
+    for name in lint::unused_variables(doc) {
+        println!("Warning: variable '{}' is assigned, but never used.", name);
+        num_problems += 1;
+    }
+    for name in lint::shadowed_builtins(doc) {
+        println!("Warning: variable '{}' shadows a builtin function.", name);
+        num_problems += 1;
+    }
+    for span in lint::inconsistent_list_separators(tokens) {
+        let (line, column) = line_column(input, span.start);
+        let (found, expected) = if input[span.start] == b',' { (',', ';') } else { (';', ',') };
+        println!(
+            "Warning: list separator '{}' at line {}, column {}, differs from the '{}' used before.",
+            found, line, column, expected,
+        );
+        num_problems += 1;
+    }
+    num_problems
 }
 
 fn report_error(input: &[u8], location: usize, len: usize) {
@@ -125,7 +1793,7 @@ fn report_error(input: &[u8], location: usize, len: usize) {
     // TODO: Deal with errors that span multiple lines.
     let mut line = 1;
     let mut start = 0;
-    let mut end = 0;
+    let mut end = input.len();
     for (&c, i) in input.iter().zip(0..) {
         if i == location { break }
         if c == b'\n' {
@@ -147,24 +1815,58 @@ fn report_error(input: &[u8], location: usize, len: usize) {
     // The length of the mark can be longer than the line, for example when
     // token to mark was a multiline string literal. In that case, highlight
     // only up to the newline, don't extend the tildes too far.
-    let mark_len = cmp::min(len, line_content.len() + start - location);
+    let mark_len = cmp::min(len, end - location);
+
+    // Identifiers can contain non-ASCII letters, so count code points rather
+    // than bytes to position the mark under the right character.
+    let column = String::from_utf8_lossy(&input[start..location]).chars().count();
+    let mark_chars = String::from_utf8_lossy(&input[location..location + mark_len]).chars().count();
+
+    let mut out = messages();
+    writeln!(out, "Parse error at line {}:\n", line).ok();
+    writeln!(out, "{}", line_content).ok();
+    for _ in 0..column { write!(out, " ").ok(); }
+    write!(out, "^").ok();
+    for _ in 1..mark_chars { write!(out, "~").ok(); }
+    write!(out, "\n").ok();
+}
+
+/// Read the source file, or stdin if the file name is "-".
+fn read_input(fname: &str) -> Result<Vec<u8>> {
+    let mut input = Vec::new();
+    let result = if fname == "-" {
+        io::stdin().read_to_end(&mut input)
+    } else {
+        File::open(fname).and_then(|f| BufReader::new(f).read_to_end(&mut input))
+    };
+    match result {
+        Ok(..) => Ok(input),
+        Err(e) => Err(Error::io(fname.to_string(), e.to_string())),
+    }
+}
+
+/// Point at the location of the error in the input, if it is a parse error.
+fn report_parse_location(input: &[u8], error: &Error) {
+    if let Error::Parse(ref e) = *error {
+        report_error(input, e.start, e.end - e.start);
+    }
+}
 
-    println!("Parse error at line {}:\n", line);
-    println!("{}", line_content);
-    for _ in 0..location - start { print!(" "); }
-    print!("^");
-    for _ in 1..mark_len { print!("~"); }
-    print!("\n");
+fn abort_parse_error(input: &[u8], error: Error) -> ! {
+    report_parse_location(input, &error);
+    exit_with_error(error)
 }
 
-fn parse_or_abort<'a>(input: &'a [u8]) -> ast::Document<'a> {
-    match lexer::lex(input).and_then(|tokens| parser::parse(&tokens[..])) {
+fn lex_or_abort<'a>(input: &'a [u8]) -> Vec<(lexer::Token<'a>, lexer::Span)> {
+    match lexer::lex(input) {
+        Ok(tokens) => tokens,
+        Err(e) => abort_parse_error(input, e),
+    }
+}
+
+fn parse_or_abort<'a>(input: &[u8], tokens: &[(lexer::Token<'a>, lexer::Span)]) -> ast::Document<'a> {
+    match parser::parse(tokens) {
         Ok(doc) => doc,
-        Err(Error::Parse(e)) => {
-            report_error(input, e.start, e.end - e.start);
-            Error::Parse(e).print();
-            std::process::exit(1)
-        }
-        _ => unreachable!(),
+        Err(e) => abort_parse_error(input, e),
     }
 }
@@ -5,26 +5,52 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
+use std::env;
+#[cfg(feature = "native")]
+use std::fs;
+#[cfg(feature = "native")]
+use std::io;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use ast::Idents;
-use cairo;
-use elements::{Element, FillPolygon, Hyperlink, StrokePolygon, PolygonKind, Text, Vec2};
+use elements::{Affine, Color, Element, FillPolygon, Gradient, GradientKind, Hyperlink, LinkTarget, Paint, PlacedElement, StrokePolygon, PolygonKind, Symbol, Vec2};
+#[cfg(feature = "native")]
+use driver::wrap_lines;
+#[cfg(feature = "native")]
+use elements::{Glyph, Text, TextCluster};
 use error::{Error, Result};
+#[cfg(feature = "native")]
 use freetype;
+#[cfg(feature = "native")]
+use glyph_lookup;
+#[cfg(feature = "shaping")]
 use harfbuzz;
+#[cfg(feature = "shaping")]
+use harfbuzz::FontFeature;
+use limits;
+use logger::Level;
+#[cfg(feature = "native")]
+use logger::Logger;
 use names;
 use png;
 use pretty::Formatter;
+#[cfg(feature = "svg")]
 use rsvg;
-use runtime::{BoundingBox, Frame, Subframe, Val};
-use types::ValType;
+use runtime::{BoundingBox, Builtin, Frame, Subframe, Val};
+#[cfg(feature = "native")]
+use runtime::{Env, FontMap};
+#[cfg(feature = "native")]
+use scene;
+use symbol;
+use types::{LenDim, ValType, describe_dimension};
+use uri;
 
 // TODO: Put that somewhere else.
 use interpreter::ExprInterpreter;
 
-fn validate_args<'a>(fn_name: &str,
+fn validate_args<'a>(fn_name: symbol::Symbol,
                      expected: &[ValType],
                      actual: &[Val<'a>])
                      -> Result<()> {
@@ -66,9 +92,7 @@ pub fn at<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
 
     for subframe in frame.get_subframes() {
         let mut dest_sf = Subframe::new();
-        for pe in subframe.get_elements() {
-            dest_sf.place_element(pe.position + off, pe.element.clone());
-        }
+        dest_sf.place_element(off, Element::Transformed(subframe.share_elements(), 1.0));
         new_frame.push_subframe(dest_sf);
     }
 
@@ -136,13 +160,9 @@ pub fn fit<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
 
     let mut scaled_frame = Frame::from_env(frame.get_env().clone());
 
-    // As the frame is immutable anyway, it would actually be possible to refer
-    // to the subframes in the frame, instead of copying them. If performance
-    // ever becomes a concern, this would be a good place to start.
     for subframe in frame.get_subframes() {
-        let elements: Vec<_> = subframe.get_elements().iter().cloned().collect();
         let mut new_sf = Subframe::new();
-        new_sf.place_element(Vec2::zero(), Element::Scaled(elements, scale));
+        new_sf.place_element(Vec2::zero(), Element::Transformed(subframe.share_elements(), scale));
         scaled_frame.push_subframe(new_sf);
     }
 
@@ -152,262 +172,1292 @@ pub fn fit<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
     Ok(Val::Frame(Rc::new(scaled_frame)))
 }
 
-pub fn line<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
-                    mut args: Vec<Val<'a>>)
-                    -> Result<Val<'a>> {
-    validate_args(names::line, &[ValType::Coord(1)], &args)?;
-    let offset = match args.remove(0) {
-        Val::Coord(x, y, 1) => Vec2::new(x, y),
+/// Parse the arguments of `fits` and `clip_or_warn` into a frame and a size.
+fn frame_and_container<'a>(fn_name: symbol::Symbol,
+                           mut args: Vec<Val<'a>>)
+                           -> Result<(Rc<Frame<'a>>, Vec2)> {
+    validate_args(fn_name, &[ValType::Frame, ValType::Coord(1)], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
         _ => unreachable!(),
     };
-
-    let line = StrokePolygon {
-        // TODO: Better idents type for non-ast use?
-        // Could have a "borrowed ident" that is like &[&str],
-        // similar to the std::Path and std::PathBuf distinctions.
-        color: interpreter.env.lookup_color(&Idents(vec![names::color]))?,
-        line_width: interpreter.env.lookup_len(&Idents(vec![names::line_width]))?,
-        close: false,
-        vertices: vec![Vec2::zero(), offset],
-        kind: PolygonKind::Lines,
+    let size = match args.remove(0) {
+        Val::Coord(w, h, 1) => Vec2::new(w, h),
+        _ => unreachable!(),
     };
-
-    let mut frame = Frame::new();
-    frame.place_element_on_last_subframe(Vec2::zero(), Element::StrokePolygon(line));
-    frame.set_anchor(offset);
-    // TODO: Make bounding box take Vec2.
-    frame.union_bounding_box(&BoundingBox::sized(offset.x, offset.y));
-
-    Ok(Val::Frame(Rc::new(frame)))
+    Ok((frame, size))
 }
 
-enum DrawKind {
-    Fill,
-    Stroke { close: bool },
+/// Return whether the bounding box of the frame fits in a box of the given size.
+fn fits_in(frame: &Frame, size: Vec2) -> bool {
+    let bb = frame.get_bounding_box();
+    bb.width <= size.x && bb.height <= size.y
 }
 
-fn make_polygon_element<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    vertices: Vec<Vec2>,
-    polygon_kind: PolygonKind,
-    draw_kind: DrawKind,
-) -> Result<Frame<'a>> {
-    // TODO: Better idents type for non-ast use?
-    let color = interpreter.env.lookup_color(&Idents(vec![names::color]))?;
+pub fn fits<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                    args: Vec<Val<'a>>)
+                    -> Result<Val<'a>> {
+    let (frame, size) = frame_and_container(names::fits, args)?;
+    // There are no booleans in Pris, so return 1 or 0 like C would.
+    let result = if fits_in(&frame, size) { 1.0 } else { 0.0 };
+    Ok(Val::Num(result, 0))
+}
 
-    let mut frame = Frame::new();
+pub fn clip_or_warn<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                            args: Vec<Val<'a>>)
+                            -> Result<Val<'a>> {
+    let (frame, size) = frame_and_container(names::clip_or_warn, args)?;
 
-    for v in &vertices {
-        frame.union_bounding_box(&BoundingBox::empty().offset(*v));
+    if fits_in(&frame, size) {
+        return Ok(Val::Frame(frame))
     }
 
-    let element = match draw_kind {
-        DrawKind::Stroke { close } => {
-            let line_width = interpreter.env.lookup_len(&Idents(vec![names::line_width]))?;
-
-            let polygon = StrokePolygon {
-                color: color,
-                line_width: line_width,
-                close: close,
-                vertices: vertices,
-                kind: polygon_kind,
-            };
-
-            Element::StrokePolygon(polygon)
-        }
-        DrawKind::Fill => {
-            let polygon = FillPolygon {
-                color: color,
-                vertices: vertices,
-                kind: polygon_kind,
-            };
-
-            Element::FillPolygon(polygon)
-        }
-    };
+    let bb = frame.get_bounding_box().clone();
+    let mut f = Formatter::new();
+    f.print("Content of size ");
+    f.print(&Val::Coord(bb.width, bb.height, 1));
+    f.print(" does not fit in a container of size ");
+    f.print(&Val::Coord(size.x, size.y, 1));
+    f.print(", it will be clipped.");
+    interpreter.logger.log(Level::Warning, &f.into_string());
+
+    // The container starts at the top left of the content, so content that
+    // fits in one direction is not clipped in that direction.
+    let top_left = Vec2::new(bb.x, bb.y);
+    let clip_size = Vec2::new(bb.width.min(size.x), bb.height.min(size.y));
+
+    let mut clipped_frame = Frame::from_env(frame.get_env().clone());
+    for subframe in frame.get_subframes() {
+        let mut new_sf = Subframe::new();
+        new_sf.place_element(Vec2::zero(), Element::Clipped(subframe.share_elements(), top_left, clip_size));
+        clipped_frame.push_subframe(new_sf);
+    }
 
-    frame.place_element_on_last_subframe(Vec2::zero(), element);
+    clipped_frame.set_anchor(frame.get_anchor());
+    clipped_frame.union_bounding_box(&BoundingBox::new(top_left, clip_size));
 
-    Ok(frame)
+    Ok(Val::Frame(Rc::new(clipped_frame)))
 }
 
-fn draw_circle<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    mut args: Vec<Val<'a>>,
-    name: &'static str,
-    kind: DrawKind,
-) -> Result<Val<'a>> {
-
-    validate_args(name, &[ValType::Num(1)], &args)?;
-    let r = match args.remove(0) {
-        Val::Num(r, 1) => r,
+/// Place the second frame next to the first one, in the given direction.
+///
+/// The direction is a unit vector along one of the axes. The bounding boxes
+/// of the two frames are separated by the `gap` variable along that axis,
+/// along the other axis the origins of the frames are aligned.
+fn juxtapose<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                     fn_name: symbol::Symbol,
+                     mut args: Vec<Val<'a>>,
+                     direction: Vec2)
+                     -> Result<Val<'a>> {
+    validate_args(fn_name, &[ValType::Frame, ValType::Frame], &args)?;
+    let first = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let second = match args.remove(0) {
+        Val::Frame(f) => f,
         _ => unreachable!(),
     };
+    let gap = interpreter.env.lookup_len(&Idents(vec![names::gap]))?;
+
+    let bb0 = first.get_bounding_box();
+    let bb1 = second.get_bounding_box();
+    let offset = match (direction.x, direction.y) {
+        (x, _) if x > 0.0 => Vec2::new(bb0.x + bb0.width + gap - bb1.x, 0.0),
+        (x, _) if x < 0.0 => Vec2::new(bb0.x - gap - bb1.x - bb1.width, 0.0),
+        (_, y) if y > 0.0 => Vec2::new(0.0, bb0.y + bb0.height + gap - bb1.y),
+        _ => Vec2::new(0.0, bb0.y - gap - bb1.y - bb1.height),
+    };
 
-    // See also http://spencermortensen.com/articles/bezier-circle/.
-    let c = 0.551915024494 * r;
-    let z = 0.0;
+    let mut frame = (*first).clone();
+    frame.place_subframes(0, &second, offset);
+    frame.union_bounding_box(&bb1.offset(offset));
 
-    let vertices = vec![
-        Vec2::new( r,  z), // Right
-        Vec2::new( r, -c),
-        Vec2::new( c, -r),
-        Vec2::new( z, -r), // Top
-        Vec2::new(-c, -r),
-        Vec2::new(-r, -c),
-        Vec2::new(-r,  z), // Left
-        Vec2::new(-r,  c),
-        Vec2::new(-c,  r),
-        Vec2::new( z,  r), // Bottom
-        Vec2::new( c,  r),
-        Vec2::new( r,  c),
-    ];
+    // Like with adjoining, the anchor is that of the frame placed last.
+    frame.set_anchor(second.get_anchor() + offset);
 
-    let mut frame = make_polygon_element(interpreter, vertices, PolygonKind::Curves, kind)?;
+    Ok(Val::Frame(Rc::new(frame)))
+}
 
-    frame.set_anchor(Vec2::zero());
+pub fn above<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                     args: Vec<Val<'a>>)
+                     -> Result<Val<'a>> {
+    juxtapose(interpreter, names::above, args, Vec2::new(0.0, 1.0))
+}
 
-    Ok(Val::Frame(Rc::new(frame)))
+pub fn below<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                     args: Vec<Val<'a>>)
+                     -> Result<Val<'a>> {
+    juxtapose(interpreter, names::below, args, Vec2::new(0.0, -1.0))
 }
 
-pub fn fill_circle<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    args: Vec<Val<'a>>
-) -> Result<Val<'a>> {
-    draw_circle(interpreter, args, names::fill_circle, DrawKind::Fill)
+pub fn left_of<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                       args: Vec<Val<'a>>)
+                       -> Result<Val<'a>> {
+    juxtapose(interpreter, names::left_of, args, Vec2::new(1.0, 0.0))
 }
 
-pub fn stroke_circle<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    args: Vec<Val<'a>>
-) -> Result<Val<'a>> {
-    let kind = DrawKind::Stroke {
-        close: true,
+pub fn right_of<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                        args: Vec<Val<'a>>)
+                        -> Result<Val<'a>> {
+    juxtapose(interpreter, names::right_of, args, Vec2::new(-1.0, 0.0))
+}
+
+pub fn adjoin_parallel<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                               mut args: Vec<Val<'a>>)
+                               -> Result<Val<'a>> {
+    validate_args(names::adjoin_parallel, &[ValType::Frame, ValType::Frame], &args)?;
+    let first = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
     };
-    draw_circle(interpreter, args, names::stroke_circle, kind)
+    let second = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    Ok(Val::Frame(Rc::new(first.adjoin(&second, 0))))
 }
 
-fn draw_rectangle<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    mut args: Vec<Val<'a>>,
-    name: &'static str,
-    kind: DrawKind,
-) -> Result<Val<'a>> {
-    validate_args(name, &[ValType::Coord(1)], &args)?;
+pub fn adjoin_sequential<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                                 mut args: Vec<Val<'a>>)
+                                 -> Result<Val<'a>> {
+    validate_args(names::adjoin_sequential, &[ValType::Frame, ValType::Frame], &args)?;
+    let first = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let second = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    // The steps of the second frame start after the last step of the first.
+    let num_steps = first.get_subframes().len();
+    Ok(Val::Frame(Rc::new(first.adjoin(&second, num_steps))))
+}
 
-    let (w, h) = match args.remove(0) {
-        Val::Coord(x, y, 1) => (x, y),
+/// Extract the frames from a list that was passed as argument `arg_index`.
+fn frames_in_list<'a>(fn_name: symbol::Symbol,
+                      list: Vec<Val<'a>>,
+                      arg_index: u32)
+                      -> Result<Vec<Rc<Frame<'a>>>> {
+    list.into_iter().map(|val| match val {
+        Val::Frame(f) => Ok(f),
+        not_frame => Err(Error::arg_type(fn_name, ValType::Frame, not_frame.get_type(), arg_index)),
+    }).collect()
+}
+
+pub fn place_all<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                         mut args: Vec<Val<'a>>)
+                         -> Result<Val<'a>> {
+    validate_args(names::place_all, &[ValType::List, ValType::List], &args)?;
+    let frames = match args.remove(0) {
+        Val::List(vs) => frames_in_list(names::place_all, vs, 0)?,
+        _ => unreachable!(),
+    };
+    let positions = match args.remove(0) {
+        Val::List(vs) => vs,
         _ => unreachable!(),
     };
 
-    let vertices = vec![
-        Vec2::zero(),
-        Vec2::new(0.0, h),
-        Vec2::new(w, h),
-        Vec2::new(w, 0.0),
-    ];
+    if frames.len() != positions.len() {
+        let msg = format!(
+            "'{}' needs a position for every frame, but found {} frames and {} positions.",
+            names::place_all, frames.len(), positions.len(),
+        );
+        return Err(Error::value(msg))
+    }
 
-    let mut frame = make_polygon_element(interpreter, vertices, PolygonKind::Lines, kind)?;
+    let mut result = Frame::new();
+    for (frame, position) in frames.iter().zip(positions.iter()) {
+        let off = match *position {
+            Val::Coord(x, y, 1) => Vec2::new(x, y),
+            ref not_coord => {
+                return Err(Error::arg_type(names::place_all, ValType::Coord(1), not_coord.get_type(), 1))
+            }
+        };
 
-    frame.set_anchor(Vec2::new(w, h));
+        // Like `at`, include the position itself in the bounding box, so the
+        // result is the same as putting every frame at its position in a block.
+        result.place_subframes(0, frame, off);
+        result.union_bounding_box(&frame.get_bounding_box().offset(off));
+        result.union_bounding_box(&BoundingBox::new(off, Vec2::zero()));
+        result.set_anchor(frame.get_anchor() + off);
+    }
 
-    Ok(Val::Frame(Rc::new(frame)))
+    Ok(Val::Frame(Rc::new(result)))
 }
 
-pub fn fill_rectangle<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    args: Vec<Val<'a>>,
-) -> Result<Val<'a>> {
-    draw_rectangle(interpreter, args, names::fill_rectangle, DrawKind::Fill)
-}
+pub fn adjoin_all<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                          mut args: Vec<Val<'a>>)
+                          -> Result<Val<'a>> {
+    validate_args(names::adjoin_all, &[ValType::List, ValType::Coord(1)], &args)?;
+    let frames = match args.remove(0) {
+        Val::List(vs) => frames_in_list(names::adjoin_all, vs, 0)?,
+        _ => unreachable!(),
+    };
+    let gap = match args.remove(0) {
+        Val::Coord(x, y, 1) => Vec2::new(x, y),
+        _ => unreachable!(),
+    };
 
-pub fn stroke_rectangle<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    args: Vec<Val<'a>>,
-) -> Result<Val<'a>> {
-    let kind = DrawKind::Stroke {
-        close: true,
+    let mut frames = frames.into_iter();
+    let mut result = match frames.next() {
+        Some(first) => (*first).clone(),
+        None => Frame::new(),
     };
-    draw_rectangle(interpreter, args, names::stroke_rectangle, kind)
-}
 
-fn draw_polygon<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    mut args: Vec<Val<'a>>,
-    name: &'static str,
-    polygon_kind: PolygonKind,
-    draw_kind: DrawKind,
-) -> Result<Val<'a>> {
-    validate_args(name, &[ValType::List], &args)?;
+    // Every frame is placed at the anchor of the previous one, moved by the
+    // gap, with the steps of the frames aligned, like `~`.
+    for frame in frames {
+        let off = result.get_anchor() + gap;
+        result.place_subframes(0, &frame, off);
+        result.union_bounding_box(&frame.get_bounding_box().offset(off));
+        result.set_anchor(frame.get_anchor() + off);
+    }
 
-    let coords = match args.remove(0) {
-        Val::List(vs) => vs,
+    Ok(Val::Frame(Rc::new(result)))
+}
+
+/// Grow the bounding box of a frame by a given amount.
+///
+/// The `sides` function maps the amount to the growth at the top left and the
+/// growth at the bottom right.
+fn pad_frame<'a, F>(fn_name: symbol::Symbol, mut args: Vec<Val<'a>>, sides: F) -> Result<Val<'a>>
+where F: Fn(f64) -> (Vec2, Vec2) {
+    validate_args(fn_name, &[ValType::Frame, ValType::Num(1)], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let amount = match args.remove(0) {
+        Val::Num(x, 1) => x,
         _ => unreachable!(),
     };
 
-    let mut vertices = Vec::with_capacity(coords.len());
-
-    // Collect the vertices, ensuring that they have the right type.
-    for vertex in &coords {
-        match vertex {
-            &Val::Coord(x, y, 1) => {
-                let v = Vec2::new(x, y);
-                vertices.push(v);
-            }
-            not_coord_of_len => {
-                let arg_num = 0;
-                let actual_type = not_coord_of_len.get_type();
-                let err = Error::arg_type(name, ValType::Coord(1), actual_type, arg_num);
-                return Err(err);
-            }
-        }
+    // A bounding box can only grow, so negative padding would be ignored.
+    // Report it instead, it is probably a mistake.
+    if amount < 0.0 {
+        let msg = format!("The padding of '{}' must not be negative.", fn_name);
+        return Err(Error::value(msg))
     }
 
-    // TODO: Demand at least two coords.
-    let anchor = vertices.last().cloned().unwrap_or(Vec2::zero());
-    let mut frame = make_polygon_element(interpreter, vertices, polygon_kind, draw_kind)?;
-    frame.set_anchor(anchor);
+    let (top_left, bottom_right) = sides(amount);
+    let bb = frame.get_bounding_box();
+    let padded_bb = BoundingBox::new(
+        Vec2::new(bb.x - top_left.x, bb.y - top_left.y),
+        Vec2::new(
+            bb.width + top_left.x + bottom_right.x,
+            bb.height + top_left.y + bottom_right.y,
+        ),
+    );
+
+    let mut padded_frame = (*frame).clone();
+    padded_frame.union_bounding_box(&padded_bb);
+
+    Ok(Val::Frame(Rc::new(padded_frame)))
+}
 
-    Ok(Val::Frame(Rc::new(frame)))
+pub fn pad<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                   args: Vec<Val<'a>>)
+                   -> Result<Val<'a>> {
+    pad_frame(names::pad, args, |d| (Vec2::new(d, d), Vec2::new(d, d)))
 }
 
-pub fn fill_polygon<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    args: Vec<Val<'a>>,
-) -> Result<Val<'a>> {
-    draw_polygon(interpreter, args, names::fill_polygon, PolygonKind::Lines, DrawKind::Fill)
+pub fn pad_left<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                        args: Vec<Val<'a>>)
+                        -> Result<Val<'a>> {
+    pad_frame(names::pad_left, args, |d| (Vec2::new(d, 0.0), Vec2::zero()))
 }
 
-pub fn stroke_polygon<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    args: Vec<Val<'a>>,
-) -> Result<Val<'a>> {
-    let kind = DrawKind::Stroke {
-        // TODO: Make this a variable.
-        close: true,
-    };
-    draw_polygon(interpreter, args, names::stroke_polygon, PolygonKind::Lines, kind)
+pub fn pad_right<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                         args: Vec<Val<'a>>)
+                         -> Result<Val<'a>> {
+    pad_frame(names::pad_right, args, |d| (Vec2::zero(), Vec2::new(d, 0.0)))
 }
 
-pub fn fill_curve<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    args: Vec<Val<'a>>,
-) -> Result<Val<'a>> {
-    draw_polygon(interpreter, args, names::fill_curve, PolygonKind::Curves, DrawKind::Fill)
+pub fn pad_top<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                       args: Vec<Val<'a>>)
+                       -> Result<Val<'a>> {
+    pad_frame(names::pad_top, args, |d| (Vec2::new(0.0, d), Vec2::zero()))
 }
 
-pub fn stroke_curve<'i, 'a>(
-    interpreter: &mut ExprInterpreter<'i, 'a>,
-    args: Vec<Val<'a>>,
-) -> Result<Val<'a>> {
+pub fn pad_bottom<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                          args: Vec<Val<'a>>)
+                          -> Result<Val<'a>> {
+    pad_frame(names::pad_bottom, args, |d| (Vec2::zero(), Vec2::new(0.0, d)))
+}
+
+/// Return whether the builtin lays out its frame argument in a container of
+/// its own, like `fit` and the `pad` functions.
+///
+/// For these builtins, the interpreter evaluates the second argument first,
+/// and then the frame with `container_size` bound to the size that
+/// `content_container_size` returns, so `pw` and `ph` in the frame measure the
+/// container rather than the canvas.
+pub fn has_content_container(builtin: &Builtin) -> bool {
+    let f = builtin.0 as usize;
+    [fit as usize, pad as usize, pad_left as usize, pad_right as usize, pad_top as usize, pad_bottom as usize]
+        .contains(&f)
+}
+
+/// Return the size of the container that a builtin lays out its frame in,
+/// given the size of the enclosing container and the second argument.
+///
+/// For `fit`, that is the size to fit in. Padding takes space from the
+/// enclosing container. Returns `None` when the argument has the wrong type,
+/// the builtin itself reports that error.
+pub fn content_container_size<'a>(builtin: &Builtin, outer: Vec2, arg: &Val<'a>) -> Option<Vec2> {
+    let f = builtin.0 as usize;
+    let size = match *arg {
+        Val::Coord(w, h, 1) if f == fit as usize => Vec2::new(w, h),
+        Val::Num(d, 1) if f == pad as usize => Vec2::new(outer.x - 2.0 * d, outer.y - 2.0 * d),
+        Val::Num(d, 1) if f == pad_left as usize || f == pad_right as usize => Vec2::new(outer.x - d, outer.y),
+        Val::Num(d, 1) if f == pad_top as usize || f == pad_bottom as usize => Vec2::new(outer.x, outer.y - d),
+        _ => return None,
+    };
+    Some(Vec2::new(size.x.max(0.0), size.y.max(0.0)))
+}
+
+pub fn opacity<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                       mut args: Vec<Val<'a>>)
+                       -> Result<Val<'a>> {
+    validate_args(names::opacity, &[ValType::Frame, ValType::Num(0)], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let alpha = match args.remove(0) {
+        Val::Num(x, 0) => x,
+        _ => unreachable!(),
+    };
+
+    if alpha < 0.0 || alpha > 1.0 {
+        let msg = format!("Opacity must be between 0 and 1, but found {}.", alpha);
+        return Err(Error::value(msg))
+    }
+
+    // The subframes are drawn as a group and then faded as a whole, so where
+    // elements overlap, the one below does not show through the one above.
+    let mut faded_frame = Frame::from_env(frame.get_env().clone());
+    for subframe in frame.get_subframes() {
+        let mut new_sf = Subframe::new();
+        new_sf.place_element(Vec2::zero(), Element::Opacity(subframe.share_elements(), alpha));
+        faded_frame.push_subframe(new_sf);
+    }
+    faded_frame.union_bounding_box(frame.get_bounding_box());
+    faded_frame.set_anchor(frame.get_anchor());
+
+    Ok(Val::Frame(Rc::new(faded_frame)))
+}
+
+pub fn boxed<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                     mut args: Vec<Val<'a>>)
+                     -> Result<Val<'a>> {
+    validate_args(names::boxed, &[ValType::Frame], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+
+    let (x0, y0, x1, y1) = {
+        let bb = frame.get_bounding_box();
+        (bb.x, bb.y, bb.x + bb.width, bb.y + bb.height)
+    };
+    let vertices = vec![
+        Vec2::new(x0, y0),
+        Vec2::new(x0, y1),
+        Vec2::new(x1, y1),
+        Vec2::new(x1, y0),
+    ];
+    let kind = DrawKind::Stroke { close: true };
+    let border = make_polygon_element(interpreter, vertices, PolygonKind::Lines, kind)?;
+
+    // The border is drawn on every step, on top of the content.
+    let mut boxed_frame = (*frame).clone();
+    boxed_frame.place_subframes(0, &border, Vec2::zero());
+
+    Ok(Val::Frame(Rc::new(boxed_frame)))
+}
+
+pub fn todo<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                    mut args: Vec<Val<'a>>)
+                    -> Result<Val<'a>> {
+    validate_args(names::todo, &[ValType::Str], &args)?;
+    let message = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+    interpreter.todos.record(message.clone(), interpreter.budget.slide());
+
+    let mut frame = todo_label(interpreter, message)?;
+    let em = interpreter.env.lookup_len(&Idents(vec![names::font_size]))?;
+    let line_width = interpreter.env.lookup_len(&Idents(vec![names::line_width]))?;
+
+    let pad = 0.25 * em;
+    let (x0, y0, x1, y1) = {
+        let bb = frame.get_bounding_box();
+        (bb.x - pad, bb.y - pad, bb.x + bb.width + pad, bb.y + bb.height + pad)
+    };
+    // The border is magenta regardless of the `color` variable, so it stands
+    // out in any style.
+    let border = StrokePolygon {
+        color: Color::new(1.0, 0.0, 1.0),
+        line_width: line_width,
+        close: true,
+        vertices: vec![
+            Vec2::new(x0, y0),
+            Vec2::new(x0, y1),
+            Vec2::new(x1, y1),
+            Vec2::new(x1, y0),
+        ],
+        kind: PolygonKind::Lines,
+    };
+    frame.place_element_on_last_subframe(Vec2::zero(), Element::StrokePolygon(border));
+    frame.union_bounding_box(&BoundingBox::new(Vec2::new(x0, y0), Vec2::new(x1 - x0, y1 - y0)));
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+/// Typeset the message of a `todo()` placeholder.
+#[cfg(feature = "native")]
+fn todo_label<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>, message: String) -> Result<Frame<'a>> {
+    match t(interpreter, vec![Val::Str(format!("TODO: {}", message))])? {
+        Val::Frame(frame) => Ok((*frame).clone()),
+        _ => unreachable!(),
+    }
+}
+
+/// Without fonts, the placeholder is an empty box.
+#[cfg(not(feature = "native"))]
+fn todo_label<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>, _message: String) -> Result<Frame<'a>> {
+    Ok(Frame::new())
+}
+
+/// Return the vertices of a closed curve that traces a rounded rectangle.
+fn rounded_rectangle_vertices(bb: &BoundingBox, radius: f64) -> Vec<Vec2> {
+    // See also http://spencermortensen.com/articles/bezier-circle/. The
+    // control points are pulled in a bit further than for a circle, to soften
+    // the sudden change in curvature where the corner meets the edge.
+    let r = radius.min(bb.width * 0.5).min(bb.height * 0.5);
+    let c = 0.551915024494 * r * 0.75;
+    let (x0, y0) = (bb.x, bb.y);
+    let (x1, y1) = (bb.x + bb.width, bb.y + bb.height);
+    vec![
+        Vec2::new(x0 + r, y0), // Top left, top edge.
+        Vec2::new(x0 + c, y0),
+        Vec2::new(x0, y0 + c),
+        Vec2::new(x0, y0 + r), // Top left, left edge.
+        Vec2::new(x0, y0 + r),
+        Vec2::new(x0, y1 - r),
+        Vec2::new(x0, y1 - r), // Bottom left, left edge.
+        Vec2::new(x0, y1 - c),
+        Vec2::new(x0 + c, y1),
+        Vec2::new(x0 + r, y1), // Bottom left, bottom edge.
+        Vec2::new(x0 + r, y1),
+        Vec2::new(x1 - r, y1),
+        Vec2::new(x1 - r, y1), // Bottom right, bottom edge.
+        Vec2::new(x1 - c, y1),
+        Vec2::new(x1, y1 - c),
+        Vec2::new(x1, y1 - r), // Bottom right, right edge.
+        Vec2::new(x1, y1 - r),
+        Vec2::new(x1, y0 + r),
+        Vec2::new(x1, y0 + r), // Top right, right edge.
+        Vec2::new(x1, y0 + c),
+        Vec2::new(x1 - c, y0),
+        Vec2::new(x1 - r, y0), // Top right, top edge.
+        Vec2::new(x1 - r, y0),
+        Vec2::new(x0 + r, y0),
+    ]
+}
+
+pub fn highlight<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                         mut args: Vec<Val<'a>>)
+                         -> Result<Val<'a>> {
+    validate_args(
+        names::highlight,
+        &[ValType::Frame, ValType::Color, ValType::Num(1)],
+        &args
+    )?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let color = match args.remove(0) {
+        Val::Col(c) => c,
+        _ => unreachable!(),
+    };
+    let padding = match args.remove(0) {
+        Val::Num(x, 1) => x,
+        _ => unreachable!(),
+    };
+
+    if padding < 0.0 {
+        let msg = format!("The padding of '{}' must not be negative.", names::highlight);
+        return Err(Error::value(msg))
+    }
+
+    let bb = frame.get_bounding_box();
+    let highlight_bb = BoundingBox::new(
+        Vec2::new(bb.x - padding, bb.y - padding),
+        Vec2::new(bb.width + 2.0 * padding, bb.height + 2.0 * padding),
+    );
+    let background = FillPolygon {
+        paint: Paint::Solid(color),
+        // The corners are rounded with the padding as radius, so the corners
+        // of the content stay inside the highlight.
+        vertices: rounded_rectangle_vertices(&highlight_bb, padding),
+        kind: PolygonKind::Curves,
+    };
+
+    // Start with the background, so the content is drawn on top of it. It is
+    // copied to every step of the content.
+    let mut highlighted = Frame::from_env(frame.get_env().clone());
+    highlighted.place_element_on_last_subframe(Vec2::zero(), Element::FillPolygon(background));
+    highlighted.place_subframes(0, &frame, Vec2::zero());
+    highlighted.union_bounding_box(&highlight_bb);
+    highlighted.set_anchor(frame.get_anchor());
+
+    Ok(Val::Frame(Rc::new(highlighted)))
+}
+
+pub fn on_step<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                       mut args: Vec<Val<'a>>)
+                       -> Result<Val<'a>> {
+    validate_args(names::on_step, &[ValType::Frame, ValType::Num(0)], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let step_f64 = match args.remove(0) {
+        Val::Num(x, 0) => x,
+        _ => unreachable!(),
+    };
+
+    // Every step becomes a page in the output, so a very large step is
+    // almost certainly a mistake; refuse it rather than running out of memory.
+    let max_step = 1000.0;
+    if step_f64 < 0.0 || step_f64 > max_step || step_f64.fract() != 0.0 {
+        let msg = format!(
+            "Expected an integer step between 0 and {} for '{}', found {}. \
+             Note: the first step is step 0.",
+            max_step, names::on_step, step_f64
+        );
+        return Err(Error::value(msg))
+    }
+    let step = step_f64 as u32;
+
+    // The delayed frame holds a copy of the elements.
+    interpreter.budget.check_new_elements(names::on_step, limits::count_frame_elements(&frame))?;
+
+    // Delay the frame by prepending empty subframes. Even an empty frame
+    // occupies the step, so it can be used to add a step to a slide.
+    let mut delayed_frame = Frame::from_env(frame.get_env().clone());
+    for _ in 0..step {
+        delayed_frame.push_subframe(Subframe::new());
+    }
+    for subframe in frame.get_subframes() {
+        delayed_frame.push_subframe(subframe.clone());
+    }
+    if frame.get_subframes().len() == 0 {
+        delayed_frame.push_subframe(Subframe::new());
+    }
+
+    delayed_frame.set_anchor(frame.get_anchor());
+    delayed_frame.union_bounding_box(frame.get_bounding_box());
+
+    Ok(Val::Frame(Rc::new(delayed_frame)))
+}
+
+pub fn symbol<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                      mut args: Vec<Val<'a>>)
+                      -> Result<Val<'a>> {
+    validate_args(names::symbol, &[ValType::Frame], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+
+    let mut symbol_frame = Frame::from_env(frame.get_env().clone());
+
+    // The elements of every subframe become one symbol. It is recorded once
+    // when it is first drawn, and every placement refers to that recording.
+    for subframe in frame.get_subframes() {
+        let symbol = Symbol {
+            elements: subframe.get_elements().to_vec(),
+        };
+        let mut new_sf = Subframe::new();
+        new_sf.place_element(Vec2::zero(), Element::Symbol(Arc::new(symbol)));
+        symbol_frame.push_subframe(new_sf);
+    }
+
+    symbol_frame.set_anchor(frame.get_anchor());
+    symbol_frame.union_bounding_box(frame.get_bounding_box());
+
+    Ok(Val::Frame(Rc::new(symbol_frame)))
+}
+
+/// Convert a number argument into a count, which must be a positive integer.
+fn count_arg(fn_name: symbol::Symbol, arg_name: &str, x: f64) -> Result<u32> {
+    // Check the range before casting, casting an out of range float is not
+    // well-defined.
+    if x < 1.0 || x > u32::max_value() as f64 || x.fract() != 0.0 {
+        let msg = format!(
+            "The '{}' argument of '{}' must be a positive integer, found {}.",
+            arg_name, fn_name, x
+        );
+        return Err(Error::value(msg))
+    }
+    Ok(x as u32)
+}
+
+pub fn tile<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                    mut args: Vec<Val<'a>>)
+                    -> Result<Val<'a>> {
+    validate_args(
+        names::tile,
+        &[ValType::Frame, ValType::Num(0), ValType::Num(0), ValType::Num(1)],
+        &args
+    )?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let columns = match args.remove(0) {
+        Val::Num(x, 0) => count_arg(names::tile, "columns", x)?,
+        _ => unreachable!(),
+    };
+    let rows = match args.remove(0) {
+        Val::Num(x, 0) => count_arg(names::tile, "rows", x)?,
+        _ => unreachable!(),
+    };
+    let gap = match args.remove(0) {
+        Val::Num(x, 1) => x,
+        _ => unreachable!(),
+    };
+
+    // Every step gets a group for every copy, check the number of groups
+    // before placing them.
+    let num_copies = (rows as u64 * columns as u64)
+        .saturating_mul(frame.get_subframes().len() as u64);
+    interpreter.budget.check_new_elements(names::tile, num_copies)?;
+
+    let bb = frame.get_bounding_box();
+    let step = Vec2::new(bb.width + gap, bb.height + gap);
+    let offset_at = |column: u32, row: u32| {
+        Vec2::new(column as f64 * step.x, row as f64 * step.y)
+    };
+
+    let mut tiled_frame = Frame::from_env(frame.get_env().clone());
+
+    // Every copy refers to the same elements, so the cost of a copy does not
+    // depend on the size of the frame; this matters for large patterns.
+    for subframe in frame.get_subframes() {
+        let elements = subframe.share_elements();
+        let mut new_sf = Subframe::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                new_sf.place_element(offset_at(column, row), Element::Group(elements.clone()));
+            }
+        }
+        tiled_frame.push_subframe(new_sf);
+    }
+
+    // The copies are laid out on a regular grid, so the first and last copy
+    // span the bounding box of all of them.
+    let last = offset_at(columns - 1, rows - 1);
+    tiled_frame.union_bounding_box(&bb);
+    tiled_frame.union_bounding_box(&bb.offset(last));
+
+    // Adjoining continues after the last copy, as it would after the frame.
+    tiled_frame.set_anchor(frame.get_anchor() + last);
+
+    Ok(Val::Frame(Rc::new(tiled_frame)))
+}
+
+pub fn line<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                    mut args: Vec<Val<'a>>)
+                    -> Result<Val<'a>> {
+    validate_args(names::line, &[ValType::Coord(1)], &args)?;
+    let offset = match args.remove(0) {
+        Val::Coord(x, y, 1) => Vec2::new(x, y),
+        _ => unreachable!(),
+    };
+
+    let line = StrokePolygon {
+        // TODO: Better idents type for non-ast use?
+        // Could have a "borrowed ident" that is like &[&str],
+        // similar to the std::Path and std::PathBuf distinctions.
+        color: interpreter.env.lookup_color(&Idents(vec![names::color]))?,
+        line_width: interpreter.env.lookup_len(&Idents(vec![names::line_width]))?,
+        close: false,
+        vertices: vec![Vec2::zero(), offset],
+        kind: PolygonKind::Lines,
+    };
+
+    let mut frame = Frame::new();
+    frame.place_element_on_last_subframe(Vec2::zero(), Element::StrokePolygon(line));
+    frame.set_anchor(offset);
+    // TODO: Make bounding box take Vec2.
+    frame.union_bounding_box(&BoundingBox::sized(offset.x, offset.y));
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+pub fn tapered_line<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                            mut args: Vec<Val<'a>>)
+                            -> Result<Val<'a>> {
+    validate_args(names::tapered_line, &[ValType::Coord(1), ValType::Num(1), ValType::Num(1)], &args)?;
+    let offset = match args.remove(0) {
+        Val::Coord(x, y, 1) => Vec2::new(x, y),
+        _ => unreachable!(),
+    };
+    let w_start = match args.remove(0) {
+        Val::Num(w, 1) => w,
+        _ => unreachable!(),
+    };
+    let w_end = match args.remove(0) {
+        Val::Num(w, 1) => w,
+        _ => unreachable!(),
+    };
+
+    let length = (offset.x * offset.x + offset.y * offset.y).sqrt();
+    if length == 0.0 {
+        return Err(Error::value("Cannot draw a tapered line of length 0.".into()))
+    }
+    if w_start < 0.0 || w_end < 0.0 {
+        let msg = format!("The widths of a tapered line must not be negative, but found {} and {}.", w_start, w_end);
+        return Err(Error::value(msg))
+    }
+
+    // The outline consists of the endpoints moved perpendicular to the line,
+    // by half the width at that end, on either side.
+    let normal = Vec2::new(-offset.y / length, offset.x / length);
+    let vertices = vec![
+        normal * (0.5 * w_start),
+        offset + normal * (0.5 * w_end),
+        offset + -normal * (0.5 * w_end),
+        -normal * (0.5 * w_start),
+    ];
+
+    let mut frame = make_polygon_element(interpreter, vertices, PolygonKind::Lines, DrawKind::Fill)?;
+    frame.set_anchor(offset);
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+/// Move text elements vertically so their baselines lie on the grid.
+///
+/// The `offset` is the vertical position of the elements relative to the
+/// origin of the frame, so text inside groups snaps to the same grid.
+fn snap_elements(elements: &[PlacedElement], offset: f64, grid: f64) -> Vec<PlacedElement> {
+    elements.iter().map(|pe| {
+        match pe.element {
+            Element::Text(..) => {
+                let y = offset + pe.position.y;
+                let snapped_y = (y / grid).round() * grid - offset;
+                PlacedElement {
+                    position: Vec2::new(pe.position.x, snapped_y),
+                    element: pe.element.clone(),
+                }
+            }
+            Element::Group(ref group) => {
+                let inner = snap_elements(group, offset + pe.position.y, grid);
+                PlacedElement {
+                    position: pe.position,
+                    element: Element::Group(Arc::new(inner)),
+                }
+            }
+            Element::Transformed(ref elements, scale) if scale == 1.0 => {
+                let inner = snap_elements(elements, offset + pe.position.y, grid);
+                PlacedElement {
+                    position: pe.position,
+                    element: Element::Transformed(Arc::new(inner), scale),
+                }
+            }
+            // Scaled elements are not on the same grid as the frame, and the
+            // other elements have no baseline, so they are left untouched.
+            _ => pe.clone(),
+        }
+    }).collect()
+}
+
+#[test]
+fn snap_elements_snaps_text_in_groups_relative_to_the_frame() {
+    use elements::{Color, Text};
+    let text = Element::Text(Text {
+        color: Color::new(0.0, 0.0, 0.0),
+        font_family: String::new(),
+        font_style: String::new(),
+        font_size: 1.0,
+        glyphs: Vec::new(),
+        text: String::new(),
+        clusters: Vec::new(),
+    });
+    let group = Element::Group(Arc::new(vec![
+        PlacedElement { position: Vec2::new(1.0, 4.0), element: text.clone() },
+    ]));
+    let elements = vec![
+        PlacedElement { position: Vec2::new(0.0, 14.0), element: text },
+        PlacedElement { position: Vec2::new(0.0, 3.0), element: group },
+    ];
+    let snapped = snap_elements(&elements, 0.0, 10.0);
+    assert_eq!(snapped[0].position.y, 10.0);
+    assert_eq!(snapped[1].position.y, 3.0);
+    match snapped[1].element {
+        // The text is at 7 in the frame, so it snaps to 10.
+        Element::Group(ref inner) => {
+            assert_eq!(inner[0].position.x, 1.0);
+            assert_eq!(inner[0].position.y, 7.0);
+        }
+        _ => panic!("Expected the group to remain a group."),
+    }
+}
+
+fn lookup_baseline_grid<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                                fn_name: symbol::Symbol)
+                                -> Result<f64> {
+    let grid = interpreter.env.lookup_len(&Idents(vec![names::baseline_grid]))?;
+    if grid <= 0.0 {
+        let msg = format!("'{}' requires a positive 'baseline_grid', but it is {}.", fn_name, grid);
+        return Err(Error::Other(msg))
+    }
+    Ok(grid)
+}
+
+pub fn snap_to_grid<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                            mut args: Vec<Val<'a>>)
+                            -> Result<Val<'a>> {
+    validate_args(names::snap_to_grid, &[ValType::Frame], &args)?;
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+    let grid = lookup_baseline_grid(interpreter, names::snap_to_grid)?;
+
+    let mut snapped_frame = Frame::from_env(frame.get_env().clone());
+
+    for subframe in frame.get_subframes() {
+        let mut dest_sf = Subframe::new();
+        for pe in snap_elements(subframe.get_elements(), 0.0, grid) {
+            dest_sf.place_element(pe.position, pe.element);
+        }
+        snapped_frame.push_subframe(dest_sf);
+    }
+
+    snapped_frame.set_anchor(frame.get_anchor());
+    snapped_frame.union_bounding_box(frame.get_bounding_box());
+
+    Ok(Val::Frame(Rc::new(snapped_frame)))
+}
+
+pub fn baseline_grid_overlay<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                                     args: Vec<Val<'a>>)
+                                     -> Result<Val<'a>> {
+    validate_args(names::baseline_grid_overlay, &[], &args)?;
+    let grid = lookup_baseline_grid(interpreter, names::baseline_grid_overlay)?;
+    let size = interpreter.env.lookup_coord_num(&Idents(vec![names::canvas_size]))?;
+    let color = interpreter.env.lookup_color(&Idents(vec![names::color]))?;
+
+    // A tiny grid on a large canvas takes many lines, check their number
+    // before drawing them.
+    let num_lines = ((size.y / grid).ceil() - 1.0).max(0.0);
+    let num_lines = num_lines.min(u64::max_value() as f64) as u64;
+    interpreter.budget.check_new_elements(names::baseline_grid_overlay, num_lines)?;
+
+    let mut frame = Frame::new();
+    // Compute every line from its index, so rounding errors do not add up.
+    let mut i = 1;
+    while (i as f64) * grid < size.y {
+        let y = (i as f64) * grid;
+        let line = StrokePolygon {
+            color: color,
+            // The overlay is a debugging aid, so draw hairlines that do not
+            // obscure the content.
+            line_width: 1.0,
+            close: false,
+            vertices: vec![Vec2::new(0.0, y), Vec2::new(size.x, y)],
+            kind: PolygonKind::Lines,
+        };
+        frame.place_element_on_last_subframe(Vec2::zero(), Element::StrokePolygon(line));
+        i += 1;
+    }
+    frame.union_bounding_box(&BoundingBox::sized(size.x, size.y));
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+enum DrawKind {
+    Fill,
+    Stroke { close: bool },
+}
+
+fn make_polygon_element<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    vertices: Vec<Vec2>,
+    polygon_kind: PolygonKind,
+    draw_kind: DrawKind,
+) -> Result<Frame<'a>> {
+    let mut frame = Frame::new();
+
+    for v in &vertices {
+        frame.union_bounding_box(&BoundingBox::empty().offset(*v));
+    }
+
+    let element = match draw_kind {
+        DrawKind::Stroke { close } => {
+            // TODO: Better idents type for non-ast use?
+            let color = interpreter.env.lookup_color(&Idents(vec![names::color]))?;
+            let line_width = interpreter.env.lookup_len(&Idents(vec![names::line_width]))?;
+
+            let polygon = StrokePolygon {
+                color: color,
+                line_width: line_width,
+                close: close,
+                vertices: vertices,
+                kind: polygon_kind,
+            };
+
+            Element::StrokePolygon(polygon)
+        }
+        DrawKind::Fill => {
+            // Fills can use a gradient, which strokes and text cannot.
+            let polygon = FillPolygon {
+                paint: interpreter.env.lookup_paint(&Idents(vec![names::color]))?,
+                vertices: vertices,
+                kind: polygon_kind,
+            };
+
+            Element::FillPolygon(polygon)
+        }
+    };
+
+    frame.place_element_on_last_subframe(Vec2::zero(), element);
+
+    Ok(frame)
+}
+
+fn draw_circle<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    mut args: Vec<Val<'a>>,
+    name: symbol::Symbol,
+    kind: DrawKind,
+) -> Result<Val<'a>> {
+
+    validate_args(name, &[ValType::Num(1)], &args)?;
+    let r = match args.remove(0) {
+        Val::Num(r, 1) => r,
+        _ => unreachable!(),
+    };
+
+    // See also http://spencermortensen.com/articles/bezier-circle/.
+    let c = 0.551915024494 * r;
+    let z = 0.0;
+
+    let vertices = vec![
+        Vec2::new( r,  z), // Right
+        Vec2::new( r, -c),
+        Vec2::new( c, -r),
+        Vec2::new( z, -r), // Top
+        Vec2::new(-c, -r),
+        Vec2::new(-r, -c),
+        Vec2::new(-r,  z), // Left
+        Vec2::new(-r,  c),
+        Vec2::new(-c,  r),
+        Vec2::new( z,  r), // Bottom
+        Vec2::new( c,  r),
+        Vec2::new( r,  c),
+    ];
+
+    let mut frame = make_polygon_element(interpreter, vertices, PolygonKind::Curves, kind)?;
+
+    frame.set_anchor(Vec2::zero());
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+pub fn fill_circle<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    args: Vec<Val<'a>>
+) -> Result<Val<'a>> {
+    draw_circle(interpreter, args, names::fill_circle, DrawKind::Fill)
+}
+
+pub fn stroke_circle<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    args: Vec<Val<'a>>
+) -> Result<Val<'a>> {
+    let kind = DrawKind::Stroke {
+        close: true,
+    };
+    draw_circle(interpreter, args, names::stroke_circle, kind)
+}
+
+fn draw_rectangle<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    mut args: Vec<Val<'a>>,
+    name: symbol::Symbol,
+    kind: DrawKind,
+) -> Result<Val<'a>> {
+    validate_args(name, &[ValType::Coord(1)], &args)?;
+
+    let (w, h) = match args.remove(0) {
+        Val::Coord(x, y, 1) => (x, y),
+        _ => unreachable!(),
+    };
+
+    let vertices = vec![
+        Vec2::zero(),
+        Vec2::new(0.0, h),
+        Vec2::new(w, h),
+        Vec2::new(w, 0.0),
+    ];
+
+    let mut frame = make_polygon_element(interpreter, vertices, PolygonKind::Lines, kind)?;
+
+    frame.set_anchor(Vec2::new(w, h));
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+pub fn fill_rectangle<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    args: Vec<Val<'a>>,
+) -> Result<Val<'a>> {
+    draw_rectangle(interpreter, args, names::fill_rectangle, DrawKind::Fill)
+}
+
+pub fn stroke_rectangle<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    args: Vec<Val<'a>>,
+) -> Result<Val<'a>> {
+    let kind = DrawKind::Stroke {
+        close: true,
+    };
+    draw_rectangle(interpreter, args, names::stroke_rectangle, kind)
+}
+
+fn draw_polygon<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    mut args: Vec<Val<'a>>,
+    name: symbol::Symbol,
+    polygon_kind: PolygonKind,
+    draw_kind: DrawKind,
+) -> Result<Val<'a>> {
+    validate_args(name, &[ValType::List], &args)?;
+
+    let coords = match args.remove(0) {
+        Val::List(vs) => vs,
+        _ => unreachable!(),
+    };
+
+    let mut vertices = Vec::with_capacity(coords.len());
+
+    // Collect the vertices, ensuring that they have the right type.
+    for vertex in &coords {
+        match vertex {
+            &Val::Coord(x, y, 1) => {
+                let v = Vec2::new(x, y);
+                vertices.push(v);
+            }
+            not_coord_of_len => {
+                let arg_num = 0;
+                let actual_type = not_coord_of_len.get_type();
+                let err = Error::arg_type(name, ValType::Coord(1), actual_type, arg_num);
+                return Err(err);
+            }
+        }
+    }
+
+    // TODO: Demand at least two coords.
+    let anchor = vertices.last().cloned().unwrap_or(Vec2::zero());
+    let mut frame = make_polygon_element(interpreter, vertices, polygon_kind, draw_kind)?;
+    frame.set_anchor(anchor);
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+pub fn fill_polygon<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    args: Vec<Val<'a>>,
+) -> Result<Val<'a>> {
+    draw_polygon(interpreter, args, names::fill_polygon, PolygonKind::Lines, DrawKind::Fill)
+}
+
+pub fn stroke_polygon<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    args: Vec<Val<'a>>,
+) -> Result<Val<'a>> {
     let kind = DrawKind::Stroke {
         // TODO: Make this a variable.
         close: true,
     };
-    draw_polygon(interpreter, args, names::stroke_curve, PolygonKind::Curves, kind)
+    draw_polygon(interpreter, args, names::stroke_polygon, PolygonKind::Lines, kind)
+}
+
+pub fn fill_curve<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    args: Vec<Val<'a>>,
+) -> Result<Val<'a>> {
+    draw_polygon(interpreter, args, names::fill_curve, PolygonKind::Curves, DrawKind::Fill)
+}
+
+pub fn stroke_curve<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    args: Vec<Val<'a>>,
+) -> Result<Val<'a>> {
+    let kind = DrawKind::Stroke {
+        // TODO: Make this a variable.
+        close: true,
+    };
+    draw_polygon(interpreter, args, names::stroke_curve, PolygonKind::Curves, kind)
+}
+
+/// Convert points into the vertices of Bézier segments through those points.
+///
+/// The result follows the layout of `PolygonKind::Curves`: the first point,
+/// followed by two control points and an end point for every segment. The
+/// tangents form a cardinal spline: at tension 0 this is a Catmull-Rom spline,
+/// at tension 1 the segments are straight. At the ends, the missing neighbor
+/// is taken to be the end point itself.
+fn cardinal_spline_vertices(points: &[Vec2], tension: f64) -> Vec<Vec2> {
+    let n = points.len();
+    let tangent = |i: usize| {
+        let prev = points[if i == 0 { 0 } else { i - 1 }];
+        let next = points[if i + 1 == n { i } else { i + 1 }];
+        (next + -prev) * (0.5 * (1.0 - tension))
+    };
+
+    let mut vertices = Vec::with_capacity(1 + 3 * (n - 1));
+    vertices.push(points[0]);
+    for i in 1..n {
+        vertices.push(points[i - 1] + tangent(i - 1) * (1.0 / 3.0));
+        vertices.push(points[i] + -tangent(i) * (1.0 / 3.0));
+        vertices.push(points[i]);
+    }
+    vertices
+}
+
+#[test]
+fn cardinal_spline_vertices_at_tension_one_are_straight() {
+    let points = [Vec2::new(0.0, 0.0), Vec2::new(3.0, 0.0), Vec2::new(3.0, 6.0)];
+    let vertices = cardinal_spline_vertices(&points, 1.0);
+    assert!(vertices == vec![
+        points[0], points[0], points[1], points[1],
+        points[1], points[2], points[2],
+    ]);
+}
+
+#[test]
+fn cardinal_spline_vertices_follow_neighbors_at_tension_zero() {
+    let points = [Vec2::new(0.0, 0.0), Vec2::new(3.0, 0.0), Vec2::new(6.0, 3.0)];
+    let vertices = cardinal_spline_vertices(&points, 0.0);
+    // The tangent at the middle point is half the vector from the first point
+    // to the last one, (3, 1.5), and control points are a third of it away.
+    assert!(vertices[2] == Vec2::new(2.0, -0.5));
+    assert!(vertices[4] == Vec2::new(4.0, 0.5));
+}
+
+pub fn smooth_curve<'i, 'a>(
+    interpreter: &mut ExprInterpreter<'i, 'a>,
+    mut args: Vec<Val<'a>>,
+) -> Result<Val<'a>> {
+    validate_args(names::smooth_curve, &[ValType::List, ValType::Num(0)], &args)?;
+    let coords = match args.remove(0) {
+        Val::List(vs) => vs,
+        _ => unreachable!(),
+    };
+    let tension = match args.remove(0) {
+        Val::Num(x, 0) => x,
+        _ => unreachable!(),
+    };
+
+    let mut points = Vec::with_capacity(coords.len());
+    for point in &coords {
+        match *point {
+            Val::Coord(x, y, 1) => points.push(Vec2::new(x, y)),
+            ref not_coord => {
+                return Err(Error::arg_type(names::smooth_curve, ValType::Coord(1), not_coord.get_type(), 0))
+            }
+        }
+    }
+
+    if points.len() < 2 {
+        let msg = format!("'{}' needs at least 2 points, but found {}.", names::smooth_curve, points.len());
+        return Err(Error::value(msg))
+    }
+
+    let anchor = points[points.len() - 1];
+    let vertices = cardinal_spline_vertices(&points, tension);
+    let kind = DrawKind::Stroke { close: false };
+    let mut frame = make_polygon_element(interpreter, vertices, PolygonKind::Curves, kind)?;
+    frame.set_anchor(anchor);
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+fn gradient_colors<'a>(args: &mut Vec<Val<'a>>) -> (Color, Color) {
+    let from = match args.remove(0) {
+        Val::Col(c) => c,
+        _ => unreachable!(),
+    };
+    let to = match args.remove(0) {
+        Val::Col(c) => c,
+        _ => unreachable!(),
+    };
+    (from, to)
+}
+
+pub fn linear_gradient<'i, 'a>(
+    _interpreter: &mut ExprInterpreter<'i, 'a>,
+    mut args: Vec<Val<'a>>,
+) -> Result<Val<'a>> {
+    validate_args(
+        names::linear_gradient,
+        &[ValType::Color, ValType::Color, ValType::Num(0)],
+        &args,
+    )?;
+    let (from, to) = gradient_colors(&mut args);
+    let angle = match args.remove(0) {
+        Val::Num(x, 0) => x,
+        _ => unreachable!(),
+    };
+    let gradient = Gradient {
+        kind: GradientKind::Linear(angle),
+        from: from,
+        to: to,
+    };
+    Ok(Val::Gradient(gradient))
+}
+
+pub fn radial_gradient<'i, 'a>(
+    _interpreter: &mut ExprInterpreter<'i, 'a>,
+    mut args: Vec<Val<'a>>,
+) -> Result<Val<'a>> {
+    validate_args(names::radial_gradient, &[ValType::Color, ValType::Color], &args)?;
+    let (from, to) = gradient_colors(&mut args);
+    let gradient = Gradient {
+        kind: GradientKind::Radial,
+        from: from,
+        to: to,
+    };
+    Ok(Val::Gradient(gradient))
+}
+
+/// Resolve and validate a hyperlink URI, log a warning if it looks malformed,
+/// or if it points at a file that does not exist.
+fn check_uri(interpreter: &ExprInterpreter, uri: &str) -> Result<String> {
+    let resolved = interpreter.link_base.resolve(uri);
+    if let Some(warning) = resolved.warning {
+        interpreter.logger.log(Level::Warning, &warning);
+    }
+    let checked = uri::check(&resolved.uri)?;
+    if let Some(warning) = checked.warning {
+        interpreter.logger.log(Level::Warning, &warning);
+    }
+    Ok(checked.uri)
 }
 
-pub fn hyperlink<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+pub fn hyperlink<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
                          mut args: Vec<Val<'a>>)
                          -> Result<Val<'a>> {
     validate_args(names::hyperlink, &[ValType::Str, ValType::Coord(1)], &args)?;
@@ -422,7 +1472,7 @@ pub fn hyperlink<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
 
     let link = Hyperlink {
         size: size,
-        uri: uri,
+        target: LinkTarget::Uri(check_uri(interpreter, &uri)?),
     };
 
     let mut frame = Frame::new();
@@ -434,6 +1484,225 @@ pub fn hyperlink<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
     Ok(Val::Frame(Rc::new(frame)))
 }
 
+pub fn link<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                    mut args: Vec<Val<'a>>)
+                    -> Result<Val<'a>> {
+    validate_args(names::link, &[ValType::Str, ValType::Frame], &args)?;
+    let uri = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+    let frame = match args.remove(0) {
+        Val::Frame(f) => f,
+        _ => unreachable!(),
+    };
+
+    // The clickable area covers the bounding box of the frame.
+    let bb = frame.get_bounding_box();
+    let link = Hyperlink {
+        size: Vec2::new(bb.width, bb.height),
+        target: LinkTarget::Uri(check_uri(interpreter, &uri)?),
+    };
+    let position = Vec2::new(bb.x, bb.y);
+
+    let mut linked_frame = (*frame).clone();
+    if linked_frame.get_subframes().len() == 0 {
+        linked_frame.push_subframe(Subframe::new());
+    }
+
+    // Make the link available from the first step onwards, like the content.
+    for i in 0..linked_frame.get_subframes().len() {
+        let subframe = linked_frame.get_subframe_mut(i);
+        subframe.place_element(position, Element::Hyperlink(link.clone()));
+    }
+
+    Ok(Val::Frame(Rc::new(linked_frame)))
+}
+
+/// Typeset a string with `t`, and return the resulting frame.
+fn typeset<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                   text: String)
+                   -> Result<Rc<Frame<'a>>> {
+    match t(interpreter, vec![Val::Str(text)])? {
+        Val::Frame(f) => Ok(f),
+        _ => unreachable!(),
+    }
+}
+
+pub fn auto_toc<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                        args: Vec<Val<'a>>)
+                        -> Result<Val<'a>> {
+    validate_args(names::auto_toc, &[], &args)?;
+    let font_size = interpreter.env.lookup_len(&Idents(vec![names::font_size]))?;
+    let line_height = interpreter.env.lookup_len(&Idents(vec![names::line_height]))?;
+    let toc = interpreter.toc;
+
+    let mut lines = Vec::new();
+    for entry in toc.entries() {
+        let title = typeset(interpreter, entry.title.clone())?;
+        let number = typeset(interpreter, entry.slide.to_string())?;
+        lines.push((entry.slide, title, number));
+    }
+
+    // The slide numbers are right-aligned in a column, one em to the right
+    // of the longest title.
+    let title_width = lines
+        .iter()
+        .map(|&(_, ref title, _)| title.get_bounding_box().width)
+        .fold(0.0, f64::max);
+    let number_width = lines
+        .iter()
+        .map(|&(_, _, ref number)| number.get_bounding_box().width)
+        .fold(0.0, f64::max);
+    let line_width = title_width + font_size + number_width;
+
+    let mut frame = Frame::new();
+    for (i, (slide, title, number)) in lines.into_iter().enumerate() {
+        let y = i as f64 * line_height;
+        let title_bb = title.get_bounding_box();
+        let number_bb = number.get_bounding_box();
+        let title_offset = Vec2::new(-title_bb.x, y);
+        let number_offset = Vec2::new(line_width - number_bb.x - number_bb.width, y);
+
+        frame.place_subframes(0, &title, title_offset);
+        frame.place_subframes(0, &number, number_offset);
+        frame.union_bounding_box(&title_bb.offset(title_offset));
+        frame.union_bounding_box(&number_bb.offset(number_offset));
+        frame.set_anchor(number.get_anchor() + number_offset);
+
+        // The entire line links to the first slide of the entry.
+        let link = Hyperlink {
+            size: Vec2::new(line_width, line_height),
+            target: LinkTarget::Slide(slide),
+        };
+        let position = Vec2::new(0.0, y - line_height);
+        frame.place_element_on_last_subframe(position, Element::Hyperlink(link));
+    }
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+/// Validate the arguments of `definition_list`, and return the pairs of term
+/// and description, and the width of the list.
+fn definition_pairs<'a>(mut args: Vec<Val<'a>>) -> Result<(Vec<(String, String)>, f64)> {
+    validate_args(names::definition_list, &[ValType::List, ValType::Num(1)], &args)?;
+    let entries = match args.remove(0) {
+        Val::List(vs) => vs,
+        _ => unreachable!(),
+    };
+    let width = match args.remove(0) {
+        Val::Num(x, 1) => x,
+        _ => unreachable!(),
+    };
+
+    let mut pairs = Vec::with_capacity(entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        let pair = match *entry {
+            Val::List(ref vs) if vs.len() == 2 => match (&vs[0], &vs[1]) {
+                (&Val::Str(ref term), &Val::Str(ref description)) => Some((term.clone(), description.clone())),
+                _ => None,
+            },
+            _ => None,
+        };
+        match pair {
+            Some(pair) => pairs.push(pair),
+            None => {
+                let msg = format!(
+                    "Every entry of '{}' must be a list of a term and a description, \
+                     but entry {} is not.",
+                    names::definition_list, i,
+                );
+                return Err(Error::value(msg))
+            }
+        }
+    }
+
+    Ok((pairs, width))
+}
+
+#[cfg(feature = "native")]
+pub fn definition_list<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                               args: Vec<Val<'a>>)
+                               -> Result<Val<'a>> {
+    let (pairs, width) = definition_pairs(args)?;
+    let gap = interpreter.env.lookup_len(&Idents(vec![names::gap]))?;
+    let line_height = interpreter.env.lookup_len(&Idents(vec![names::line_height]))?;
+
+    let mut terms = Vec::with_capacity(pairs.len());
+    for &(ref term, _) in &pairs {
+        terms.push(typeset(interpreter, term.clone())?);
+    }
+
+    // The descriptions start `gap` to the right of the longest term, and fill
+    // the rest of the width.
+    let term_width = terms
+        .iter()
+        .map(|term| term.get_bounding_box().width)
+        .fold(0.0, f64::max);
+    let description_x = term_width + gap;
+    if description_x >= width {
+        let msg = format!(
+            "The terms of '{}' are wider than the list, \
+             which leaves no room for the descriptions.",
+            names::definition_list,
+        );
+        return Err(Error::value(msg))
+    }
+
+    // Break the descriptions into lines that fit the column before typesetting
+    // them, so they wrap at word boundaries.
+    let descriptions: Vec<String> = {
+        let layout = TextLayout::from_env(interpreter.env)?;
+        let font_features = lookup_font_features(interpreter.env)?;
+        let segment = lookup_segment(interpreter.env)?;
+        let ft_face = layout.load_face(interpreter.font_map, &**interpreter.logger)?;
+        // A line that fails to shape fails again when it is typeset below,
+        // which reports the error.
+        let mut measure = |line: &str| {
+            match typeset_line(ft_face, &layout, &font_features, &segment, line) {
+                Ok((_, _, width)) => width,
+                Err(..) => 0.0,
+            }
+        };
+        pairs
+            .iter()
+            .map(|&(_, ref description)| {
+                wrap_lines(description, width - description_x, &mut measure).join("\n")
+            })
+            .collect()
+    };
+
+    let mut frame = Frame::new();
+    let mut y = 0.0;
+    for (term, description) in terms.into_iter().zip(descriptions) {
+        let description = typeset(interpreter, description)?;
+        let term_bb = term.get_bounding_box().clone();
+        let description_bb = description.get_bounding_box().clone();
+        let term_offset = Vec2::new(-term_bb.x, y);
+        let description_offset = Vec2::new(description_x - description_bb.x, y);
+
+        frame.place_subframes(0, &term, term_offset);
+        frame.place_subframes(0, &description, description_offset);
+        frame.union_bounding_box(&term_bb.offset(term_offset));
+        frame.union_bounding_box(&description_bb.offset(description_offset));
+        frame.set_anchor(description.get_anchor() + description_offset);
+
+        // The next row starts a line below the lowest line of this row.
+        let bottom = (term_bb.y + term_bb.height).max(description_bb.y + description_bb.height);
+        y += bottom + line_height;
+    }
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn definition_list<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                               args: Vec<Val<'a>>)
+                               -> Result<Val<'a>> {
+    definition_pairs(args)?;
+    Err(requires_native(names::definition_list))
+}
+
 pub fn str<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
                    mut args: Vec<Val<'a>>)
                    -> Result<Val<'a>> {
@@ -447,6 +1716,106 @@ pub fn str<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
     Ok(Val::Str(format!("{}", num)))
 }
 
+pub fn str_len<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                       mut args: Vec<Val<'a>>)
+                       -> Result<Val<'a>> {
+    validate_args(names::str_len, &[ValType::Num(1), ValType::Str], &args)?;
+    let unit = match args.remove(1) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+    let x = match args.remove(0) {
+        Val::Num(x, _) => x,
+        _ => unreachable!(),
+    };
+
+    let decimals = interpreter.env.lookup_num(&Idents(vec![names::decimals]))?;
+    if decimals < 0.0 || decimals > 17.0 || decimals.fract() != 0.0 {
+        let msg = format!("'decimals' must be an integer from 0 to 17, but it is {}.", decimals);
+        return Err(Error::value(msg))
+    }
+
+    // Canvas units are PDF points, which are 1/72 inch. Physical units are
+    // separated from the number by a space, relative units are written the
+    // way they are written in a Pris source file.
+    let canvas_size = interpreter.env.lookup_coord_num(&Idents(vec![names::canvas_size]))?;
+    let (unit_size, separator) = match &unit[..] {
+        "w" => (canvas_size.x, ""),
+        "h" => (canvas_size.y, ""),
+        "pt" => (canvas_size.y / 1080.0, ""),
+        "em" => (interpreter.env.lookup_len(&Idents(vec![names::font_size]))?, ""),
+        "pw" => (interpreter.env.lookup_container_size()?.x / 100.0, ""),
+        "ph" => (interpreter.env.lookup_container_size()?.y / 100.0, ""),
+        "in" => (72.0, " "),
+        "cm" => (72.0 / 2.54, " "),
+        "mm" => (72.0 / 25.4, " "),
+        _ => {
+            let msg = format!("Unknown unit '{}' in 'str_len'. \
+                               Expected one of w, h, pt, em, pw, ph, in, cm, or mm.", unit);
+            return Err(Error::value(msg))
+        }
+    };
+
+    let formatted = format_decimals(x / unit_size, decimals as usize);
+    Ok(Val::Str(format!("{}{}{}", formatted, separator, unit)))
+}
+
+/// Format a number with at most the given number of decimals.
+///
+/// Trailing zeros are dropped, so 2.50 with two decimals formats as "2.5".
+fn format_decimals(x: f64, decimals: usize) -> String {
+    let mut result = format!("{:.*}", decimals, x);
+    if result.contains('.') {
+        let len = result.trim_right_matches('0').trim_right_matches('.').len();
+        result.truncate(len);
+    }
+    if result == "-0" {
+        result = String::from("0");
+    }
+    result
+}
+
+#[test]
+fn format_decimals_drops_trailing_zeros() {
+    assert_eq!(format_decimals(2.5, 2), "2.5");
+    assert_eq!(format_decimals(2.0, 2), "2");
+    assert_eq!(format_decimals(1.0 / 3.0, 3), "0.333");
+    assert_eq!(format_decimals(120.0, 0), "120");
+    assert_eq!(format_decimals(-0.001, 2), "0");
+}
+
+pub fn getenv<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                      mut args: Vec<Val<'a>>)
+                      -> Result<Val<'a>> {
+    validate_args(names::getenv, &[ValType::Str], &args)?;
+    let name = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+
+    match env::var(&name) {
+        Ok(value) => Ok(Val::Str(value)),
+        Err(env::VarError::NotPresent) => {
+            let msg = format!("Environment variable '{}' is not set.", name);
+            Err(Error::value(msg))
+        }
+        Err(env::VarError::NotUnicode(..)) => {
+            let msg = format!("Environment variable '{}' is not valid UTF-8.", name);
+            Err(Error::value(msg))
+        }
+    }
+}
+
+/// Stand-in for `getenv` when reading the environment was not allowed.
+pub fn getenv_disabled<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                               _args: Vec<Val<'a>>)
+                               -> Result<Val<'a>> {
+    let msg = "Reading environment variables is disabled, \
+               to keep documents from depending on the environment by accident. \
+               Note: pass --allow-getenv to enable 'getenv'.";
+    Err(Error::Other(msg.into()))
+}
+
 pub fn sqrt<'i, 'a>(
     _interpreter: &mut ExprInterpreter<'i, 'a>,
     args: Vec<Val<'a>>
@@ -469,54 +1838,372 @@ pub fn sqrt<'i, 'a>(
     // be an arity mismatch still.
     validate_args(names::sqrt, &[ValType::Num(dim)], &args)?;
 
-    // TODO: Do proper error reporting.
-    assert!(dim % 2 == 0, "Dimension must be multiple of 2.");
+    if dim % 2 != 0 {
+        let msg = format!(
+            "'sqrt' expects a number, an area, or another even dimension, but found {}.",
+            describe_dimension(dim),
+        );
+        return Err(Error::value(msg))
+    }
 
     Ok(Val::Num(num.sqrt(), dim / 2))
 }
 
+/// Change the dimension of a number or coord from `from` to `to`.
+fn convert_dimension<'a>(fn_name: symbol::Symbol, from: LenDim, to: LenDim, mut args: Vec<Val<'a>>) -> Result<Val<'a>> {
+    if args.len() != 1 {
+        return Err(Error::arity(fn_name, 1, args.len() as u32))
+    }
+    match args.remove(0) {
+        Val::Num(x, d) if d == from => Ok(Val::Num(x, to)),
+        Val::Coord(x, y, d) if d == from => Ok(Val::Coord(x, y, to)),
+        Val::Coord(_, _, d) => Err(Error::arg_type(fn_name, ValType::Coord(from), ValType::Coord(d), 0)),
+        other => Err(Error::arg_type(fn_name, ValType::Num(from), other.get_type(), 0)),
+    }
+}
+
+pub fn as_len<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                      args: Vec<Val<'a>>)
+                      -> Result<Val<'a>> {
+    convert_dimension(names::as_len, 0, 1, args)
+}
+
+pub fn as_num<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                      args: Vec<Val<'a>>)
+                      -> Result<Val<'a>> {
+    convert_dimension(names::as_num, 1, 0, args)
+}
+
+/// Return the sine and cosine of an angle in degrees.
+///
+/// At multiples of 90 degrees the result is exact, so rotating by a quarter
+/// turn does not introduce rounding errors.
+fn sin_cos_degrees(degrees: f64) -> (f64, f64) {
+    let quarter_turns = degrees / 90.0;
+    if quarter_turns.fract() == 0.0 {
+        match ((quarter_turns as i64) % 4 + 4) % 4 {
+            0 => (0.0, 1.0),
+            1 => (1.0, 0.0),
+            2 => (0.0, -1.0),
+            _ => (-1.0, 0.0),
+        }
+    } else {
+        degrees.to_radians().sin_cos()
+    }
+}
+
+#[test]
+fn sin_cos_degrees_is_exact_for_quarter_turns() {
+    assert_eq!(sin_cos_degrees(90.0), (1.0, 0.0));
+    assert_eq!(sin_cos_degrees(-90.0), (-1.0, 0.0));
+    assert_eq!(sin_cos_degrees(540.0), (0.0, -1.0));
+    assert_eq!(sin_cos_degrees(30.0).0, 30.0_f64.to_radians().sin());
+}
+
+pub fn polar<'i, 'a>(
+    _interpreter: &mut ExprInterpreter<'i, 'a>,
+    args: Vec<Val<'a>>
+) -> Result<Val<'a>> {
+    // Like `sqrt`, this is generic over the dimension of the radius, so
+    // inspect the first argument before validating.
+    let dim = match args.first() {
+        Some(&Val::Num(_, n)) => n,
+        _ => 1,
+    };
+    validate_args(names::polar, &[ValType::Num(dim), ValType::Num(0)], &args)?;
+    let (radius, degrees) = match (&args[0], &args[1]) {
+        (&Val::Num(r, _), &Val::Num(a, 0)) => (r, a),
+        _ => unreachable!(),
+    };
+
+    let (sin, cos) = sin_cos_degrees(degrees);
+    Ok(Val::Coord(radius * cos, radius * sin, dim))
+}
+
+pub fn rotate_coord<'i, 'a>(
+    _interpreter: &mut ExprInterpreter<'i, 'a>,
+    args: Vec<Val<'a>>
+) -> Result<Val<'a>> {
+    let dim = match args.first() {
+        Some(&Val::Coord(_, _, n)) => n,
+        _ => 1,
+    };
+    validate_args(names::rotate_coord, &[ValType::Coord(dim), ValType::Num(0)], &args)?;
+    let (x, y, degrees) = match (&args[0], &args[1]) {
+        (&Val::Coord(x, y, _), &Val::Num(a, 0)) => (x, y, a),
+        _ => unreachable!(),
+    };
+
+    let (sin, cos) = sin_cos_degrees(degrees);
+    Ok(Val::Coord(x * cos - y * sin, x * sin + y * cos, dim))
+}
+
+pub fn translate<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                         args: Vec<Val<'a>>)
+                         -> Result<Val<'a>> {
+    validate_args(names::translate, &[ValType::Coord(1)], &args)?;
+    match args[0] {
+        Val::Coord(x, y, 1) => Ok(Val::Transform(Affine::translate(Vec2::new(x, y)))),
+        _ => unreachable!(),
+    }
+}
+
+pub fn rotate<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                      args: Vec<Val<'a>>)
+                      -> Result<Val<'a>> {
+    // Rotate returns a transform, or with a frame, rotates the frame directly.
+    match args.first() {
+        Some(&Val::Frame(..)) => {
+            validate_args(names::rotate, &[ValType::Frame, ValType::Num(0)], &args)?;
+            let (sin, cos) = match args[1] {
+                Val::Num(degrees, 0) => sin_cos_degrees(degrees),
+                _ => unreachable!(),
+            };
+            match args[0] {
+                Val::Frame(ref frame) => transform_frame(frame, Affine::rotate(sin, cos)),
+                _ => unreachable!(),
+            }
+        }
+        _ => {
+            validate_args(names::rotate, &[ValType::Num(0)], &args)?;
+            let (sin, cos) = match args[0] {
+                Val::Num(degrees, 0) => sin_cos_degrees(degrees),
+                _ => unreachable!(),
+            };
+            Ok(Val::Transform(Affine::rotate(sin, cos)))
+        }
+    }
+}
+
+pub fn scale<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                     args: Vec<Val<'a>>)
+                     -> Result<Val<'a>> {
+    // Scale accepts a single factor, or a factor per axis.
+    let expected = match args.first() {
+        Some(&Val::Coord(..)) => ValType::Coord(0),
+        _ => ValType::Num(0),
+    };
+    validate_args(names::scale, &[expected], &args)?;
+    let m = match args[0] {
+        Val::Num(s, 0) => Affine::scale(s, s),
+        Val::Coord(sx, sy, 0) => Affine::scale(sx, sy),
+        _ => unreachable!(),
+    };
+    Ok(Val::Transform(m))
+}
+
+pub fn transform<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                         args: Vec<Val<'a>>)
+                         -> Result<Val<'a>> {
+    validate_args(names::transform, &[ValType::Frame, ValType::Transform], &args)?;
+    match (&args[0], &args[1]) {
+        (&Val::Frame(ref frame), &Val::Transform(m)) => transform_frame(frame, m),
+        _ => unreachable!(),
+    }
+}
+
+/// Return the frame transformed by `m`, with the bounding box around it.
+fn transform_frame<'a>(frame: &Frame<'a>, m: Affine) -> Result<Val<'a>> {
+    // Cairo cannot draw with a transform that has no inverse, and there would
+    // be nothing to see anyway.
+    if m.determinant() == 0.0 {
+        let msg = "Cannot transform a frame with a transform that flattens it, \
+                   such as a scale of 0.";
+        return Err(Error::value(msg.to_string()))
+    }
+
+    let mut transformed_frame = Frame::from_env(frame.get_env().clone());
+    for subframe in frame.get_subframes() {
+        let mut new_sf = Subframe::new();
+        new_sf.place_element(Vec2::zero(), Element::Affine(subframe.share_elements(), m));
+        transformed_frame.push_subframe(new_sf);
+    }
+
+    // The bounding box of the transformed frame is that of its corners, which
+    // is larger than the content when the frame is rotated.
+    let bb = frame.get_bounding_box();
+    let corners = [
+        Vec2::new(bb.x, bb.y),
+        Vec2::new(bb.x + bb.width, bb.y),
+        Vec2::new(bb.x, bb.y + bb.height),
+        Vec2::new(bb.x + bb.width, bb.y + bb.height),
+    ];
+    let mut min = m.apply(corners[0]);
+    let mut max = min;
+    for &corner in &corners[1..] {
+        let p = m.apply(corner);
+        min = Vec2::new(min.x.min(p.x), min.y.min(p.y));
+        max = Vec2::new(max.x.max(p.x), max.y.max(p.y));
+    }
+    transformed_frame.union_bounding_box(&BoundingBox::new(min, Vec2::new(max.x - min.x, max.y - min.y)));
+    transformed_frame.set_anchor(m.apply(frame.get_anchor()));
+
+    Ok(Val::Frame(Rc::new(transformed_frame)))
+}
+
+/// Return the error for a builtin that needs fonts or images to evaluate.
+#[cfg(not(feature = "native"))]
+fn requires_native(fn_name: symbol::Symbol) -> Error {
+    let msg = format!("'{}' requires the 'native' feature, which this build of Pris does not have.", fn_name);
+    Error::Other(msg)
+}
+
+/// Return the error for a feature that needs a library this build lacks.
+#[cfg(all(feature = "native", not(all(feature = "svg", feature = "shaping"))))]
+fn requires_feature(what: &str, feature: &str, library: &str) -> Error {
+    let msg = format!(
+        "{} requires the '{}' feature, which needs {}. This build of Pris does not have it.",
+        what, feature, library
+    );
+    Error::Other(msg)
+}
+
+/// Placeholder for Harfbuzz font features, in builds without shaping.
+///
+/// There are no values of this type: `lookup_font_features` fails instead.
+#[cfg(all(feature = "native", not(feature = "shaping")))]
+enum FontFeature {}
+
+/// The language and script of a run of text, for Harfbuzz.
+///
+/// Properties that are `None` are guessed from the text.
+#[cfg(feature = "shaping")]
+struct Segment {
+    language: Option<harfbuzz::Language>,
+    script: Option<harfbuzz::Script>,
+}
+
+/// Placeholder for the segment properties, in builds without shaping.
+#[cfg(all(feature = "native", not(feature = "shaping")))]
+struct Segment;
+
 /// Typesets a single line of text.
+#[cfg(feature = "shaping")]
 ///
 /// Returns the glyphs as well as the width of the line.
 fn typeset_line(
     ft_face: &mut freetype::Face,
-    font_size: f64,
-    features: &[harfbuzz::FontFeature],
+    layout: &TextLayout,
+    features: &[FontFeature],
+    segment: &Segment,
     text: &str
-) -> (Vec<cairo::Glyph>, f64) {
+) -> Result<(Vec<Glyph>, Option<Vec<TextCluster>>, f64)> {
     // Shape the text using Harfbuzz: convert the UTF-8 string and input font
     // into a list of glyphs with offsets.
     let mut hb_font = harfbuzz::Font::from_ft_face(ft_face);
 
     let mut hb_buffer = harfbuzz::Buffer::new(harfbuzz::Direction::LeftToRight);
+    if let Some(language) = segment.language {
+        hb_buffer.set_language(language);
+    }
+    if let Some(script) = segment.script {
+        hb_buffer.set_script(script);
+    }
     hb_buffer.add_str(&text);
+    hb_buffer.guess_segment_properties();
     hb_buffer.shape(&mut hb_font, features);
 
     // Position all the glyphs: Harfbuzz gives offsets, but we need absolute
     // locations. Store them in the representation that Cairo expects.
     let hb_glyphs = hb_buffer.glyphs();
+    let glyph_clusters: Vec<u32> = hb_glyphs.iter().map(|hg| hg.cluster).collect();
+    let clusters = text_clusters(&glyph_clusters, text.len());
     let mut cr_glyphs = Vec::with_capacity(hb_glyphs.len());
     let (mut cur_x, mut cur_y) = (0.0, 0.0);
 
-    // Compensate for the fixed font size which is set for the Freetype font,
-    // and apply the desired font size.
-    let size_factor = font_size / 1000.0;
+    let size_factor = layout.size_factor();
 
     for hg in hb_glyphs {
         cur_x += hg.x_offset as f64 * size_factor;
         cur_y += hg.y_offset as f64 * size_factor;
-        let cg = cairo::Glyph::new(hg.codepoint as u64, cur_x, cur_y);
+        let cg = Glyph::new(hg.codepoint as u64, cur_x, cur_y);
         cur_x += hg.x_advance as f64 * size_factor;
         cur_y += hg.y_advance as f64 * size_factor;
         cr_glyphs.push(cg);
     }
 
-    (cr_glyphs, cur_x)
+    Ok((cr_glyphs, clusters, cur_x))
+}
+
+/// Typesets a single line of text, without Harfbuzz.
+#[cfg(all(feature = "native", not(feature = "shaping")))]
+///
+/// Every character maps to the glyph that the font has for it, placed at the
+/// advance of the previous one. There is no kerning, and there are no
+/// ligatures, but that is good enough for Latin text.
+fn typeset_line(
+    ft_face: &mut freetype::Face,
+    layout: &TextLayout,
+    _features: &[FontFeature],
+    _segment: &Segment,
+    text: &str
+) -> Result<(Vec<Glyph>, Option<Vec<TextCluster>>, f64)> {
+    let mut glyphs = Vec::with_capacity(text.len());
+    let mut clusters = Vec::with_capacity(text.len());
+    let mut cur_x = 0.0;
+
+    for ch in text.chars() {
+        // Index 0 is the missing glyph, which the font draws as a box.
+        let index = glyph_lookup::char_index(ft_face, ch).unwrap_or(0);
+        glyphs.push(Glyph::new(index, cur_x, 0.0));
+        clusters.push(TextCluster::new(ch.len_utf8(), 1));
+        cur_x += layout.glyph_advance(ft_face, index)?;
+    }
+
+    Ok((glyphs, Some(clusters), cur_x))
+}
+
+/// Group glyphs into text clusters for Cairo.
+///
+/// Takes the byte offset of the cluster of every glyph, as Harfbuzz reports
+/// them for left-to-right text. Returns `None` if the offsets are not ordered,
+/// in which case the glyphs cannot be mapped back to the text.
+#[cfg(feature = "shaping")]
+fn text_clusters(glyph_clusters: &[u32], text_len: usize) -> Option<Vec<TextCluster>> {
+    let mut clusters = Vec::new();
+    let mut i = 0;
+    while i < glyph_clusters.len() {
+        let start = glyph_clusters[i] as usize;
+        let mut j = i + 1;
+        while j < glyph_clusters.len() && glyph_clusters[j] as usize == start {
+            j += 1;
+        }
+        let end = if j < glyph_clusters.len() { glyph_clusters[j] as usize } else { text_len };
+        // The first cluster also covers any bytes before it.
+        let begin = if i == 0 { 0 } else { start };
+        if end < start || end > text_len {
+            return None
+        }
+        clusters.push(TextCluster::new(end - begin, j - i));
+        i = j;
+    }
+    if glyph_clusters.len() == 0 && text_len > 0 {
+        clusters.push(TextCluster::new(text_len, 0));
+    }
+    Some(clusters)
+}
+
+#[test]
+#[cfg(feature = "shaping")]
+fn text_clusters_groups_glyphs_with_the_same_offset() {
+    // For "affix" with an "ffi" ligature: a, ffi, x.
+    assert_eq!(
+        text_clusters(&[0, 1, 4], 5),
+        Some(vec![TextCluster::new(1, 1), TextCluster::new(3, 1), TextCluster::new(1, 1)])
+    );
+    // A character that is decomposed into two glyphs.
+    assert_eq!(
+        text_clusters(&[0, 0, 2], 3),
+        Some(vec![TextCluster::new(2, 2), TextCluster::new(1, 1)])
+    );
+    assert_eq!(text_clusters(&[], 0), Some(vec![]));
+    assert_eq!(text_clusters(&[1, 0], 2), None);
 }
 
 /// Split a string on newlines.
 ///
 /// Unlike `std::str::lines`, the final newline is not swallowed.
+#[cfg(feature = "native")]
 fn split_lines(text: &str) -> Vec<&str> {
     // TODO: This might return an iterator instead of a vector.
     // At this point it is not worth the trouble performance-wise.
@@ -531,63 +2218,131 @@ fn split_lines(text: &str) -> Vec<&str> {
 }
 
 #[test]
+#[cfg(feature = "native")]
 fn split_lines_returns_as_many_lines_as_newlines_plus_one() {
     let text = "\nfoo\nbar\n";
     let lines = split_lines(text);
     assert_eq!(&lines, &["", "foo", "bar", ""]);
 }
 
-pub fn t<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
-                 mut args: Vec<Val<'a>>)
-                 -> Result<Val<'a>> {
-    validate_args(names::t, &[ValType::Str], &args)?;
-    let text = match args.remove(0) {
-        Val::Str(s) => s,
-        _ => unreachable!(),
-    };
-    let text_lines = split_lines(&text);
+/// The font and line settings that text is laid out with.
+///
+/// This is shared by the builtins that produce text, so they resolve fonts and
+/// place lines the same way.
+#[cfg(feature = "native")]
+struct TextLayout {
+    color: Color,
+    font_family: String,
+    font_style: String,
+    font_size: f64,
+    line_height: f64,
+}
 
-    enum TextAlign { Left, Center, Right }
+#[cfg(feature = "native")]
+impl TextLayout {
+    /// Read the layout from the 'font_family', 'font_style', and related variables.
+    fn from_env<'a>(env: &Env<'a>) -> Result<TextLayout> {
+        // The line height is a bit of a problem; we could make it dimensionless
+        // and relative to the font size -- which would make it scale
+        // automatically -- but then specifying absolute line heights would be a
+        // bit of a hassle. We could make it absolute, but then it does not
+        // scale automatically. Or we could allow both here: numbers have units,
+        // so we could figure out what to do. But my gut feeling is that dynamic
+        // typing will be confusing in the end.
+        let layout = TextLayout {
+            color: env.lookup_color(&Idents(vec![names::color]))?,
+            font_family: env.lookup_str(&Idents(vec![names::font_family]))?,
+            font_style: env.lookup_str(&Idents(vec![names::font_style]))?,
+            font_size: env.lookup_len(&Idents(vec![names::font_size]))?,
+            line_height: env.lookup_len(&Idents(vec![names::line_height]))?,
+        };
+        Ok(layout)
+    }
 
-    // Read the font details from the 'font_family' and 'font_style' variables,
-    // and locate the corresponding FreeType face. The line height is a bit of a
-    // problem; we could make it dimensionless and relative to the font size --
-    // which would make it scale automatically -- but then specifying absolute
-    // line heights would be a bit of a hassle. We could make it absolute, but
-    // then it does not scale automatically. Or we could allow both here:
-    // numbers have units, so we could figure out what to do. But my gut feeling
-    // is that dynamic typing will be confusing in the end.
-    let font_family = interpreter.env.lookup_str(&Idents(vec![names::font_family]))?;
-    let font_style = interpreter.env.lookup_str(&Idents(vec![names::font_style]))?;
-    let font_size = interpreter.env.lookup_len(&Idents(vec![names::font_size]))?;
-    let font_features_list = interpreter.env.lookup_list(&Idents(vec![names::font_features]))?;
-    let line_height = interpreter.env.lookup_len(&Idents(vec![names::line_height]))?;
-    let text_align = interpreter.env.lookup_str(&Idents(vec![names::text_align]))?;
-    let ft_face = match interpreter.font_map.get(&font_family, &font_style) {
-        Some(face) => face,
-        None => return Err(Error::missing_font(font_family, font_style)),
-    };
-    let text_align = match text_align.as_ref() {
-        "left" => TextAlign::Left,
-        "center" => TextAlign::Center,
-        "right" => TextAlign::Right,
-        other => {
-            // TODO: Move this error to an error at assignment time, not at
-            // evaluation time. More type safety is more better.
-            let mut fmt = Formatter::new();
-            fmt.print("'");
-            fmt.print(other);
-            fmt.print("' is not a valid value for 'text_align'. ");
-            fmt.print("Must be one of 'left', 'center', 'right'.");
-            return Err(Error::value(fmt.into_string()))
+    /// Locate the FreeType face, and warn if a different font was substituted.
+    fn load_face<'f>(&self, font_map: &'f mut FontMap, logger: &Logger) -> Result<&'f mut freetype::Face> {
+        let ft_face = match font_map.get(&self.font_family, &self.font_style) {
+            Some(face) => face,
+            None => return Err(Error::missing_font(self.font_family.clone(), self.font_style.clone())),
+        };
+
+        if ft_face.family_name().as_ref() != Some(&self.font_family) {
+            let msg = format!("requested font family '{}', but loaded '{}'.",
+                              self.font_family, ft_face.family_name().unwrap_or("?".into()));
+            logger.log(Level::Warning, &msg);
         }
-    };
+        if ft_face.style_name().as_ref() != Some(&self.font_style) {
+            let msg = format!("requested font style '{}', but loaded '{}'.",
+                              self.font_style, ft_face.style_name().unwrap_or("?".into()));
+            logger.log(Level::Warning, &msg);
+        }
+
+        Ok(ft_face)
+    }
+
+    /// Return the factor that converts font units to the font size.
+    ///
+    /// This compensates for the fixed size that the FreeType face is loaded
+    /// with, see `FontMap::get`.
+    fn size_factor(&self) -> f64 {
+        self.font_size / 1000.0
+    }
+
+    /// Return the horizontal advance of the glyph with the given index.
+    fn glyph_advance(&self, ft_face: &mut freetype::Face, index: u64) -> Result<f64> {
+        match ft_face.load_glyph(index as u32, freetype::face::LoadFlag::empty()) {
+            Ok(..) => {}
+            // TODO: Better structural error.
+            Err(..) => return Err(Error::Other(format!("Could not load glyph {}.", index))),
+        }
+
+        // There is a 16.6 factor that `linear_hori_advance` adds according to
+        // the docs, but it turns out that actually the advance with is returned
+        // with a multiplication factor of 1024.
+        Ok(ft_face.glyph().linear_hori_advance() as f64 * self.size_factor() / 1024.0)
+    }
+
+    /// Place the glyphs in a frame.
+    ///
+    /// The origin of the frame is on the baseline of the first line, and the
+    /// bounding box starts one line height above it, at `left`.
+    fn into_frame<'a>(
+        self,
+        glyphs: Vec<Glyph>,
+        text: String,
+        clusters: Vec<TextCluster>,
+        left: f64,
+        size: Vec2,
+        anchor: Vec2
+    ) -> Frame<'a> {
+        let top_left = Vec2::new(left, -self.line_height);
+        let text_elem = Text {
+            color: self.color,
+            font_family: self.font_family,
+            font_style: self.font_style,
+            font_size: self.font_size,
+            glyphs: glyphs,
+            text: text,
+            clusters: clusters,
+        };
+
+        let mut frame = Frame::new();
+        frame.place_element_on_last_subframe(Vec2::zero(), Element::Text(text_elem));
+        frame.set_anchor(anchor);
+        frame.union_bounding_box(&BoundingBox::new(top_left, size));
+        frame
+    }
+}
 
+/// Read the 'font_features' variable as Harfbuzz font feature specifiers.
+#[cfg(feature = "shaping")]
+fn lookup_font_features<'a>(env: &Env<'a>) -> Result<Vec<FontFeature>> {
     // Parse the font features into Harfbuzz font feature specifiers.
     // Unfortunately this does not tell us whether the font actually supports
     // these features, it only instructs Harfbuzz to use it. It would be nice
     // if we could warn when a feature does not exist in the font.
-    let mut font_features: Vec<harfbuzz::FontFeature> = Vec::new();
+    let font_features_list = env.lookup_list(&Idents(vec![names::font_features]))?;
+    let mut font_features: Vec<FontFeature> = Vec::new();
     for (i, value) in font_features_list.iter().enumerate() {
         match value {
             Val::Str(desc) => match harfbuzz::FontFeature::from_str(desc) {
@@ -612,25 +2367,124 @@ pub fn t<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
         }
     }
 
-    // TODO: Extract this, warn properly.
-    if ft_face.family_name().as_ref() != Some(&font_family) {
-        println!("Warning: requested font family '{}', but loaded '{}'.",
-                 font_family, ft_face.family_name().unwrap_or("?".into()));
+    // TODO: Validate that the font features exist.
+
+    Ok(font_features)
+}
+
+/// Read the 'text_language' and 'text_script' variables for Harfbuzz.
+///
+/// An empty string leaves it to Harfbuzz to guess the property.
+#[cfg(feature = "shaping")]
+fn lookup_segment<'a>(env: &Env<'a>) -> Result<Segment> {
+    let language = env.lookup_str(&Idents(vec![names::text_language]))?;
+    let script = env.lookup_str(&Idents(vec![names::text_script]))?;
+    let segment = Segment {
+        language: match harfbuzz::Language::from_str(&language) {
+            Some(language) => Some(language),
+            None if language.is_empty() => None,
+            None => {
+                let msg = format!(
+                    "'{}' is not a valid value for 'text_language'. \
+                    Note: Use a BCP 47 language tag, such as \"tr\" or \"sr-Latn\".",
+                    language
+                );
+                return Err(Error::value(msg))
+            }
+        },
+        script: match harfbuzz::Script::from_str(&script) {
+            Some(script) => Some(script),
+            None if script.is_empty() => None,
+            None => {
+                let msg = format!(
+                    "'{}' is not a valid value for 'text_script'. \
+                    Note: Use a four-letter ISO 15924 script tag, such as \"Latn\" or \"Deva\".",
+                    script
+                );
+                return Err(Error::value(msg))
+            }
+        },
+    };
+    Ok(segment)
+}
+
+/// Check that 'text_language' and 'text_script' are unset, in builds without Harfbuzz.
+#[cfg(all(feature = "native", not(feature = "shaping")))]
+fn lookup_segment<'a>(env: &Env<'a>) -> Result<Segment> {
+    for &name in &[names::text_language, names::text_script] {
+        if env.lookup_str(&Idents(vec![name]))?.len() > 0 {
+            return Err(requires_feature(&format!("Setting '{}'", name), "shaping", "Harfbuzz"))
+        }
     }
-    if ft_face.style_name().as_ref() != Some(&font_style) {
-        println!("Warning: requested font style '{}', but loaded '{}'.",
-                 font_style, ft_face.style_name().unwrap_or("?".into()));
+    Ok(Segment)
+}
+
+/// Check that 'font_features' is empty, in builds without Harfbuzz.
+#[cfg(all(feature = "native", not(feature = "shaping")))]
+fn lookup_font_features<'a>(env: &Env<'a>) -> Result<Vec<FontFeature>> {
+    let font_features_list = env.lookup_list(&Idents(vec![names::font_features]))?;
+    if font_features_list.len() > 0 {
+        return Err(requires_feature("Setting 'font_features'", "shaping", "Harfbuzz"))
     }
+    Ok(Vec::new())
+}
 
-    // TODO: Validate that the font features exist.
+#[cfg(feature = "native")]
+pub fn t<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                 mut args: Vec<Val<'a>>)
+                 -> Result<Val<'a>> {
+    validate_args(names::t, &[ValType::Str], &args)?;
+    let text = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+    let text_lines = split_lines(&text);
+
+    enum TextAlign { Left, Center, Right }
+
+    let layout = TextLayout::from_env(interpreter.env)?;
+    let font_features = lookup_font_features(interpreter.env)?;
+    let segment = lookup_segment(interpreter.env)?;
+    let text_align = interpreter.env.lookup_str(&Idents(vec![names::text_align]))?;
+    let ft_face = layout.load_face(interpreter.font_map, &**interpreter.logger)?;
+    let text_align = match text_align.as_ref() {
+        "left" => TextAlign::Left,
+        "center" => TextAlign::Center,
+        "right" => TextAlign::Right,
+        other => {
+            // TODO: Move this error to an error at assignment time, not at
+            // evaluation time. More type safety is more better.
+            let mut fmt = Formatter::new();
+            fmt.print("'");
+            fmt.print(other);
+            fmt.print("' is not a valid value for 'text_align'. ");
+            fmt.print("Must be one of 'left', 'center', 'right'.");
+            return Err(Error::value(fmt.into_string()))
+        }
+    };
 
     let mut glyphs = Vec::new();
+    // The clusters map the glyphs back to the text, so it can be embedded in
+    // the output. If any line cannot be mapped, we omit the text entirely.
+    let mut clusters = Some(Vec::new());
     let mut max_width: f64 = 0.0;
     let mut min_offset: f64 = 0.0;
     let mut cur_x = 0.0;
     let mut cur_y = 0.0;
-    for line in text_lines {
-        let (line_glyphs, width) = typeset_line(ft_face, font_size, &font_features, line);
+    for (i, line) in text_lines.into_iter().enumerate() {
+        let (line_glyphs, line_clusters, width) = typeset_line(ft_face, &layout, &font_features, &segment, line)?;
+
+        clusters = match (clusters, line_clusters) {
+            (Some(mut cs), Some(line_cs)) => {
+                // The newline that precedes the line maps to no glyphs.
+                if i > 0 {
+                    cs.push(TextCluster::new(1, 0));
+                }
+                cs.extend(line_cs);
+                Some(cs)
+            }
+            _ => None,
+        };
 
         // Apply x offset to enforce text alignment.
         let offset = match text_align {
@@ -645,29 +2499,59 @@ pub fn t<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
 
         max_width = max_width.max(width);
         min_offset = min_offset.min(offset);
-        cur_y += line_height;
+        cur_y += layout.line_height;
         cur_x = offset + width;
     }
 
-    let text_elem = Text {
-        color: interpreter.env.lookup_color(&Idents(vec!["color"]))?,
-        font_family: font_family,
-        font_style: font_style,
-        font_size: font_size,
-        glyphs: glyphs,
+    let text = if clusters.is_some() { text.clone() } else { String::new() };
+    let anchor = Vec2::new(cur_x, cur_y - layout.line_height);
+    let size = Vec2::new(max_width, cur_y);
+    let frame = layout.into_frame(glyphs, text, clusters.unwrap_or_else(Vec::new), min_offset, size, anchor);
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+#[cfg(feature = "native")]
+pub fn measure<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                       mut args: Vec<Val<'a>>)
+                       -> Result<Val<'a>> {
+    validate_args(names::measure, &[ValType::Str], &args)?;
+    let text = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
     };
 
-    let mut frame = Frame::new();
-    frame.place_element_on_last_subframe(Vec2::zero(), Element::Text(text_elem));
-    frame.set_anchor(Vec2::new(cur_x, cur_y - line_height));
+    let layout = TextLayout::from_env(interpreter.env)?;
+    let font_features = lookup_font_features(interpreter.env)?;
+    let segment = lookup_segment(interpreter.env)?;
+    let ft_face = layout.load_face(interpreter.font_map, &**interpreter.logger)?;
 
-    let top_left = Vec2::new(min_offset, -line_height);
-    let size = Vec2::new(max_width, cur_y);
-    frame.union_bounding_box(&BoundingBox::new(top_left, size));
+    // Shape the lines like `t()` does, but only keep the widths.
+    let mut max_width: f64 = 0.0;
+    let mut height = 0.0;
+    for line in split_lines(&text) {
+        let (_, _, width) = typeset_line(ft_face, &layout, &font_features, &segment, line)?;
+        max_width = max_width.max(width);
+        height += layout.line_height;
+    }
+
+    // The ascender and descender are in font units; the descender is negative
+    // for fonts that extend below the baseline, but we report it as a distance.
+    let em_size = ft_face.em_size() as f64;
+    let ascent = ft_face.ascender() as f64 * layout.font_size / em_size;
+    let descent = ft_face.descender() as f64 * layout.font_size / em_size * -1.0;
+
+    // The result is an empty frame with the same size as the text would have,
+    // so the metrics can be read as fields.
+    let mut frame = Frame::new();
+    frame.union_bounding_box(&BoundingBox::new(Vec2::new(0.0, -layout.line_height), Vec2::new(max_width, height)));
+    frame.put_in_env(names::ascent, Val::Num(ascent, 1));
+    frame.put_in_env(names::descent, Val::Num(descent, 1));
 
     Ok(Val::Frame(Rc::new(frame)))
 }
 
+#[cfg(feature = "native")]
 pub fn glyph<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
                      mut args: Vec<Val<'a>>)
                      -> Result<Val<'a>> {
@@ -677,60 +2561,228 @@ pub fn glyph<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
         _ => unreachable!(),
     };
 
-    let index = index_f64 as u64;
+    let index = glyph_index(index_f64)?;
+
+    // A glyph selected by index does not represent any particular text.
+    single_glyph(interpreter, String::new(), |_| Ok(index))
+}
 
-    if index as f64 != index_f64 {
+/// Convert a number into a glyph index, which must be an unsigned integer.
+fn glyph_index(index_f64: f64) -> Result<u64> {
+    // Check the range before casting, like `count_arg`. Glyph indices of
+    // FreeType fit in 32 bits.
+    if !(index_f64 >= 0.0 && index_f64 <= u32::max_value() as f64 && index_f64.fract() == 0.0) {
         let msg = format!("Expected an unsigned integer glyph index, found {}.", index_f64);
         return Err(Error::value(msg))
     }
 
-    // TODO: This was copy-pasted from the `t()` function. Extract the common
-    // stuff.
+    Ok(index_f64 as u64)
+}
 
-    let font_family = interpreter.env.lookup_str(&Idents(vec![names::font_family]))?;
-    let font_style = interpreter.env.lookup_str(&Idents(vec![names::font_style]))?;
-    let font_size = interpreter.env.lookup_len(&Idents(vec![names::font_size]))?;
-    let line_height = interpreter.env.lookup_len(&Idents(vec![names::line_height]))?;
-    let ft_face = match interpreter.font_map.get(&font_family, &font_style) {
-        Some(face) => face,
-        None => return Err(Error::missing_font(font_family, font_style)),
+#[test]
+fn glyph_index_rejects_values_out_of_range() {
+    assert_eq!(glyph_index(0.0).unwrap(), 0);
+    assert_eq!(glyph_index(4294967295.0).unwrap(), 4294967295);
+    for &x in &[-1.0, 0.5, 4294967296.0, 1e300, ::std::f64::NAN, ::std::f64::INFINITY] {
+        assert!(glyph_index(x).is_err(), "{} must be rejected", x);
+    }
+}
+
+/// Parse the arguments of `place_glyphs` into glyph indices and positions.
+fn placed_glyphs<'a>(mut args: Vec<Val<'a>>) -> Result<Vec<(u64, Vec2)>> {
+    validate_args(names::place_glyphs, &[ValType::List, ValType::List], &args)?;
+    let indices = match args.remove(0) {
+        Val::List(vs) => vs,
+        _ => unreachable!(),
+    };
+    let positions = match args.remove(0) {
+        Val::List(vs) => vs,
+        _ => unreachable!(),
     };
 
-    // Compensate for the fixed font size which is set for the Freetype font.
-    // There is a 16.6 factor that `linear_hori_advance` adds according to the
-    // docs, but it turns out that actually the advance with is returned with a
-    // multiplication factor of 1024.
-    let size_factor = font_size / 1000.0 / 1024.0;
+    if indices.len() != positions.len() {
+        let msg = format!(
+            "'{}' needs a position for every glyph, but found {} glyphs and {} positions.",
+            names::place_glyphs, indices.len(), positions.len(),
+        );
+        return Err(Error::value(msg))
+    }
+
+    let mut glyphs = Vec::with_capacity(indices.len());
+    for (index, position) in indices.iter().zip(positions.iter()) {
+        let index = match *index {
+            Val::Num(x, 0) => glyph_index(x)?,
+            ref not_num => {
+                return Err(Error::arg_type(names::place_glyphs, ValType::Num(0), not_num.get_type(), 0))
+            }
+        };
+        let position = match *position {
+            Val::Coord(x, y, 1) => Vec2::new(x, y),
+            ref not_coord => {
+                return Err(Error::arg_type(names::place_glyphs, ValType::Coord(1), not_coord.get_type(), 1))
+            }
+        };
+        glyphs.push((index, position));
+    }
+
+    Ok(glyphs)
+}
 
-    // Get the x-advance from the font, which will be used as the glyph width.
-    match ft_face.load_glyph(index as u32, freetype::face::LoadFlag::empty()) {
-        Ok(..) => {}
-        // TODO: Better structural error.
-        Err(..) => return Err(Error::Other(format!("Could not load glyph {}.", index))),
+#[cfg(feature = "native")]
+pub fn place_glyphs<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                            args: Vec<Val<'a>>)
+                            -> Result<Val<'a>> {
+    let placed = placed_glyphs(args)?;
+
+    let layout = TextLayout::from_env(interpreter.env)?;
+    let ft_face = layout.load_face(interpreter.font_map, &**interpreter.logger)?;
+
+    // The bounding box spans the origin and the advance of every glyph, and
+    // the anchor is at the end of the last glyph, like for a line of text.
+    let mut glyphs = Vec::with_capacity(placed.len());
+    let (mut left, mut right): (f64, f64) = (0.0, 0.0);
+    let mut anchor = Vec2::zero();
+    for (index, position) in placed {
+        let advance = layout.glyph_advance(ft_face, index)?;
+        glyphs.push(Glyph::new(index, position.x, position.y));
+        left = left.min(position.x);
+        right = right.max(position.x + advance);
+        anchor = Vec2::new(position.x + advance, position.y);
     }
-    let width = ft_face.glyph().linear_hori_advance() as f64 * size_factor;
 
-    let glyphs = vec![cairo::Glyph::new(index, 0.0, 0.0)];
+    // Manually placed glyphs do not represent any particular text.
+    let size = Vec2::new(right - left, layout.line_height);
+    let frame = layout.into_frame(glyphs, String::new(), Vec::new(), left, size, anchor);
 
-    let text_elem = Text {
-        color: interpreter.env.lookup_color(&Idents(vec![names::color]))?,
-        font_family: font_family,
-        font_style: font_style,
-        font_size: font_size,
-        glyphs: glyphs,
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+#[cfg(feature = "native")]
+pub fn glyph_by_name<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                             mut args: Vec<Val<'a>>)
+                             -> Result<Val<'a>> {
+    validate_args(names::glyph_by_name, &[ValType::Str], &args)?;
+    let name = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
     };
 
-    let mut frame = Frame::new();
-    frame.place_element_on_last_subframe(Vec2::zero(), Element::Text(text_elem));
-    frame.set_anchor(Vec2::new(width, 0.0));
+    single_glyph(interpreter, String::new(), |ft_face| {
+        match glyph_lookup::name_index(ft_face, &name) {
+            Some(index) => Ok(index),
+            None => {
+                let msg = format!("The font has no glyph named '{}'. Note: not all fonts contain glyph names.", name);
+                Err(Error::value(msg))
+            }
+        }
+    })
+}
+
+#[cfg(feature = "native")]
+pub fn char_glyph<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                          mut args: Vec<Val<'a>>)
+                          -> Result<Val<'a>> {
+    validate_args(names::char_glyph, &[ValType::Str], &args)?;
+    let text = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+
+    let ch = {
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => ch,
+            _ => {
+                let msg = format!("Expected a string of a single character, found '{}'.", text);
+                return Err(Error::value(msg))
+            }
+        }
+    };
+
+    // Unlike a glyph selected by index, this glyph represents the character,
+    // so it can be copied from the output.
+    single_glyph(interpreter, text, |ft_face| {
+        match glyph_lookup::char_index(ft_face, ch) {
+            Some(index) => Ok(index),
+            None => Err(Error::value(format!("The font has no glyph for '{}'.", ch))),
+        }
+    })
+}
+
+/// Lay out a single glyph, with the index that `select` finds in the font.
+///
+/// If `text` is not empty, the glyph represents that text in the output.
+#[cfg(feature = "native")]
+fn single_glyph<'i, 'a, F>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                           text: String,
+                           select: F)
+                           -> Result<Val<'a>>
+    where F: FnOnce(&mut freetype::Face) -> Result<u64> {
+    let layout = TextLayout::from_env(interpreter.env)?;
+    let ft_face = layout.load_face(interpreter.font_map, &**interpreter.logger)?;
+    let index = select(ft_face)?;
+
+    // The x-advance from the font is used as the glyph width.
+    let width = layout.glyph_advance(ft_face, index)?;
+
+    let glyphs = vec![Glyph::new(index, 0.0, 0.0)];
+    let clusters = if text.len() > 0 { vec![TextCluster::new(text.len(), 1)] } else { Vec::new() };
 
-    let top_left = Vec2::new(0.0, -line_height);
     let size = Vec2::new(width, 0.0);
-    frame.union_bounding_box(&BoundingBox::new(top_left, size));
+    let anchor = Vec2::new(width, 0.0);
+    let frame = layout.into_frame(glyphs, text, clusters, 0.0, size, anchor);
 
     Ok(Val::Frame(Rc::new(frame)))
 }
 
+#[cfg(not(feature = "native"))]
+pub fn t<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                 args: Vec<Val<'a>>)
+                 -> Result<Val<'a>> {
+    validate_args(names::t, &[ValType::Str], &args)?;
+    Err(requires_native(names::t))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn measure<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                       args: Vec<Val<'a>>)
+                       -> Result<Val<'a>> {
+    validate_args(names::measure, &[ValType::Str], &args)?;
+    Err(requires_native(names::measure))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn glyph<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                     args: Vec<Val<'a>>)
+                     -> Result<Val<'a>> {
+    validate_args(names::glyph, &[ValType::Num(0)], &args)?;
+    Err(requires_native(names::glyph))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn place_glyphs<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                            args: Vec<Val<'a>>)
+                            -> Result<Val<'a>> {
+    placed_glyphs(args)?;
+    Err(requires_native(names::place_glyphs))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn glyph_by_name<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                             args: Vec<Val<'a>>)
+                             -> Result<Val<'a>> {
+    validate_args(names::glyph_by_name, &[ValType::Str], &args)?;
+    Err(requires_native(names::glyph_by_name))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn char_glyph<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                          args: Vec<Val<'a>>)
+                          -> Result<Val<'a>> {
+    validate_args(names::char_glyph, &[ValType::Str], &args)?;
+    Err(requires_native(names::char_glyph))
+}
+
 pub fn image<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
                      mut args: Vec<Val<'a>>)
                      -> Result<Val<'a>> {
@@ -744,14 +2796,7 @@ pub fn image<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
     // required for this. Time to replace the FontMap everywhere with a more
     // elaborate state structure.
 
-    let (width, height, element) = match () {
-        _ if path.ends_with(".svg") => image_svg(path)?,
-        _ if path.ends_with(".png") => image_png(path)?,
-        _ => {
-            let msg = format!("Cannot load '{}', only svg and png images are supported for now.", path);
-            return Err(Error::Other(msg))
-        }
-    };
+    let (width, height, element) = load_image(path)?;
 
     let mut frame = Frame::new();
     frame.place_element_on_last_subframe(Vec2::zero(), element);
@@ -764,6 +2809,150 @@ pub fn image<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
     Ok(Val::Frame(Rc::new(frame)))
 }
 
+#[cfg(feature = "native")]
+pub fn scene<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                     mut args: Vec<Val<'a>>)
+                     -> Result<Val<'a>> {
+    validate_args(names::scene, &[ValType::Str], &args)?;
+    let path = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+
+    let source = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(Error::missing_file(path)),
+        Err(e) => return Err(Error::io(path, e.to_string())),
+    };
+    let fragment = scene::read(&path, &source, load_image)?;
+
+    // The renderer expects the fonts of text to be loaded already.
+    for &(ref family, ref style) in &fragment.fonts {
+        if interpreter.font_map.get(family, style).is_none() {
+            return Err(Error::missing_font(family.clone(), style.clone()))
+        }
+    }
+
+    let mut frame = Frame::new();
+    for pe in fragment.elements {
+        frame.place_element_on_last_subframe(pe.position, pe.element);
+    }
+    frame.union_bounding_box(&fragment.bounding_box);
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn scene<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                     args: Vec<Val<'a>>)
+                     -> Result<Val<'a>> {
+    validate_args(names::scene, &[ValType::Str], &args)?;
+    Err(requires_native(names::scene))
+}
+
+#[cfg(feature = "native")]
+pub fn external_svg<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                            mut args: Vec<Val<'a>>)
+                            -> Result<Val<'a>> {
+    validate_args(names::external_svg, &[ValType::Str, ValType::Str], &args)?;
+    let tool = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+    let input = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+
+    let path = interpreter.tools.render(&tool, &input)?;
+    let (width, height, element) = image_svg(path.to_string_lossy().into_owned())?;
+
+    let mut frame = Frame::new();
+    frame.place_element_on_last_subframe(Vec2::zero(), element);
+    frame.union_bounding_box(&BoundingBox::sized(width, height));
+    // Like for images, the anchor is in the top right.
+    frame.set_anchor(Vec2::new(width, 0.0));
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+#[cfg(not(feature = "native"))]
+pub fn external_svg<'i, 'a>(_interpreter: &mut ExprInterpreter<'i, 'a>,
+                            args: Vec<Val<'a>>)
+                            -> Result<Val<'a>> {
+    validate_args(names::external_svg, &[ValType::Str, ValType::Str], &args)?;
+    Err(requires_native(names::external_svg))
+}
+
+pub fn background_image<'i, 'a>(interpreter: &mut ExprInterpreter<'i, 'a>,
+                                mut args: Vec<Val<'a>>)
+                                -> Result<Val<'a>> {
+    validate_args(names::background_image, &[ValType::Str, ValType::Str], &args)?;
+    let path = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+    let mode = match args.remove(0) {
+        Val::Str(s) => s,
+        _ => unreachable!(),
+    };
+
+    let canvas = interpreter.env.lookup_coord_num(&Idents(vec![names::canvas_size]))?;
+
+    let (width, height, element) = load_image(path)?;
+
+    if width == 0.0 || height == 0.0 {
+        return Err(Error::Other("Cannot scale an image of size 0 to the canvas.".into()))
+    }
+
+    // In "cover" mode the image fills the entire canvas and the parts that do
+    // not fit are cut off by the page edges, in "contain" mode the entire image
+    // is visible, and parts of the canvas may remain uncovered.
+    let (sx, sy) = (canvas.x / width, canvas.y / height);
+    let scale = match mode.as_ref() {
+        "cover" => if sx > sy { sx } else { sy },
+        "contain" => if sx < sy { sx } else { sy },
+        other => {
+            let mut fmt = Formatter::new();
+            fmt.print("'");
+            fmt.print(other);
+            fmt.print("' is not a valid mode for '");
+            fmt.print(names::background_image);
+            fmt.print("'. Must be one of 'cover', 'contain'.");
+            return Err(Error::value(fmt.into_string()))
+        }
+    };
+
+    // Center the image on the canvas.
+    let offset = Vec2::new(
+        (canvas.x - width * scale) * 0.5,
+        (canvas.y - height * scale) * 0.5,
+    );
+    let placed = PlacedElement {
+        position: Vec2::zero(),
+        element: element,
+    };
+
+    let mut frame = Frame::new();
+    frame.place_element_on_last_subframe(offset, Element::Transformed(Arc::new(vec![placed]), scale));
+    frame.union_bounding_box(&BoundingBox::sized(canvas.x, canvas.y));
+
+    Ok(Val::Frame(Rc::new(frame)))
+}
+
+/// Load an svg or png image, and return its width, height, and element.
+fn load_image(path: String) -> Result<(f64, f64, Element)> {
+    match () {
+        _ if path.ends_with(".svg") => image_svg(path),
+        _ if path.ends_with(".png") => image_png(path),
+        _ => {
+            let msg = format!("Cannot load '{}', only svg and png images are supported for now.", path);
+            Err(Error::Other(msg))
+        }
+    }
+}
+
+#[cfg(feature = "svg")]
 fn image_svg<'a>(path: String) -> Result<(f64, f64, Element)> {
     let svg = match rsvg::Svg::open(&path) {
         Ok(svg) => svg,
@@ -774,9 +2963,22 @@ fn image_svg<'a>(path: String) -> Result<(f64, f64, Element)> {
     };
     let (width, height) = svg.size();
 
-    Ok((width as f64, height as f64, Element::Svg(svg)))
+    // The element refers to the file, because the handle cannot be sent to
+    // the thread that renders. The renderer loads the file again.
+    Ok((width as f64, height as f64, Element::Svg(PathBuf::from(path))))
  }
 
+#[cfg(all(feature = "native", not(feature = "svg")))]
+fn image_svg<'a>(path: String) -> Result<(f64, f64, Element)> {
+    let what = format!("Loading '{}'", path);
+    Err(requires_feature(&what, "svg", "librsvg"))
+}
+
+#[cfg(not(feature = "native"))]
+fn image_svg<'a>(_path: String) -> Result<(f64, f64, Element)> {
+    Err(requires_native(names::image))
+}
+
 fn image_png<'a>(path: String) -> Result<(f64, f64, Element)> {
     let (width, height) = png::get_dimensions(&path)?;
     Ok((width as f64, height as f64, Element::Png(PathBuf::from(path))))
@@ -7,10 +7,17 @@
 
 use freetype;
 use freetype::freetype_sys::FT_Face;
-use std::mem;
-use std::os::raw::{c_char, c_int, c_ulong};
+use std::cell::RefCell;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::ptr;
 use std::path::Path;
 use std::ffi::{CStr, CString};
+use std::slice;
+
+use elements::{Affine, Glyph, TextCluster};
+use error::{Error, Result};
 
 #[allow(non_camel_case_types)]
 enum cairo_surface_t {}
@@ -21,18 +28,46 @@ pub enum cairo_t {}
 #[allow(non_camel_case_types)]
 enum cairo_font_face_t {}
 
+#[allow(non_camel_case_types)]
+enum cairo_font_options_t {}
+
+#[allow(non_camel_case_types)]
+enum cairo_pattern_t {}
+
 #[allow(non_camel_case_types)]
 type cairo_status_t = c_int;
 
+#[allow(non_camel_case_types)]
+type cairo_content_t = c_int;
+
+#[allow(non_camel_case_types)]
+type cairo_format_t = c_int;
+
+const CAIRO_CONTENT_COLOR_ALPHA: cairo_content_t = 0x3000;
+const CAIRO_FORMAT_ARGB32: cairo_format_t = 0;
+const CAIRO_STATUS_WRITE_ERROR: cairo_status_t = 11;
+const CAIRO_HINT_METRICS_OFF: c_int = 1;
+const CAIRO_FONT_SLANT_NORMAL: c_int = 0;
+const CAIRO_FONT_WEIGHT_NORMAL: c_int = 0;
+
+#[allow(non_camel_case_types)]
+type cairo_write_func_t = extern "C" fn(closure: *mut c_void, data: *const u8, length: c_uint) -> cairo_status_t;
+
+#[allow(non_camel_case_types)]
+type cairo_destroy_func_t = extern "C" fn(data: *mut c_void);
+
 #[repr(C)]
 #[allow(non_camel_case_types)]
-#[derive(Copy, Clone)]
-pub struct cairo_glyph_t {
-    index: c_ulong,
-    x: f64,
-    y: f64,
+struct cairo_user_data_key_t {
+    unused: c_int,
 }
 
+/// Identifies the writer that a stream surface owns, in its user data.
+static WRITER_KEY: cairo_user_data_key_t = cairo_user_data_key_t { unused: 0 };
+
+#[allow(non_camel_case_types)]
+type cairo_text_cluster_flags_t = c_int;
+
 #[repr(C)]
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone)]
@@ -45,14 +80,49 @@ pub struct cairo_matrix_t {
     y0: f64,
 }
 
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct cairo_text_extents_t {
+    x_bearing: f64,
+    y_bearing: f64,
+    width: f64,
+    height: f64,
+    x_advance: f64,
+    y_advance: f64,
+}
+
 #[link(name = "cairo")]
 extern {
     fn cairo_create(surf: *mut cairo_surface_t) -> *mut cairo_t;
+    fn cairo_image_surface_create(format: cairo_format_t, width: c_int, height: c_int) -> *mut cairo_surface_t;
     fn cairo_image_surface_create_from_png(fname: *const c_char) -> *mut cairo_surface_t;
+    fn cairo_image_surface_get_data(surf: *mut cairo_surface_t) -> *mut u8;
+    fn cairo_image_surface_get_height(surf: *mut cairo_surface_t) -> c_int;
+    fn cairo_image_surface_get_stride(surf: *mut cairo_surface_t) -> c_int;
+    fn cairo_surface_flush(surf: *mut cairo_surface_t);
+    fn cairo_surface_mark_dirty(surf: *mut cairo_surface_t);
     fn cairo_pdf_surface_create(fname: *const c_char, width: f64, height: f64) -> *mut cairo_surface_t;
+    fn cairo_svg_surface_create(fname: *const c_char, width: f64, height: f64) -> *mut cairo_surface_t;
+    fn cairo_surface_write_to_png(surf: *mut cairo_surface_t, fname: *const c_char) -> cairo_status_t;
+    fn cairo_pdf_surface_create_for_stream(write_func: cairo_write_func_t, closure: *mut c_void, width: f64, height: f64) -> *mut cairo_surface_t;
+    fn cairo_svg_surface_create_for_stream(write_func: cairo_write_func_t, closure: *mut c_void, width: f64, height: f64) -> *mut cairo_surface_t;
+    fn cairo_surface_write_to_png_stream(surf: *mut cairo_surface_t, write_func: cairo_write_func_t, closure: *mut c_void) -> cairo_status_t;
+    fn cairo_surface_set_user_data(
+        surf: *mut cairo_surface_t,
+        key: *const cairo_user_data_key_t,
+        user_data: *mut c_void,
+        destroy: cairo_destroy_func_t
+    ) -> cairo_status_t;
+    fn cairo_surface_finish(surf: *mut cairo_surface_t);
+    fn cairo_recording_surface_create(content: cairo_content_t, extents: *const c_void) -> *mut cairo_surface_t;
     fn cairo_set_source_surface(cr: *mut cairo_t, surface: *mut cairo_surface_t, x: f64, y: f64);
     fn cairo_set_source_rgb(cr: *mut cairo_t, r: f64, g: f64, b: f64);
     fn cairo_set_source_rgba(cr: *mut cairo_t, r: f64, g: f64, b: f64, a: f64);
+    fn cairo_set_source(cr: *mut cairo_t, source: *mut cairo_pattern_t);
+    fn cairo_pattern_create_linear(x0: f64, y0: f64, x1: f64, y1: f64) -> *mut cairo_pattern_t;
+    fn cairo_pattern_create_radial(cx0: f64, cy0: f64, radius0: f64, cx1: f64, cy1: f64, radius1: f64) -> *mut cairo_pattern_t;
+    fn cairo_pattern_add_color_stop_rgba(pattern: *mut cairo_pattern_t, offset: f64, r: f64, g: f64, b: f64, a: f64);
+    fn cairo_pattern_destroy(pattern: *mut cairo_pattern_t);
     fn cairo_set_line_width(cr: *mut cairo_t, width: f64);
     fn cairo_curve_to(cr: *mut cairo_t, x1: f64, y1: f64, x2: f64, y2: f64, x3: f64, y3: f64);
     fn cairo_move_to(cr: *mut cairo_t, x: f64, y: f64);
@@ -61,21 +131,52 @@ extern {
     fn cairo_rectangle(cr: *mut cairo_t, x: f64, y: f64, w: f64, h: f64);
     fn cairo_stroke(cr: *mut cairo_t);
     fn cairo_fill(cr: *mut cairo_t);
+    fn cairo_clip(cr: *mut cairo_t);
+    fn cairo_reset_clip(cr: *mut cairo_t);
+    fn cairo_save(cr: *mut cairo_t);
+    fn cairo_restore(cr: *mut cairo_t);
     fn cairo_paint(cr: *mut cairo_t);
     fn cairo_show_page(cr: *mut cairo_t);
     fn cairo_destroy(cr: *mut cairo_t);
     fn cairo_surface_destroy(surf: *mut cairo_surface_t);
+    fn cairo_surface_reference(surf: *mut cairo_surface_t) -> *mut cairo_surface_t;
+    fn cairo_surface_status(surf: *mut cairo_surface_t) -> cairo_status_t;
     fn cairo_ft_font_face_create_for_ft_face(face: FT_Face, load_flags: c_int) -> *mut cairo_font_face_t;
     fn cairo_font_face_destroy(face: *mut cairo_font_face_t);
     fn cairo_set_font_face(cr: *mut cairo_t, font: *mut cairo_font_face_t);
     fn cairo_set_font_size(cr: *mut cairo_t, size: f64);
-    fn cairo_show_glyphs(cr: *mut cairo_t, glyphs: *const cairo_glyph_t, num_glyphs: c_int);
+    fn cairo_font_options_create() -> *mut cairo_font_options_t;
+    fn cairo_font_options_destroy(options: *mut cairo_font_options_t);
+    fn cairo_font_options_set_antialias(options: *mut cairo_font_options_t, antialias: c_int);
+    fn cairo_font_options_set_hint_style(options: *mut cairo_font_options_t, hint_style: c_int);
+    fn cairo_font_options_set_hint_metrics(options: *mut cairo_font_options_t, hint_metrics: c_int);
+    fn cairo_set_font_options(cr: *mut cairo_t, options: *const cairo_font_options_t);
+    fn cairo_select_font_face(cr: *mut cairo_t, family: *const c_char, slant: c_int, weight: c_int);
+    fn cairo_show_text(cr: *mut cairo_t, utf8: *const c_char);
+    fn cairo_text_extents(cr: *mut cairo_t, utf8: *const c_char, extents: *mut cairo_text_extents_t);
+    fn cairo_show_glyphs(cr: *mut cairo_t, glyphs: *const Glyph, num_glyphs: c_int);
+    fn cairo_show_text_glyphs(
+        cr: *mut cairo_t,
+        utf8: *const c_char,
+        utf8_len: c_int,
+        glyphs: *const Glyph,
+        num_glyphs: c_int,
+        clusters: *const TextCluster,
+        num_clusters: c_int,
+        cluster_flags: cairo_text_cluster_flags_t
+    );
     fn cairo_get_matrix(cr: *mut cairo_t, matrix: *mut cairo_matrix_t);
     fn cairo_set_matrix(cr: *mut cairo_t, matrix: *const cairo_matrix_t);
     fn cairo_translate(cr: *mut cairo_t, tx: f64, ty: f64);
     fn cairo_scale(cr: *mut cairo_t, sx: f64, sy: f64);
+    fn cairo_transform(cr: *mut cairo_t, matrix: *const cairo_matrix_t);
     fn cairo_user_to_device(cr: *mut cairo_t, x: *mut f64, y: *mut f64);
     fn cairo_user_to_device_distance(cr: *mut cairo_t, dx: *mut f64, dy: *mut f64);
+    fn cairo_device_to_user(cr: *mut cairo_t, x: *mut f64, y: *mut f64);
+    fn cairo_device_to_user_distance(cr: *mut cairo_t, dx: *mut f64, dy: *mut f64);
+    fn cairo_push_group(cr: *mut cairo_t);
+    fn cairo_pop_group_to_source(cr: *mut cairo_t);
+    fn cairo_paint_with_alpha(cr: *mut cairo_t, alpha: f64);
     fn cairo_status(cr: *mut cairo_t) -> cairo_status_t;
     fn cairo_status_to_string(status: cairo_status_t) -> *const c_char;
 }
@@ -87,8 +188,25 @@ extern {
 extern {
     fn cairo_tag_begin(cr: *mut cairo_t, tag_name: *const c_char, attributes: *const c_char);
     fn cairo_tag_end(cr: *mut cairo_t, tag_name: *const c_char);
+    fn cairo_pdf_surface_add_outline(
+        surf: *mut cairo_surface_t,
+        parent_id: c_int,
+        utf8: *const c_char,
+        link_attribs: *const c_char,
+        flags: c_int
+    ) -> c_int;
 }
 
+/// The parent id of top-level outline items.
+#[cfg(feature = "hyperlink")]
+pub const OUTLINE_ROOT: i32 = 0;
+
+#[cfg(feature = "hyperlink")]
+const CAIRO_PDF_OUTLINE_FLAG_OPEN: c_int = 1;
+
+/// The maximum number of glyphs to pass to a single `cairo_show_glyphs` call.
+const MAX_GLYPHS_PER_CALL: usize = 1024;
+
 pub struct Surface {
     ptr: *mut cairo_surface_t,
 }
@@ -104,29 +222,321 @@ pub struct FontFace {
     ft_face: freetype::Face,
 }
 
-#[derive(Copy, Clone)]
-pub struct Glyph(cairo_glyph_t);
+/// A source to paint with, other than a single color.
+pub struct Pattern {
+    ptr: *mut cairo_pattern_t,
+}
 
 #[derive(Copy, Clone)]
 pub struct Matrix(cairo_matrix_t);
 
+/// How glyphs are rasterized, the values of `cairo_antialias_t`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Antialias {
+    /// Use the default of the font backend, which depends on the system.
+    Default = 0,
+    /// Pixels are either fully covered or not covered at all.
+    None = 1,
+    /// Grayscale antialiasing.
+    Gray = 2,
+    /// Antialiasing using the color subpixels of an LCD screen.
+    Subpixel = 3,
+}
+
+impl Antialias {
+    pub fn from_name(name: &str) -> Option<Antialias> {
+        match name {
+            "default" => Some(Antialias::Default),
+            "none" => Some(Antialias::None),
+            "gray" => Some(Antialias::Gray),
+            "subpixel" => Some(Antialias::Subpixel),
+            _ => None,
+        }
+    }
+}
+
+/// How far glyph outlines are moved to fit the pixel grid, the values of
+/// `cairo_hint_style_t`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HintStyle {
+    /// Use the default of the font backend, which depends on the system.
+    Default = 0,
+    None = 1,
+    Slight = 2,
+    Medium = 3,
+    Full = 4,
+}
+
+impl HintStyle {
+    pub fn from_name(name: &str) -> Option<HintStyle> {
+        match name {
+            "default" => Some(HintStyle::Default),
+            "none" => Some(HintStyle::None),
+            "slight" => Some(HintStyle::Slight),
+            "medium" => Some(HintStyle::Medium),
+            "full" => Some(HintStyle::Full),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn font_option_names_are_parsed() {
+    assert_eq!(Antialias::from_name("gray"), Some(Antialias::Gray));
+    assert_eq!(Antialias::from_name("slight"), None);
+    assert_eq!(HintStyle::from_name("none"), Some(HintStyle::None));
+    assert_eq!(HintStyle::from_name("Full"), None);
+}
+
+/// Settings for rendering glyphs that only affect raster output.
+///
+/// The defaults of the font backend depend on the system configuration and
+/// the FreeType version. Setting both explicitly makes png output the same
+/// across machines, which matters for comparing against reference images.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FontOptions {
+    pub antialias: Antialias,
+    pub hint_style: HintStyle,
+}
+
+impl Default for FontOptions {
+    fn default() -> FontOptions {
+        FontOptions {
+            antialias: Antialias::Default,
+            hint_style: HintStyle::Default,
+        }
+    }
+}
+
+/// Convert a Cairo status into a result, with Cairo's description as message.
+fn check_status(status: cairo_status_t) -> Result<()> {
+    if status == 0 { return Ok(()) }
+    let message = unsafe {
+        let message_ptr = cairo_status_to_string(status);
+        CStr::from_ptr(message_ptr).to_string_lossy().into_owned()
+    };
+    Err(Error::cairo(message))
+}
+
+/// A writer that appends to a buffer, which can be taken out later.
+///
+/// A stream surface owns its writer. Give it a clone of a `SharedBuffer` to
+/// be able to read back the output after the surface is finished.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn new() -> SharedBuffer {
+        SharedBuffer::default()
+    }
+
+    /// Return the bytes written so far, and leave the buffer empty.
+    pub fn take(&self) -> Vec<u8> {
+        self.0.replace(Vec::new())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The writer that Cairo calls back into, boxed once more to get a thin pointer.
+type BoxedWriter = Box<Write>;
+
+/// Write callback for Cairo, the closure is a `BoxedWriter` or `&mut Write`.
+extern "C" fn write_to_writer(closure: *mut c_void, data: *const u8, length: c_uint) -> cairo_status_t {
+    let writer = unsafe { &mut *(closure as *mut BoxedWriter) };
+    let bytes = unsafe { slice::from_raw_parts(data, length as usize) };
+    match writer.write_all(bytes) {
+        Ok(()) => 0,
+        Err(..) => CAIRO_STATUS_WRITE_ERROR,
+    }
+}
+
+/// Destroy callback for Cairo, which drops the writer of a stream surface.
+extern "C" fn drop_writer(data: *mut c_void) {
+    unsafe { drop(Box::from_raw(data as *mut BoxedWriter)) }
+}
+
 impl Surface {
-    pub fn new_pdf(fname: &Path, width: f64, height: f64) -> Surface {
-        let fname_cstr = CString::new(fname.to_str().unwrap()).unwrap();
-        Surface {
-            ptr: unsafe { cairo_pdf_surface_create(fname_cstr.as_ptr(), width, height) }
+    /// Wrap a newly created surface, and check that creating it succeeded.
+    ///
+    /// Cairo never returns a null surface; on failure it returns a surface in
+    /// an error state. We still own that surface and must destroy it, which
+    /// happens when the `Surface` is dropped here.
+    fn from_raw(ptr: *mut cairo_surface_t) -> Result<Surface> {
+        let surface = Surface { ptr: ptr };
+        check_status(unsafe { cairo_surface_status(surface.ptr) })?;
+        Ok(surface)
+    }
+
+    pub fn new_pdf(fname: &Path, width: f64, height: f64) -> Result<Surface> {
+        let fname_cstr = path_to_cstring(fname)?;
+        Surface::from_raw(unsafe {
+            cairo_pdf_surface_create(fname_cstr.as_ptr(), width, height)
+        })
+    }
+
+    /// Create a surface that writes pdf output to `writer`.
+    ///
+    /// The surface owns the writer, and drops it when the surface is
+    /// destroyed. Call `finish` to ensure all output has been written.
+    pub fn new_pdf_for_stream<W: Write + 'static>(writer: W, width: f64, height: f64) -> Result<Surface> {
+        Surface::new_for_stream(writer, |write_func, closure| unsafe {
+            cairo_pdf_surface_create_for_stream(write_func, closure, width, height)
+        })
+    }
+
+    /// Create a surface that writes a single page as svg to `writer`.
+    pub fn new_svg_for_stream<W: Write + 'static>(writer: W, width: f64, height: f64) -> Result<Surface> {
+        Surface::new_for_stream(writer, |write_func, closure| unsafe {
+            cairo_svg_surface_create_for_stream(write_func, closure, width, height)
+        })
+    }
+
+    fn new_for_stream<W, F>(writer: W, create: F) -> Result<Surface>
+    where W: Write + 'static,
+          F: FnOnce(cairo_write_func_t, *mut c_void) -> *mut cairo_surface_t {
+        let boxed: Box<BoxedWriter> = Box::new(Box::new(writer));
+        let closure = Box::into_raw(boxed) as *mut c_void;
+        let ptr = create(write_to_writer, closure);
+
+        // Tie the lifetime of the writer to the surface. Even a surface in an
+        // error state can hold user data, but if attaching fails, the writer
+        // must be dropped here instead.
+        let status = unsafe {
+            cairo_surface_set_user_data(ptr, &WRITER_KEY, closure, drop_writer)
+        };
+        if status != 0 {
+            drop_writer(closure);
+        }
+
+        let surface = Surface::from_raw(ptr)?;
+        check_status(status)?;
+        Ok(surface)
+    }
+
+    /// Finish the surface, which writes any remaining output.
+    ///
+    /// Drawing to a finished surface is an error. Dropping the last reference
+    /// to a surface finishes it too, but then errors go unnoticed.
+    pub fn finish(&self) -> Result<()> {
+        unsafe {
+            cairo_surface_finish(self.ptr);
+            check_status(cairo_surface_status(self.ptr))
+        }
+    }
+
+    /// Create a surface that writes a single page as svg when finished.
+    pub fn new_svg(fname: &Path, width: f64, height: f64) -> Result<Surface> {
+        let fname_cstr = path_to_cstring(fname)?;
+        Surface::from_raw(unsafe {
+            cairo_svg_surface_create(fname_cstr.as_ptr(), width, height)
+        })
+    }
+
+    /// Create an in-memory bitmap, with size rounded up to whole pixels.
+    pub fn new_image(width: f64, height: f64) -> Result<Surface> {
+        Surface::from_raw(unsafe {
+            cairo_image_surface_create(
+                CAIRO_FORMAT_ARGB32,
+                width.ceil() as c_int,
+                height.ceil() as c_int,
+            )
+        })
+    }
+
+    /// Modify the pixels of an image surface in place.
+    ///
+    /// The pixels are in Cairo's ARGB32 format: 32-bit words in native byte
+    /// order, with premultiplied alpha. Only call this on a surface created
+    /// with `new_image`, when no context draws to it.
+    pub fn modify_image_data<F: FnOnce(&mut [u8])>(&self, modify: F) -> Result<()> {
+        unsafe {
+            cairo_surface_flush(self.ptr);
+            check_status(cairo_surface_status(self.ptr))?;
+            let data = cairo_image_surface_get_data(self.ptr);
+            if data.is_null() {
+                return Err(Error::Other("Surface has no image data to modify.".into()))
+            }
+            let len = cairo_image_surface_get_stride(self.ptr) * cairo_image_surface_get_height(self.ptr);
+            modify(slice::from_raw_parts_mut(data, len as usize));
+            cairo_surface_mark_dirty(self.ptr);
+        }
+        Ok(())
+    }
+
+    /// Write the contents of an image surface to a png file.
+    pub fn write_to_png(&self, fname: &Path) -> Result<()> {
+        let fname_cstr = path_to_cstring(fname)?;
+        check_status(unsafe { cairo_surface_write_to_png(self.ptr, fname_cstr.as_ptr()) })
+    }
+
+    /// Write the contents of an image surface as png to `writer`.
+    pub fn write_to_png_stream(&self, writer: &mut Write) -> Result<()> {
+        // The writer only needs to outlive the call, but the callback cannot
+        // express that lifetime. The layout of the box is the same regardless.
+        let mut boxed: Box<Write> = Box::new(writer);
+        let closure = &mut boxed as *mut Box<Write> as *mut c_void;
+        check_status(unsafe { cairo_surface_write_to_png_stream(self.ptr, write_to_writer, closure) })
+    }
+
+    pub fn from_png(fname: &Path) -> Result<Surface> {
+        let fname_cstr = path_to_cstring(fname)?;
+        Surface::from_raw(unsafe {
+            cairo_image_surface_create_from_png(fname_cstr.as_ptr())
+        })
+    }
+
+    /// Add an item that links to `page` (starting at 1) to the pdf outline.
+    ///
+    /// Returns the id of the item, to pass as parent for nested items. Items
+    /// with children are shown expanded.
+    #[cfg(feature = "hyperlink")]
+    pub fn add_pdf_outline(&self, parent_id: i32, title: &str, page: usize, open: bool) -> i32 {
+        // A nul byte would end the title early, drop them instead.
+        let title = CString::new(title.replace('\0', "")).unwrap();
+        let attributes = CString::new(format!("page={}", page)).unwrap();
+        let flags = if open { CAIRO_PDF_OUTLINE_FLAG_OPEN } else { 0 };
+        unsafe {
+            cairo_pdf_surface_add_outline(self.ptr, parent_id, title.as_ptr(), attributes.as_ptr(), flags)
         }
     }
 
-    pub fn from_png(fname: &Path) -> Surface {
-        let fname_cstr = CString::new(fname.to_str().unwrap()).unwrap();
+    /// Create an unbounded surface that records drawing operations.
+    ///
+    /// The recording can be replayed by using it as source surface. Vector
+    /// backends such as PDF replay it as a single object that is shared by
+    /// every place where it is painted.
+    pub fn new_recording() -> Result<Surface> {
+        Surface::from_raw(unsafe {
+            cairo_recording_surface_create(CAIRO_CONTENT_COLOR_ALPHA, ptr::null())
+        })
+    }
+}
+
+impl Clone for Surface {
+    fn clone(&self) -> Surface {
+        // Cairo surfaces are refcounted; a clone refers to the same surface.
         Surface {
-            // TODO: Check cairo_surface_status, see the manual.
-            ptr: unsafe { cairo_image_surface_create_from_png(fname_cstr.as_ptr()) }
+            ptr: unsafe { cairo_surface_reference(self.ptr) },
         }
     }
 }
 
+fn path_to_cstring(fname: &Path) -> Result<CString> {
+    match fname.to_str().and_then(|s| CString::new(s).ok()) {
+        Some(cstr) => Ok(cstr),
+        None => Err(Error::cairo(format!("Invalid path '{}'.", fname.display()))),
+    }
+}
+
 impl Drop for Surface {
     fn drop(&mut self) {
         unsafe { cairo_surface_destroy(self.ptr) }
@@ -134,27 +544,32 @@ impl Drop for Surface {
 }
 
 impl Cairo {
-    pub fn new(surf: Surface) -> Cairo {
+    pub fn new(surf: Surface) -> Result<Cairo> {
         // Note that we take the surface by value and destroy it afterwards.
         // Cairo employs refcounting internally, so this is safe to do.
-        Cairo {
+        let cr = Cairo {
             ptr: unsafe { cairo_create(surf.ptr) }
-        }
+        };
+        cr.status()?;
+        Ok(cr)
     }
 
     pub unsafe fn get_raw_ptr(&mut self) -> *mut cairo_t {
         self.ptr
     }
 
+    /// Return an error if the context is in an error state.
+    ///
+    /// Once a Cairo context is in an error state, all further drawing
+    /// operations on it are no-ops, so it suffices to check once in a while.
+    pub fn status(&self) -> Result<()> {
+        check_status(unsafe { cairo_status(self.ptr) })
+    }
+
     pub fn assert_status_success(&mut self) {
-        unsafe {
-            let status = cairo_status(self.ptr);
-            if status == 0 { return }
-            let message_ptr = cairo_status_to_string(status);
-            match CStr::from_ptr(message_ptr).to_str() {
-                Ok(msg) => panic!("Cairo status is not success: {}.", msg),
-                Err(_) => panic!("Cairo status is not success."),
-            }
+        if let Err(err) = self.status() {
+            err.print();
+            panic!("Cairo status is not success.");
         }
     }
 
@@ -166,6 +581,10 @@ impl Cairo {
         unsafe { cairo_set_source_rgba(self.ptr, r, g, b, a) }
     }
 
+    pub fn set_source(&mut self, pattern: &Pattern) {
+        unsafe { cairo_set_source(self.ptr, pattern.ptr) }
+    }
+
     pub fn set_source_surface(&mut self, surface: &Surface, x: f64, y: f64) {
         unsafe { cairo_set_source_surface(self.ptr, surface.ptr, x, y) }
     }
@@ -206,45 +625,209 @@ impl Cairo {
         unsafe { cairo_paint(self.ptr) }
     }
 
+    /// Restrict drawing to the current path, and clear the path.
+    pub fn clip(&mut self) {
+        unsafe { cairo_clip(self.ptr) }
+    }
+
+    pub fn reset_clip(&mut self) {
+        unsafe { cairo_reset_clip(self.ptr) }
+    }
+
+    /// Push the drawing state, including the transform and clip, on a stack.
+    pub fn save(&mut self) {
+        unsafe { cairo_save(self.ptr) }
+    }
+
+    /// Restore the drawing state from the last call to `save`.
+    pub fn restore(&mut self) {
+        unsafe { cairo_restore(self.ptr) }
+    }
+
     pub fn show_page(&mut self) {
         unsafe { cairo_show_page(self.ptr) }
     }
 
+    pub fn paint_with_alpha(&mut self, alpha: f64) {
+        unsafe { cairo_paint_with_alpha(self.ptr, alpha) }
+    }
+
+    /// Redirect drawing to an intermediate group, until `pop_group_to_source`.
+    pub fn push_group(&mut self) {
+        unsafe { cairo_push_group(self.ptr) }
+    }
+
+    /// End the group started by `push_group`, and make it the source pattern.
+    ///
+    /// The group can then be painted with `paint` or `paint_with_alpha`.
+    pub fn pop_group_to_source(&mut self) {
+        unsafe { cairo_pop_group_to_source(self.ptr) }
+    }
+
+    /// Open a tag with the given name and attributes, see `cairo_tag_begin`.
+    ///
+    /// Tags must be closed with `tag_end`, in reverse order of opening.
+    #[cfg(feature = "hyperlink")]
+    pub fn tag_begin(&mut self, tag_name: &str, attributes: &str) {
+        // Tag names and attributes are generated by Pris, so they never
+        // contain interior nul bytes; the unwraps are safe.
+        let name = CString::new(tag_name).unwrap();
+        let attrs = CString::new(attributes).unwrap();
+        unsafe { cairo_tag_begin(self.ptr, name.as_ptr(), attrs.as_ptr()) }
+        self.assert_status_success();
+    }
+
+    /// Close the most recently opened tag with the given name.
+    #[cfg(feature = "hyperlink")]
+    pub fn tag_end(&mut self, tag_name: &str) {
+        let name = CString::new(tag_name).unwrap();
+        unsafe { cairo_tag_end(self.ptr, name.as_ptr()) }
+        self.assert_status_success();
+    }
+
+    /// Create a link tag without content, the attributes specify the area.
     #[cfg(feature = "hyperlink")]
     pub fn tag_link(&mut self, attributes: &str) {
-        unsafe {
-            let tag_name = CStr::from_bytes_with_nul_unchecked(b"Link\0");
-            let attrs = CStr::from_bytes_with_nul(attributes.as_bytes()).unwrap();
-            cairo_tag_begin(self.ptr, tag_name.as_ptr(), attrs.as_ptr());
-            self.assert_status_success();
-            cairo_tag_end(self.ptr, tag_name.as_ptr());
-            self.assert_status_success();
-        }
+        self.tag_begin("Link", attributes);
+        self.tag_end("Link");
+    }
+
+    /// Create a named destination at the top left of the current page.
+    #[cfg(feature = "hyperlink")]
+    pub fn tag_dest(&mut self, name: &str) {
+        self.tag_begin("cairo.dest", &format!("name='{}' x=0 y=0", name));
+        self.tag_end("cairo.dest");
     }
 
     pub fn set_font_face(&mut self, face: &FontFace) {
         unsafe { cairo_set_font_face(self.ptr, face.ptr) }
     }
 
+    /// Set how glyphs are rasterized, for text drawn after this call.
+    pub fn set_font_options(&mut self, options: &FontOptions) {
+        unsafe {
+            let ptr = cairo_font_options_create();
+            cairo_font_options_set_antialias(ptr, options.antialias as c_int);
+            cairo_font_options_set_hint_style(ptr, options.hint_style as c_int);
+            // Glyph positions come from shaping, so metrics need not be hinted.
+            // Rounding them would make the output depend on the hint style.
+            cairo_font_options_set_hint_metrics(ptr, CAIRO_HINT_METRICS_OFF);
+            // Cairo copies the options, so we can destroy ours right away.
+            cairo_set_font_options(self.ptr, ptr);
+            cairo_font_options_destroy(ptr);
+        }
+    }
+
     pub fn set_font_size(&mut self, size: f64) {
         unsafe { cairo_set_font_size(self.ptr, size) }
     }
 
-    pub fn show_glyphs(&mut self, glyphs: &[Glyph]) {
+    /// Select a font by family name, for `show_text` and `text_advance`.
+    ///
+    /// This uses Cairo's toy text api, which does not shape text. It is meant
+    /// for text that Pris draws itself, not for text on slides.
+    pub fn select_font_family(&mut self, family: &str) {
+        let family = CString::new(family.replace('\0', "")).unwrap();
         unsafe {
-            let cgs: *const cairo_glyph_t = mem::transmute(glyphs.as_ptr());
-            cairo_show_glyphs(self.ptr, cgs, glyphs.len() as c_int);
+            cairo_select_font_face(self.ptr, family.as_ptr(), CAIRO_FONT_SLANT_NORMAL, CAIRO_FONT_WEIGHT_NORMAL)
         }
     }
 
-    pub fn get_matrix(&self) -> Matrix {
+    /// Show text at the current point with the font selected by `select_font_family`.
+    pub fn show_text(&mut self, text: &str) {
+        let text = CString::new(text.replace('\0', "")).unwrap();
+        unsafe { cairo_show_text(self.ptr, text.as_ptr()) }
+    }
+
+    /// Return the horizontal advance of `text` when drawn with `show_text`.
+    pub fn text_advance(&mut self, text: &str) -> f64 {
+        let text = CString::new(text.replace('\0', "")).unwrap();
+        let mut extents = cairo_text_extents_t {
+            x_bearing: 0.0, y_bearing: 0.0,
+            width: 0.0, height: 0.0,
+            x_advance: 0.0, y_advance: 0.0,
+        };
+        unsafe { cairo_text_extents(self.ptr, text.as_ptr(), &mut extents) }
+        extents.x_advance
+    }
+
+    pub fn show_glyphs(&mut self, glyphs: &[Glyph]) {
+        // Glyph positions are absolute, so submitting the glyphs in batches
+        // renders the same as submitting them at once. Batching keeps the
+        // count well within `c_int`, and avoids backend limits on the number
+        // of glyphs per call for very long runs of text.
+        for chunk in glyphs.chunks(MAX_GLYPHS_PER_CALL) {
+            unsafe {
+                cairo_show_glyphs(self.ptr, chunk.as_ptr(), chunk.len() as c_int);
+            }
+        }
+    }
+
+    /// Show glyphs, and the text that they represent.
+    ///
+    /// The clusters must cover all of the bytes of `text` and all glyphs, in
+    /// order. If there are no clusters, this is the same as `show_glyphs`.
+    pub fn show_text_glyphs(&mut self, text: &str, glyphs: &[Glyph], clusters: &[TextCluster]) {
+        if clusters.len() == 0 {
+            return self.show_glyphs(glyphs)
+        }
+
+        debug_assert_eq!(
+            clusters.iter().map(|c| c.num_bytes()).sum::<usize>(),
+            text.len()
+        );
+        debug_assert_eq!(
+            clusters.iter().map(|c| c.num_glyphs()).sum::<usize>(),
+            glyphs.len()
+        );
+
+        // Like `show_glyphs`, submit the glyphs in batches. A cluster cannot be
+        // split, so a batch ends at the last cluster that fits.
+        let bytes = text.as_bytes();
+        let (mut b0, mut g0, mut c0) = (0, 0, 0);
+        let (mut b1, mut g1) = (0, 0);
+        for (c1, cluster) in clusters.iter().enumerate() {
+            let num_glyphs = cluster.num_glyphs();
+            if g1 - g0 + num_glyphs > MAX_GLYPHS_PER_CALL && g1 > g0 {
+                self.show_text_glyphs_batch(&bytes[b0..b1], &glyphs[g0..g1], &clusters[c0..c1]);
+                b0 = b1;
+                g0 = g1;
+                c0 = c1;
+            }
+            b1 += cluster.num_bytes();
+            g1 += num_glyphs;
+        }
+        self.show_text_glyphs_batch(&bytes[b0..b1], &glyphs[g0..g1], &clusters[c0..]);
+    }
+
+    fn show_text_glyphs_batch(&mut self, text: &[u8], glyphs: &[Glyph], clusters: &[TextCluster]) {
         unsafe {
-            let mut mtx: cairo_matrix_t = mem::uninitialized();
-            cairo_get_matrix(self.ptr, &mut mtx);
-            Matrix(mtx)
+            cairo_show_text_glyphs(
+                self.ptr,
+                text.as_ptr() as *const c_char,
+                text.len() as c_int,
+                glyphs.as_ptr(),
+                glyphs.len() as c_int,
+                clusters.as_ptr(),
+                clusters.len() as c_int,
+                // No flags: clusters are in the same order as the glyphs.
+                0,
+            )
         }
     }
 
+    pub fn get_matrix(&self) -> Matrix {
+        // Start from the identity; Cairo overwrites it, but this way we never
+        // read uninitialized memory.
+        let mut mtx = cairo_matrix_t {
+            xx: 1.0, yx: 0.0,
+            xy: 0.0, yy: 1.0,
+            x0: 0.0, y0: 0.0,
+        };
+        unsafe { cairo_get_matrix(self.ptr, &mut mtx) };
+        Matrix(mtx)
+    }
+
     pub fn set_matrix(&mut self, matrix: &Matrix) {
         let &Matrix(ref mtx) = matrix;
         unsafe { cairo_set_matrix(self.ptr, mtx) }
@@ -258,6 +841,18 @@ impl Cairo {
         unsafe { cairo_scale(self.ptr, sx, sy) }
     }
 
+    /// Apply the transform to user space, before the current transform.
+    ///
+    /// The transform must be invertible, or Cairo enters an error state.
+    pub fn transform(&mut self, m: &Affine) {
+        let mtx = cairo_matrix_t {
+            xx: m.xx, yx: m.yx,
+            xy: m.xy, yy: m.yy,
+            x0: m.x0, y0: m.y0,
+        };
+        unsafe { cairo_transform(self.ptr, &mtx) }
+    }
+
     pub fn user_to_device(&mut self, x: f64, y: f64) -> (f64, f64) {
         unsafe {
             let (mut x_, mut y_) = (x, y);
@@ -273,6 +868,22 @@ impl Cairo {
             (x_, y_)
         }
     }
+
+    pub fn device_to_user(&mut self, x: f64, y: f64) -> (f64, f64) {
+        unsafe {
+            let (mut x_, mut y_) = (x, y);
+            cairo_device_to_user(self.ptr, &mut x_, &mut y_);
+            (x_, y_)
+        }
+    }
+
+    pub fn device_to_user_distance(&mut self, dx: f64, dy: f64) -> (f64, f64) {
+        unsafe {
+            let (mut x_, mut y_) = (dx, dy);
+            cairo_device_to_user_distance(self.ptr, &mut x_, &mut y_);
+            (x_, y_)
+        }
+    }
 }
 
 impl Drop for Cairo {
@@ -292,6 +903,33 @@ impl FontFace {
     }
 }
 
+impl Pattern {
+    /// Create a gradient along the line from `(x0, y0)` to `(x1, y1)`.
+    pub fn new_linear(x0: f64, y0: f64, x1: f64, y1: f64) -> Pattern {
+        Pattern {
+            ptr: unsafe { cairo_pattern_create_linear(x0, y0, x1, y1) },
+        }
+    }
+
+    /// Create a gradient from the center `(cx, cy)` to the circle of `radius` around it.
+    pub fn new_radial(cx: f64, cy: f64, radius: f64) -> Pattern {
+        Pattern {
+            ptr: unsafe { cairo_pattern_create_radial(cx, cy, 0.0, cx, cy, radius) },
+        }
+    }
+
+    /// Add the color at `offset`, between 0 at the start and 1 at the end.
+    pub fn add_color_stop_rgba(&mut self, offset: f64, r: f64, g: f64, b: f64, a: f64) {
+        unsafe { cairo_pattern_add_color_stop_rgba(self.ptr, offset, r, g, b, a) }
+    }
+}
+
+impl Drop for Pattern {
+    fn drop(&mut self) {
+        unsafe { cairo_pattern_destroy(self.ptr) }
+    }
+}
+
 impl Drop for FontFace {
     fn drop(&mut self) {
         // Because the struct also contains the FreeType font face, it
@@ -301,18 +939,70 @@ impl Drop for FontFace {
     }
 }
 
-impl Glyph {
-    pub fn new(index: u64, x: f64, y: f64) -> Glyph {
-        let cg = cairo_glyph_t {
-            index: index as c_ulong,
-            x: x,
-            y: y,
-        };
-        Glyph(cg)
-    }
+#[test]
+fn show_glyphs_handles_more_glyphs_than_fit_in_one_call() {
+    use std::env;
+    use std::fs;
+
+    let fname = env::temp_dir().join("pris-show-glyphs-test.pdf");
+    let surface = Surface::new_pdf(&fname, 1920.0, 1080.0).unwrap();
+    let mut cr = Cairo::new(surface).unwrap();
+
+    // A long code listing, several times the batch size, that does not divide
+    // evenly into batches.
+    let n = 5 * MAX_GLYPHS_PER_CALL + 17;
+    let glyphs: Vec<Glyph> = (0..n)
+        .map(|i| Glyph::new(36 + (i % 26) as u64, (i % 120) as f64 * 16.0, (i / 120) as f64 * 24.0))
+        .collect();
+    cr.set_font_size(20.0);
+    cr.show_glyphs(&glyphs);
+    cr.show_page();
+
+    assert!(cr.status().is_ok());
+    drop(cr);
+    let _ = fs::remove_file(&fname);
+}
 
-    /// Make a copy of the glyph, offset by the specified amount.
-    pub fn offset(&self, dx: f64, dy: f64) -> Glyph {
-        Glyph::new(self.0.index as u64, self.0.x + dx, self.0.y + dy)
-    }
+#[test]
+fn show_text_glyphs_handles_more_glyphs_than_fit_in_one_call() {
+    use std::env;
+    use std::fs;
+
+    let fname = env::temp_dir().join("pris-show-text-glyphs-test.pdf");
+    let surface = Surface::new_pdf(&fname, 1920.0, 1080.0).unwrap();
+    let mut cr = Cairo::new(surface).unwrap();
+
+    // Clusters of two bytes and one glyph, so a batch cannot end exactly at
+    // the batch size if clusters were split.
+    let n = 3 * MAX_GLYPHS_PER_CALL + 5;
+    let text: String = (0..n).map(|_| "\u{e9}").collect();
+    let glyphs: Vec<Glyph> = (0..n)
+        .map(|i| Glyph::new(72, (i % 120) as f64 * 16.0, (i / 120) as f64 * 24.0))
+        .collect();
+    let clusters: Vec<TextCluster> = (0..n).map(|_| TextCluster::new(2, 1)).collect();
+    cr.set_font_size(20.0);
+    cr.show_text_glyphs(&text, &glyphs, &clusters);
+    cr.show_page();
+
+    assert!(cr.status().is_ok());
+    drop(cr);
+    let _ = fs::remove_file(&fname);
+}
+
+#[test]
+fn stream_surfaces_write_to_any_writer() {
+    let buffer = SharedBuffer::new();
+    let surface = Surface::new_pdf_for_stream(buffer.clone(), 100.0, 100.0).unwrap();
+    let mut cr = Cairo::new(surface.clone()).unwrap();
+    cr.rectangle(10.0, 10.0, 80.0, 80.0);
+    cr.fill();
+    cr.show_page();
+    drop(cr);
+    surface.finish().unwrap();
+    assert!(buffer.take().starts_with(b"%PDF-"));
+
+    let image = Surface::new_image(16.0, 16.0).unwrap();
+    let mut png_bytes = Vec::new();
+    image.write_to_png_stream(&mut png_bytes).unwrap();
+    assert!(png_bytes.starts_with(b"\x89PNG"));
 }
@@ -0,0 +1,51 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module lets the host abort evaluation and rendering.
+//!
+//! An editor that previews a document starts a new render whenever the user
+//! types. A render for an outdated version of the document can be abandoned,
+//! by setting the flag that was passed to the interpreter or renderer.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use error::{Error, Result};
+
+/// A flag that the host sets to cancel work, or no flag at all.
+#[derive(Clone, Default)]
+pub struct CancelToken(Option<Arc<AtomicBool>>);
+
+impl CancelToken {
+    /// Return a token that cancels once `flag` is set to true.
+    pub fn new(flag: Arc<AtomicBool>) -> CancelToken {
+        CancelToken(Some(flag))
+    }
+
+    /// Return a token that never cancels.
+    pub fn none() -> CancelToken {
+        CancelToken(None)
+    }
+
+    /// Return `Error::Cancelled` if the host requested cancellation.
+    pub fn check(&self) -> Result<()> {
+        match self.0 {
+            Some(ref flag) if flag.load(Ordering::Relaxed) => Err(Error::Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn cancel_token_checks_flag() {
+    let flag = Arc::new(AtomicBool::new(false));
+    let token = CancelToken::new(flag.clone());
+    assert!(token.check().is_ok());
+    flag.store(true, Ordering::Relaxed);
+    assert!(token.check().is_err());
+    assert!(CancelToken::none().check().is_ok());
+}
@@ -0,0 +1,200 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module turns the source of a document into slides to render.
+//!
+//! `compile` does all steps at once: it parses the document, loads the
+//! modules that it imports, and evaluates it. The command-line program parses
+//! and evaluates separately, to lint the document and to check its slides, but
+//! it evaluates with `evaluate` and collects the slides with `Slides::compile`
+//! just like `compile` does.
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use ast;
+use driver::{CompiledDoc, PageSetup, Slide};
+use error::Result;
+use external::Tools;
+use interpreter::StmtInterpreter;
+use lexer;
+use limits::Limits;
+use logger::{Level, Logger, StdoutLogger};
+use module::{self, Modules, SearchPath};
+use parser;
+use profile::Profile;
+use runtime::{FontMap, Frame, Val};
+use todo::Todos;
+use toc::{self, Toc};
+
+/// The settings that evaluation of a document depends on.
+pub struct Options {
+    /// Variables to define as strings before the document is evaluated.
+    pub defines: Vec<(String, String)>,
+    pub allow_getenv: bool,
+    pub tools: Rc<Tools>,
+    /// Whether to record a profile of the time spent in evaluation.
+    pub profile: bool,
+    pub limits: Limits,
+    pub logger: Rc<Logger>,
+    /// Where to look for the modules that the document imports.
+    pub search_path: SearchPath,
+    /// The directory that the pdf is written to, if it is written to a file.
+    pub output_dir: Option<PathBuf>,
+}
+
+impl Options {
+    /// Return options without tools, limits, or search directories.
+    pub fn new() -> Options {
+        Options {
+            defines: Vec::new(),
+            allow_getenv: false,
+            tools: Rc::new(Tools::none()),
+            profile: false,
+            limits: Limits::default(),
+            logger: Rc::new(StdoutLogger),
+            search_path: SearchPath::new(Vec::new()),
+            output_dir: None,
+        }
+    }
+}
+
+/// The slides of an evaluated document, with the time it took to evaluate each.
+pub struct Slides<'a> {
+    pub frames: Vec<Rc<Frame<'a>>>,
+    pub eval_times: Vec<Duration>,
+    pub profile: Rc<Profile>,
+    pub todos: Rc<Todos>,
+}
+
+impl<'a> Slides<'a> {
+    /// Collect the slides and the table of contents for rendering.
+    pub fn compile(&self, page: PageSetup) -> Result<CompiledDoc> {
+        let slides = self.frames
+            .iter()
+            .map(|frame| Slide::from_frame(frame))
+            .collect::<Result<Vec<_>>>()?;
+        let mut doc = CompiledDoc::new(slides, page);
+        doc.toc = toc::collect(&self.frames);
+        Ok(doc)
+    }
+}
+
+/// Describe a top-level statement for the profile, `None` if it does nothing.
+fn statement_label(statement: &ast::Stmt, slide: usize) -> Option<String> {
+    match *statement {
+        ast::Stmt::Assign(ref assign) => Some(format!("assignment to '{}'", assign.0)),
+        ast::Stmt::Update(ref update) => Some(format!("update of '{}'", update.0)),
+        ast::Stmt::Block(..) => Some(format!("slide {}", slide)),
+        ast::Stmt::Put(..) => Some(String::from("put")),
+        ast::Stmt::Repeat(..) => Some(String::from("repeat")),
+        ast::Stmt::Section(ref section) => Some(format!("section '{}'", section.0)),
+        _ => None,
+    }
+}
+
+/// Evaluate the document once, with the table of contents `toc`.
+fn evaluate_pass<'a>(fm: &mut FontMap,
+                     doc: &'a ast::Document<'a>,
+                     modules: &'a Modules<'a>,
+                     document_dir: Option<&Path>,
+                     options: &Options,
+                     toc: Rc<Toc>)
+                     -> Result<(Slides<'a>, PageSetup)> {
+    let mut frames = Vec::new();
+    let mut eval_times = Vec::new();
+    let profile = Rc::new(if options.profile { Profile::new() } else { Profile::disabled() });
+    let todos = Rc::new(Todos::new());
+    // Statements between slides count towards the time of the next slide.
+    let mut slide_start = Instant::now();
+    let mut stmt_interpreter = StmtInterpreter::new(fm);
+    stmt_interpreter.set_toc(toc);
+    stmt_interpreter.set_limits(options.limits.clone());
+    stmt_interpreter.set_logger(options.logger.clone());
+    stmt_interpreter.set_profile(profile.clone());
+    stmt_interpreter.set_todos(todos.clone());
+    stmt_interpreter.set_section_count(doc.count_sections());
+    stmt_interpreter.set_modules(modules);
+    stmt_interpreter.set_tools(options.tools.clone());
+    if let Some(document_dir) = document_dir {
+        stmt_interpreter.set_link_dirs(document_dir, options.output_dir.as_ref().map(|dir| dir.as_path()));
+    }
+    if options.allow_getenv {
+        stmt_interpreter.allow_getenv();
+    }
+    for &(ref key, ref value) in &options.defines {
+        stmt_interpreter.define(key, Val::Str(value.clone()));
+    }
+    for statement in &doc.0 {
+        let statement_start = Instant::now();
+        let result = stmt_interpreter.eval_statement(statement)?;
+        if let Some(label) = statement_label(statement, frames.len() + 1) {
+            profile.record_statement(label, statement_start.elapsed());
+        }
+        if let Some(frame) = result {
+            frames.push(frame);
+            eval_times.push(slide_start.elapsed());
+            slide_start = Instant::now();
+        }
+    }
+
+    let slides = Slides {
+        frames: frames,
+        eval_times: eval_times,
+        profile: profile,
+        todos: todos,
+    };
+
+    let page = PageSetup::from_env(stmt_interpreter.env())?;
+    Ok((slides, page))
+}
+
+/// Evaluate the document, twice if it contains a table of contents.
+///
+/// Relative links resolve against `document_dir`, the directory of the
+/// document, which is `None` when the document is not a file.
+pub fn evaluate<'a>(fm: &mut FontMap,
+                    doc: &'a ast::Document<'a>,
+                    modules: &'a Modules<'a>,
+                    document_dir: Option<&Path>,
+                    options: &Options)
+                    -> Result<(Slides<'a>, PageSetup)> {
+    // A table of contents lists slides that come after it, so if the document
+    // uses one, evaluate it a second time with the entries of the first pass.
+    let toc = Rc::new(Toc::new(Vec::new()));
+    let (slides, page) = evaluate_pass(fm, doc, modules, document_dir, options, toc.clone())?;
+    if !toc.is_used() {
+        return Ok((slides, page))
+    }
+
+    let toc = Rc::new(Toc::new(toc::collect(&slides.frames)));
+    let (slides_2, page_2) = evaluate_pass(fm, doc, modules, document_dir, options, toc.clone())?;
+    if toc.differs_from(&toc::collect(&slides_2.frames)) {
+        options.logger.log(Level::Warning, "the table of contents changed in the second pass, \
+                                            so it may be inaccurate.");
+    }
+    Ok((slides_2, page_2))
+}
+
+/// Parse and evaluate the document in `source`, and collect its slides.
+///
+/// Imports and relative links resolve against `document_dir`, the directory
+/// of the document, or against the working directory when it is `None`.
+pub fn compile(fm: &mut FontMap,
+               source: &[u8],
+               document_dir: Option<&Path>,
+               options: &Options)
+               -> Result<CompiledDoc> {
+    let tokens = lexer::lex(source)?;
+    let doc = parser::parse(&tokens)?;
+    let base_dir = document_dir.unwrap_or(Path::new(""));
+    let sources = module::load_sources(&doc, base_dir, &options.search_path)?;
+    let modules = Modules::parse(&sources)?;
+    let (slides, page) = evaluate(fm, &doc, &modules, document_dir, options)?;
+    slides.compile(page)
+}
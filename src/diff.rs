@@ -0,0 +1,142 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module compares two versions of a document, to review changes to it.
+//!
+//! Slides are first aligned by their content hash, so inserting a slide does
+//! not make all slides after it appear changed. Slides that remain unpaired
+//! between two unchanged slides are compared pixel by pixel.
+
+use std::cmp;
+
+/// How a slide in the old document relates to a slide in the new document.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Change {
+    /// The slide at index `.0` in the old document is at index `.1` in the new one.
+    Same(usize, usize),
+    /// The slide at index `.0` in the old document became the slide at `.1`.
+    Changed(usize, usize),
+    /// The slide at this index in the old document has no counterpart.
+    Removed(usize),
+    /// The slide at this index in the new document has no counterpart.
+    Added(usize),
+}
+
+/// Pair up the slides that are between two common slides.
+///
+/// Slides are paired in order; the remaining ones were removed or added.
+fn pair_up(changes: &mut Vec<Change>, removed: &mut Vec<usize>, added: &mut Vec<usize>) {
+    for (&i, &j) in removed.iter().zip(added.iter()) {
+        changes.push(Change::Changed(i, j));
+    }
+    let num_pairs = cmp::min(removed.len(), added.len());
+    changes.extend(removed[num_pairs..].iter().map(|&i| Change::Removed(i)));
+    changes.extend(added[num_pairs..].iter().map(|&j| Change::Added(j)));
+    removed.clear();
+    added.clear();
+}
+
+/// Align the slides of two documents by their content hashes.
+///
+/// Slides in the longest common subsequence of hashes are the same. Changes
+/// are returned in order of the slides.
+pub fn align(old: &[u64], new: &[u64]) -> Vec<Change> {
+    let (n, m) = (old.len(), new.len());
+
+    // lengths[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..].
+    let mut lengths = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lengths[i + 1][j], lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            pair_up(&mut changes, &mut removed, &mut added);
+            changes.push(Change::Same(i, j));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            removed.push(i);
+            i += 1;
+        } else {
+            added.push(j);
+            j += 1;
+        }
+    }
+    pair_up(&mut changes, &mut removed, &mut added);
+
+    changes
+}
+
+/// Return the number of pixels that differ between two images.
+///
+/// The images are in Cairo's ARGB32 format, and must have the same size.
+pub fn count_changed_pixels(old: &[u8], new: &[u8]) -> usize {
+    assert_eq!(old.len(), new.len());
+    old.chunks(4).zip(new.chunks(4)).filter(|&(a, b)| a != b).count()
+}
+
+/// Mark the pixels of `new` that differ from `old`, to show what changed.
+///
+/// Changed pixels become opaque magenta, and unchanged pixels are faded
+/// towards white, so the changes stand out.
+pub fn highlight_changes(old: &[u8], new: &mut [u8]) {
+    assert_eq!(old.len(), new.len());
+    // Byte offsets of the red, green, blue, and alpha channels.
+    let (r, g, b, a) = if cfg!(target_endian = "little") { (2, 1, 0, 3) } else { (1, 2, 3, 0) };
+    for (before, after) in old.chunks(4).zip(new.chunks_mut(4)) {
+        if before != &after[..] {
+            after[r] = 255;
+            after[g] = 0;
+            after[b] = 255;
+            after[a] = 255;
+        } else {
+            // Alpha is premultiplied, so fading every channel, alpha
+            // included, towards 255 blends the pixel with opaque white.
+            for c in after.iter_mut() {
+                *c = ((*c as u32 + 3 * 255) / 4) as u8;
+            }
+        }
+    }
+}
+
+#[test]
+fn align_matches_unchanged_slides_around_insertions() {
+    let changes = align(&[1, 2, 3, 4], &[1, 9, 2, 3, 8]);
+    assert_eq!(changes, vec![
+        Change::Same(0, 0),
+        Change::Added(1),
+        Change::Same(1, 2),
+        Change::Same(2, 3),
+        Change::Changed(3, 4),
+    ]);
+    let changes = align(&[1, 2, 3], &[1, 3]);
+    assert_eq!(changes, vec![Change::Same(0, 0), Change::Removed(1), Change::Same(2, 1)]);
+}
+
+#[test]
+fn highlight_changes_marks_changed_pixels() {
+    let old = [0, 0, 0, 255, 10, 20, 30, 255];
+    let mut new = [0, 0, 0, 255, 11, 20, 30, 255];
+    assert_eq!(count_changed_pixels(&old, &new), 1);
+    highlight_changes(&old, &mut new);
+    // The unchanged black pixel is faded to gray.
+    assert_eq!(&new[..4], &[191, 191, 191, 255]);
+    let (r, g, b, a) = if cfg!(target_endian = "little") { (2, 1, 0, 3) } else { (1, 2, 3, 0) };
+    assert_eq!((new[4 + r], new[4 + g], new[4 + b], new[4 + a]), (255, 0, 255, 255));
+}
@@ -5,17 +5,907 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
+use std::cmp;
+use std::collections::HashMap;
+use std::mem;
+use std::path::PathBuf;
+use std::slice;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
 use ast::Idents;
-use cairo::{Cairo, FontFace, Surface};
-use elements::{Color, Element, PlacedElement, PolygonKind, Vec2};
-use runtime::{FontMap, Frame};
+use cancel::CancelToken;
+use cairo::{Cairo, FontFace, FontOptions, Pattern, SharedBuffer, Surface};
+use elements::{Affine, Color, ContentHasher, Element, GradientKind, Paint, PlacedElement, PolygonKind, Symbol, Text, Vec2};
+#[cfg(feature = "hyperlink")]
+use elements::LinkTarget;
+#[cfg(test)]
+use elements::{FillPolygon, Glyph};
+use error::{Error, Result};
+use logger::Logger;
+#[cfg(not(feature = "hyperlink"))]
+use logger::Level;
+use names;
+use pdf::{self, PageBoxes};
+#[cfg(feature = "svg")]
+use rsvg::Svg;
+use runtime::{Env, FontMap, Frame};
+use simulation::Simulation;
+use toc::TocEntry;
+
+/// Crop marks are conventionally hairlines of a quarter point wide.
+const CROP_MARK_WIDTH: f64 = 0.25;
+
+/// The layout of a printed page around the canvas.
+#[derive(Clone, PartialEq)]
+pub struct PageSetup {
+    pub canvas_size: Vec2,
+    /// Distance by which backgrounds extend beyond the canvas, to be cut off.
+    pub bleed: f64,
+    /// Length of the crop marks outside of the bleed, or 0 for no crop marks.
+    pub crop_marks: f64,
+}
+
+impl PageSetup {
+    /// Read the page setup from the top-level variables.
+    pub fn from_env(env: &Env) -> Result<PageSetup> {
+        let page = PageSetup {
+            canvas_size: env.lookup_coord_num(&Idents(vec![names::canvas_size]))?,
+            bleed: env.lookup_len(&Idents(vec![names::bleed]))?,
+            crop_marks: env.lookup_len(&Idents(vec![names::crop_marks]))?,
+        };
+        Ok(page)
+    }
+
+    /// Return the distance between the edge of the canvas and of the page.
+    pub fn margin(&self) -> f64 {
+        self.bleed + self.crop_marks
+    }
+
+    pub fn page_size(&self) -> Vec2 {
+        let margin = self.margin();
+        self.canvas_size + Vec2::new(2.0 * margin, 2.0 * margin)
+    }
+
+    /// Return the trim box and bleed box in PDF coordinates.
+    pub fn page_boxes(&self) -> PageBoxes {
+        // The margin is the same on every side, so it does not matter that the
+        // y-axis in PDF points up, whereas it points down in Pris.
+        let (m, b) = (self.margin(), self.bleed);
+        let (w, h) = (self.canvas_size.x, self.canvas_size.y);
+        PageBoxes {
+            trim: [m, m, m + w, m + h],
+            bleed: [m - b, m - b, m + w + b, m + h + b],
+        }
+    }
+}
+
+/// The content of a slide that is needed to render it.
+///
+/// A `Frame` references the environment it was evaluated in, which holds
+/// values that must stay on the thread that evaluated them. A slide owns only
+/// its elements, so it can be sent to another thread for rendering.
+pub struct Slide {
+    background_color: Option<Color>,
+    /// The steps of the `background` frame, drawn beneath the content.
+    background: Vec<Vec<PlacedElement>>,
+    steps: Vec<Vec<PlacedElement>>,
+    notes: String,
+}
+
+fn steps_of<'a>(frame: &Frame<'a>) -> Vec<Vec<PlacedElement>> {
+    frame.get_subframes().iter().map(|sf| sf.get_elements().to_vec()).collect()
+}
+
+/// Approximate an affine transform by a translation and a uniform scale.
+///
+/// Checks that only track an offset and a scale use this for transformed
+/// elements. Rotation and skew are ignored, and the scale preserves area.
+fn approximate_affine(m: &Affine) -> (Vec2, f64) {
+    (Vec2::new(m.x0, m.y0), m.determinant().abs().sqrt())
+}
+
+impl Slide {
+    /// Collect the content of the slide, and the settings that it is drawn with.
+    ///
+    /// Returns a type error when `background` is set to something other than
+    /// a frame.
+    pub fn from_frame<'a>(frame: &Frame<'a>) -> Result<Slide> {
+        // TODO: Ensure that writing to background_color only accepts a color
+        // value, so a lookup failure here is never a type error.
+        let var_bgcolor = Idents(vec![names::background_color]);
+        let var_background = Idents(vec![names::background]);
+        let var_notes = Idents(vec![names::notes]);
+        let env = frame.get_env();
+        let slide = Slide {
+            background_color: env.lookup_color(&var_bgcolor).ok(),
+            background: env.try_lookup_frame(&var_background)?.map(|bg| steps_of(&bg)).unwrap_or_default(),
+            steps: steps_of(frame),
+            notes: env.lookup_str(&var_notes).unwrap_or_default(),
+        };
+        Ok(slide)
+    }
+
+    /// Return the number of steps, which is the number of pages it produces.
+    pub fn num_steps(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Return the speaker notes, or an empty string if the slide has none.
+    pub fn notes(&self) -> &str {
+        &self.notes
+    }
+
+    /// Return the elements of every step, in drawing order.
+    pub fn steps(&self) -> &[Vec<PlacedElement>] {
+        &self.steps
+    }
+
+    /// Return the elements of every step of the background frame.
+    pub fn background(&self) -> &[Vec<PlacedElement>] {
+        &self.background
+    }
+
+    /// Return the color that the page is filled with, if the slide sets one.
+    pub fn background_color(&self) -> Option<Color> {
+        self.background_color
+    }
+
+    /// Return the text of the text elements in the final step, in drawing order.
+    ///
+    /// This includes text in the background frame. Text of which the glyphs
+    /// could not be mapped back to characters is omitted.
+    pub fn texts(&self) -> Vec<&str> {
+        fn collect<'s>(elements: &'s [PlacedElement], texts: &mut Vec<&'s str>) {
+            for pe in elements {
+                match pe.element {
+                    Element::Text(ref text) if !text.text.is_empty() => texts.push(&text.text),
+                    Element::Clipped(ref inner, ..) => collect(inner, texts),
+                    Element::Group(ref inner) => collect(inner, texts),
+                    Element::Transformed(ref inner, _) => collect(inner, texts),
+                    Element::Affine(ref inner, _) => collect(inner, texts),
+                    Element::Opacity(ref inner, _) => collect(inner, texts),
+                    Element::Symbol(ref symbol) => collect(&symbol.elements, texts),
+                    _ => {}
+                }
+            }
+        }
+        let mut texts = Vec::new();
+        for steps in &[&self.background, &self.steps] {
+            if let Some(step) = steps.last() {
+                collect(step, &mut texts);
+            }
+        }
+        texts
+    }
+
+    /// Return the text of the text elements in the final step, in reading order.
+    ///
+    /// Text is ordered by the position of its first glyph, top to bottom, and
+    /// left to right for text on the same baseline. Text in the background
+    /// frame is ordered along with the content.
+    pub fn texts_in_reading_order(&self) -> Vec<&str> {
+        fn collect<'s>(elements: &'s [PlacedElement], offset: Vec2, scale: f64, texts: &mut Vec<(Vec2, &'s str)>) {
+            for pe in elements {
+                let position = offset + pe.position * scale;
+                match pe.element {
+                    Element::Text(ref text) if !text.text.is_empty() => {
+                        let start = text.glyphs.first().map_or(Vec2::zero(), |g| g.position());
+                        texts.push((position + start * scale, &text.text));
+                    }
+                    Element::Clipped(ref inner, ..) => collect(inner, position, scale, texts),
+                    Element::Group(ref inner) => collect(inner, position, scale, texts),
+                    Element::Transformed(ref inner, s) => collect(inner, position, scale * s, texts),
+                    Element::Affine(ref inner, ref m) => {
+                        let (offset, s) = approximate_affine(m);
+                        collect(inner, position + offset * scale, scale * s, texts)
+                    }
+                    Element::Opacity(ref inner, _) => collect(inner, position, scale, texts),
+                    Element::Symbol(ref symbol) => collect(&symbol.elements, position, scale, texts),
+                    _ => {}
+                }
+            }
+        }
+        let mut texts = Vec::new();
+        for steps in &[&self.background, &self.steps] {
+            if let Some(step) = steps.last() {
+                collect(step, Vec2::zero(), 1.0, &mut texts);
+            }
+        }
+        // The sort is stable, so text at the same position stays in drawing order.
+        texts.sort_by(|&(a, _), &(b, _)| {
+            (a.y, a.x).partial_cmp(&(b.y, b.x)).unwrap_or(cmp::Ordering::Equal)
+        });
+        texts.into_iter().map(|(_, text)| text).collect()
+    }
+
+    /// Return a hash of everything that affects how the slide is rendered.
+    ///
+    /// Slides with the same hash render the same, so a program that renders
+    /// a document repeatedly, such as a preview server, can skip slides that
+    /// did not change. The hash is the same across runs and platforms, so it
+    /// can be stored, but it may change with a new version of Pris. Images are
+    /// hashed by their path, not by their contents, so a cache must drop
+    /// slides when their images change, as `SlideCache::forget_images` does.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = ContentHasher::new();
+        match self.background_color {
+            Some(color) => { hasher.write(b"c"); hasher.write_color(color); }
+            None => hasher.write(b"-"),
+        }
+        for steps in &[&self.background, &self.steps] {
+            hasher.write_u64(steps.len() as u64);
+            for step in steps.iter() {
+                hasher.write_elements(step);
+            }
+        }
+        hasher.finish()
+    }
+}
+
+/// The fonts and images that slides refer to.
+#[derive(Default)]
+pub struct Resources {
+    /// Family and style of the fonts, in order of first use.
+    pub fonts: Vec<(String, String)>,
+    /// Paths of svg and png images, in order of first use.
+    pub images: Vec<PathBuf>,
+}
+
+impl Resources {
+    pub fn new() -> Resources {
+        Resources::default()
+    }
+
+    /// Add the resources that the slide and its background refer to.
+    pub fn collect(&mut self, slide: &Slide) {
+        for step in slide.background.iter().chain(slide.steps.iter()) {
+            self.collect_elements(step);
+        }
+    }
+
+    fn collect_elements(&mut self, elements: &[PlacedElement]) {
+        for pe in elements {
+            match pe.element {
+                Element::Text(ref text) => {
+                    let font = (text.font_family.clone(), text.font_style.clone());
+                    if !self.fonts.contains(&font) {
+                        self.fonts.push(font);
+                    }
+                }
+                Element::Png(ref path) | Element::Svg(ref path) => {
+                    if !self.images.contains(path) {
+                        self.images.push(path.clone());
+                    }
+                }
+                Element::Clipped(ref inner, ..) => self.collect_elements(inner),
+                Element::Group(ref inner) => self.collect_elements(inner),
+                Element::Transformed(ref inner, _) => self.collect_elements(inner),
+                Element::Affine(ref inner, _) => self.collect_elements(inner),
+                Element::Opacity(ref inner, _) => self.collect_elements(inner),
+                Element::Symbol(ref symbol) => self.collect_elements(&symbol.elements),
+                Element::FillPolygon(..) => {}
+                Element::StrokePolygon(..) => {}
+                Element::Hyperlink(..) => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn resources_collect_finds_nested_images_once() {
+    let svg = PlacedElement {
+        position: Vec2::zero(),
+        element: Element::Svg(PathBuf::from("logo.svg")),
+    };
+    let png = PlacedElement {
+        position: Vec2::zero(),
+        element: Element::Png(PathBuf::from("photo.png")),
+    };
+    let group = PlacedElement {
+        position: Vec2::zero(),
+        element: Element::Group(Arc::new(vec![svg.clone(), png])),
+    };
+    let slide = Slide {
+        background_color: None,
+        background: vec![vec![svg]],
+        steps: vec![vec![group.clone()], vec![group]],
+        notes: String::new(),
+    };
+    let mut resources = Resources::new();
+    resources.collect(&slide);
+    assert_eq!(resources.images, vec![PathBuf::from("logo.svg"), PathBuf::from("photo.png")]);
+    assert!(resources.fonts.is_empty());
+}
+
+/// The text styles and colors on a slide, to check a deck for consistency.
+#[derive(Default)]
+pub struct Styles {
+    /// Family, style, and size of text, in order of first use. The size is
+    /// the size on the canvas, after scaling.
+    pub fonts: Vec<(String, String, f64)>,
+    /// Colors of the background, text, and shapes, in order of first use.
+    /// Colors are rounded to bytes, the precision that they are written with,
+    /// and the last byte is the alpha.
+    pub colors: Vec<[u8; 4]>,
+}
+
+impl Styles {
+    fn add_color(&mut self, color: Color) {
+        let byte = |x: f64| (x.max(0.0).min(1.0) * 255.0).round() as u8;
+        let rgba = [byte(color.r), byte(color.g), byte(color.b), byte(color.a)];
+        if !self.colors.contains(&rgba) {
+            self.colors.push(rgba);
+        }
+    }
+
+    fn collect_elements(&mut self, elements: &[PlacedElement], scale: f64) {
+        for pe in elements {
+            match pe.element {
+                Element::Text(ref text) => {
+                    let font = (text.font_family.clone(), text.font_style.clone(), text.font_size * scale);
+                    if !self.fonts.contains(&font) {
+                        self.fonts.push(font);
+                    }
+                    self.add_color(text.color);
+                }
+                Element::FillPolygon(ref polygon) => match polygon.paint {
+                    Paint::Solid(color) => self.add_color(color),
+                    Paint::Gradient(gradient) => {
+                        self.add_color(gradient.from);
+                        self.add_color(gradient.to);
+                    }
+                },
+                Element::StrokePolygon(ref polygon) => self.add_color(polygon.color),
+                Element::Clipped(ref inner, ..) => self.collect_elements(inner, scale),
+                Element::Group(ref inner) => self.collect_elements(inner, scale),
+                Element::Transformed(ref inner, s) => self.collect_elements(inner, scale * s),
+                Element::Affine(ref inner, ref m) => {
+                    self.collect_elements(inner, scale * approximate_affine(m).1)
+                }
+                Element::Opacity(ref inner, _) => self.collect_elements(inner, scale),
+                Element::Symbol(ref symbol) => self.collect_elements(&symbol.elements, scale),
+                Element::Png(..) | Element::Svg(..) => {}
+                Element::Hyperlink(..) => {}
+            }
+        }
+    }
+}
+
+impl Slide {
+    /// Return the fonts and colors that the final step of the slide uses.
+    ///
+    /// The colors inside images are not included.
+    pub fn styles(&self) -> Styles {
+        let mut styles = Styles::default();
+        if let Some(color) = self.background_color {
+            styles.add_color(color);
+        }
+        for steps in &[&self.background, &self.steps] {
+            if let Some(step) = steps.last() {
+                styles.collect_elements(step, 1.0);
+            }
+        }
+        styles
+    }
+}
+
+#[test]
+fn slide_styles_finds_scaled_fonts_and_colors_once() {
+    let text = |size: f64, gray: f64| PlacedElement {
+        position: Vec2::zero(),
+        element: Element::Text(Text {
+            color: Color::new(gray, gray, gray),
+            font_family: "Cantarell".into(),
+            font_style: "Regular".into(),
+            font_size: size,
+            glyphs: Vec::new(),
+            text: "x".into(),
+            clusters: Vec::new(),
+        }),
+    };
+    let scaled = PlacedElement {
+        position: Vec2::zero(),
+        element: Element::Transformed(Arc::new(vec![text(20.0, 0.5)]), 2.0),
+    };
+    let slide = Slide {
+        background_color: Some(Color::new(1.0, 1.0, 1.0)),
+        background: Vec::new(),
+        steps: vec![vec![text(40.0, 0.2)], vec![text(40.0, 0.2), scaled, text(20.0, 0.2)]],
+        notes: String::new(),
+    };
+    let styles = slide.styles();
+    let fonts: Vec<f64> = styles.fonts.iter().map(|f| f.2).collect();
+    assert_eq!(fonts, vec![40.0, 20.0]);
+    assert_eq!(styles.colors, vec![[255, 255, 255, 255], [51, 51, 51, 255], [128, 128, 128, 255]]);
+}
+
+/// Text that is hard to read against the color beneath it.
+pub struct LowContrast {
+    /// The text, empty if it is unknown.
+    pub text: String,
+    /// The WCAG 2.0 contrast ratio between the text and its background.
+    pub ratio: f64,
+    /// The minimum ratio for text of this size.
+    pub required: f64,
+}
+
+/// A part of the slide, and its color if it is filled with a single color.
+struct Region {
+    min: Vec2,
+    max: Vec2,
+    color: Option<Color>,
+}
+
+/// Finds text with low contrast, by tracking what is drawn beneath it.
+struct ContrastChecker {
+    /// Regions in the order they are drawn, the last one is on top.
+    regions: Vec<Region>,
+    /// The font size from which text counts as large.
+    large_size: f64,
+    /// The opacity of the elements being visited, less than 1 inside of
+    /// translucent groups.
+    opacity: f64,
+    problems: Vec<LowContrast>,
+}
+
+impl ContrastChecker {
+    fn visit(&mut self, elements: &[PlacedElement], offset: Vec2, scale: f64) {
+        for pe in elements {
+            let position = offset + pe.position * scale;
+            match pe.element {
+                Element::FillPolygon(ref polygon) => {
+                    let (min, max) = bounds(polygon.vertices.iter().map(|&v| position + v * scale));
+                    let is_rectangle = polygon.kind == PolygonKind::Lines && is_rectangle(&polygon.vertices);
+                    // A translucent rectangle mixes with the color beneath.
+                    let color = match polygon.paint {
+                        Paint::Solid(color) if is_rectangle => {
+                            let color = color.fade(self.opacity);
+                            if color.is_opaque() {
+                                Some(color)
+                            } else {
+                                self.color_beneath(min, max).map(|beneath| color.over(beneath))
+                            }
+                        }
+                        _ => None,
+                    };
+                    self.regions.push(Region {
+                        min: min,
+                        max: max,
+                        color: color,
+                    });
+                }
+                Element::Png(..) | Element::Svg(..) => {
+                    // The size of an image is not known here, so anything can
+                    // be beneath text that is drawn after it.
+                    let inf = ::std::f64::INFINITY;
+                    self.regions.push(Region {
+                        min: Vec2::new(-inf, -inf),
+                        max: Vec2::new(inf, inf),
+                        color: None,
+                    });
+                }
+                Element::Text(ref text) => self.check_text(text, position, scale),
+                Element::Clipped(ref inner, ..) => self.visit(inner, position, scale),
+                Element::Group(ref inner) => self.visit(inner, position, scale),
+                Element::Transformed(ref inner, s) => self.visit(inner, position, scale * s),
+                Element::Affine(ref inner, ref m) => {
+                    let (offset, s) = approximate_affine(m);
+                    self.visit(inner, position + offset * scale, scale * s)
+                }
+                Element::Opacity(ref inner, opacity) => {
+                    let outer_opacity = self.opacity;
+                    self.opacity *= opacity;
+                    self.visit(inner, position, scale);
+                    self.opacity = outer_opacity;
+                }
+                Element::Symbol(ref symbol) => self.visit(&symbol.elements, position, scale),
+                Element::StrokePolygon(..) => {}
+                Element::Hyperlink(..) => {}
+            }
+        }
+    }
+
+    fn check_text(&mut self, text: &Text, position: Vec2, scale: f64) {
+        if text.glyphs.is_empty() {
+            return
+        }
+        let font_size = text.font_size * scale;
+        let (origin_min, origin_max) = bounds(text.glyphs.iter().map(|g| position + g.position() * scale));
+        // Glyphs extend above their origin on the baseline, roughly by the
+        // cap height of the font, and the last glyph extends to the right.
+        let min = Vec2::new(origin_min.x, origin_min.y - 0.7 * font_size);
+        let max = Vec2::new(origin_max.x + 0.5 * font_size, origin_max.y);
 
-fn draw_background(cr: &mut Cairo, canvas_size: Vec2, color: Color) {
-    cr.rectangle(0.0, 0.0, canvas_size.x, canvas_size.y);
-    cr.set_source_rgb(color.r, color.g, color.b);
+        if let Some(background) = self.color_beneath(min, max) {
+            // Translucent text is lighter or darker than its color on its own.
+            let color = text.color.fade(self.opacity).over(background);
+            let ratio = color.contrast_ratio(background);
+            let required = if font_size >= self.large_size { 3.0 } else { 4.5 };
+            if ratio < required {
+                self.problems.push(LowContrast {
+                    text: text.text.clone(),
+                    ratio: ratio,
+                    required: required,
+                });
+            }
+        }
+    }
+
+    /// Return the color beneath the rectangle from `min` to `max`, if it is a
+    /// single known color.
+    fn color_beneath(&self, min: Vec2, max: Vec2) -> Option<Color> {
+        // The background is the topmost region that the rectangle overlaps.
+        // If it does not cover the rectangle entirely, the background is mixed.
+        let region = self.regions.iter().rev().find(|r| {
+            min.x < r.max.x && max.x > r.min.x && min.y < r.max.y && max.y > r.min.y
+        });
+        match region {
+            Some(r) if r.min.x <= min.x && r.max.x >= max.x && r.min.y <= min.y && r.max.y >= max.y => r.color,
+            _ => None,
+        }
+    }
+}
+
+/// Return the top left and bottom right corner of the bounding box of the points.
+fn bounds<I: Iterator<Item = Vec2>>(points: I) -> (Vec2, Vec2) {
+    let inf = ::std::f64::INFINITY;
+    let mut min = Vec2::new(inf, inf);
+    let mut max = Vec2::new(-inf, -inf);
+    for p in points {
+        min = Vec2::new(min.x.min(p.x), min.y.min(p.y));
+        max = Vec2::new(max.x.max(p.x), max.y.max(p.y));
+    }
+    (min, max)
+}
+
+/// Return whether the polygon is a rectangle with horizontal and vertical sides.
+fn is_rectangle(vertices: &[Vec2]) -> bool {
+    vertices.len() == 4 && (0..4).all(|i| {
+        let (a, b) = (vertices[i], vertices[(i + 1) % 4]);
+        a.x == b.x || a.y == b.y
+    })
+}
+
+impl Slide {
+    /// Find text that has too little contrast with the color beneath it.
+    ///
+    /// Only the final step is checked. The thresholds are those of WCAG 2.0
+    /// level AA: 4.5:1 for normal text, and 3:1 for text of 24pt and larger.
+    /// The background of text is known when it is a filled rectangle or the
+    /// background color. Text on images or other shapes is not checked.
+    pub fn low_contrast_text(&self, canvas_size: Vec2) -> Vec<LowContrast> {
+        let inf = ::std::f64::INFINITY;
+        // Without background color, the page is white.
+        let white = Color::new(1.0, 1.0, 1.0);
+        let background = self.background_color.map_or(white, |color| color.over(white));
+        let mut checker = ContrastChecker {
+            regions: vec![Region {
+                min: Vec2::new(-inf, -inf),
+                max: Vec2::new(inf, inf),
+                color: Some(background),
+            }],
+            // A pt is 1/1080 of the canvas height.
+            large_size: 24.0 * canvas_size.y / 1080.0,
+            opacity: 1.0,
+            problems: Vec::new(),
+        };
+        for steps in &[&self.background, &self.steps] {
+            if let Some(step) = steps.last() {
+                checker.visit(step, Vec2::zero(), 1.0);
+            }
+        }
+        checker.problems
+    }
+}
+
+#[test]
+fn low_contrast_text_considers_rectangles_beneath_text() {
+    let placed = |x: f64, element: Element| PlacedElement {
+        position: Vec2::new(x, 100.0),
+        element: element,
+    };
+    let text = |gray: f64| Element::Text(Text {
+        color: Color::new(gray, gray, gray),
+        font_family: "Cantarell".into(),
+        font_style: "Regular".into(),
+        font_size: 20.0,
+        glyphs: vec![Glyph::new(1, 0.0, 0.0), Glyph::new(2, 10.0, 0.0)],
+        text: "ab".into(),
+        clusters: Vec::new(),
+    });
+    let rect = Element::FillPolygon(FillPolygon {
+        paint: Paint::Solid(Color::new(0.0, 0.0, 0.0)),
+        vertices: vec![Vec2::new(-5.0, -30.0), Vec2::new(-5.0, 5.0), Vec2::new(50.0, 5.0), Vec2::new(50.0, -30.0)],
+        kind: PolygonKind::Lines,
+    });
+    let slide = Slide {
+        background_color: None,
+        background: Vec::new(),
+        steps: vec![vec![
+            // Light gray on the white page, too little contrast.
+            placed(0.0, text(0.8)),
+            // The same text on a black rectangle is fine.
+            placed(200.0, rect.clone()),
+            placed(200.0, text(0.8)),
+            // Text that sticks out of the rectangle has a mixed background.
+            placed(400.0, rect),
+            placed(440.0, text(0.1)),
+            // Text on an image has an unknown background.
+            placed(600.0, Element::Png(PathBuf::from("photo.png"))),
+            placed(600.0, text(0.9)),
+        ]],
+        notes: String::new(),
+    };
+    let problems = slide.low_contrast_text(Vec2::new(1920.0, 1080.0));
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].text, "ab");
+    assert!(problems[0].ratio < 2.0);
+    assert_eq!(problems[0].required, 4.5);
+}
+
+#[test]
+fn low_contrast_text_blends_translucent_colors() {
+    use std::sync::Arc;
+    let placed = |x: f64, element: Element| PlacedElement {
+        position: Vec2::new(x, 100.0),
+        element: element,
+    };
+    let white_text = Element::Text(Text {
+        color: Color::new(1.0, 1.0, 1.0),
+        font_family: "Cantarell".into(),
+        font_style: "Regular".into(),
+        font_size: 20.0,
+        glyphs: vec![Glyph::new(1, 0.0, 0.0)],
+        text: "a".into(),
+        clusters: Vec::new(),
+    });
+    let rect = |color: Color| Element::FillPolygon(FillPolygon {
+        paint: Paint::Solid(color),
+        vertices: vec![Vec2::new(-5.0, -30.0), Vec2::new(-5.0, 5.0), Vec2::new(50.0, 5.0), Vec2::new(50.0, -30.0)],
+        kind: PolygonKind::Lines,
+    });
+    let slide = Slide {
+        background_color: None,
+        background: Vec::new(),
+        steps: vec![vec![
+            // White on black is fine.
+            placed(0.0, rect(Color::new(0.0, 0.0, 0.0))),
+            placed(0.0, white_text.clone()),
+            // White on half transparent black is white on gray.
+            placed(200.0, rect(Color::new_rgba(0.0, 0.0, 0.0, 0.5))),
+            placed(200.0, white_text.clone()),
+            // The same when the rectangle is faded as a whole.
+            placed(400.0, Element::Opacity(Arc::new(vec![PlacedElement {
+                position: Vec2::zero(),
+                element: rect(Color::new(0.0, 0.0, 0.0)),
+            }]), 0.5)),
+            placed(400.0, white_text),
+        ]],
+        notes: String::new(),
+    };
+    let problems = slide.low_contrast_text(Vec2::new(1920.0, 1080.0));
+    assert_eq!(problems.len(), 2);
+    // Not white on white, which would have a ratio of 1.
+    assert!(problems.iter().all(|p| p.ratio > 2.0 && p.ratio < 4.5));
+}
+
+#[test]
+fn slide_texts_finds_nested_text_in_final_step() {
+    let text = |s: &str| PlacedElement {
+        position: Vec2::zero(),
+        element: Element::Text(Text {
+            color: Color::new(0.0, 0.0, 0.0),
+            font_family: "Cantarell".into(),
+            font_style: "Regular".into(),
+            font_size: 20.0,
+            glyphs: Vec::new(),
+            text: s.into(),
+            clusters: Vec::new(),
+        }),
+    };
+    let group = PlacedElement {
+        position: Vec2::zero(),
+        element: Element::Transformed(Arc::new(vec![text("nested"), text("")]), 2.0),
+    };
+    let slide = Slide {
+        background_color: None,
+        background: vec![vec![text("footer")]],
+        steps: vec![vec![text("first")], vec![text("first"), group]],
+        notes: String::new(),
+    };
+    assert_eq!(slide.texts(), vec!["footer", "first", "nested"]);
+}
+
+#[test]
+fn slide_texts_in_reading_order_sorts_by_position() {
+    let text = |s: &str, x: f64, y: f64| PlacedElement {
+        position: Vec2::new(x, y),
+        element: Element::Text(Text {
+            color: Color::new(0.0, 0.0, 0.0),
+            font_family: "Cantarell".into(),
+            font_style: "Regular".into(),
+            font_size: 20.0,
+            glyphs: Vec::new(),
+            text: s.into(),
+            clusters: Vec::new(),
+        }),
+    };
+    // The group is scaled, so "right" ends up at (50, 20), right of "left".
+    let group = PlacedElement {
+        position: Vec2::new(30.0, 0.0),
+        element: Element::Transformed(Arc::new(vec![text("right", 10.0, 10.0)]), 2.0),
+    };
+    let slide = Slide {
+        background_color: None,
+        background: vec![vec![text("footer", 0.0, 100.0)]],
+        steps: vec![vec![text("body", 0.0, 50.0), group, text("left", 0.0, 20.0), text("title", 0.0, 0.0)]],
+        notes: String::new(),
+    };
+    assert_eq!(slide.texts_in_reading_order(), vec!["title", "left", "right", "body", "footer"]);
+}
+
+/// Drawing resources that are created once, and reused on every page.
+///
+/// These hold Cairo and librsvg objects, which cannot be sent to another
+/// thread, so every thread that renders needs its own cache.
+#[derive(Default)]
+pub struct RenderCache {
+    /// Recordings of symbols by address. The cache holds on to the symbol, so
+    /// the address cannot be reused by a different symbol.
+    symbols: HashMap<usize, (Arc<Symbol>, Surface)>,
+    #[cfg(feature = "svg")]
+    svgs: HashMap<PathBuf, Svg>,
+    /// Recordings of steps that pages refer to. A PDF surface writes them when
+    /// the document is finished, so they must stay alive until then.
+    steps: Vec<Surface>,
+    /// Whether to round straight lines to the pixel grid of the device.
+    pixel_snap: bool,
+    /// How to rasterize glyphs, or `None` to leave it to the font backend.
+    font_options: Option<FontOptions>,
+    /// The number of slides of earlier documents rendered to the same file.
+    slide_offset: usize,
+}
+
+impl RenderCache {
+    pub fn new() -> RenderCache {
+        RenderCache::default()
+    }
+
+    /// Round the vertices of straight lines and rectangles to device pixels.
+    ///
+    /// This only makes sense for raster output, where it keeps hairlines and
+    /// rectangle edges from blurring across two rows of pixels.
+    pub fn set_pixel_snap(&mut self, pixel_snap: bool) {
+        self.pixel_snap = pixel_snap;
+    }
+
+    /// Rasterize glyphs with the given options, rather than the system default.
+    ///
+    /// Like pixel snapping, this only affects raster output.
+    pub fn set_font_options(&mut self, options: FontOptions) {
+        self.font_options = Some(options);
+    }
+
+    /// Number the slides that follow after `offset` slides of other documents.
+    ///
+    /// When several documents are rendered into one pdf, internal links of a
+    /// document must keep pointing at its own slides.
+    pub fn set_slide_offset(&mut self, offset: usize) {
+        self.slide_offset = offset;
+    }
+
+    /// Prepare to render the document again, after the files in `changed` changed.
+    ///
+    /// Recordings of symbols and steps belong to the previous evaluation, so
+    /// they are dropped. Svg images stay loaded, unless their file changed.
+    pub fn start_document(&mut self, changed: &[PathBuf]) {
+        self.symbols.clear();
+        self.steps.clear();
+        self.forget_svgs(changed);
+    }
+
+    #[cfg(feature = "svg")]
+    fn forget_svgs(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            self.svgs.remove(path);
+        }
+    }
+
+    #[cfg(not(feature = "svg"))]
+    fn forget_svgs(&mut self, _paths: &[PathBuf]) {}
+}
+
+#[test]
+fn render_cache_start_document_drops_recordings() {
+    use logger::MemoryLogger;
+    let square = PlacedElement {
+        position: Vec2::zero(),
+        element: Element::FillPolygon(FillPolygon {
+            paint: Paint::Solid(Color::new(0.0, 0.0, 0.0)),
+            vertices: vec![Vec2::zero(), Vec2::new(0.0, 10.0), Vec2::new(10.0, 10.0), Vec2::new(10.0, 0.0)],
+            kind: PolygonKind::Lines,
+        }),
+    };
+    let symbol = PlacedElement {
+        position: Vec2::zero(),
+        element: Element::Symbol(Arc::new(Symbol { elements: vec![square] })),
+    };
+    let slide = Slide {
+        background_color: None,
+        background: Vec::new(),
+        steps: vec![vec![symbol.clone()], vec![symbol]],
+        notes: String::new(),
+    };
+    let page = PageSetup { canvas_size: Vec2::new(100.0, 100.0), bleed: 0.0, crop_marks: 0.0 };
+    let surf = Surface::new_pdf_for_stream(SharedBuffer::new(), 100.0, 100.0).unwrap();
+    let mut cr = Cairo::new(surf).unwrap();
+    let mut fm = FontMap::new();
+    let mut cache = RenderCache::new();
+    render_slide(&mut fm, &mut cache, &MemoryLogger::new(), &mut cr, &page, 1, &slide).unwrap();
+    assert_eq!((cache.symbols.len(), cache.steps.len()), (1, 2));
+    cache.start_document(&[]);
+    assert_eq!((cache.symbols.len(), cache.steps.len()), (0, 0));
+}
+
+fn draw_background(cr: &mut Cairo, page: &PageSetup, color: Color) {
+    let b = page.bleed;
+    cr.rectangle(-b, -b, page.canvas_size.x + 2.0 * b, page.canvas_size.y + 2.0 * b);
+    set_color(cr, color);
     cr.fill();
 }
 
+fn set_color(cr: &mut Cairo, color: Color) {
+    cr.set_source_rgba(color.r, color.g, color.b, color.a);
+}
+
+/// Set the source to fill the polygon with `vertices` with.
+///
+/// Gradients are stretched over the bounds of the polygon.
+fn set_paint(cr: &mut Cairo, paint: Paint, vertices: &[Vec2]) {
+    let gradient = match paint {
+        Paint::Solid(color) => return set_color(cr, color),
+        Paint::Gradient(gradient) => gradient,
+    };
+    let (min, max) = bounds(vertices.iter().cloned());
+    let center = (min + max) * 0.5;
+    let half = (max + -min) * 0.5;
+    let mut pattern = match gradient.kind {
+        GradientKind::Linear(angle) => {
+            // The line is just long enough for the colors at its ends to touch
+            // the corners of the bounds.
+            let (sin, cos) = angle.to_radians().sin_cos();
+            let d = Vec2::new(cos, sin) * (half.x * cos.abs() + half.y * sin.abs());
+            Pattern::new_linear(center.x - d.x, center.y - d.y, center.x + d.x, center.y + d.y)
+        }
+        GradientKind::Radial => {
+            Pattern::new_radial(center.x, center.y, (half.x * half.x + half.y * half.y).sqrt())
+        }
+    };
+    for &(offset, color) in &[(0.0, gradient.from), (1.0, gradient.to)] {
+        pattern.add_color_stop_rgba(offset, color.r, color.g, color.b, color.a);
+    }
+    cr.set_source(&pattern);
+}
+
+/// Draw marks in line with the edges of the canvas, outside of the bleed.
+fn draw_crop_marks(cr: &mut Cairo, page: &PageSetup) {
+    let (w, h) = (page.canvas_size.x, page.canvas_size.y);
+    let start = page.bleed;
+    let end = page.bleed + page.crop_marks;
+    let corners = [(0.0, 0.0, -1.0, -1.0), (w, 0.0, 1.0, -1.0), (0.0, h, -1.0, 1.0), (w, h, 1.0, 1.0)];
+    for &(x, y, dx, dy) in &corners {
+        cr.move_to(x + dx * start, y);
+        cr.line_to(x + dx * end, y);
+        cr.move_to(x, y + dy * start);
+        cr.line_to(x, y + dy * end);
+    }
+    cr.set_source_rgb(0.0, 0.0, 0.0);
+    cr.set_line_width(CROP_MARK_WIDTH);
+    cr.stroke();
+}
+
 /// Draw the lines for a polygon, but don't stroke or fill it yet.
 fn draw_polygon_lines(cr: &mut Cairo, vertices: &[Vec2], close: bool) {
     debug_assert!(vertices.len() >= 2, "Polygon must have at least one line segment.");
@@ -76,15 +966,62 @@ fn draw_polygon(cr: &mut Cairo, vertices: &[Vec2], kind: PolygonKind, close: boo
     }
 }
 
-fn draw_element(fm: &mut FontMap, cr: &mut Cairo, pe: &PlacedElement) {
+/// Round a device coordinate to the nearest pixel boundary plus `offset`.
+fn snap_coordinate(x: f64, offset: f64) -> f64 {
+    (x - offset).round() + offset
+}
+
+/// Return the offset from the pixel boundary that makes a stroke crisp.
+///
+/// A stroke that covers an odd number of pixels is centered on a pixel, one
+/// that covers an even number of pixels is centered on a pixel boundary.
+fn stroke_snap_offset(device_width: f64) -> f64 {
+    if device_width.round().max(1.0) as u64 % 2 == 1 { 0.5 } else { 0.0 }
+}
+
+#[test]
+fn stroke_snap_offset_centers_odd_widths_on_pixels() {
+    assert_eq!(stroke_snap_offset(0.3), 0.5);
+    assert_eq!(stroke_snap_offset(1.0), 0.5);
+    assert_eq!(stroke_snap_offset(2.2), 0.0);
+    assert_eq!(stroke_snap_offset(3.0), 0.5);
+    assert_eq!(snap_coordinate(10.2, 0.5), 10.5);
+    assert_eq!(snap_coordinate(10.2, 0.0), 10.0);
+    assert_eq!(snap_coordinate(9.9, 0.5), 9.5);
+}
+
+/// Move vertices in user space so they fall on the device pixel grid.
+fn snap_vertices(cr: &mut Cairo, vertices: &[Vec2], offset: f64) -> Vec<Vec2> {
+    vertices.iter().map(|v| {
+        let (x, y) = cr.user_to_device(v.x, v.y);
+        let (x, y) = (snap_coordinate(x, offset), snap_coordinate(y, offset));
+        let (x, y) = cr.device_to_user(x, y);
+        Vec2::new(x, y)
+    }).collect()
+}
+
+fn draw_element(
+    fm: &mut FontMap,
+    cache: &mut RenderCache,
+    logger: &Logger,
+    cr: &mut Cairo,
+    pe: &PlacedElement
+) -> Result<()> {
     match pe.element {
         Element::StrokePolygon(ref polygon) => {
             let matrix = cr.get_matrix();
             cr.translate(pe.position.x, pe.position.y);
 
-            draw_polygon(cr, &polygon.vertices, polygon.kind, polygon.close);
+            if cache.pixel_snap && polygon.kind == PolygonKind::Lines {
+                let (dx, dy) = cr.user_to_device_distance(polygon.line_width, 0.0);
+                let offset = stroke_snap_offset(dx.hypot(dy));
+                let vertices = snap_vertices(cr, &polygon.vertices, offset);
+                draw_polygon(cr, &vertices, polygon.kind, polygon.close);
+            } else {
+                draw_polygon(cr, &polygon.vertices, polygon.kind, polygon.close);
+            }
 
-            cr.set_source_rgb(polygon.color.r, polygon.color.g, polygon.color.b);
+            set_color(cr, polygon.color);
             cr.set_line_width(polygon.line_width);
             cr.stroke();
 
@@ -96,9 +1033,14 @@ fn draw_element(fm: &mut FontMap, cr: &mut Cairo, pe: &PlacedElement) {
             cr.translate(pe.position.x, pe.position.y);
 
             let close = true;
-            draw_polygon(cr, &polygon.vertices, polygon.kind, close);
+            if cache.pixel_snap && polygon.kind == PolygonKind::Lines {
+                let vertices = snap_vertices(cr, &polygon.vertices, 0.0);
+                draw_polygon(cr, &vertices, polygon.kind, close);
+            } else {
+                draw_polygon(cr, &polygon.vertices, polygon.kind, close);
+            }
 
-            cr.set_source_rgb(polygon.color.r, polygon.color.g, polygon.color.b);
+            set_paint(cr, polygon.paint, &polygon.vertices);
             cr.fill();
 
             cr.set_matrix(&matrix);
@@ -120,94 +1062,994 @@ fn draw_element(fm: &mut FontMap, cr: &mut Cairo, pe: &PlacedElement) {
             let cr_face = FontFace::from_ft_face(ft_face.clone());
             cr.set_font_face(&cr_face);
             cr.set_font_size(text.font_size);
-            cr.set_source_rgb(text.color.r, text.color.g, text.color.b);
-            cr.show_glyphs(&glyphs_offset);
+            set_color(cr, text.color);
+            // Show the glyphs along with the text, so the text can be
+            // extracted from the output, by copy-paste or a screen reader.
+            cr.show_text_glyphs(&text.text, &glyphs_offset, &text.clusters);
             // TODO: The cr_font should outlive the Cairo, because Cairo
             // might internally reference the font still. How to model this?
         }
 
-        Element::Scaled(ref elements, scale) => {
-            // Store the current transform so we can restore it later.
+        Element::Transformed(ref elements, scale) => {
+            // Store the current transform so we can restore it later. The
+            // transform of nested elements composes with this one.
             let matrix = cr.get_matrix();
             cr.translate(pe.position.x, pe.position.y);
             cr.scale(scale, scale);
-            for inner_pe in elements {
-                draw_element(fm, cr, inner_pe);
+            for inner_pe in elements.iter() {
+                draw_element(fm, cache, logger, cr, inner_pe)?;
+            }
+            cr.set_matrix(&matrix);
+        }
+
+        Element::Affine(ref elements, ref m) => {
+            let matrix = cr.get_matrix();
+            cr.translate(pe.position.x, pe.position.y);
+            cr.transform(m);
+            for inner_pe in elements.iter() {
+                draw_element(fm, cache, logger, cr, inner_pe)?;
+            }
+            cr.set_matrix(&matrix);
+        }
+
+        Element::Opacity(ref elements, opacity) => {
+            let matrix = cr.get_matrix();
+            cr.translate(pe.position.x, pe.position.y);
+            // Draw into a group first, so the elements are blended with what
+            // is beneath as a whole, not one by one.
+            cr.push_group();
+            for inner_pe in elements.iter() {
+                draw_element(fm, cache, logger, cr, inner_pe)?;
             }
+            cr.pop_group_to_source();
+            cr.paint_with_alpha(opacity);
             cr.set_matrix(&matrix);
         }
 
-        Element::Svg(ref svg) => {
+        Element::Clipped(ref elements, top_left, size) => {
+            // Save the state rather than resetting the clip afterwards, so the
+            // clip intersects with any clip that is already in place.
+            cr.save();
+            cr.translate(pe.position.x, pe.position.y);
+            cr.rectangle(top_left.x, top_left.y, size.x, size.y);
+            cr.clip();
+            for inner_pe in elements.iter() {
+                draw_element(fm, cache, logger, cr, inner_pe)?;
+            }
+            cr.restore();
+        }
+
+        Element::Group(ref elements) => {
+            let matrix = cr.get_matrix();
+            cr.translate(pe.position.x, pe.position.y);
+            for inner_pe in elements.iter() {
+                draw_element(fm, cache, logger, cr, inner_pe)?;
+            }
+            cr.set_matrix(&matrix);
+        }
+
+        #[cfg(feature = "svg")]
+        Element::Svg(ref path) => {
+            if !cache.svgs.contains_key(path) {
+                let svg = match Svg::open(path) {
+                    Ok(svg) => svg,
+                    Err(()) => return Err(Error::missing_file(path.display().to_string())),
+                };
+                cache.svgs.insert(path.clone(), svg);
+            }
+
             // Store the current transform so we can restore it later.
             let matrix = cr.get_matrix();
             cr.translate(pe.position.x, pe.position.y);
-            svg.draw(cr);
+            cache.svgs[path].draw(cr);
             cr.set_matrix(&matrix);
         }
 
+        // Without librsvg, `image()` refuses svg files, so there should be no
+        // svg elements to draw, but fail gracefully rather than panic.
+        #[cfg(not(feature = "svg"))]
+        Element::Svg(ref path) => {
+            let msg = format!("Cannot draw '{}', this build of Pris does not support svg images.", path.display());
+            return Err(Error::Other(msg))
+        }
+
+        Element::Symbol(ref symbol) => {
+            let key = &**symbol as *const Symbol as usize;
+            if !cache.symbols.contains_key(&key) {
+                let surface = Surface::new_recording()?;
+                {
+                    let mut rec_cr = Cairo::new(surface.clone())?;
+                    // The recording keeps the font options of the context it
+                    // was recorded with, not the one it is replayed onto.
+                    if let Some(ref options) = cache.font_options {
+                        rec_cr.set_font_options(options);
+                    }
+                    for inner_pe in &symbol.elements {
+                        draw_element(fm, cache, logger, &mut rec_cr, inner_pe)?;
+                    }
+                    rec_cr.status()?;
+                }
+                cache.symbols.insert(key, (symbol.clone(), surface));
+            }
+            let surface = &cache.symbols[&key].1;
+            cr.set_source_surface(surface, pe.position.x, pe.position.y);
+            cr.paint();
+        }
+
         Element::Png(ref path) => {
-            // TODO: This will need error handling.
-            let png_surface = Surface::from_png(path);
+            let png_surface = Surface::from_png(path)?;
             cr.set_source_surface(&png_surface, pe.position.x, pe.position.y);
             cr.paint();
         }
 
         #[cfg(not(feature = "hyperlink"))]
         Element::Hyperlink(..) => {
-            println!(
-                "Warning: hyperlink not created, Pris was compiled without \
-                hyperlink support.");
+            logger.log(
+                Level::Warning,
+                "hyperlink not created, Pris was compiled without hyperlink support.",
+            );
         }
 
         #[cfg(feature = "hyperlink")]
         Element::Hyperlink(ref hyperlink) => {
-            // Escape the uri: backslashes and single quotes must be escaped
-            // with a backslash, to fit the format of the tag "attributes".
-            let mut uri_escaped = String::with_capacity(hyperlink.uri.len());
-            for ch in hyperlink.uri.chars() {
-                match ch {
-                    '\'' => uri_escaped.push_str("\\'"),
-                    // Not sure why a single backslash should turn into *four*
-                    // instead of two, but when I push two backslashes, nothing
-                    // shows up in Evince. Could be a bug in Cairo or Evince
-                    // too.
-                    '\\' => uri_escaped.push_str("\\\\\\\\"),
-                    _ => uri_escaped.push(ch),
+            let target = match hyperlink.target {
+                LinkTarget::Uri(ref uri) => {
+                    // Escape the uri: backslashes and single quotes must be
+                    // escaped with a backslash, to fit the format of the tag
+                    // "attributes".
+                    let mut uri_escaped = String::with_capacity(uri.len());
+                    for ch in uri.chars() {
+                        match ch {
+                            '\'' => uri_escaped.push_str("\\'"),
+                            // Not sure why a single backslash should turn into
+                            // *four* instead of two, but when I push two
+                            // backslashes, nothing shows up in Evince. Could be
+                            // a bug in Cairo or Evince too.
+                            '\\' => uri_escaped.push_str("\\\\\\\\"),
+                            _ => uri_escaped.push(ch),
+                        }
+                    }
+                    format!("uri='{}'", uri_escaped)
                 }
-            }
+                LinkTarget::Slide(slide) => {
+                    format!("dest='{}'", slide_dest(cache.slide_offset + slide))
+                }
+            };
 
             let (x, y) = cr.user_to_device(pe.position.x, pe.position.y);
             let (w, h) = cr.user_to_device_distance(hyperlink.size.x, hyperlink.size.y);
             let attributes = format!(
-                "uri='{}' rect=[{:0.3} {:0.3} {:0.3} {:0.3}]\0",
-                uri_escaped,
+                "{} rect=[{:0.3} {:0.3} {:0.3} {:0.3}]",
+                target,
                 x, y, w, h
             );
             cr.tag_link(&attributes);
         }
     }
+
+    Ok(())
 }
 
-pub fn render_frame<'a>(
+/// Return the name of the destination at the first page of a slide.
+#[cfg(feature = "hyperlink")]
+fn slide_dest(slide: usize) -> String {
+    format!("slide-{}", slide)
+}
+
+/// Mark the current page as the start of a slide, for internal links.
+///
+/// Links refer to the destination by name rather than by page number, because
+/// Cairo cannot resolve links to pages that have not been written yet.
+#[cfg(feature = "hyperlink")]
+fn add_slide_dest(cr: &mut Cairo, slide: usize) {
+    cr.tag_dest(&slide_dest(slide));
+}
+
+#[cfg(not(feature = "hyperlink"))]
+fn add_slide_dest(_cr: &mut Cairo, _slide: usize) {}
+
+/// An item in the outline of a pdf, which viewers show as bookmarks.
+pub struct OutlineItem {
+    pub title: String,
+    /// The page that the item links to, starting at 1.
+    pub page: usize,
+    pub children: Vec<OutlineItem>,
+}
+
+/// Add the items to the outline of a pdf surface, before it is finished.
+#[cfg(feature = "hyperlink")]
+pub fn add_outline(surface: &Surface, _logger: &Logger, items: &[OutlineItem]) {
+    fn add_items(surface: &Surface, parent_id: i32, items: &[OutlineItem]) {
+        for item in items {
+            let open = !item.children.is_empty();
+            let id = surface.add_pdf_outline(parent_id, &item.title, item.page, open);
+            add_items(surface, id, &item.children);
+        }
+    }
+    add_items(surface, ::cairo::OUTLINE_ROOT, items);
+}
+
+#[cfg(not(feature = "hyperlink"))]
+pub fn add_outline(_surface: &Surface, logger: &Logger, items: &[OutlineItem]) {
+    if !items.is_empty() {
+        logger.log(
+            Level::Warning,
+            "outline not created, Pris was compiled without hyperlink support.",
+        );
+    }
+}
+
+/// Render the pages of the slide with the given number, starting at 1.
+pub fn render_slide(
+    fm: &mut FontMap,
+    cache: &mut RenderCache,
+    logger: &Logger,
+    cr: &mut Cairo,
+    page: &PageSetup,
+    number: usize,
+    slide: &Slide
+) -> Result<()> {
+    // Hyperlinks are annotations of the page, which do not survive a
+    // recording, so slides with links are drawn step by step.
+    let recordings = if slide.num_steps() > 1 && !slide.steps.iter().any(|s| contains_hyperlink(s)) {
+        record_steps(fm, cache, logger, &slide.steps)?
+    } else {
+        Vec::new()
+    };
+
+    for i in 0..slide.num_steps() {
+        draw_step(fm, cache, logger, cr, page, slide, i, recordings.get(i))?;
+
+        if i == 0 {
+            add_slide_dest(cr, cache.slide_offset + number);
+        }
+
+        cr.show_page();
+        cr.status()?;
+    }
+
+    cache.steps.extend(recordings);
+
+    Ok(())
+}
+
+/// Recordings of slides from a previous render, keyed by content hash.
+///
+/// A program that renders a document repeatedly, such as a preview server,
+/// passes the same cache to every render. Slides whose content hash did not
+/// change are then painted from their recording, rather than drawn again.
+/// Recordings refer to fonts of the `FontMap` they were drawn with, so the
+/// cache must be used with the same font map on every render.
+pub struct SlideCache {
+    slides: HashMap<u64, CachedSlide>,
+    /// The slides of the render in progress.
+    fresh: HashMap<u64, CachedSlide>,
+    hits: usize,
+}
+
+/// The recordings of the steps of a slide, and the images that it shows.
+#[derive(Clone)]
+struct CachedSlide {
+    steps: Vec<Surface>,
+    images: Vec<PathBuf>,
+}
+
+impl SlideCache {
+    pub fn new() -> SlideCache {
+        SlideCache {
+            slides: HashMap::new(),
+            fresh: HashMap::new(),
+            hits: 0,
+        }
+    }
+
+    /// Return the number of slides in the cache.
+    pub fn len(&self) -> usize {
+        self.slides.len()
+    }
+
+    /// Return how many slides the last render took from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Drop the slides that show one of the images in `changed`.
+    ///
+    /// The content hash covers the path of an image, not its contents, so
+    /// after an image file changed, the slides that show it are drawn again.
+    pub fn forget_images(&mut self, changed: &[PathBuf]) {
+        self.slides.retain(|_, slide| !slide.images.iter().any(|image| changed.contains(image)));
+    }
+
+    /// Prepare to render a document with `render_slide_cached`.
+    pub fn start_render(&mut self) {
+        self.hits = 0;
+        self.fresh.clear();
+    }
+
+    /// Keep the slides of the render that just finished, and drop the others.
+    pub fn finish_render(&mut self) {
+        self.slides = mem::replace(&mut self.fresh, HashMap::new());
+    }
+}
+
+#[test]
+fn slide_cache_forget_images_drops_slides_that_show_them() {
+    let mut cache = SlideCache::new();
+    for (hash, image) in vec![(1, "a.png"), (2, "b.svg")] {
+        let slide = CachedSlide { steps: Vec::new(), images: vec![PathBuf::from(image)] };
+        cache.slides.insert(hash, slide);
+    }
+    cache.forget_images(&[PathBuf::from("a.png")]);
+    assert_eq!(cache.len(), 1);
+    assert!(cache.slides.contains_key(&2));
+}
+
+/// Render the slide like `render_slide`, reusing recordings from `slides`.
+///
+/// The slide is added to the slides of the render in progress, which
+/// `SlideCache::start_render` and `finish_render` delimit, so that after the
+/// render, the cache can drop the slides that are no longer in the document.
+pub fn render_slide_cached(
     fm: &mut FontMap,
+    cache: &mut RenderCache,
+    logger: &Logger,
     cr: &mut Cairo,
-    canvas_size: Vec2,
-    frame: &Frame<'a>
-) {
-    // TODO: Ensure that writing to background_color only accepts a color value,
-    // so a lookup failure here is never a type error.
-    let var_bgcolor = Idents(vec!["background_color"]);
+    page: &PageSetup,
+    number: usize,
+    slide: &Slide,
+    slides: &mut SlideCache
+) -> Result<()> {
+    // Links do not survive a recording, so these slides are drawn every time.
+    if slide.steps.iter().any(|s| contains_hyperlink(s)) {
+        return render_slide(fm, cache, logger, cr, page, number, slide)
+    }
 
-    for subframe in frame.get_subframes() {
-        if let Ok(bgcolor) = frame.get_env().lookup_color(&var_bgcolor) {
-            draw_background(cr, canvas_size, bgcolor);
+    let hash = slide.content_hash();
+    let cached = slides.fresh.get(&hash).cloned().or_else(|| slides.slides.remove(&hash));
+    let cached = match cached {
+        Some(cached) => { slides.hits += 1; cached }
+        None => {
+            let mut resources = Resources::new();
+            resources.collect(slide);
+            CachedSlide {
+                steps: record_steps(fm, cache, logger, &slide.steps)?,
+                images: resources.images,
+            }
         }
+    };
+
+    for i in 0..slide.num_steps() {
+        draw_step(fm, cache, logger, cr, page, slide, i, cached.steps.get(i))?;
 
-        for pe in subframe.get_elements() {
-            draw_element(fm, cr, pe);
+        if i == 0 {
+            add_slide_dest(cr, cache.slide_offset + number);
         }
 
         cr.show_page();
-        cr.assert_status_success();
+        cr.status()?;
+    }
+
+    slides.fresh.insert(hash, cached);
+
+    Ok(())
+}
+
+fn contains_hyperlink(elements: &[PlacedElement]) -> bool {
+    elements.iter().any(|pe| match pe.element {
+        Element::Hyperlink(..) => true,
+        Element::Clipped(ref inner, _, _) => contains_hyperlink(inner),
+        Element::Group(ref inner) => contains_hyperlink(inner),
+        Element::Symbol(ref symbol) => contains_hyperlink(&symbol.elements),
+        Element::Transformed(ref inner, _) => contains_hyperlink(inner),
+        Element::Affine(ref inner, _) => contains_hyperlink(inner),
+        Element::Opacity(ref inner, _) => contains_hyperlink(inner),
+        _ => false,
+    })
+}
+
+/// Record the content of every step on a surface of its own.
+///
+/// When a step starts with all elements of the previous step, as it does when
+/// content is revealed, its recording paints the recording of the previous
+/// step, rather than drawing those elements again. A PDF stores a recording
+/// once, so content that stays visible for several steps is not repeated on
+/// every page.
+fn record_steps(
+    fm: &mut FontMap,
+    cache: &mut RenderCache,
+    logger: &Logger,
+    steps: &[Vec<PlacedElement>]
+) -> Result<Vec<Surface>> {
+    let mut recordings: Vec<Surface> = Vec::with_capacity(steps.len());
+
+    for (i, step) in steps.iter().enumerate() {
+        let surface = Surface::new_recording()?;
+        {
+            let mut rec_cr = Cairo::new(surface.clone())?;
+            let mut remaining = &step[..];
+            if i > 0 && step.starts_with(&steps[i - 1]) {
+                rec_cr.set_source_surface(&recordings[i - 1], 0.0, 0.0);
+                rec_cr.paint();
+                remaining = &step[steps[i - 1].len()..];
+            }
+            for pe in remaining {
+                draw_element(fm, cache, logger, &mut rec_cr, pe)?;
+            }
+            rec_cr.status()?;
+        }
+        recordings.push(surface);
+    }
+
+    Ok(recordings)
+}
+
+/// Draw step `i` (starting at 0) of the given slide, without ending the page.
+///
+/// This is the counterpart of `render_slide` for surfaces without pages,
+/// which hold a single step.
+pub fn render_step(
+    fm: &mut FontMap,
+    cache: &mut RenderCache,
+    logger: &Logger,
+    cr: &mut Cairo,
+    page: &PageSetup,
+    slide: &Slide,
+    i: usize
+) -> Result<()> {
+    draw_step(fm, cache, logger, cr, page, slide, i, None)
+}
+
+/// Draw step `i` of the slide, taking the content from `recording` if given.
+fn draw_step(
+    fm: &mut FontMap,
+    cache: &mut RenderCache,
+    logger: &Logger,
+    cr: &mut Cairo,
+    page: &PageSetup,
+    slide: &Slide,
+    i: usize,
+    recording: Option<&Surface>
+) -> Result<()> {
+    let margin = page.margin();
+
+    if let Some(ref options) = cache.font_options {
+        cr.set_font_options(options);
+    }
+
+    let matrix = cr.get_matrix();
+    cr.translate(margin, margin);
+
+    // Content outside of the bleed would end up between the crop marks.
+    if margin > 0.0 {
+        let b = page.bleed;
+        cr.rectangle(-b, -b, page.canvas_size.x + 2.0 * b, page.canvas_size.y + 2.0 * b);
+        cr.clip();
+    }
+
+    if let Some(bgcolor) = slide.background_color {
+        draw_background(cr, page, bgcolor);
+    }
+
+    // The background frame is drawn beneath everything else. If it has
+    // fewer steps than the slide, it stays in its final state.
+    if slide.background.len() > 0 {
+        let bg_step = &slide.background[cmp::min(i, slide.background.len() - 1)];
+        for pe in bg_step {
+            draw_element(fm, cache, logger, cr, pe)?;
+        }
+    }
+
+    match recording {
+        Some(surface) => {
+            cr.set_source_surface(surface, 0.0, 0.0);
+            cr.paint();
+        }
+        None => for pe in &slide.steps[i] {
+            draw_element(fm, cache, logger, cr, pe)?;
+        }
+    }
+
+    if margin > 0.0 {
+        cr.reset_clip();
+    }
+    if page.crop_marks > 0.0 {
+        draw_crop_marks(cr, page);
+    }
+
+    cr.set_matrix(&matrix);
+    cr.status()
+}
+
+/// Break `text` into lines no wider than `width`, as measured by `measure`.
+///
+/// Lines break at spaces, and at every newline in `text`. A word that is wider
+/// than `width` gets a line of its own.
+pub fn wrap_lines<F: FnMut(&str) -> f64>(text: &str, width: f64, mut measure: F) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() {
+                let candidate = format!("{} {}", line, word);
+                if measure(&candidate) <= width {
+                    line = candidate;
+                    continue
+                }
+                lines.push(line);
+            }
+            line = word.to_string();
+        }
+        lines.push(line);
     }
+    lines
+}
+
+#[test]
+fn wrap_lines_breaks_at_spaces_and_newlines() {
+    // A width of one per byte, like a monospace font.
+    let lines = wrap_lines("Mention the\nbenchmark results first", 13.0, |s| s.len() as f64);
+    assert_eq!(lines, vec!["Mention the", "benchmark", "results first"]);
+    let lines = wrap_lines("incomprehensibilities", 5.0, |s| s.len() as f64);
+    assert_eq!(lines, vec!["incomprehensibilities"]);
+}
+
+/// Draw step `i` of the slide at `offset`, scaled by `scale`, on a white page.
+fn draw_scaled_step(
+    fm: &mut FontMap,
+    cache: &mut RenderCache,
+    logger: &Logger,
+    cr: &mut Cairo,
+    page: &PageSetup,
+    slide: &Slide,
+    i: usize,
+    offset: Vec2,
+    scale: f64
+) -> Result<()> {
+    let size = page.page_size();
+    cr.save();
+    cr.translate(offset.x, offset.y);
+    cr.scale(scale, scale);
+    cr.rectangle(0.0, 0.0, size.x, size.y);
+    cr.set_source_rgb(1.0, 1.0, 1.0);
+    cr.fill();
+    cr.rectangle(0.0, 0.0, size.x, size.y);
+    cr.clip();
+    cr.set_source_rgb(0.0, 0.0, 0.0);
+    cr.set_line_width(6.0);
+    draw_step(fm, cache, logger, cr, page, slide, i, None)?;
+    cr.restore();
+    cr.status()
+}
+
+/// Render a speaker view of every page of `slides`, one page per step.
+///
+/// The current page is on the left, with the slide number below it. The next
+/// page and the notes of the slide are on the right. The speaker view has the
+/// same size as a page of the document, so on a landscape canvas it is
+/// landscape too.
+pub fn render_speaker_view(
+    fm: &mut FontMap,
+    cache: &mut RenderCache,
+    logger: &Logger,
+    cr: &mut Cairo,
+    page: &PageSetup,
+    slides: &[Slide]
+) -> Result<()> {
+    let size = page.page_size();
+    let margin = size.y / 40.0;
+    let font_size = size.y / 36.0;
+    let line_height = 1.3 * font_size;
+
+    let current_scale = 0.62;
+    let column_x = 2.0 * margin + current_scale * size.x;
+    let column_width = size.x - column_x - margin;
+    let next_scale = column_width / size.x;
+
+    // Pages in order, as (slide index, step index) pairs.
+    let pages: Vec<(usize, usize)> = slides
+        .iter()
+        .enumerate()
+        .flat_map(|(k, slide)| (0..slide.num_steps()).map(move |i| (k, i)))
+        .collect();
+
+    for (n, &(k, i)) in pages.iter().enumerate() {
+        let slide = &slides[k];
+
+        cr.set_source_rgb(0.15, 0.15, 0.15);
+        cr.rectangle(0.0, 0.0, size.x, size.y);
+        cr.fill();
+
+        draw_scaled_step(fm, cache, logger, cr, page, slide, i, Vec2::new(margin, margin), current_scale)?;
+        if let Some(&(next_k, next_i)) = pages.get(n + 1) {
+            let offset = Vec2::new(column_x, margin);
+            draw_scaled_step(fm, cache, logger, cr, page, &slides[next_k], next_i, offset, next_scale)?;
+        }
+
+        cr.select_font_family("sans-serif");
+        cr.set_font_size(font_size);
+        cr.set_source_rgb(0.7, 0.7, 0.7);
+        cr.move_to(margin, 2.0 * margin + current_scale * size.y + font_size);
+        cr.show_text(&format!(
+            "Slide {} of {}, step {} of {}",
+            k + 1, slides.len(), i + 1, slide.num_steps(),
+        ));
+
+        // Notes that do not fit are cut off, rather than shrunk to fit.
+        cr.set_source_rgb(1.0, 1.0, 1.0);
+        let lines = wrap_lines(slide.notes(), column_width, |s| cr.text_advance(s));
+        let mut y = 2.0 * margin + next_scale * size.y + font_size;
+        for line in lines {
+            if y > size.y - margin {
+                break
+            }
+            cr.move_to(column_x, y);
+            cr.show_text(&line);
+            y += line_height;
+        }
+
+        cr.show_page();
+        cr.status()?;
+    }
+
+    Ok(())
+}
+
+/// The grid of thumbnails on the pages of a contact sheet.
+struct ContactSheet {
+    columns: usize,
+    rows: usize,
+    margin: f64,
+    font_size: f64,
+    cell_size: Vec2,
+    /// The scale of a thumbnail relative to a page.
+    scale: f64,
+}
+
+impl ContactSheet {
+    /// Lay out four columns and three rows of thumbnails on a page of `size`.
+    fn new(size: Vec2) -> ContactSheet {
+        let (columns, rows) = (4, 3);
+        let margin = size.y / 30.0;
+        let font_size = size.y / 40.0;
+        let cell_width = (size.x - (columns + 1) as f64 * margin) / columns as f64;
+        let cell_height = (size.y - (rows + 1) as f64 * margin) / rows as f64;
+        ContactSheet {
+            columns: columns,
+            rows: rows,
+            margin: margin,
+            font_size: font_size,
+            cell_size: Vec2::new(cell_width, cell_height),
+            // Below the thumbnail there is room for its label.
+            scale: (cell_width / size.x).min((cell_height - 1.5 * font_size) / size.y),
+        }
+    }
+
+    fn thumbnails_per_page(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    /// Return the top left of the thumbnail at index `j` on its page.
+    fn position(&self, j: usize) -> Vec2 {
+        let column = (j % self.columns) as f64;
+        let row = (j / self.columns) as f64;
+        Vec2::new(
+            self.margin + column * (self.cell_size.x + self.margin),
+            self.margin + row * (self.cell_size.y + self.margin),
+        )
+    }
+}
+
+#[test]
+fn contact_sheet_fits_labelled_thumbnails_on_the_page() {
+    let size = Vec2::new(1920.0, 1080.0);
+    let sheet = ContactSheet::new(size);
+    assert_eq!(sheet.thumbnails_per_page(), 12);
+
+    // Thumbnails fill the page in reading order.
+    let first = sheet.position(0);
+    assert_eq!((first.x, first.y), (36.0, 36.0));
+    assert_eq!(sheet.position(1).y, sheet.position(0).y);
+    assert!(sheet.position(1).x > sheet.position(0).x);
+    assert_eq!(sheet.position(4).x, sheet.position(0).x);
+    assert!(sheet.position(4).y > sheet.position(0).y);
+
+    // Every thumbnail and its label stay inside the page, without overlap.
+    let thumbnail = Vec2::new(sheet.scale * size.x, sheet.scale * size.y);
+    let label = 1.5 * sheet.font_size;
+    let last = sheet.position(11);
+    assert!(last.x + thumbnail.x <= size.x - sheet.margin + 1e-9);
+    assert!(last.y + thumbnail.y + label <= size.y - sheet.margin + 1e-9);
+    assert!(sheet.position(0).x + thumbnail.x < sheet.position(1).x);
+    assert!(sheet.position(0).y + thumbnail.y + label < sheet.position(4).y);
+}
+
+/// Render the final step of every slide as a thumbnail, in a grid per page.
+///
+/// Pages have the size of a page of the document, and hold four columns and
+/// three rows of thumbnails, each labelled with its slide number. Slides
+/// without content have no page to show, so they are skipped.
+pub fn render_contact_sheet(
+    fm: &mut FontMap,
+    cache: &mut RenderCache,
+    logger: &Logger,
+    cr: &mut Cairo,
+    page: &PageSetup,
+    slides: &[Slide]
+) -> Result<()> {
+    let size = page.page_size();
+    let sheet = ContactSheet::new(size);
+    let scale = sheet.scale;
+
+    let numbered: Vec<(usize, &Slide)> = slides
+        .iter()
+        .enumerate()
+        .filter(|&(_, slide)| slide.num_steps() > 0)
+        .collect();
+
+    for page_slides in numbered.chunks(sheet.thumbnails_per_page()) {
+        for (j, &(k, slide)) in page_slides.iter().enumerate() {
+            let position = sheet.position(j);
+            draw_scaled_step(fm, cache, logger, cr, page, slide, slide.num_steps() - 1, position, scale)?;
+
+            // A border sets white slides apart from the white page.
+            cr.set_source_rgb(0.6, 0.6, 0.6);
+            cr.set_line_width(1.0);
+            cr.rectangle(position.x, position.y, scale * size.x, scale * size.y);
+            cr.stroke();
+
+            cr.select_font_family("sans-serif");
+            cr.set_font_size(sheet.font_size);
+            cr.set_source_rgb(0.3, 0.3, 0.3);
+            cr.move_to(position.x, position.y + scale * size.y + 1.2 * sheet.font_size);
+            cr.show_text(&format!("{}", k + 1));
+        }
+
+        cr.show_page();
+        cr.status()?;
+    }
+
+    Ok(())
+}
+
+/// Render the slides of several documents to one pdf, with a page for every step.
+///
+/// The documents share the page setup and the pdf options of the first one.
+/// When there is more than one document, the outline gets an item for every
+/// document, with the entries of its table of contents nested below it.
+/// After every slide, `slide_done` is called with the number of the slide in
+/// the merged pdf (starting at 1) and the time it took to render.
+pub fn render_pdf_merged(
+    fm: &mut FontMap,
+    cache: &mut RenderCache,
+    logger: &Logger,
+    docs: &[CompiledDoc],
+    mut slides: Option<&mut SlideCache>,
+    slide_done: &mut FnMut(usize, Duration)
+) -> Result<Vec<u8>> {
+    let page = match docs.first() {
+        Some(doc) => &doc.page,
+        None => return Err(Error::value("There are no documents to render.".to_string())),
+    };
+    let page_size = page.page_size();
+    let buffer = SharedBuffer::new();
+    let surf = Surface::new_pdf_for_stream(buffer.clone(), page_size.x, page_size.y)?;
+    let mut cr = Cairo::new(surf.clone())?;
+    cr.set_source_rgb(0.0, 0.0, 0.0);
+    cr.set_line_width(6.0);
+
+    if let Some(ref mut slides) = slides {
+        slides.start_render();
+    }
+
+    let mut outline = Vec::new();
+    let mut num_slides = 0;
+    let mut num_pages = 0;
+    for doc in docs {
+        cache.set_slide_offset(num_slides);
+
+        // The page at which every slide starts, for the outline.
+        let mut slide_pages = Vec::with_capacity(doc.slides.len());
+        for (i, slide) in doc.slides.iter().enumerate() {
+            doc.cancel.check()?;
+            let slide_start = Instant::now();
+            slide_pages.push(num_pages + 1);
+            match slides {
+                Some(ref mut slides) => render_slide_cached(
+                    fm, cache, logger, &mut cr, page, i + 1, slide, slides,
+                )?,
+                None => render_slide(fm, cache, logger, &mut cr, page, i + 1, slide)?,
+            }
+            num_pages += slide.num_steps();
+            slide_done(num_slides + i + 1, slide_start.elapsed());
+        }
+        num_slides += doc.slides.len();
+
+        // A slide without content has no page, so its item links to the page
+        // after it, if there is one.
+        let children = doc.toc
+            .iter()
+            .map(|entry| OutlineItem {
+                title: entry.title.clone(),
+                page: slide_pages[entry.slide - 1],
+                children: Vec::new(),
+            })
+            .filter(|item| item.page <= num_pages)
+            .collect();
+        if let Some(&first_page) = slide_pages.first() {
+            if first_page <= num_pages {
+                outline.push(OutlineItem {
+                    title: doc.name.clone(),
+                    page: first_page,
+                    children: children,
+                });
+            }
+        }
+    }
+
+    if docs.len() > 1 {
+        add_outline(&surf, logger, &outline);
+    }
+
+    // Finishing the surface writes the last of the output.
+    drop(cr);
+    surf.finish()?;
+    if let Some(slides) = slides {
+        slides.finish_render();
+    }
+    let mut bytes = buffer.take();
+
+    // Only posters that are meant to be cut need the extra page boxes.
+    if page.margin() > 0.0 {
+        pdf::append_page_boxes(&mut bytes, &page.page_boxes())?;
+    }
+
+    if docs[0].pdfa {
+        pdf::append_pdfa(&mut bytes)?;
+    }
+
+    Ok(bytes)
+}
+
+/// The slides of an evaluated document, ready to be rendered.
+///
+/// Rendering happens in memory, so a program that embeds Pris, such as a web
+/// service, does not need to touch the filesystem. A compiled document can be
+/// sent to a worker thread to render it there.
+pub struct CompiledDoc {
+    pub slides: Vec<Slide>,
+    pub page: PageSetup,
+    /// The title of the outline item of the document in a merged pdf.
+    pub name: String,
+    /// The table of contents, for the outline of a merged pdf.
+    pub toc: Vec<TocEntry>,
+    cancel: CancelToken,
+    pixel_snap: bool,
+    font_options: Option<FontOptions>,
+    simulation: Option<Simulation>,
+    pdfa: bool,
+}
+
+impl CompiledDoc {
+    pub fn new(slides: Vec<Slide>, page: PageSetup) -> CompiledDoc {
+        CompiledDoc {
+            slides: slides,
+            page: page,
+            name: String::new(),
+            toc: Vec::new(),
+            cancel: CancelToken::none(),
+            pixel_snap: false,
+            font_options: None,
+            simulation: None,
+            pdfa: false,
+        }
+    }
+
+    /// Stop rendering with `Error::Cancelled` once `flag` is set.
+    ///
+    /// The flag is checked before every slide.
+    pub fn set_cancel(&mut self, flag: Arc<AtomicBool>) {
+        self.cancel = CancelToken::new(flag);
+    }
+
+    /// Round straight lines to the pixel grid in `render_png_bytes`.
+    ///
+    /// See `RenderCache::set_pixel_snap`. It does not affect pdf output.
+    pub fn set_pixel_snap(&mut self, pixel_snap: bool) {
+        self.pixel_snap = pixel_snap;
+    }
+
+    /// Rasterize glyphs with `options` in `render_png_bytes`.
+    ///
+    /// See `RenderCache::set_font_options`. It does not affect pdf output.
+    pub fn set_font_options(&mut self, options: FontOptions) {
+        self.font_options = Some(options);
+    }
+
+    /// Transform the colors of `render_png_bytes` output with `simulation`.
+    ///
+    /// It does not affect pdf output.
+    pub fn set_simulation(&mut self, simulation: Simulation) {
+        self.simulation = Some(simulation);
+    }
+
+    /// Make `render_pdf_bytes` write archival pdf, conforming to PDF/A-2b.
+    pub fn set_pdfa(&mut self, pdfa: bool) {
+        self.pdfa = pdfa;
+    }
+
+    /// Render all slides to a pdf, with a page for every step.
+    pub fn render_pdf_bytes(&self, fm: &mut FontMap, logger: &Logger) -> Result<Vec<u8>> {
+        self.render_pdf(fm, logger, None)
+    }
+
+    /// Render all slides to a pdf like `render_pdf_bytes`, reusing the
+    /// recordings in `slides` for slides that did not change.
+    ///
+    /// Afterwards, the cache holds the slides of this document only.
+    pub fn render_pdf_bytes_cached(
+        &self,
+        fm: &mut FontMap,
+        logger: &Logger,
+        slides: &mut SlideCache
+    ) -> Result<Vec<u8>> {
+        self.render_pdf(fm, logger, Some(slides))
+    }
+
+    fn render_pdf(&self, fm: &mut FontMap, logger: &Logger, slides: Option<&mut SlideCache>) -> Result<Vec<u8>> {
+        let mut cache = RenderCache::new();
+        render_pdf_merged(fm, &mut cache, logger, slice::from_ref(self), slides, &mut |_, _| ())
+    }
+
+    /// Render the final step of the slide at `slide_index` (starting at 0) to a png.
+    pub fn render_png_bytes(&self, fm: &mut FontMap, logger: &Logger, slide_index: usize) -> Result<Vec<u8>> {
+        self.cancel.check()?;
+        let slide = match self.slides.get(slide_index) {
+            Some(slide) => slide,
+            None => {
+                let msg = format!(
+                    "Slide index {} is out of range, the document has {} slides.",
+                    slide_index, self.slides.len(),
+                );
+                return Err(Error::value(msg))
+            }
+        };
+
+        // A slide without content has no steps, and it produces no pages.
+        let last_step = match slide.num_steps() {
+            0 => {
+                let msg = format!("Slide {} is empty, it has no page to render.", slide_index + 1);
+                return Err(Error::value(msg))
+            }
+            n => n - 1,
+        };
+
+        let page_size = self.page.page_size();
+        let surf = Surface::new_image(page_size.x, page_size.y)?;
+        let mut cr = Cairo::new(surf.clone())?;
+        cr.set_source_rgb(0.0, 0.0, 0.0);
+        cr.set_line_width(6.0);
+
+        let mut cache = RenderCache::new();
+        cache.set_pixel_snap(self.pixel_snap);
+        if let Some(options) = self.font_options {
+            cache.set_font_options(options);
+        }
+        render_step(fm, &mut cache, logger, &mut cr, &self.page, slide, last_step)?;
+        drop(cr);
+
+        if let Some(simulation) = self.simulation {
+            surf.modify_image_data(|data| simulation.apply_argb32(data))?;
+        }
+
+        let mut bytes = Vec::new();
+        surf.write_to_png_stream(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+#[test]
+fn compiled_doc_can_be_sent_to_another_thread() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<CompiledDoc>();
 }
@@ -5,37 +5,59 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
-use cairo;
-use rsvg::Svg;
+//! Elements are the output of evaluation: what to draw, and where.
+//!
+//! Elements are plain data. They do not reference the environment they were
+//! evaluated in, nor hold Cairo or librsvg objects, so they can be sent to
+//! another thread for rendering.
 
 use std::ops;
+use std::os::raw::{c_int, c_ulong};
 use std::path::PathBuf;
+use std::sync::Arc;
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct PlacedElement {
     pub position: Vec2,
     pub element: Element,
 }
 
 /// A 2D vector type used for coordinates and offsets.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct Vec2 {
     pub x: f64,
     pub y: f64,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum Element {
+    /// Elements that are only visible inside a rectangle, given by its top
+    /// left corner and size, relative to the position of the element.
+    Clipped(Arc<Vec<PlacedElement>>, Vec2, Vec2),
     FillPolygon(FillPolygon),
+    /// Elements shared between several placements, positioned relative to the
+    /// position of the group.
+    Group(Arc<Vec<PlacedElement>>),
     Png(PathBuf),
-    Scaled(Vec<PlacedElement>, f64),
     StrokePolygon(StrokePolygon),
-    Svg(Svg),
+    Svg(PathBuf),
+    Symbol(Arc<Symbol>),
     Text(Text),
+    /// Elements scaled by the factor, and positioned relative to the position
+    /// of the element. The elements may be shared with the frame they were
+    /// taken from, so translating or scaling a frame does not copy them.
+    Transformed(Arc<Vec<PlacedElement>>, f64),
+    /// Elements transformed by the affine transform, relative to the position
+    /// of the element. Like `Transformed`, the elements may be shared.
+    Affine(Arc<Vec<PlacedElement>>, Affine),
+    /// Elements that are drawn together, and then blended onto what is
+    /// beneath with the opacity, from 0 to 1. Overlapping elements do not
+    /// show through each other, unlike with translucent colors.
+    Opacity(Arc<Vec<PlacedElement>>, f64),
     Hyperlink(Hyperlink),
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum PolygonKind {
     /// The points are vertices are connected by lines.
     Lines,
@@ -43,14 +65,14 @@ pub enum PolygonKind {
     Curves,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct FillPolygon {
-    pub color: Color,
+    pub paint: Paint,
     pub vertices: Vec<Vec2>,
     pub kind: PolygonKind,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct StrokePolygon {
     pub color: Color,
     pub line_width: f64,
@@ -59,27 +81,142 @@ pub struct StrokePolygon {
     pub kind: PolygonKind,
 }
 
+/// How the inside of a shape is colored.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Paint {
+    Solid(Color),
+    Gradient(Gradient),
+}
+
+/// A transition between two colors, stretched over the bounds of a shape.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    pub from: Color,
+    pub to: Color,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum GradientKind {
+    /// From one side to the other, at an angle in degrees. At 0 degrees,
+    /// `from` is on the left, and positive angles rotate clockwise.
+    Linear(f64),
+    /// From the center outward, reaching `to` at the corners.
+    Radial,
+}
+
 // TODO: What color space is this? A linear RGB space would be nice.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct Color {
     pub r: f64,
     pub g: f64,
     pub b: f64,
+    /// Opacity, from 0 for transparent to 1 for opaque. Not premultiplied.
+    pub a: f64,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Text {
     pub color: Color,
     pub font_family: String,
     pub font_style: String,
     pub font_size: f64,
-    pub glyphs: Vec<cairo::Glyph>,
+    pub glyphs: Vec<Glyph>,
+    /// The text that the glyphs represent, empty if unknown.
+    pub text: String,
+    /// Mapping of bytes in `text` to glyphs, empty if the text is unknown.
+    pub clusters: Vec<TextCluster>,
 }
 
-#[derive(Clone)]
+/// A glyph index and the position of the glyph.
+///
+/// The representation must match that of `cairo_glyph_t`, so a slice of glyphs
+/// can be passed to Cairo directly.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq)]
+pub struct Glyph {
+    index: c_ulong,
+    x: f64,
+    y: f64,
+}
+
+/// A mapping of a number of bytes of text to a number of glyphs.
+///
+/// Clusters tell Cairo which glyphs represent which text, so the text can be
+/// embedded in the output for copy-paste and screen readers. The
+/// representation must match that of `cairo_text_cluster_t`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextCluster {
+    num_bytes: c_int,
+    num_glyphs: c_int,
+}
+
+/// Elements that are drawn once, and then stamped wherever they are placed.
+///
+/// The renderer records the drawing operations when the symbol is first drawn,
+/// and every placement refers to that recording.
+#[derive(PartialEq)]
+pub struct Symbol {
+    pub elements: Vec<PlacedElement>,
+}
+
+#[derive(Clone, PartialEq)]
 pub struct Hyperlink {
     pub size: Vec2,
-    pub uri: String,
+    pub target: LinkTarget,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum LinkTarget {
+    /// An external link, validated and escaped.
+    Uri(String),
+    /// An internal link to a slide of the document, starting at 1.
+    Slide(usize),
+}
+
+impl Glyph {
+    pub fn new(index: u64, x: f64, y: f64) -> Glyph {
+        Glyph {
+            index: index as c_ulong,
+            x: x,
+            y: y,
+        }
+    }
+
+    /// Return the position of the glyph origin, on the baseline.
+    #[cfg(feature = "native")]
+    pub fn position(&self) -> Vec2 {
+        Vec2::new(self.x, self.y)
+    }
+
+    /// Return the index of the glyph in its font.
+    #[cfg(feature = "native")]
+    pub fn index(&self) -> u64 {
+        self.index as u64
+    }
+
+    /// Make a copy of the glyph, offset by the specified amount.
+    pub fn offset(&self, dx: f64, dy: f64) -> Glyph {
+        Glyph::new(self.index as u64, self.x + dx, self.y + dy)
+    }
+}
+
+impl TextCluster {
+    pub fn new(num_bytes: usize, num_glyphs: usize) -> TextCluster {
+        TextCluster {
+            num_bytes: num_bytes as c_int,
+            num_glyphs: num_glyphs as c_int,
+        }
+    }
+
+    pub fn num_bytes(&self) -> usize {
+        self.num_bytes as usize
+    }
+
+    pub fn num_glyphs(&self) -> usize {
+        self.num_glyphs as usize
+    }
 }
 
 impl Vec2 {
@@ -131,8 +268,350 @@ impl ops::Neg for Vec2 {
     }
 }
 
+/// An affine transform of the plane.
+///
+/// A point `(x, y)` maps to `(xx * x + xy * y + x0, yx * x + yy * y + y0)`.
+/// The fields are in the same order as those of Cairo's `cairo_matrix_t`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Affine {
+    pub xx: f64,
+    pub yx: f64,
+    pub xy: f64,
+    pub yy: f64,
+    pub x0: f64,
+    pub y0: f64,
+}
+
+impl Affine {
+    pub fn identity() -> Affine {
+        Affine::scale(1.0, 1.0)
+    }
+
+    pub fn translate(offset: Vec2) -> Affine {
+        Affine {
+            xx: 1.0, yx: 0.0,
+            xy: 0.0, yy: 1.0,
+            x0: offset.x, y0: offset.y,
+        }
+    }
+
+    /// Rotate by the angle with the given sine and cosine.
+    ///
+    /// The y-axis points down, so a positive angle rotates clockwise.
+    pub fn rotate(sin: f64, cos: f64) -> Affine {
+        Affine {
+            xx: cos, yx: sin,
+            xy: -sin, yy: cos,
+            x0: 0.0, y0: 0.0,
+        }
+    }
+
+    pub fn scale(sx: f64, sy: f64) -> Affine {
+        Affine {
+            xx: sx, yx: 0.0,
+            xy: 0.0, yy: sy,
+            x0: 0.0, y0: 0.0,
+        }
+    }
+
+    pub fn apply(&self, p: Vec2) -> Vec2 {
+        Vec2::new(
+            self.xx * p.x + self.xy * p.y + self.x0,
+            self.yx * p.x + self.yy * p.y + self.y0,
+        )
+    }
+
+    /// Return the factor by which the transform scales areas.
+    ///
+    /// The transform is invertible if and only if this is nonzero.
+    pub fn determinant(&self) -> f64 {
+        self.xx * self.yy - self.xy * self.yx
+    }
+}
+
+/// Compose transforms: `a * b` applies `b` first, and then `a`.
+impl ops::Mul<Affine> for Affine {
+    type Output = Affine;
+
+    fn mul(self, rhs: Affine) -> Affine {
+        Affine {
+            xx: self.xx * rhs.xx + self.xy * rhs.yx,
+            yx: self.yx * rhs.xx + self.yy * rhs.yx,
+            xy: self.xx * rhs.xy + self.xy * rhs.yy,
+            yy: self.yx * rhs.xy + self.yy * rhs.yy,
+            x0: self.xx * rhs.x0 + self.xy * rhs.y0 + self.x0,
+            y0: self.yx * rhs.x0 + self.yy * rhs.y0 + self.y0,
+        }
+    }
+}
+
+#[test]
+fn affine_mul_applies_right_hand_side_first() {
+    let m = Affine::translate(Vec2::new(10.0, 0.0)) * Affine::rotate(1.0, 0.0) * Affine::scale(2.0, 2.0);
+    let p = m.apply(Vec2::new(1.0, 0.0));
+    assert_eq!((p.x, p.y), (10.0, 2.0));
+    assert_eq!(m.determinant(), 4.0);
+    assert_eq!(Affine::identity() * m, m);
+}
+
 impl Color {
+    /// Return an opaque color.
     pub fn new(r: f64, g: f64, b: f64) -> Color {
-        Color { r: r, g: g, b: b }
+        Color::new_rgba(r, g, b, 1.0)
+    }
+
+    pub fn new_rgba(r: f64, g: f64, b: f64, a: f64) -> Color {
+        Color { r: r, g: g, b: b, a: a }
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        self.a >= 1.0
+    }
+
+    /// Return the color with its alpha multiplied by `opacity`.
+    pub fn fade(&self, opacity: f64) -> Color {
+        Color::new_rgba(self.r, self.g, self.b, self.a * opacity)
+    }
+
+    /// Return the opaque color that results from drawing this color on top
+    /// of the color `beneath`.
+    pub fn over(&self, beneath: Color) -> Color {
+        let blend = |x: f64, y: f64| x * self.a + y * (1.0 - self.a);
+        Color::new(blend(self.r, beneath.r), blend(self.g, beneath.g), blend(self.b, beneath.b))
+    }
+
+    /// Return the relative luminance, as defined by WCAG 2.0.
+    ///
+    /// This assumes that the components are sRGB-encoded.
+    pub fn luminance(&self) -> f64 {
+        fn linear(x: f64) -> f64 {
+            if x <= 0.03928 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) }
+        }
+        0.2126 * linear(self.r) + 0.7152 * linear(self.g) + 0.0722 * linear(self.b)
+    }
+
+    /// Return the WCAG 2.0 contrast ratio between two colors, from 1 to 21.
+    pub fn contrast_ratio(&self, other: Color) -> f64 {
+        let (l1, l2) = (self.luminance(), other.luminance());
+        (l1.max(l2) + 0.05) / (l1.min(l2) + 0.05)
+    }
+}
+
+#[test]
+fn color_contrast_ratio_matches_wcag() {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let white = Color::new(1.0, 1.0, 1.0);
+    assert!((black.contrast_ratio(white) - 21.0).abs() < 1e-9);
+    assert!((white.contrast_ratio(black) - 21.0).abs() < 1e-9);
+    assert_eq!(white.contrast_ratio(white), 1.0);
+    // #777777 on white is just below the 4.5:1 threshold for normal text.
+    let gray = Color::new(0x77 as f64 / 255.0, 0x77 as f64 / 255.0, 0x77 as f64 / 255.0);
+    let ratio = gray.contrast_ratio(white);
+    assert!(ratio > 4.47 && ratio < 4.48);
+}
+
+/// A 64-bit FNV-1a hash of the content of elements.
+///
+/// Unlike the hasher in the standard library, the algorithm is fixed, so the
+/// hash of the same content stays the same across runs and Rust versions.
+pub struct ContentHasher {
+    state: u64,
+}
+
+impl ContentHasher {
+    pub fn new() -> ContentHasher {
+        ContentHasher { state: 0xcbf2_9ce4_8422_2325 }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.state ^= b as u64;
+            self.state = self.state.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, x: u64) {
+        let bytes = [
+            x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8,
+            (x >> 32) as u8, (x >> 40) as u8, (x >> 48) as u8, (x >> 56) as u8,
+        ];
+        self.write(&bytes);
+    }
+
+    /// Write a string, prefixed with its length so that the boundary between
+    /// consecutive strings is part of the hash.
+    pub fn write_str(&mut self, s: &str) {
+        self.write_u64(s.len() as u64);
+        self.write(s.as_bytes());
+    }
+}
+
+#[cfg(feature = "native")]
+impl ContentHasher {
+    pub fn write_f64(&mut self, x: f64) {
+        self.write_u64(x.to_bits());
+    }
+
+    pub fn write_vec2(&mut self, v: Vec2) {
+        self.write_f64(v.x);
+        self.write_f64(v.y);
+    }
+
+    pub fn write_color(&mut self, c: Color) {
+        self.write_f64(c.r);
+        self.write_f64(c.g);
+        self.write_f64(c.b);
+        self.write_f64(c.a);
+    }
+
+    pub fn write_paint(&mut self, paint: Paint) {
+        match paint {
+            Paint::Solid(color) => {
+                self.write(b"s");
+                self.write_color(color);
+            }
+            Paint::Gradient(gradient) => {
+                match gradient.kind {
+                    GradientKind::Linear(angle) => {
+                        self.write(b"l");
+                        self.write_f64(angle);
+                    }
+                    GradientKind::Radial => self.write(b"r"),
+                }
+                self.write_color(gradient.from);
+                self.write_color(gradient.to);
+            }
+        }
+    }
+
+    pub fn write_elements(&mut self, elements: &[PlacedElement]) {
+        self.write_u64(elements.len() as u64);
+        for pe in elements {
+            self.write_vec2(pe.position);
+            self.write_element(&pe.element);
+        }
+    }
+
+    fn write_vertices(&mut self, vertices: &[Vec2], kind: PolygonKind) {
+        self.write(&[kind as u8]);
+        self.write_u64(vertices.len() as u64);
+        for &v in vertices {
+            self.write_vec2(v);
+        }
     }
+
+    fn write_element(&mut self, element: &Element) {
+        // Every variant starts with a distinct tag, so different elements with
+        // the same fields do not collide.
+        match *element {
+            Element::Clipped(ref elements, top_left, size) => {
+                self.write(b"C");
+                self.write_vec2(top_left);
+                self.write_vec2(size);
+                self.write_elements(elements);
+            }
+            Element::FillPolygon(ref polygon) => {
+                self.write(b"F");
+                self.write_paint(polygon.paint);
+                self.write_vertices(&polygon.vertices, polygon.kind);
+            }
+            Element::Group(ref elements) => {
+                self.write(b"G");
+                self.write_elements(elements);
+            }
+            Element::Png(ref path) => {
+                self.write(b"P");
+                self.write_str(&path.to_string_lossy());
+            }
+            Element::StrokePolygon(ref polygon) => {
+                self.write(b"S");
+                self.write_color(polygon.color);
+                self.write_f64(polygon.line_width);
+                self.write(&[polygon.close as u8]);
+                self.write_vertices(&polygon.vertices, polygon.kind);
+            }
+            Element::Svg(ref path) => {
+                self.write(b"V");
+                self.write_str(&path.to_string_lossy());
+            }
+            Element::Symbol(ref symbol) => {
+                self.write(b"Y");
+                self.write_elements(&symbol.elements);
+            }
+            Element::Text(ref text) => {
+                self.write(b"T");
+                self.write_color(text.color);
+                self.write_str(&text.font_family);
+                self.write_str(&text.font_style);
+                self.write_f64(text.font_size);
+                self.write_u64(text.glyphs.len() as u64);
+                for glyph in &text.glyphs {
+                    self.write_u64(glyph.index as u64);
+                    self.write_f64(glyph.x);
+                    self.write_f64(glyph.y);
+                }
+                self.write_str(&text.text);
+                for cluster in &text.clusters {
+                    self.write_u64(cluster.num_bytes() as u64);
+                    self.write_u64(cluster.num_glyphs() as u64);
+                }
+            }
+            Element::Transformed(ref elements, scale) => {
+                self.write(b"X");
+                self.write_f64(scale);
+                self.write_elements(elements);
+            }
+            Element::Affine(ref elements, m) => {
+                self.write(b"A");
+                for &x in &[m.xx, m.yx, m.xy, m.yy, m.x0, m.y0] {
+                    self.write_f64(x);
+                }
+                self.write_elements(elements);
+            }
+            Element::Opacity(ref elements, opacity) => {
+                self.write(b"O");
+                self.write_f64(opacity);
+                self.write_elements(elements);
+            }
+            Element::Hyperlink(ref hyperlink) => {
+                self.write(b"L");
+                self.write_vec2(hyperlink.size);
+                match hyperlink.target {
+                    LinkTarget::Uri(ref uri) => { self.write(b"U"); self.write_str(uri); }
+                    LinkTarget::Slide(n) => { self.write(b"N"); self.write_u64(n as u64); }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn content_hasher_is_fnv_1a() {
+    // Reference values of the FNV-1a 64 hash, which must never change.
+    let mut hasher = ContentHasher::new();
+    assert_eq!(hasher.finish(), 0xcbf29ce484222325);
+    hasher.write(b"a");
+    assert_eq!(hasher.finish(), 0xaf63dc4c8601ec8c);
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn content_hasher_distinguishes_moved_elements() {
+    let polygon = Element::FillPolygon(FillPolygon {
+        paint: Paint::Solid(Color::new(0.0, 0.0, 0.0)),
+        vertices: vec![Vec2::zero(), Vec2::new(1.0, 0.0), Vec2::new(0.0, 1.0)],
+        kind: PolygonKind::Lines,
+    });
+    let hash_at = |x: f64| {
+        let mut hasher = ContentHasher::new();
+        hasher.write_elements(&[PlacedElement { position: Vec2::new(x, 0.0), element: polygon.clone() }]);
+        hasher.finish()
+    };
+    assert_eq!(hash_at(1.0), hash_at(1.0));
+    assert!(hash_at(1.0) != hash_at(2.0));
 }
@@ -5,11 +5,14 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
+use std::io;
 use std::result;
 
 use ast::Idents;
 use pretty::Formatter;
-use types::ValType;
+use runtime::Val;
+use symbol::Symbol;
+use types::{LenDim, ValType, describe_dimension};
 
 // Error message guidelines:
 //
@@ -19,10 +22,30 @@ use types::ValType;
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// Broad categories of errors, for callers that handle them differently.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// The source is not syntactically valid.
+    Parse,
+    /// Evaluating the document failed, for example due to a type error.
+    Eval,
+    /// A font, image, or other input that the document refers to is missing
+    /// or invalid.
+    Resource,
+    /// Reading the source or writing the output failed.
+    Io,
+    /// The host cancelled evaluation or rendering.
+    Cancelled,
+}
+
 #[derive(Debug)]
 pub enum Error {
     Arity(ArityError),
+    Cairo(CairoError),
+    Cancelled,
     Format(FormatError),
+    Io(IoError),
+    Limit(LimitError),
     MissingFile(MissingFileError),
     MissingFont(MissingFontError),
     Parse(ParseError),
@@ -31,12 +54,28 @@ pub enum Error {
     Other(String),
 }
 
+#[derive(Debug)]
+pub struct CairoError {
+    message: String,
+}
+
 #[derive(Debug)]
 pub struct FormatError {
     path: String,
     message: &'static str,
 }
 
+#[derive(Debug)]
+pub struct IoError {
+    path: String,
+    message: String,
+}
+
+#[derive(Debug)]
+pub struct LimitError {
+    message: String,
+}
+
 #[derive(Debug)]
 pub struct MissingFileError {
     path: String,
@@ -81,7 +120,7 @@ pub struct ValueError {
 }
 
 impl Error {
-    pub fn arity(fn_name: &str, expected: u32, actual: u32) -> Error {
+    pub fn arity(fn_name: Symbol, expected: u32, actual: u32) -> Error {
         let mut f = Formatter::new();
         f.print("'");
         f.print(fn_name);
@@ -130,7 +169,33 @@ impl Error {
         Error::Type(type_error)
     }
 
-    pub fn arg_type(fn_name: &str,
+    /// Error for an operator applied to values of different dimensions.
+    ///
+    /// The expression is included, because there is no source location.
+    pub fn dimension(op_name: &str,
+                     expression: &str,
+                     actual_lhs: LenDim,
+                     actual_rhs: LenDim)
+                     -> Error {
+        let mut f = Formatter::new();
+        f.print("'");
+        f.print(op_name);
+        f.print("' expects operands of the same dimension, but found ");
+        f.print(&describe_dimension(actual_lhs)[..]);
+        f.print(" and ");
+        f.print(&describe_dimension(actual_rhs)[..]);
+        f.print(", in '");
+        f.print(expression);
+        f.print("'. Note: 'as_len' and 'as_num' convert between numbers and lengths.");
+        let type_error = TypeError {
+            expected: ValType::Num(actual_lhs),
+            actual: ValType::Num(actual_rhs),
+            message: f.into_string(),
+        };
+        Error::Type(type_error)
+    }
+
+    pub fn arg_type(fn_name: Symbol,
                     expected: ValType,
                     actual: ValType,
                     arg_num: u32)
@@ -203,16 +268,20 @@ impl Error {
         Error::Type(type_error)
     }
 
-    pub fn list_type(expected_element_type: ValType, actual_element_type: ValType) -> Error {
+    /// Error for a list element at `index` that has a different type than the
+    /// elements before it.
+    pub fn list_type(expected_element: &Val, actual_element: &Val, index: usize) -> Error {
         let mut f = Formatter::new();
         f.print("Encountered elements of type '");
-        f.print(expected_element_type);
+        print_element_type(&mut f, expected_element);
         f.print("' as well as '");
-        f.print(actual_element_type);
-        f.print("' in one list, but all elements must have the same type.");
+        print_element_type(&mut f, actual_element);
+        f.print("' in one list, at index ");
+        f.print(index);
+        f.print(", but all elements must have the same type.");
         let type_error = TypeError {
-            expected: expected_element_type,
-            actual: actual_element_type,
+            expected: expected_element.get_type(),
+            actual: actual_element.get_type(),
             message: f.into_string(),
         };
         Error::Type(type_error)
@@ -225,6 +294,13 @@ impl Error {
         Error::Value(err)
     }
 
+    pub fn limit(message: String) -> Error {
+        let err = LimitError {
+            message: message,
+        };
+        Error::Limit(err)
+    }
+
     pub fn missing_font(family: String, style: String) -> Error {
         let err = MissingFontError {
             family: family,
@@ -240,6 +316,21 @@ impl Error {
         Error::MissingFile(err)
     }
 
+    pub fn cairo(message: String) -> Error {
+        let err = CairoError {
+            message: message,
+        };
+        Error::Cairo(err)
+    }
+
+    pub fn io(path: String, message: String) -> Error {
+        let err = IoError {
+            path: path,
+            message: message,
+        };
+        Error::Io(err)
+    }
+
     pub fn format(path: String, message: &'static str) -> Error {
         let err = FormatError {
             path: path,
@@ -257,18 +348,59 @@ impl Error {
         Error::Parse(err)
     }
 
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            Error::Parse(..) => ErrorKind::Parse,
+            Error::Arity(..) => ErrorKind::Eval,
+            Error::Limit(..) => ErrorKind::Eval,
+            Error::Type(..) => ErrorKind::Eval,
+            Error::Value(..) => ErrorKind::Eval,
+            Error::Other(..) => ErrorKind::Eval,
+            Error::Format(..) => ErrorKind::Resource,
+            Error::MissingFile(..) => ErrorKind::Resource,
+            Error::MissingFont(..) => ErrorKind::Resource,
+            Error::Cairo(..) => ErrorKind::Io,
+            Error::Io(..) => ErrorKind::Io,
+            Error::Cancelled => ErrorKind::Cancelled,
+        }
+    }
+
+    /// Return the message that describes the error.
+    pub fn message(&self) -> String {
+        match *self {
+            Error::Arity(ref ae) => ae.message.clone(),
+            Error::Cairo(ref ce) => format!("Cairo reported an error: {}", ce.message),
+            Error::Cancelled => String::from("Cancelled."),
+            Error::Format(ref f) => format!("The file '{}' is invalid. {}", f.path, f.message),
+            Error::Io(ref ie) => format!("Failed to access '{}': {}", ie.path, ie.message),
+            Error::Limit(ref le) => le.message.clone(),
+            Error::MissingFile(ref mf) => format!("The file '{}' does not exist.", mf.path),
+            Error::MissingFont(ref mf) => format!("The font '{} {}' cannot be found.", mf.family, mf.style),
+            Error::Other(ref ot) => ot.clone(),
+            Error::Parse(ref pe) => pe.message.clone(),
+            Error::Type(ref tye) => tye.message.clone(),
+            Error::Value(ref ve) => ve.message.clone(),
+        }
+    }
+
     pub fn print(&self) {
+        self.write_to(&mut io::stdout()).ok();
+    }
+
+    /// Like `print`, but write to `out` rather than stdout.
+    pub fn write_to(&self, out: &mut io::Write) -> io::Result<()> {
         // Print in red using ANSI escape codes.
-        print!("\x1b[31;1mError: \x1b[0m");
-        match *self {
-            Error::Arity(ref ae) => println!("{}\n", ae.message),
-            Error::Format(ref f) => println!("The file '{}' is invalid. {}\n", f.path, f.message),
-            Error::MissingFile(ref mf) => println!("The file '{}' does not exist.\n", mf.path),
-            Error::MissingFont(ref mf) => println!("The font '{} {}' cannot be found.\n", mf.family, mf.style),
-            Error::Other(ref ot) => println!("{}\n", ot),
-            Error::Parse(ref pe) => println!("{}\n", pe.message),
-            Error::Type(ref tye) => println!("{}\n", tye.message),
-            Error::Value(ref ve) => println!("{}\n", ve.message),
+        write!(out, "\x1b[31;1mError: \x1b[0m{}\n\n", self.message())
+    }
+}
+
+/// Print the type of a value, including the element type of nested lists.
+fn print_element_type(f: &mut Formatter, val: &Val) {
+    f.print(val.get_type());
+    if let Val::List(ref elements) = *val {
+        if let Some(first) = elements.first() {
+            f.print(" of ");
+            print_element_type(f, first);
         }
     }
 }
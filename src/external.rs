@@ -0,0 +1,174 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module runs external programs that draw a diagram from a description,
+//! such as Mermaid or PlantUML, for `external_svg()`.
+//!
+//! A document cannot run arbitrary commands. The host configures tools by
+//! name, and the document can only refer to a tool by that name. The program
+//! reads the description on stdin, and prints an svg image on stdout. Images
+//! are cached by the hash of the command and the input, because these programs
+//! are often slow to start.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use elements::ContentHasher;
+use error::{Error, Result};
+
+/// The commands that documents can run, and where their output is cached.
+pub struct Tools {
+    /// Pairs of a name and a shell command.
+    commands: Vec<(String, String)>,
+    cache_dir: PathBuf,
+}
+
+impl Tools {
+    /// Return a set of tools that is empty, so running any tool fails.
+    pub fn none() -> Tools {
+        Tools::new(PathBuf::new())
+    }
+
+    /// Return an empty set of tools that caches images in `cache_dir`.
+    ///
+    /// The directory is created when the first image is written to it.
+    pub fn new(cache_dir: PathBuf) -> Tools {
+        Tools {
+            commands: Vec::new(),
+            cache_dir: cache_dir,
+        }
+    }
+
+    /// Make the shell command `command` available under `name`.
+    pub fn add(&mut self, name: String, command: String) {
+        self.commands.retain(|&(ref n, _)| *n != name);
+        self.commands.push((name, command));
+    }
+
+    fn command(&self, name: &str) -> Result<&str> {
+        match self.commands.iter().find(|&&(ref n, _)| n == name) {
+            Some(&(_, ref command)) => Ok(command),
+            None => {
+                let msg = format!(
+                    "There is no external tool named '{}'. \
+                     Note: pass --tool {}=<command> to let documents run <command>.",
+                    name, name,
+                );
+                Err(Error::Other(msg))
+            }
+        }
+    }
+
+    /// Return the path of the svg that tool `name` prints for `input`.
+    ///
+    /// The tool only runs when the cache has no image for the same command
+    /// and input yet.
+    pub fn render(&self, name: &str, input: &str) -> Result<PathBuf> {
+        let command = self.command(name)?;
+
+        let mut hasher = ContentHasher::new();
+        hasher.write_str(command);
+        hasher.write_str(input);
+        let path = self.cache_dir.join(format!("{}-{:016x}.svg", name, hasher.finish()));
+        if path.is_file() {
+            return Ok(path)
+        }
+
+        let svg = run(name, command, input)?;
+
+        // Write to a temporary file first, so an interrupted write does not
+        // leave an incomplete image in the cache.
+        let dir = self.cache_dir.to_string_lossy().into_owned();
+        let io_error = |e: ::std::io::Error| Error::io(dir.clone(), e.to_string());
+        fs::create_dir_all(&self.cache_dir).map_err(&io_error)?;
+        let tmp_path = path.with_extension("svg.tmp");
+        fs::write(&tmp_path, &svg).map_err(&io_error)?;
+        fs::rename(&tmp_path, &path).map_err(&io_error)?;
+        Ok(path)
+    }
+}
+
+/// Run `command` in a shell with `input` on stdin, and return its stdout.
+fn run(name: &str, command: &str, input: &str) -> Result<Vec<u8>> {
+    let mut child = Command::new("sh")
+        .args(&["-c", command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Other(format!("Failed to run external tool '{}': {}.", name, e)))?;
+
+    // Write from a different thread, so the tool does not block on a full
+    // stdout pipe while we block on a full stdin pipe.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().map_err(|e| Error::Other(e.to_string()))?;
+    // The tool may exit without reading all of its input, and then the write
+    // fails. The exit status tells whether that was a problem.
+    writer.join().unwrap().ok();
+
+    if !output.status.success() {
+        let msg = format!(
+            "External tool '{}' failed: {}",
+            name, String::from_utf8_lossy(&output.stderr).trim(),
+        );
+        return Err(Error::Other(msg))
+    }
+
+    if !String::from_utf8_lossy(&output.stdout).contains("<svg") {
+        let msg = format!("External tool '{}' did not print an svg image.", name);
+        return Err(Error::Other(msg))
+    }
+
+    Ok(output.stdout)
+}
+
+#[test]
+fn render_caches_output_by_command_and_input() {
+    let dir = ::std::env::temp_dir().join(format!("pris-external-{}", ::std::process::id()));
+    let mut tools = Tools::new(dir.clone());
+    tools.add("cat".to_string(), "cat".to_string());
+    let svg = "<svg xmlns='http://www.w3.org/2000/svg' width='2' height='1'/>";
+
+    let path = tools.render("cat", svg).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), svg.as_bytes());
+
+    // With a cached image, the tool does not run again.
+    tools.add("cat".to_string(), "cat".to_string());
+    fs::write(&path, b"<svg/>").unwrap();
+    assert_eq!(tools.render("cat", svg).unwrap(), path);
+    assert_eq!(fs::read(&path).unwrap(), b"<svg/>");
+
+    // A different command is a different cache entry.
+    tools.add("cat".to_string(), "cat -".to_string());
+    assert!(tools.render("cat", svg).unwrap() != path);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn render_reports_failing_and_missing_tools() {
+    let mut tools = Tools::none();
+    tools.add("fail".to_string(), "echo 'no such diagram' >&2; exit 1".to_string());
+    tools.add("text".to_string(), "echo hello".to_string());
+    match tools.render("fail", "") {
+        Err(Error::Other(msg)) => assert_eq!(msg, "External tool 'fail' failed: no such diagram"),
+        _ => panic!("Expected the tool to fail."),
+    }
+    match tools.render("text", "") {
+        Err(Error::Other(msg)) => assert!(msg.contains("did not print an svg"), "{}", msg),
+        _ => panic!("Expected the tool to fail."),
+    }
+    match tools.render("mermaid", "") {
+        Err(Error::Other(msg)) => assert!(msg.contains("--tool mermaid=<command>"), "{}", msg),
+        _ => panic!("Expected the tool to be missing."),
+    }
+}
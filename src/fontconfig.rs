@@ -6,7 +6,6 @@
 // of the License is available in the root of the repository.
 
 use std::ffi::{CStr, CString, OsStr};
-use std::mem;
 use std::os::raw::{c_int, c_char, c_uchar};
 use std::os::unix::ffi::OsStrExt;
 use std::path::PathBuf;
@@ -63,7 +62,7 @@ pub fn get_font_location(font_query: &str) -> Option<PathBuf> {
         // Fontconfig insists on using a non-standard character type, but it
         // only differs in signedness, which is arbitrary for characters anyway.
         let query_cstr = CString::new(font_query).unwrap();
-        let query_char8: *const FcChar8 = mem::transmute(query_cstr.as_ptr());
+        let query_char8 = query_cstr.as_ptr() as *const FcChar8;
         let pattern = FcNameParse(query_char8);
 
         // The docs say that FcConfigSubstitute must be called, although it
@@ -93,9 +92,9 @@ pub fn get_font_location(font_query: &str) -> Option<PathBuf> {
             // Do the conversion dance: from *mut c_str to PathBuf. PathBuf owns
             // its contents: we make a copy of Fontconfig's string, so we can
             // free it afterwards. It is an extra copy, but it is far more
-            // convenient than the alternative. Also transmute Fontconfig's
-            // signed character strings once more.
-            let fname_cstr = CStr::from_ptr(mem::transmute(fname_ptr));
+            // convenient than the alternative. Also cast Fontconfig's
+            // unsigned character strings once more.
+            let fname_cstr = CStr::from_ptr(fname_ptr as *const c_char);
             let fname_osstr = OsStr::from_bytes(fname_cstr.to_bytes());
             result = Some(PathBuf::from(fname_osstr));
         }
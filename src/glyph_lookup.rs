@@ -0,0 +1,48 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module finds glyph indices in a FreeType face by character or by name.
+//!
+//! The `freetype` crate does not wrap these lookups, so we call FreeType
+//! directly, with the face that the crate owns.
+
+use freetype;
+use freetype::freetype_sys::FT_Face;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_uint, c_ulong};
+
+#[link(name = "freetype")]
+extern {
+    fn FT_Get_Char_Index(face: FT_Face, charcode: c_ulong) -> c_uint;
+    fn FT_Get_Name_Index(face: FT_Face, glyph_name: *const c_char) -> c_uint;
+}
+
+/// Return the index of the glyph that the font maps the character to.
+///
+/// Returns `None` if the font has no glyph for the character.
+pub fn char_index(face: &mut freetype::Face, ch: char) -> Option<u64> {
+    // Index 0 is the "missing glyph", which FreeType returns when not found.
+    match unsafe { FT_Get_Char_Index(face.raw_mut(), ch as c_ulong) } {
+        0 => None,
+        index => Some(index as u64),
+    }
+}
+
+/// Return the index of the glyph with the given PostScript name, such as "uni2713".
+///
+/// Returns `None` if there is no such glyph, or if the font has no glyph names.
+pub fn name_index(face: &mut freetype::Face, name: &str) -> Option<u64> {
+    // A name with a nul byte cannot be the name of any glyph.
+    let name_cstr = match CString::new(name) {
+        Ok(cstr) => cstr,
+        Err(..) => return None,
+    };
+    match unsafe { FT_Get_Name_Index(face.raw_mut(), name_cstr.as_ptr()) } {
+        0 => None,
+        index => Some(index as u64),
+    }
+}
@@ -7,7 +7,6 @@
 
 use freetype::freetype_sys::FT_Face;
 use freetype;
-use std::mem;
 use std::os::raw::{c_char, c_int, c_uint, c_void};
 use std::ptr;
 use std::slice;
@@ -27,6 +26,14 @@ type hb_direction_t = c_int;
 #[allow(non_camel_case_types)]
 type hb_bool_t = c_int;
 
+// An interned language tag, a null pointer is the invalid language.
+#[allow(non_camel_case_types)]
+type hb_language_t = *const c_void;
+
+// Like hb_direction_t, this is an enum in C. Its values are ISO 15924 tags.
+#[allow(non_camel_case_types)]
+type hb_script_t = c_int;
+
 #[repr(C)]
 #[allow(non_camel_case_types)]
 struct hb_glyph_info_t {
@@ -81,7 +88,7 @@ impl FontFeature {
         };
         let parsed_ok = unsafe {
             hb_feature_from_string(
-                mem::transmute(feature_description.as_ptr()),
+                feature_description.as_ptr() as *const c_char,
                 feature_description.len() as c_int,
                 &mut feature,
             )
@@ -109,6 +116,11 @@ mod hb {
     pub const HB_DIRECTION_RTL: hb_direction_t = 5;
     pub const HB_DIRECTION_TTB: hb_direction_t = 6;
     pub const HB_DIRECTION_BTT: hb_direction_t = 7;
+
+    use harfbuzz::hb_script_t;
+    pub const HB_SCRIPT_INVALID: hb_script_t = 0;
+    // The tag 'Zzzz', for malformed script tags.
+    pub const HB_SCRIPT_UNKNOWN: hb_script_t = 0x5a7a7a7a;
 }
 
 #[link(name = "harfbuzz")]
@@ -118,6 +130,11 @@ extern {
     fn hb_buffer_create() -> *mut hb_buffer_t;
     fn hb_buffer_destroy(buffer: *mut hb_buffer_t);
     fn hb_buffer_set_direction(buffer: *mut hb_buffer_t, direction: hb_direction_t);
+    fn hb_buffer_set_language(buffer: *mut hb_buffer_t, language: hb_language_t);
+    fn hb_buffer_set_script(buffer: *mut hb_buffer_t, script: hb_script_t);
+    fn hb_buffer_guess_segment_properties(buffer: *mut hb_buffer_t);
+    fn hb_language_from_string(string: *const c_char, length: c_int) -> hb_language_t;
+    fn hb_script_from_string(string: *const c_char, length: c_int) -> hb_script_t;
     fn hb_buffer_add_utf8(buffer: *mut hb_buffer_t, text: *const c_char, text_len: c_int, item_offset: c_uint, item_length: c_int);
     fn hb_shape(font: *mut hb_font_t, buffer: *mut hb_buffer_t, features: *const hb_feature_t, num_features: c_uint);
     fn hb_buffer_get_glyph_infos(buffer: *mut hb_buffer_t, length: *mut c_uint) -> *mut hb_glyph_info_t;
@@ -142,9 +159,46 @@ pub enum Direction {
     BottomToTop,
 }
 
+/// A language, which selects language-specific forms in shaping.
+#[derive(Copy, Clone)]
+pub struct Language(hb_language_t);
+
+impl Language {
+    /// Parse a BCP 47 language tag, such as `tr` or `pl-PL`.
+    pub fn from_str(tag: &str) -> Option<Language> {
+        let is_valid = !tag.is_empty() && tag.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+        if !is_valid {
+            return None
+        }
+        // Harfbuzz interns languages, the pointer stays valid forever.
+        let ptr = unsafe { hb_language_from_string(tag.as_ptr() as *const c_char, tag.len() as c_int) };
+        if ptr.is_null() { None } else { Some(Language(ptr)) }
+    }
+}
+
+/// A writing system, which selects the shaper that Harfbuzz uses.
+#[derive(Copy, Clone)]
+pub struct Script(hb_script_t);
+
+impl Script {
+    /// Parse an ISO 15924 script tag, such as `Latn` or `Deva`.
+    pub fn from_str(tag: &str) -> Option<Script> {
+        if tag.len() != 4 {
+            return None
+        }
+        let script = unsafe { hb_script_from_string(tag.as_ptr() as *const c_char, tag.len() as c_int) };
+        match script {
+            hb::HB_SCRIPT_INVALID | hb::HB_SCRIPT_UNKNOWN => None,
+            _ => Some(Script(script)),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Glyph {
     pub codepoint: u32,
+    /// Byte offset into the input text of the cluster that the glyph belongs to.
+    pub cluster: u32,
     pub x_advance: i32,
     pub y_advance: i32,
     pub x_offset: i32,
@@ -195,11 +249,27 @@ impl Buffer {
         }
     }
 
+    pub fn set_language(&mut self, language: Language) {
+        unsafe { hb_buffer_set_language(self.ptr, language.0) }
+    }
+
+    pub fn set_script(&mut self, script: Script) {
+        unsafe { hb_buffer_set_script(self.ptr, script.0) }
+    }
+
+    /// Fill in the script and language that were not set, based on the text.
+    ///
+    /// The script is that of the first character that has one, the language
+    /// is that of the locale.
+    pub fn guess_segment_properties(&mut self) {
+        unsafe { hb_buffer_guess_segment_properties(self.ptr) }
+    }
+
     pub fn add_str(&mut self, string: &str) {
         // Rust strings are utf-8, and the Harfbuzz API takes a (ptr, len) pair
         // as opposed to a null-terminated string, so we can pass it into
         // Harfbuzz directly.
-        let chars: *const c_char = unsafe { mem::transmute(string.as_bytes().as_ptr()) };
+        let chars = string.as_bytes().as_ptr() as *const c_char;
         let count = string.as_bytes().len() as i32;
         // TODO: What is the difference between the first two and the last two
         // characters?
@@ -221,6 +291,13 @@ impl Buffer {
             // worry.
             let iptr = hb_buffer_get_glyph_infos(self.ptr, &mut ilen);
             let pptr = hb_buffer_get_glyph_positions(self.ptr, &mut plen);
+
+            // For an empty buffer, Harfbuzz may return null pointers, and it
+            // is not valid to make a slice from those, even with length 0.
+            if iptr.is_null() || pptr.is_null() {
+                return Vec::new()
+            }
+
             let islice = slice::from_raw_parts(iptr, ilen as usize);
             let pslice = slice::from_raw_parts(pptr, plen as usize);
             (islice, pslice)
@@ -228,6 +305,7 @@ impl Buffer {
         infos.iter().zip(poss.iter()).map(|(info, pos)| {
             Glyph {
                 codepoint: info.codepoint,
+                cluster: info.cluster,
                 x_offset: pos.x_offset,
                 y_offset: pos.y_offset,
                 x_advance: pos.x_advance,
@@ -244,3 +322,20 @@ impl Drop for Buffer {
         unsafe { hb_buffer_destroy(self.ptr) }
     }
 }
+
+#[test]
+fn script_from_str_accepts_only_script_tags() {
+    assert!(Script::from_str("Latn").is_some());
+    assert!(Script::from_str("deva").is_some());
+    assert!(Script::from_str("Zzzz").is_none());
+    assert!(Script::from_str("Latin").is_none());
+    assert!(Script::from_str("").is_none());
+}
+
+#[test]
+fn language_from_str_rejects_malformed_tags() {
+    assert!(Language::from_str("tr").is_some());
+    assert!(Language::from_str("sr-Latn").is_some());
+    assert!(Language::from_str("").is_none());
+    assert!(Language::from_str("en US").is_none());
+}
@@ -0,0 +1,293 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module decompresses zlib data, which PDF calls the Flate filter.
+//!
+//! Recent versions of Cairo store the objects of a PDF file in compressed
+//! object streams, and to update such a file, we need to read those objects.
+//! Only decompression is needed, which is small enough to implement here
+//! rather than linking zlib. The decoder follows the structure of `puff`, the
+//! reference decoder that ships with zlib: it favors simplicity over speed.
+
+/// Reads bits from a byte slice, least significant bit first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    num_bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data: data, pos: 0, buffer: 0, num_bits: 0 }
+    }
+
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        while self.num_bits < n {
+            let byte = *self.data.get(self.pos)?;
+            self.pos += 1;
+            self.buffer |= (byte as u32) << self.num_bits;
+            self.num_bits += 8;
+        }
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.num_bits -= n;
+        Some(value)
+    }
+
+    /// Discard the bits up to the next byte boundary.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.num_bits = 0;
+    }
+}
+
+/// A canonical Huffman code, described by the number of codes of every
+/// length, and the symbols ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build the code from the code length of every symbol, 0 for unused ones.
+    fn new(lengths: &[u8]) -> Option<Huffman> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+
+        // An over-subscribed set of lengths does not describe a code.
+        let mut left: i32 = 1;
+        for len in 1..16 {
+            left = (left << 1) - counts[len] as i32;
+            if left < 0 { return None }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        counts[0] = 0;
+        Some(Huffman { counts: counts, symbols: symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        // Codes are stored most significant bit first, so read them one bit
+        // at a time, and compare against the first code of every length.
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return self.symbols.get((index + code - first) as usize).cloned()
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// The order in which the code lengths of the code length code are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Decode the literals and matches of a compressed block into `out`.
+fn inflate_codes(reader: &mut BitReader,
+                 out: &mut Vec<u8>,
+                 literals: &Huffman,
+                 distances: &Huffman)
+                 -> Option<()> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue
+        }
+        if symbol == 256 {
+            return Some(())
+        }
+
+        let i = symbol - 257;
+        if i >= LENGTH_BASE.len() { return None }
+        let len = LENGTH_BASE[i] as usize + reader.bits(LENGTH_EXTRA[i] as u32)? as usize;
+
+        let j = distances.decode(reader)? as usize;
+        if j >= DIST_BASE.len() { return None }
+        let dist = DIST_BASE[j] as usize + reader.bits(DIST_EXTRA[j] as u32)? as usize;
+        if dist > out.len() { return None }
+
+        // The match may overlap the bytes that it produces, so copy bytewise.
+        let start = out.len() - dist;
+        for k in 0..len {
+            let byte = out[start + k];
+            out.push(byte);
+        }
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, len) in lengths.iter_mut().enumerate() {
+        *len = match symbol {
+            0...143 => 8,
+            144...255 => 9,
+            256...279 => 7,
+            _ => 8,
+        };
+    }
+    let literals = Huffman::new(&lengths).unwrap();
+    let distances = Huffman::new(&[5; 30]).unwrap();
+    (literals, distances)
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    let num_literals = reader.bits(5)? as usize + 257;
+    let num_distances = reader.bits(5)? as usize + 1;
+    let num_code_lengths = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..num_code_lengths] {
+        code_lengths[i] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = Vec::with_capacity(num_literals + num_distances);
+    while lengths.len() < num_literals + num_distances {
+        let (len, repeat) = match code_length_code.decode(reader)? {
+            symbol @ 0...15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            18 => (0, 11 + reader.bits(7)?),
+            _ => return None,
+        };
+        for _ in 0..repeat {
+            lengths.push(len);
+        }
+    }
+    if lengths.len() != num_literals + num_distances || lengths[256] == 0 {
+        return None
+    }
+
+    let literals = Huffman::new(&lengths[..num_literals])?;
+    let distances = Huffman::new(&lengths[num_literals..])?;
+    Some((literals, distances))
+}
+
+/// Decompress raw deflate data.
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = BitReader::new(data);
+    let mut out = Vec::new();
+    loop {
+        let is_last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let pos = reader.pos;
+                let header = data.get(pos..pos + 4)?;
+                let len = header[0] as usize | (header[1] as usize) << 8;
+                let nlen = header[2] as usize | (header[3] as usize) << 8;
+                if len != !nlen & 0xffff { return None }
+                out.extend_from_slice(data.get(pos + 4..pos + 4 + len)?);
+                reader.pos = pos + 4 + len;
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_codes(&mut reader, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_codes(&mut reader, &mut out, &literals, &distances)?;
+            }
+            _ => return None,
+        }
+        if is_last {
+            return Some(out)
+        }
+    }
+}
+
+/// Decompress zlib data, a two-byte header followed by deflate data.
+///
+/// The checksum at the end is not verified, the PDF structure around the data
+/// is checked when it is parsed.
+pub fn zlib_decompress(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 2 { return None }
+    let (method, flags) = (data[0], data[1]);
+    let is_valid_header = method & 0x0f == 8 && (method as u32 * 256 + flags as u32) % 31 == 0;
+    // A preset dictionary is not used in PDF.
+    if !is_valid_header || flags & 0x20 != 0 {
+        return None
+    }
+    inflate(&data[2..])
+}
+
+#[test]
+fn zlib_decompress_handles_stored_blocks() {
+    let data = [0x78, 0x01, 0x01, 0x04, 0x00, 0xfb, 0xff, 0x50, 0x72, 0x69, 0x73, 0x03, 0xdf, 0x01, 0x9f];
+    assert_eq!(zlib_decompress(&data).unwrap(), b"Pris");
+}
+
+#[test]
+fn zlib_decompress_handles_fixed_codes() {
+    let data = [
+        0x78, 0xda, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xc8, 0x40, 0xa2,
+        0x14, 0x01, 0x4b, 0x1e, 0x06, 0xf6,
+    ];
+    assert_eq!(zlib_decompress(&data).unwrap(), &b"hello, hello, hello!"[..]);
+}
+
+#[test]
+fn zlib_decompress_handles_dynamic_codes() {
+    let data = [
+        0x78, 0xda, 0x0d, 0xcb, 0xc9, 0x01, 0x80, 0x20, 0x10, 0x04, 0xc1, 0x54,
+        0x26, 0x02, 0x62, 0xf1, 0x61, 0x02, 0xa0, 0x5c, 0x0a, 0xac, 0xdc, 0x62,
+        0xf4, 0xee, 0xbb, 0xab, 0x77, 0xa7, 0x91, 0xbb, 0x3f, 0x6e, 0xa8, 0x42,
+        0x33, 0xc1, 0xd0, 0x8b, 0xab, 0xc7, 0xa7, 0x82, 0x86, 0x2e, 0x68, 0x9c,
+        0x83, 0xfc, 0x16, 0x4e, 0xb2, 0x02, 0x9b, 0x64, 0x17, 0x17, 0x14, 0xa3,
+        0xe9, 0x9b, 0x83, 0xf1, 0x43, 0x73, 0xfa, 0x74, 0x42, 0xf0, 0xb9, 0x53,
+        0xe1, 0xd7, 0x56, 0xf1, 0x03, 0x29, 0x50, 0x1e, 0xbf,
+    ];
+    let expected = "The quick brown fox jumps over the lazy dog. Pack my box with five dozen liquor jugs.";
+    assert_eq!(zlib_decompress(&data).unwrap(), expected.as_bytes());
+}
+
+#[test]
+fn zlib_decompress_rejects_invalid_data() {
+    assert!(zlib_decompress(b"").is_none());
+    assert!(zlib_decompress(b"not zlib").is_none());
+    // A valid header, followed by a truncated block.
+    assert!(zlib_decompress(&[0x78, 0xda, 0xcb, 0x48]).is_none());
+}
@@ -5,16 +5,32 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
+use std::path::Path;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
 
 use ast;
 use ast::{Assign, BinOp, BinTerm, Block, Coord, FnCall, FnDef, Idents};
-use ast::{List, Num, Put, Return, Stmt, Term, UnOp, UnTerm, Unit};
+use ast::{List, Match, Num, Put, Repeat, Return, Stmt, Term, UnOp, UnTerm, Unit, Update, Version, With};
+use builtins;
+use cancel::CancelToken;
 use error::{Error, Result};
-use elements::Color;
-use pretty::Formatter;
-use runtime::{Builtin, FontMap, Frame, Env, Subframe, Val};
-use types::ValType;
+use elements::{Color, Vec2};
+use external::Tools;
+use limits::{Budget, Limits};
+use logger::{Logger, StdoutLogger};
+use module::Modules;
+use names;
+use pretty::{self, Formatter, Print};
+use profile::Profile;
+use runtime::{Builtin, FontMap, Frame, Env, Val};
+use symbol::Symbol;
+use toc::Toc;
+use todo::Todos;
+use types::{ValType, describe_dimension};
+use uri::LinkBase;
 
 // Expression interpreter.
 
@@ -22,6 +38,16 @@ use types::ValType;
 pub struct ExprInterpreter<'i, 'a: 'i> {
     pub font_map: &'i mut FontMap,
     pub env: &'i Env<'a>,
+    pub toc: &'i Rc<Toc>,
+    pub budget: &'i Rc<Budget>,
+    pub logger: &'i Rc<Logger>,
+    pub profile: &'i Rc<Profile>,
+    pub todos: &'i Rc<Todos>,
+    pub tools: &'i Rc<Tools>,
+    pub link_base: &'i Rc<LinkBase>,
+    pub cancel: &'i CancelToken,
+    pub modules: Option<&'a Modules<'a>>,
+    pub version: Version,
 }
 
 impl<'i, 'a> ExprInterpreter<'i, 'a> {
@@ -31,7 +57,7 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
             Term::String(ref s) => Ok(Val::Str(s.clone())),
             Term::Number(ref x) => self.eval_num(x),
             Term::Color(ref co) => Ok(ExprInterpreter::eval_color(co)),
-            Term::Idents(ref i) => self.env.lookup(i),
+            Term::Idents(ref i) => self.env.lookup(i).map(|val| (*val).clone()),
             Term::Coord(ref co) => self.eval_coord(co),
             Term::BinOp(ref bo) => self.eval_binop(bo),
             Term::UnOp(ref uop) => self.eval_unop(uop),
@@ -39,13 +65,14 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
             Term::FnDef(ref fd) => Ok(Val::FnExtrin(fd)),
             Term::Block(ref bk) => self.eval_block(bk),
             Term::List(ref lst) => self.eval_list(lst),
+            Term::Match(ref m) => self.eval_match(m),
         }
     }
 
     fn eval_num(&mut self, num: &'a Num) -> Result<Val<'a>> {
         let Num(x, opt_unit) = *num;
-        let ident_font_size = Idents(vec!["font_size"]);
-        let ident_canvas_size = Idents(vec!["canvas_size"]);
+        let ident_font_size = Idents(vec![names::font_size]);
+        let ident_canvas_size = Idents(vec![names::canvas_size]);
         if let Some(unit) = opt_unit {
             match unit {
                 Unit::W => {
@@ -61,7 +88,15 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
                 Unit::Pt => {
                     // A pt is h/1080, so on a 1920x1080 canvas, a pt is 1 px.
                     let canvas_size = self.env.lookup_coord_num(&ident_canvas_size)?;
-                    Ok(Val::Num((1.0 / 1080.0) * canvas_size.y, 1))
+                    Ok(Val::Num(x * canvas_size.y / 1080.0, 1))
+                }
+                Unit::Pw => {
+                    let container_size = self.env.lookup_container_size()?;
+                    Ok(Val::Num(x * container_size.x / 100.0, 1))
+                }
+                Unit::Ph => {
+                    let container_size = self.env.lookup_container_size()?;
+                    Ok(Val::Num(x * container_size.y / 100.0, 1))
                 }
                 Unit::Em => {
                     // The variable "font_size" should always be set, it is present
@@ -76,8 +111,13 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
     }
 
     fn eval_color(col: &ast::Color) -> Val<'a> {
-        let ast::Color(rbyte, gbyte, bbyte) = *col;
-        let cf64 = Color::new(rbyte as f64 / 255.0, gbyte as f64 / 255.0, bbyte as f64 / 255.0);
+        let ast::Color(rbyte, gbyte, bbyte, abyte) = *col;
+        let cf64 = Color::new_rgba(
+            rbyte as f64 / 255.0,
+            gbyte as f64 / 255.0,
+            bbyte as f64 / 255.0,
+            abyte as f64 / 255.0,
+        );
         Val::Col(cf64)
     }
 
@@ -86,32 +126,44 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
         let y = self.eval_expr(&coord.1)?;
         match (x, y) {
             (Val::Num(a, d), Val::Num(b, e)) if d == e => Ok(Val::Coord(a, b, d)),
-            _ => {
-                let msg = "Type error: coord must be (num, num) or (len, len), \
-                           but found (<TODO>, <TODO>) instead.";
-                Err(Error::Other(String::from(msg)))
+            (Val::Num(_, d), Val::Num(_, e)) => {
+                let msg = format!(
+                    "Expected the components of a coord to have the same dimension, \
+                     but found {} and {}, in '{}'. \
+                     Note: 'as_len' and 'as_num' convert between numbers and lengths.",
+                    describe_dimension(d), describe_dimension(e), pretty::print(coord),
+                );
+                Err(Error::Other(msg))
+            }
+            (x, y) => {
+                let mut f = Formatter::new();
+                f.print("Type error: coord must be (num, num) or (len, len), but found (");
+                f.print(x.get_type());
+                f.print(", ");
+                f.print(y.get_type());
+                f.print(") instead.");
+                Err(Error::Other(f.into_string()))
             }
         }
     }
 
     fn eval_list(&mut self, list: &'a List<'a>) -> Result<Val<'a>> {
-        let mut elements = Vec::with_capacity(list.0.len());
-        let mut list_element_type = None;
-
+        let mut elements: Vec<Val<'a>> = Vec::with_capacity(list.0.len());
 
-        for term in &list.0 {
+        for (i, term) in list.0.iter().enumerate() {
             let element = self.eval_expr(term)?;
-            let term_type = element.get_type();
 
             // Enforce a bit of type safety, even though there is no requirement
             // to do so from a runtime point of view, and even though the type
             // system is limited. This will ease the transition to static
             // typing (if that will ever happen) by preventing some overly
-            // dynamic constructs such as heterogeneous lists.
-            match list_element_type {
-                None => list_element_type = Some(term_type),
-                Some(t) if t != term_type => return Err(Error::list_type(t, term_type)),
-                _ => { /* Okay, the list is homogeneous. */ }
+            // dynamic constructs such as heterogeneous lists. Because every
+            // element was checked against the first one, comparing with the
+            // first one is sufficient.
+            if let Some(first) = elements.first() {
+                if !same_element_type(first, &element) {
+                    return Err(Error::list_type(first, &element, i))
+                }
             }
 
             elements.push(element);
@@ -121,41 +173,87 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
     }
 
     fn eval_binop(&mut self, binop: &'a BinTerm<'a>) -> Result<Val<'a>> {
+        if binop.1 == BinOp::Or {
+            return self.eval_or(binop)
+        }
         let lhs = self.eval_expr(&binop.0)?;
         let rhs = self.eval_expr(&binop.2)?;
-        match binop.1 {
+        self.apply_binop(&binop.1, binop, lhs, rhs)
+    }
+
+    /// Apply a binary operator to evaluated operands.
+    ///
+    /// The `expression` is printed in the error when the dimensions of the
+    /// operands of addition or subtraction differ.
+    fn apply_binop<P: Print>(&mut self,
+                             op: &'a BinOp,
+                             expression: &P,
+                             lhs: Val<'a>,
+                             rhs: Val<'a>)
+                             -> Result<Val<'a>> {
+        match *op {
+            BinOp::Add | BinOp::Sub => ExprInterpreter::check_same_dimension(op, expression, &lhs, &rhs)?,
+            _ => {}
+        }
+        match *op {
             BinOp::Adj => ExprInterpreter::eval_adj(lhs, rhs),
             BinOp::Add => ExprInterpreter::eval_add(lhs, rhs),
             BinOp::Sub => ExprInterpreter::eval_sub(lhs, rhs),
             BinOp::Mul => ExprInterpreter::eval_mul(lhs, rhs),
             BinOp::Div => ExprInterpreter::eval_div(lhs, rhs),
-            BinOp::Exp => unimplemented!("TODO: eval exp"),
-            BinOp::Infix(ref op) => self.eval_infix(lhs, op, rhs),
+            BinOp::Exp => ExprInterpreter::eval_exp(lhs, rhs),
+            BinOp::Infix(ref infix) => self.eval_infix(lhs, infix, rhs),
+            BinOp::Or => match lhs {
+                Val::None => Ok(rhs),
+                lhs => Ok(lhs),
+            },
+        }
+    }
+
+    /// Evaluate `x or y`, which is `y` when `x` is not set or `none`.
+    ///
+    /// A variable on the left-hand side does not need to exist, and the
+    /// right-hand side is only evaluated when it is needed.
+    fn eval_or(&mut self, binop: &'a BinTerm<'a>) -> Result<Val<'a>> {
+        let lhs = match binop.0 {
+            Term::Idents(ref idents) => self.env.try_lookup(idents)?.map(|val| (*val).clone()),
+            ref term => match self.eval_expr(term)? {
+                Val::None => None,
+                val => Some(val),
+            },
+        };
+        match lhs {
+            Some(val) => Ok(val),
+            None => self.eval_expr(&binop.2),
+        }
+    }
+
+    /// Check that the operands of addition or subtraction have the same dimension.
+    fn check_same_dimension<P: Print>(op: &BinOp,
+                                      expression: &P,
+                                      lhs: &Val<'a>,
+                                      rhs: &Val<'a>)
+                                      -> Result<()> {
+        let (d0, d1) = match (lhs, rhs) {
+            (&Val::Num(_, d0), &Val::Num(_, d1)) => (d0, d1),
+            (&Val::Coord(_, _, d0), &Val::Coord(_, _, d1)) => (d0, d1),
+            _ => return Ok(()),
+        };
+        if d0 == d1 {
+            return Ok(())
         }
+        let op_name = if *op == BinOp::Add { "+" } else { "-" };
+        Err(Error::dimension(op_name, &pretty::print(expression), d0, d1))
     }
 
     /// Adjoins two frames.
     fn eval_adj(lhs: Val<'a>, rhs: Val<'a>) -> Result<Val<'a>> {
         match (lhs, rhs) {
             (Val::Frame(f0), Val::Frame(f1)) => {
-                let mut frame = (*f0).clone();
-                let anchor = f0.get_anchor();
                 // Copy the elements of f1 onto the new frame (cloned from f0),
-                // subframe by subframe.
-                for (i, sf1) in f1.get_subframes().iter().enumerate() {
-                    // If f1 had more subframes than f0, the result will have as
-                    // many subframes as f1.
-                    if frame.get_subframes().len() <= i {
-                        frame.push_subframe(Subframe::new());
-                    }
-                    let subframe = frame.get_subframe_mut(i);
-                    for pe in sf1.get_elements() {
-                        subframe.place_element(anchor + pe.position, pe.element.clone());
-                    }
-                }
-                frame.set_anchor(anchor + f1.get_anchor());
-                frame.union_bounding_box(&f1.get_bounding_box().offset(anchor));
-                Ok(Val::Frame(Rc::new(frame)))
+                // subframe by subframe. If f1 had more subframes than f0, the
+                // result will have as many subframes as f1.
+                Ok(Val::Frame(Rc::new(f0.adjoin(&f1, 0))))
             }
             (lhs, rhs) => {
                 Err(Error::binop_type("~", ValType::Frame, lhs.get_type(), rhs.get_type()))
@@ -215,6 +313,11 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
             (Val::Num(x, d), Val::Num(y, e)) => Ok(Val::Num(x * y, d + e)),
             (Val::Coord(x, y, d), Val::Num(z, e)) => Ok(Val::Coord(x * z, y * z, d + e)),
             (Val::Num(z, e), Val::Coord(x, y, d)) => Ok(Val::Coord(x * z, y * z, d + e)),
+            (Val::Transform(a), Val::Transform(b)) => Ok(Val::Transform(a * b)),
+            (Val::Transform(m), Val::Coord(x, y, 1)) => {
+                let p = m.apply(Vec2::new(x, y));
+                Ok(Val::Coord(p.x, p.y, 1))
+            }
             (lhs, rhs) => {
                 let err = Error::binop_type(
                     "*",
@@ -227,6 +330,41 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
         }
     }
 
+    /// Raise a number to a power.
+    ///
+    /// Numbers can be raised to any power, but other dimensions only to whole
+    /// powers, so a length squared is an area.
+    fn eval_exp(lhs: Val<'a>, rhs: Val<'a>) -> Result<Val<'a>> {
+        let (x, d, y) = match (lhs, rhs) {
+            (Val::Num(x, d), Val::Num(y, 0)) => (x, d, y),
+            (lhs, rhs) => {
+                return Err(Error::binop_type("^", ValType::Num(0), lhs.get_type(), rhs.get_type()))
+            }
+        };
+        let result = if d == 0 {
+            Val::Num(x.powf(y), 0)
+        } else if y.fract() == 0.0 && (y * d as f64).abs() <= 1000.0 {
+            Val::Num(x.powi(y as i32), d * y as i32)
+        } else if y.fract() == 0.0 {
+            let msg = format!("Cannot raise {} to the power {}, the dimension would be too large.", describe_dimension(d), y);
+            return Err(Error::value(msg))
+        } else {
+            let msg = format!(
+                "Cannot raise {} to the power {}, only numbers can be raised to \
+                 a fractional power. Note: Use 'sqrt' for the root of an area.",
+                describe_dimension(d), y,
+            );
+            return Err(Error::value(msg))
+        };
+        match result {
+            Val::Num(z, _) if z.is_nan() => {
+                let msg = format!("Cannot raise {} to the power {}, the result is not a real number.", x, y);
+                Err(Error::value(msg))
+            }
+            result => Ok(result),
+        }
+    }
+
     fn eval_div(lhs: Val<'a>, rhs: Val<'a>) -> Result<Val<'a>> {
         match (lhs, rhs) {
             (Val::Num(x, d), Val::Num(y, e)) => Ok(Val::Num(x / y, d - e)),
@@ -246,9 +384,9 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
         }
     }
 
-    fn eval_infix(&mut self, lhs: Val<'a>, op: &Idents<'a>, rhs: Val<'a>) -> Result<Val<'a>> {
-        let func = self.env.lookup(op)?;
-        self.eval_call_values(func, vec![lhs, rhs])
+    fn eval_infix(&mut self, lhs: Val<'a>, op: &Idents, rhs: Val<'a>) -> Result<Val<'a>> {
+        let func = (*self.env.lookup(op)?).clone();
+        self.eval_call_named(op, func, vec![lhs, rhs])
     }
 
     fn eval_unop(&mut self, unop: &'a UnTerm<'a>) -> Result<Val<'a>> {
@@ -271,12 +409,76 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
     }
 
     fn eval_call(&mut self, call: &'a FnCall<'a>) -> Result<Val<'a>> {
-        let mut args = Vec::with_capacity(call.1.len());
-        for arg in &call.1 {
-            args.push(self.eval_expr(arg)?);
-        }
         let func = self.eval_expr(&call.0)?;
-        self.eval_call_values(func, args)
+        let args = match func {
+            Val::FnIntrin(ref builtin) if call.1.len() == 2 && builtins::has_content_container(builtin) => {
+                self.eval_args_in_container(builtin, &call.1[0], &call.1[1])?
+            }
+            _ => {
+                let mut args = Vec::with_capacity(call.1.len());
+                for arg in &call.1 {
+                    args.push(self.eval_expr(arg)?);
+                }
+                args
+            }
+        };
+        match call.0 {
+            Term::Idents(ref idents) => self.eval_call_named(idents, func, args),
+            _ => self.eval_call_values(func, args),
+        }
+    }
+
+    /// Evaluate the arguments of a builtin such as `fit` that lays out its
+    /// frame in a container, with `container_size` bound to that container
+    /// while the frame is evaluated.
+    fn eval_args_in_container(&mut self,
+                              builtin: &Builtin,
+                              frame: &'a Term<'a>,
+                              arg: &'a Term<'a>)
+                              -> Result<Vec<Val<'a>>> {
+        let arg = self.eval_expr(arg)?;
+        let outer = self.env.lookup_container_size()?;
+        let size = match builtins::content_container_size(builtin, outer, &arg) {
+            Some(size) => size,
+            None => return Ok(vec![self.eval_expr(frame)?, arg]),
+        };
+
+        let mut inner_env = self.env.clone();
+        inner_env.put(names::container_size, Val::Coord(size.x, size.y, 1));
+        let mut inner_interpreter = ExprInterpreter {
+            font_map: &mut *self.font_map,
+            env: &inner_env,
+            toc: self.toc,
+            budget: self.budget,
+            logger: self.logger,
+            profile: self.profile,
+            todos: self.todos,
+            tools: self.tools,
+            link_base: self.link_base,
+            cancel: self.cancel,
+            modules: self.modules,
+            version: self.version,
+        };
+        let frame = inner_interpreter.eval_expr(frame)?;
+        Ok(vec![frame, arg])
+    }
+
+    /// Call a function that was looked up by name.
+    fn eval_call_named(&mut self,
+                       name: &Idents,
+                       func: Val<'a>,
+                       args: Vec<Val<'a>>)
+                       -> Result<Val<'a>> {
+        // Time builtins by the name they were called with. Calls of
+        // user-defined functions are not timed, the builtins they call are.
+        let is_builtin = match func { Val::FnIntrin(..) => true, _ => false };
+        if !(is_builtin && self.profile.is_enabled()) {
+            return self.eval_call_values(func, args)
+        }
+        let start = Instant::now();
+        let result = self.eval_call_values(func, args);
+        self.profile.record_builtin(&name.to_string(), start.elapsed());
+        result
     }
 
     fn eval_call_values(&mut self, func: Val<'a>, args: Vec<Val<'a>>) -> Result<Val<'a>> {
@@ -295,6 +497,36 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
         }
     }
 
+    fn eval_match(&mut self, m: &'a Match<'a>) -> Result<Val<'a>> {
+        let value = match self.eval_expr(&m.0)? {
+            Val::Str(s) => s,
+            other => {
+                let mut f = Formatter::new();
+                f.print("Expected the value of 'match' to have type '");
+                f.print(ValType::Str);
+                f.print("', but found '");
+                f.print(other.get_type());
+                f.print("'.");
+                return Err(Error::Other(f.into_string()))
+            }
+        };
+
+        // Only the selected arm is evaluated.
+        match m.1.iter().find(|arm| arm.0 == value) {
+            Some(arm) => self.eval_expr(&arm.1),
+            None => match m.2 {
+                Some(ref default) => self.eval_expr(default),
+                None => {
+                    let msg = format!(
+                        "No arm of 'match' matches \"{}\". Note: add an 'else' arm.",
+                        value,
+                    );
+                    Err(Error::value(msg))
+                }
+            },
+        }
+    }
+
     fn eval_call_extrin(&mut self,
                         fn_def: &'a FnDef<'a>,
                         args: Vec<Val<'a>>)
@@ -311,12 +543,22 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
         // then evaluate the body block in the modified environment.
         let mut inner_env = self.env.clone();
         for (arg_name, val) in fn_def.0.iter().zip(args) {
-            inner_env.put(arg_name, val);
+            inner_env.put(*arg_name, val);
         }
 
         let mut inner_interpreter = ExprInterpreter {
             font_map: &mut *self.font_map,
             env: &inner_env,
+            toc: self.toc,
+            budget: self.budget,
+            logger: self.logger,
+            profile: self.profile,
+            todos: self.todos,
+            tools: self.tools,
+            link_base: self.link_base,
+            cancel: self.cancel,
+            modules: self.modules,
+            version: self.version,
         };
 
         inner_interpreter.eval_block(&fn_def.1)
@@ -335,6 +577,18 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
             font_map: self.font_map,
             frame: Frame::from_env(inner_env),
             current_subframe: 0,
+            defined: Vec::new(),
+            toc: self.toc.clone(),
+            budget: self.budget.clone(),
+            logger: self.logger.clone(),
+            profile: self.profile.clone(),
+            todos: self.todos.clone(),
+            tools: self.tools.clone(),
+            link_base: self.link_base.clone(),
+            cancel: self.cancel.clone(),
+            modules: self.modules,
+            version: self.version,
+            section_index: 0,
         };
 
         for statement in &block.0 {
@@ -342,6 +596,7 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
                 // A return statement in a block determines the value that the
                 // block evalates to, if a return is present.
                 Stmt::Return(Return(ref r)) => {
+                    self.budget.count_statement()?;
                     return stmt_interpreter.get_expr_interpreter().eval_expr(r)
                 }
                 // A block statemen to make a frame can only be used at the top
@@ -351,6 +606,10 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
                                Note: use 'put { ... }' to place a frame.";
                     return Err(Error::Other(String::from(msg)));
                 }
+                Stmt::Section(..) => {
+                    let msg = "Error: sections can only be introduced at the top level.";
+                    return Err(Error::Other(String::from(msg)));
+                }
                 // Otherwise, evaluating a statement only mutates the frame.
                 _ => {
                     let maybe_frame = stmt_interpreter.eval_statement(statement)?;
@@ -365,6 +624,42 @@ impl<'i, 'a> ExprInterpreter<'i, 'a> {
     }
 }
 
+/// Return whether two list elements have the same type.
+///
+/// For nested lists, the element types must match too, so a list of numbers
+/// and a list of strings cannot be elements of the same list. An empty list
+/// matches a list of any element type.
+fn same_element_type<'a>(a: &Val<'a>, b: &Val<'a>) -> bool {
+    match (a, b) {
+        (&Val::List(ref xs), &Val::List(ref ys)) => match (xs.first(), ys.first()) {
+            (Some(x), Some(y)) => same_element_type(x, y),
+            _ => true,
+        },
+        _ => a.get_type() == b.get_type(),
+    }
+}
+
+/// Fail if this build of Pris does not support the language version.
+fn check_version(version: Version) -> Result<()> {
+    if version > Version::CURRENT {
+        let msg = format!(
+            "The document requires language version {}, but this build of Pris \
+             supports version {} at most. Note: upgrade Pris to render the document.",
+            version, Version::CURRENT,
+        );
+        return Err(Error::Other(msg))
+    }
+    if version < Version::FIRST {
+        let msg = format!(
+            "Language version {} cannot be declared, 'pris_version' was introduced \
+             in version {}. Note: declare version {} instead.",
+            version, Version::FIRST, Version::FIRST,
+        );
+        return Err(Error::Other(msg))
+    }
+    Ok(())
+}
+
 // Statement interpreter.
 
 // TODO: This should not be public, or at least, not in this form.
@@ -372,6 +667,43 @@ pub struct StmtInterpreter<'i, 'a: 'i> {
     font_map: &'i mut FontMap,
     frame: Frame<'a>,
     current_subframe: usize,
+
+    /// Variables set with `define`, which assignments do not override.
+    defined: Vec<Symbol>,
+
+    /// The table of contents for `auto_toc`, from a previous evaluation.
+    toc: Rc<Toc>,
+
+    /// Resources used so far, shared with nested interpreters.
+    budget: Rc<Budget>,
+
+    /// Receives warnings, shared with nested interpreters.
+    logger: Rc<Logger>,
+
+    /// Records the time spent in builtins, shared with nested interpreters.
+    profile: Rc<Profile>,
+
+    /// Records calls to `todo()`, shared with nested interpreters.
+    todos: Rc<Todos>,
+
+    /// The external programs that `external_svg` can run.
+    tools: Rc<Tools>,
+
+    /// Where relative hyperlinks point.
+    link_base: Rc<LinkBase>,
+
+    /// Checked before every statement, to abort evaluation.
+    cancel: CancelToken,
+
+    /// The modules that `import` statements can import.
+    modules: Option<&'a Modules<'a>>,
+
+    /// The language version that the document declared with `pris_version`,
+    /// to gate changes in semantics.
+    version: Version,
+
+    /// The number of sections started so far.
+    section_index: u32,
 }
 
 impl<'i, 'a> StmtInterpreter<'i, 'a> {
@@ -381,9 +713,93 @@ impl<'i, 'a> StmtInterpreter<'i, 'a> {
             font_map: font_map,
             frame: Frame::new(),
             current_subframe: 0,
+            defined: Vec::new(),
+            toc: Rc::new(Toc::new(Vec::new())),
+            budget: Rc::new(Budget::unlimited()),
+            logger: Rc::new(StdoutLogger),
+            profile: Rc::new(Profile::disabled()),
+            todos: Rc::new(Todos::new()),
+            tools: Rc::new(Tools::none()),
+            link_base: Rc::new(LinkBase::none()),
+            cancel: CancelToken::none(),
+            modules: None,
+            version: Version::CURRENT,
+            section_index: 0,
         }
     }
 
+    /// Set the total number of sections, see `Document::count_sections`.
+    pub fn set_section_count(&mut self, count: usize) {
+        self.frame.put_in_env(names::section_count, Val::Num(count as f64, 0));
+    }
+
+    /// Set the table of contents that `auto_toc` lists.
+    pub fn set_toc(&mut self, toc: Rc<Toc>) {
+        self.toc = toc;
+    }
+
+    /// Limit the resources that evaluation may use, see `limits::Limits`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget = Rc::new(Budget::new(limits));
+    }
+
+    /// Send warnings to `logger`, instead of printing them.
+    pub fn set_logger(&mut self, logger: Rc<Logger>) {
+        self.logger = logger;
+    }
+
+    /// Record the time spent in builtins in `profile`.
+    pub fn set_profile(&mut self, profile: Rc<Profile>) {
+        self.profile = profile;
+    }
+
+    /// Record calls to `todo()` in `todos`.
+    pub fn set_todos(&mut self, todos: Rc<Todos>) {
+        self.todos = todos;
+    }
+
+    /// Let `external_svg` run the programs in `tools`.
+    pub fn set_tools(&mut self, tools: Rc<Tools>) {
+        self.tools = tools;
+    }
+
+    /// Check relative hyperlinks against `document_dir`, and rewrite them to
+    /// point at the same files from `output_dir`, where the pdf is written.
+    ///
+    /// Without a document directory, relative links are left as they are.
+    pub fn set_link_dirs(&mut self, document_dir: &Path, output_dir: Option<&Path>) {
+        self.link_base = Rc::new(LinkBase::new(document_dir, output_dir));
+    }
+
+    /// Stop evaluation with `Error::Cancelled` once `flag` is set.
+    ///
+    /// The flag is checked before every statement, including statements in
+    /// blocks and function bodies.
+    pub fn set_cancel(&mut self, flag: Arc<AtomicBool>) {
+        self.cancel = CancelToken::new(flag);
+    }
+
+    /// Make the modules in `modules` available to `import` statements.
+    pub fn set_modules(&mut self, modules: &'a Modules<'a>) {
+        self.modules = Some(modules);
+    }
+
+    /// Make `getenv` read environment variables, instead of failing.
+    pub fn allow_getenv(&mut self) {
+        let getenv = Val::FnIntrin(Builtin(builtins::getenv));
+        self.frame.put_in_env(names::getenv, getenv);
+    }
+
+    /// Bind a variable in the top-level environment before evaluation.
+    ///
+    /// Top-level assignments to the variable are ignored afterwards, so a
+    /// document can assign a default value that the definition overrides.
+    pub fn define(&mut self, name: &str, value: Val<'a>) {
+        let symbol = Symbol::intern(name);
+        self.frame.put_in_env(symbol, value);
+        self.defined.push(symbol);
+    }
+
     /// Return the top-level environment.
     // TODO: Name consistently. It seems that Rust does not use `get` prefixes,
     // e.g. `len` does not.
@@ -396,19 +812,39 @@ impl<'i, 'a> StmtInterpreter<'i, 'a> {
         ExprInterpreter {
             font_map: self.font_map,
             env: env,
+            toc: &self.toc,
+            budget: &self.budget,
+            logger: &self.logger,
+            profile: &self.profile,
+            todos: &self.todos,
+            tools: &self.tools,
+            link_base: &self.link_base,
+            cancel: &self.cancel,
+            modules: self.modules,
+            version: self.version,
         }
     }
 
     pub fn eval_statement(&mut self,
                           stmt: &'a Stmt<'a>)
                           -> Result<Option<Rc<Frame<'a>>>> {
+        self.cancel.check()?;
+        self.budget.count_statement()?;
         match *stmt {
-            Stmt::Import(ref _i) => {
-                println!("TODO: eval import");
+            Stmt::Import(ref import) => {
+                self.eval_import(&import.0)?;
                 Ok(None)
             }
             Stmt::Assign(ref a) => {
-                self.eval_assign(a)?;
+                if !self.defined.contains(&a.0) {
+                    self.eval_assign(a)?;
+                }
+                Ok(None)
+            }
+            Stmt::Update(ref u) => {
+                if !self.defined.contains(&u.0) {
+                    self.eval_update(u)?;
+                }
                 Ok(None)
             }
             Stmt::Return(..) => {
@@ -418,8 +854,10 @@ impl<'i, 'a> StmtInterpreter<'i, 'a> {
                 Err(Error::Other(String::from(msg)))
             }
             Stmt::Block(ref bk) => {
-                let mut expr_interpreter = self.get_expr_interpreter();
-                if let Val::Frame(frame) = expr_interpreter.eval_block(bk)? {
+                self.budget.begin_slide();
+                let value = self.get_expr_interpreter().eval_block(bk)?;
+                if let Val::Frame(frame) = value {
+                    self.budget.end_slide(&frame)?;
                     Ok(Some(frame))
                 } else {
                     let msg = "Type error: top-level blocks must evaluate to \
@@ -431,16 +869,177 @@ impl<'i, 'a> StmtInterpreter<'i, 'a> {
                 self.eval_put(put)?;
                 Ok(None)
             }
+            Stmt::Repeat(ref repeat) => {
+                self.eval_repeat(repeat)?;
+                Ok(None)
+            }
+            Stmt::With(ref with) => {
+                self.eval_with(with)?;
+                Ok(None)
+            }
+            Stmt::Section(ref section) => {
+                self.section_index += 1;
+                let index = Val::Num(self.section_index as f64, 0);
+                self.frame.put_in_env(names::current_section, Val::Str(section.0.clone()));
+                self.frame.put_in_env(names::section_index, index);
+                Ok(None)
+            }
+            Stmt::Version(version) => {
+                check_version(version)?;
+                self.version = version;
+                Ok(None)
+            }
+            // Infix declarations only affect parsing.
+            Stmt::Infix(..) => Ok(None),
+            Stmt::Comment(..) => Ok(None),
         }
     }
 
     fn eval_assign(&mut self, stmt: &'a Assign<'a>) -> Result<()> {
-        let Assign(target, ref expression) = *stmt;
+        let Assign(target, ref expression, _) = *stmt;
+
+        // These names refer to properties of the frame when looked up as a
+        // field, so a variable with the same name would never be visible.
+        match target {
+            names::width | names::height | names::size | names::offset => {
+                let msg = format!(
+                    "Cannot assign to '{}', it is a read-only property of frames. \
+                     Note: use a different name for the variable.",
+                    target
+                );
+                return Err(Error::Other(msg))
+            }
+            _ => {}
+        }
+
         let value = self.get_expr_interpreter().eval_expr(expression)?;
         self.frame.put_in_env(target, value);
         Ok(())
     }
 
+    fn eval_import(&mut self, idents: &Idents) -> Result<()> {
+        let name = idents.to_string();
+        let (modules, module) = match self.modules {
+            Some(modules) => match modules.get(&name) {
+                Some(module) => (modules, module),
+                None => return Err(Error::Other(format!("The module '{}' was not loaded.", name))),
+            },
+            None => {
+                let msg = format!("Cannot import '{}', imports are not available here.", name);
+                return Err(Error::Other(msg))
+            }
+        };
+        modules.enter(&name)?;
+
+        // A module is evaluated in the scope of the import, so the variables
+        // that it assigns become visible after the import.
+        for statement in &module.0 {
+            let result = match *statement {
+                Stmt::Block(..) | Stmt::Section(..) => {
+                    let msg = format!(
+                        "The module '{}' introduces a slide or section, but modules can only \
+                         define variables. Note: define a function that returns the slide instead.",
+                        name,
+                    );
+                    Err(Error::Other(msg))
+                }
+                // A module can be written for a different version than the
+                // document that imports it, but it must still be supported.
+                Stmt::Version(version) => check_version(version),
+                _ => self.eval_statement(statement).map(|_| ()),
+            };
+            if result.is_err() {
+                modules.leave();
+                return result
+            }
+        }
+
+        modules.leave();
+        Ok(())
+    }
+
+    fn eval_update(&mut self, stmt: &'a Update<'a>) -> Result<()> {
+        let Update(target, ref op, ref expression) = *stmt;
+        let value = {
+            let mut expr_interpreter = self.get_expr_interpreter();
+            let lhs = (*expr_interpreter.env.lookup(&Idents(vec![target]))?).clone();
+            let rhs = expr_interpreter.eval_expr(expression)?;
+            expr_interpreter.apply_binop(op, stmt, lhs, rhs)?
+        };
+        self.frame.put_in_env(target, value);
+        Ok(())
+    }
+
+    fn eval_repeat(&mut self, repeat: &'a Repeat<'a>) -> Result<()> {
+        let count = match self.get_expr_interpreter().eval_expr(&repeat.0)? {
+            Val::Num(x, 0) if x >= 0.0 && x <= u32::max_value() as f64 && x.fract() == 0.0 => x as u32,
+            Val::Num(x, 0) => {
+                let msg = format!("The count of 'repeat' must be a non-negative integer, found {}.", x);
+                return Err(Error::value(msg))
+            }
+            other => {
+                let mut f = Formatter::new();
+                f.print("Expected the count of 'repeat' to have type '");
+                f.print(ValType::Num(0));
+                f.print("', but found '");
+                f.print(other.get_type());
+                f.print("'.");
+                return Err(Error::Other(f.into_string()))
+            }
+        };
+
+        // The body runs in the enclosing scope, so assignments carry over to
+        // the next iteration, and puts place into the enclosing frame. Only
+        // the counter is restored afterwards, so repeats can be nested, and
+        // outside of any repeat it is not defined.
+        let outer_index = self.frame.get_env().get(names::repeat_index).map(|val| (**val).clone());
+        for i in 0..count {
+            // Every iteration counts as a statement, so a huge count is
+            // stopped by the statement limit, even when the body is empty.
+            self.cancel.check()?;
+            self.budget.count_statement()?;
+            self.frame.put_in_env(names::repeat_index, Val::Num(i as f64, 0));
+            for statement in &(repeat.1).0 {
+                match *statement {
+                    Stmt::Block(..) | Stmt::Section(..) => {
+                        let msg = "Error: slides and sections cannot be introduced in 'repeat'. \
+                                   Note: use 'put { ... }' to place a frame.";
+                        return Err(Error::Other(String::from(msg)))
+                    }
+                    _ => {
+                        self.eval_statement(statement)?;
+                    }
+                }
+            }
+        }
+        match outer_index {
+            Some(index) => self.frame.put_in_env(names::repeat_index, index),
+            None => self.frame.remove_from_env(names::repeat_index),
+        }
+
+        Ok(())
+    }
+
+    fn eval_with(&mut self, with: &'a With<'a>) -> Result<()> {
+        // The body places into the enclosing frame, but the overrides, and
+        // any other assignments in the body, are undone afterwards.
+        let outer_env = self.frame.get_env().clone();
+        for statement in (with.0).0.iter().chain(&(with.1).0) {
+            match *statement {
+                Stmt::Block(..) | Stmt::Section(..) => {
+                    let msg = "Error: slides and sections cannot be introduced in 'with'. \
+                               Note: use 'put { ... }' to place a frame.";
+                    return Err(Error::Other(String::from(msg)))
+                }
+                _ => {
+                    self.eval_statement(statement)?;
+                }
+            }
+        }
+        self.frame.set_env(outer_env);
+        Ok(())
+    }
+
     fn eval_put(&mut self, put: &'a Put<'a>) -> Result<()> {
         let content = match self.get_expr_interpreter().eval_expr(&put.0)? {
             Val::Frame(f) => f,
@@ -450,25 +1049,12 @@ impl<'i, 'a> StmtInterpreter<'i, 'a> {
             }
         };
 
-        // Ensure that the current frame has enough subframes to place the
-        // elements in content subframes. If the content has more subframes than
-        // the current frame, more must be added.
-        while content.get_subframes().len() > self.frame.get_subframes().len() {
-            self.frame.push_subframe(Subframe::new());
-        }
-
-        for (i, subframe) in content.get_subframes().iter().enumerate() {
-            // Getting the subframe will not fail here, because we ensured that
-            // they all exist before entering the loop.
-            let sf_idx = self.current_subframe + i;
-            let dest_sf = self.frame.get_subframe_mut(sf_idx);
-
-            for pe in subframe.get_elements() {
-                dest_sf.place_element(pe.position, pe.element.clone());
-            }
-        }
+        // Place the content subframes starting at the current subframe. If the
+        // content has more subframes than the current frame, more are added.
+        self.frame.place_subframes(self.current_subframe, &content, Vec2::zero());
 
         self.frame.union_bounding_box(&content.get_bounding_box());
+        self.budget.check_elements(&self.frame)?;
 
         // Update the anchor of the frame: the anchor of a block is the anchor
         // of the element that was placed last.
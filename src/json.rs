@@ -0,0 +1,250 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! A minimal JSON parser, for reading scenes that other programs wrote.
+
+use std::result;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Array(Vec<Json>),
+    /// The members of an object, in the order of the source.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Return the value of a member, if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.iter().find(|m| m.0 == key).map(|m| &m.1),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+type PResult<T> = result::Result<T, String>;
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, what: &str) -> PResult<T> {
+        Err(format!("Expected {} at byte {}.", what, self.pos))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.input.len() {
+            match self.input[self.pos] {
+                b' ' | b'\t' | b'\n' | b'\r' => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, byte: u8) -> PResult<()> {
+        self.skip_whitespace();
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(&format!("'{}'", byte as char))
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: Json) -> PResult<Json> {
+        if self.input[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            self.error("a value")
+        }
+    }
+
+    fn parse_value(&mut self) -> PResult<Json> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.parse_literal("null", Json::Null),
+            Some(b't') => self.parse_literal("true", Json::Bool(true)),
+            Some(b'f') => self.parse_literal("false", Json::Bool(false)),
+            Some(b'"') => self.parse_string().map(Json::Str),
+            Some(b'[') => self.parse_array(),
+            Some(b'{') => self.parse_object(),
+            Some(b'-') | Some(b'0'..=b'9') => self.parse_number(),
+            _ => self.error("a value"),
+        }
+    }
+
+    fn parse_number(&mut self) -> PResult<Json> {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            match b {
+                b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9' => self.pos += 1,
+                _ => break,
+            }
+        }
+        // The slice contains only ascii, so it is valid utf-8.
+        let digits = String::from_utf8_lossy(&self.input[start..self.pos]);
+        match digits.parse() {
+            Ok(x) => Ok(Json::Num(x)),
+            Err(..) => {
+                self.pos = start;
+                self.error("a number")
+            }
+        }
+    }
+
+    fn parse_hex4(&mut self) -> PResult<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = match self.peek().and_then(|b| (b as char).to_digit(16)) {
+                Some(digit) => digit,
+                None => return self.error("a hexadecimal digit"),
+            };
+            code = code * 16 + digit;
+            self.pos += 1;
+        }
+        Ok(code)
+    }
+
+    fn parse_string(&mut self) -> PResult<String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    break
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.pos += 1;
+                            let mut code = self.parse_hex4()?;
+                            // Characters outside of the basic multilingual
+                            // plane are written as a surrogate pair.
+                            if code >= 0xd800 && code < 0xdc00 && self.input[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.parse_hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            self.pos -= 1;
+                            ::std::char::from_u32(code).unwrap_or('\u{fffd}')
+                        }
+                        _ => return self.error("an escape sequence"),
+                    };
+                    self.pos += 1;
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(b) if b >= 0x20 => {
+                    bytes.push(b);
+                    self.pos += 1;
+                }
+                _ => return self.error("'\"'"),
+            }
+        }
+        String::from_utf8(bytes).or_else(|_| self.error("valid UTF-8"))
+    }
+
+    fn parse_array(&mut self) -> PResult<Json> {
+        self.expect(b'[')?;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(elements))
+        }
+        loop {
+            elements.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(elements))
+                }
+                _ => return self.error("',' or ']'"),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> PResult<Json> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members))
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(b':')?;
+            members.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members))
+                }
+                _ => return self.error("',' or '}'"),
+            }
+        }
+    }
+}
+
+/// Parse a JSON document, or describe where it is invalid.
+pub fn parse(input: &[u8]) -> PResult<Json> {
+    let mut parser = Parser { input: input, pos: 0 };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.pos < input.len() {
+        return parser.error("the end of the input")
+    }
+    Ok(value)
+}
+
+#[test]
+fn parse_parses_nested_values() {
+    let json = parse(b" {\"a\": [1, -2.5e1, true, null], \"b\": {\"c\": \"\\\"\\u00e9\\ud83d\\ude00\"}} ").unwrap();
+    assert_eq!(json.get("a"), Some(&Json::Array(vec![
+        Json::Num(1.0),
+        Json::Num(-25.0),
+        Json::Bool(true),
+        Json::Null,
+    ])));
+    assert_eq!(json.get("b").and_then(|b| b.get("c")), Some(&Json::Str("\"é😀".to_string())));
+}
+
+#[test]
+fn parse_rejects_invalid_json() {
+    assert_eq!(parse(b"[1, 2"), Err("Expected ',' or ']' at byte 5.".to_string()));
+    assert!(parse(b"[1] 2").is_err());
+    assert!(parse(b"{\"a\" 1}").is_err());
+    assert!(parse(b"\"\n\"").is_err());
+    assert!(parse(b"nul").is_err());
+}
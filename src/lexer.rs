@@ -8,8 +8,9 @@
 //! This module contains the Pris lexer.
 //!
 //! The lexer turns the input into a stream of tokens. It strips comments that
-//! run to the end of the line, and it removes whitespace. The lexer is hand-
-//! written for a few reasons:
+//! run to the end of the line, and it removes whitespace. For tools that need
+//! those, such as a formatter, `lex_with_comments` retains them as tokens. The
+//! lexer is hand-written for a few reasons:
 //!
 //!  * It can produce helpful error messages in this way.
 //!  * It can support non-greedy triple quoted strings that cannot be expressed
@@ -47,6 +48,13 @@ impl Span {
     }
 }
 
+/// A token, the unit of input for the parser.
+///
+/// Tokens that carry a string slice contain the exact source text that the
+/// token spans, including delimiters such as quotes. The `Comment` and `Space`
+/// tokens are only produced by `lex_with_comments`, never by `lex`. Doc
+/// comments are produced by both, because the parser attaches them to the
+/// assignment that follows.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Token<'a> {
     // TODO: These should not contain slices, that information is redundant and
@@ -58,20 +66,34 @@ pub enum Token<'a> {
     Number(&'a str),
     Ident(&'a str),
 
+    KwElse,
     KwFunction,
     KwImport,
+    KwInfix,
+    KwMatch,
+    KwOr,
+    KwPrisVersion,
     KwPut,
+    KwRepeat,
     KwReturn,
+    KwSection,
+    KwWith,
 
     UnitEm,
     UnitH,
     UnitW,
     UnitPt,
+    UnitPw,
+    UnitPh,
 
+    Arrow,
     Comma,
     Dot,
     Semicolon,
     Equals,
+    PlusEquals,
+    MinusEquals,
+    StarEquals,
     Hat,
     Minus,
     Plus,
@@ -85,17 +107,40 @@ pub enum Token<'a> {
     RBrace,
     LBracket,
     RBracket,
+
+    /// A comment, from the "//" up to but excluding the newline, or a block
+    /// comment from "/*" up to and including the matching "*/".
+    Comment(&'a str),
+    /// A run of spaces and newlines.
+    Space(&'a str),
+    /// A doc comment, from the "///" up to but excluding the newline.
+    DocComment(&'a str),
 }
 
 /// Lexes a UTF-8 input file into tokens with source location.
+///
+/// The tokens are in source order, and their spans do not overlap and are not
+/// empty. Comments and whitespace are not included.
 pub fn lex(input: &[u8]) -> Result<Vec<(Token, Span)>> {
-    Lexer::new(input).run()
+    Lexer::new(input, false).run()
+}
+
+/// Lexes a UTF-8 input file into tokens, including comments and whitespace.
+///
+/// In addition to the guarantees of `lex`, the spans of the tokens are
+/// contiguous and together cover the entire input, so the input can be
+/// reconstructed from the tokens. This is intended for tools such as
+/// formatters and syntax highlighters. Unlike `lex`, this requires comments
+/// to be valid UTF-8.
+pub fn lex_with_comments(input: &[u8]) -> Result<Vec<(Token, Span)>> {
+    Lexer::new(input, true).run()
 }
 
 #[derive(Debug, Eq, PartialEq)]
 enum State {
     Base,
     Done,
+    InBlockComment,
     InColor,
     InComment,
     InIdent,
@@ -110,15 +155,18 @@ struct Lexer<'a> {
     start: usize,
     state: State,
     tokens: Vec<(Token<'a>, Span)>,
+    /// Whether to emit `Comment` and `Space` tokens.
+    keep_trivia: bool,
 }
 
 impl<'a> Lexer<'a> {
-    fn new(input: &'a [u8]) -> Lexer<'a> {
+    fn new(input: &'a [u8], keep_trivia: bool) -> Lexer<'a> {
         Lexer {
             input: input,
             start: 0,
             state: State::Base,
             tokens: Vec::new(),
+            keep_trivia: keep_trivia,
         }
     }
 
@@ -127,6 +175,7 @@ impl<'a> Lexer<'a> {
         loop {
             let (start, state) = match self.state {
                 State::Base => self.lex_base()?,
+                State::InBlockComment => self.lex_block_comment()?,
                 State::InColor => self.lex_color()?,
                 State::InComment => self.lex_comment()?,
                 State::InIdent => self.lex_ident()?,
@@ -195,17 +244,38 @@ impl<'a> Lexer<'a> {
     fn lex_base(&mut self) -> Result<(usize, State)> {
         for i in self.start..self.input.len() {
             match self.input[i] {
-                // There are two characters that require a brief lookahead:
-                // * '/', to find the start of a comment "//".
+                // A few characters require a brief lookahead:
+                // * '/', to find the start of a comment "//" or "/*".
                 // * '-', to find the start of a raw string "---".
+                // * '+', '-', and '*', to find the operators "+=", "-=", "*=".
+                // * '-', to find the arrow "->".
                 // If the lookahead does not match, these characters are matched
                 // again as single-character tokens further below.
                 b'/' if self.has_at(i + 1, b"/") => {
                     return change_state(i, State::InComment)
                 }
+                b'/' if self.has_at(i + 1, b"*") => {
+                    return change_state(i, State::InBlockComment)
+                }
                 b'-' if self.has_at(i + 1, b"--") => {
                     return change_state(i, State::InRawString)
                 }
+                b'+' if self.has_at(i + 1, b"=") => {
+                    self.push_from(i, Token::PlusEquals, i + 2);
+                    return change_state(i + 2, State::Base)
+                }
+                b'-' if self.has_at(i + 1, b"=") => {
+                    self.push_from(i, Token::MinusEquals, i + 2);
+                    return change_state(i + 2, State::Base)
+                }
+                b'-' if self.has_at(i + 1, b">") => {
+                    self.push_from(i, Token::Arrow, i + 2);
+                    return change_state(i + 2, State::Base)
+                }
+                b'*' if self.has_at(i + 1, b"=") => {
+                    self.push_from(i, Token::StarEquals, i + 2);
+                    return change_state(i + 2, State::Base)
+                }
 
                 // A few characters signal a change of state immediately. Note
                 // that only spaces and newlines are considered whitespace.
@@ -222,6 +292,12 @@ impl<'a> Lexer<'a> {
                 byte if is_alphabetic_or_underscore(byte) => {
                     return change_state(i, State::InIdent)
                 }
+                byte if byte >= 0x80 && starts_with_ident_char(&self.input[i..]) => {
+                    // A non-ASCII letter. This arm must come before the byte
+                    // order mark check below, because 0xef is also the first
+                    // byte of valid letters, such as the fullwidth ones.
+                    return change_state(i, State::InIdent)
+                }
                 byte if is_digit(byte) => {
                     return change_state(i, State::InNumber)
                 }
@@ -267,104 +343,182 @@ impl<'a> Lexer<'a> {
     }
 
     /// Lex in the color state until a state change occurs.
+    ///
+    /// A color has six hexadecimal digits, or eight if it includes alpha.
     fn lex_color(&mut self) -> Result<(usize, State)> {
         debug_assert!(self.has_at(self.start, b"#"));
 
         // Skip over the first '#' byte.
         for i in self.start + 1..self.input.len() {
             let c = self.input[i];
+            let num_digits = i - self.start - 1;
 
             // A hexadecimal character, as expected.
-            if i < self.start + 7 && is_hexadecimal(c) {
+            if num_digits < 8 && is_hexadecimal(c) {
                 continue
             }
 
             // We expected more hexadecimal digits, but found something else.
-            if i < self.start + 7 {
+            if num_digits < 6 {
                 let msg = format!("Expected hexadecimal digit, found '{}'.", char::from(c));
                 return Err(Error::parse(self.start, i + 1, msg))
             }
 
-            // We expect at most 6 hexadecimal digits, but if another
+            // We expect at most 8 hexadecimal digits, but if another
             // alphanumeric character comes after this, we don't want to
             // terminate the color and switch to identifier; that would lead to
             // very confusing parse errors later on. Report an error here
             // instead.
-            if i == self.start + 7 && is_hexadecimal(c) {
-                let msg = "Expected only six hexadecimal digits, found one more.";
+            if is_hexadecimal(c) {
+                let msg = "Expected at most eight hexadecimal digits, found one more.";
                 return Err(Error::parse(self.start, i + 1, msg.into()))
             }
-            if i == self.start + 7 && is_alphanumeric_or_underscore(c) {
-                let msg = format!("Expected six hexadecimal digits, found extra '{}'.", char::from(c));
+            if num_digits == 7 {
+                let msg = "Expected six or eight hexadecimal digits, found seven.";
+                return Err(Error::parse(self.start, i, msg.into()))
+            }
+            if is_alphanumeric_or_underscore(c) {
+                let msg = format!("Expected {} hexadecimal digits, found extra '{}'.",
+                                  if num_digits == 6 { "six" } else { "eight" }, char::from(c));
                 return Err(Error::parse(self.start, i + 1, msg))
             }
 
             // The end of the color in a non-hexadecimal character, as expected.
             // Re-inspect the current character from the base state.
-            if i == self.start + 7 && !is_hexadecimal(c) {
-                // TODO: The new parser does not need the tokens to have content
-                // if the source available to the parser. (Which it needs to be
-                // anyway to generate errors.)
-                let inner = self.parse_utf8_str(self.start, i).unwrap();
-                self.push(Token::Color(inner), i);
-                return change_state(i, State::Base)
-            }
-
-            assert!(i < self.start + 7, "Would enter infinite loop when lexing color.");
+            // TODO: The new parser does not need the tokens to have content
+            // if the source available to the parser. (Which it needs to be
+            // anyway to generate errors.)
+            let inner = self.parse_utf8_str(self.start, i).unwrap();
+            self.push(Token::Color(inner), i);
+            return change_state(i, State::Base)
         }
 
-        if self.start + 7 == self.input.len() {
-            // The input ends in a color.
-            let inner = self.parse_utf8_str(self.start, self.input.len()).unwrap();
-            self.push(Token::Color(inner), self.input.len());
-            done_at_end_of_input()
-        } else {
-            // The input ends in a color, but we were still expecting digits.
-            let msg = "Expected six hexadecimal digits, but input ended.";
-            Err(Error::parse(self.start, self.input.len(), msg.into()))
+        match self.input.len() - self.start - 1 {
+            6 | 8 => {
+                // The input ends in a color.
+                let inner = self.parse_utf8_str(self.start, self.input.len()).unwrap();
+                self.push(Token::Color(inner), self.input.len());
+                done_at_end_of_input()
+            }
+            7 => {
+                let msg = "Expected six or eight hexadecimal digits, found seven.";
+                Err(Error::parse(self.start, self.input.len(), msg.into()))
+            }
+            _ => {
+                // The input ends in a color, but we were still expecting digits.
+                let msg = "Expected six hexadecimal digits, but input ended.";
+                Err(Error::parse(self.start, self.input.len(), msg.into()))
+            }
         }
     }
 
     /// Skip until a newline is found, then switch to the whitespace state.
+    ///
+    /// A comment that starts with exactly three slashes is a doc comment. Doc
+    /// comments are not trivia, they are always pushed.
     fn lex_comment(&mut self) -> Result<(usize, State)> {
         debug_assert!(self.has_at(self.start, b"//"));
 
         // Skip the first two bytes, those are the "//" characters.
         for i in self.start + 2..self.input.len() {
             if self.input[i] == b'\n' {
-                // Change to the whitespace state, because the last character
-                // we saw was whitespace after all. Continue immediately at
-                // the next byte (i + 1), there is no need to re-inspect the
-                // newline.
-                return change_state(i + 1, State::Space)
+                // Change to the whitespace state, because the newline is
+                // whitespace after all. It is inspected again in that state,
+                // so it becomes part of the whitespace token, if any.
+                self.push_comment(i)?;
+                return change_state(i, State::Space)
             }
         }
 
+        let end = self.input.len();
+        self.push_comment(end)?;
         done_at_end_of_input()
     }
 
+    /// Push the line comment that starts at `self.start` and ends at `end`.
+    fn push_comment(&mut self, end: usize) -> Result<()> {
+        let is_doc = self.has_at(self.start, b"///") && !self.has_at(self.start, b"////");
+        if is_doc {
+            let inner = self.parse_utf8_str(self.start, end)?;
+            self.push(Token::DocComment(inner), end);
+            Ok(())
+        } else {
+            self.push_trivia(Token::Comment, end)
+        }
+    }
+
+    /// Skip until the "*/" that matches the opening "/*", then switch to the
+    /// base state.
+    ///
+    /// Block comments nest, so a part of a document that already contains
+    /// block comments can itself be commented out.
+    fn lex_block_comment(&mut self) -> Result<(usize, State)> {
+        debug_assert!(self.has_at(self.start, b"/*"));
+
+        let mut depth = 0;
+        let mut i = self.start;
+        while i < self.input.len() {
+            if self.has_at(i, b"/*") {
+                depth += 1;
+                i += 2;
+            } else if self.has_at(i, b"*/") {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    self.push_trivia(Token::Comment, i)?;
+                    return change_state(i, State::Base)
+                }
+            } else {
+                i += 1;
+            }
+        }
+
+        // If we get here, the input ended inside the comment. Point at the
+        // opening "/*", because the end of input is not a helpful location.
+        let msg = "Block comment is not closed. Expected '*/' before the end of input.";
+        Err(Error::parse(self.start, self.start + 2, msg.into()))
+    }
+
+    /// Push a comment or whitespace token that ends at `end`, if trivia are
+    /// kept. The `make_token` function receives the source text of the token.
+    fn push_trivia<F>(&mut self, make_token: F, end: usize) -> Result<()>
+    where F: FnOnce(&'a str) -> Token<'a> {
+        if self.keep_trivia && end > self.start {
+            let inner = self.parse_utf8_str(self.start, end)?;
+            self.push(make_token(inner), end);
+        }
+        Ok(())
+    }
+
     /// Lex an identifier untl a state change occurs.
     fn lex_ident(&mut self) -> Result<(usize, State)> {
-        debug_assert!(is_alphabetic_or_underscore(self.input[self.start]));
-
-        // Skip the first byte, because we already know that it contains
-        // either an alphabetic character or underscore. For the other
-        // characters, digits are allowed too.
-        for i in self.start + 1..self.input.len() {
-            if !is_alphanumeric_or_underscore(self.input[i]) {
-                // An identifier consists of alphanumeric characters or
-                // underscores, so at the first one that is not one of those,
-                // change to the base state and re-inspect it.
-                let inner = self.parse_utf8_str(self.start, i).unwrap();
-                self.push(make_keyword_or_ident(inner), i);
-                return change_state(i, State::Base)
+        debug_assert!(starts_with_ident_char(&self.input[self.start..]));
+
+        // Identifiers may contain non-ASCII letters, so we step through the
+        // input a code point at a time, rather than a byte at a time. We
+        // already know that the first code point is a letter or underscore.
+        // For the other code points, digits are allowed too.
+        let mut i = self.start;
+        while let Some(c) = decode_char(&self.input[i..]) {
+            if c != '_' && !c.is_alphanumeric() {
+                break
             }
+            i += c.len_utf8();
         }
 
-        // The input ended in an identifier.
-        let inner = self.parse_utf8_str(self.start, self.input.len()).unwrap();
-        self.push(make_keyword_or_ident(inner), self.input.len());
-        done_at_end_of_input()
+        // An identifier consists of alphanumeric characters or underscores, so
+        // at the first one that is not one of those, change to the base state
+        // and re-inspect it. The unwrap is safe, because `decode_char` only
+        // steps over valid UTF-8.
+        let inner = self.parse_utf8_str(self.start, i).unwrap();
+        self.push(make_keyword_or_ident(inner), i);
+
+        if i == self.input.len() {
+            // The input ended in an identifier.
+            done_at_end_of_input()
+        } else {
+            change_state(i, State::Base)
+        }
     }
 
     /// Lex in the number state until a state change occurs.
@@ -401,6 +555,18 @@ impl<'a> Lexer<'a> {
                     self.push_from(i, Token::UnitPt, i + 2);
                     return change_state(i + 2, State::Base)
                 }
+                b'p' if self.has_at(i + 1, b"w") => {
+                    let inner = self.parse_utf8_str(self.start, i).unwrap();
+                    self.push(Token::Number(inner), i);
+                    self.push_from(i, Token::UnitPw, i + 2);
+                    return change_state(i + 2, State::Base)
+                }
+                b'p' if self.has_at(i + 1, b"h") => {
+                    let inner = self.parse_utf8_str(self.start, i).unwrap();
+                    self.push(Token::Number(inner), i);
+                    self.push_from(i, Token::UnitPh, i + 2);
+                    return change_state(i + 2, State::Base)
+                }
                 b'h' => {
                     let inner = self.parse_utf8_str(self.start, i).unwrap();
                     self.push(Token::Number(inner), i);
@@ -501,11 +667,14 @@ impl<'a> Lexer<'a> {
                 _ => {
                     // On anything else we switch back to the base state and
                     // inspect the current byte again in that state.
+                    self.push_trivia(Token::Space, i)?;
                     return change_state(i, State::Base)
                 }
             }
         }
 
+        let end = self.input.len();
+        self.push_trivia(Token::Space, end)?;
         done_at_end_of_input()
     }
 
@@ -552,6 +721,32 @@ fn is_alphanumeric_or_underscore(byte: u8) -> bool {
     is_alphabetic_or_underscore(byte) || (b'0' <= byte && byte <= b'9')
 }
 
+/// Decode the code point that the UTF-8 input starts with, if it is valid.
+fn decode_char(input: &[u8]) -> Option<char> {
+    use std::str;
+    let len = match input.first() {
+        None => return None,
+        Some(&byte) if byte < 0x80 => 1,
+        Some(&byte) if byte >= 0xf0 => 4,
+        Some(&byte) if byte >= 0xe0 => 3,
+        Some(_) => 2,
+    };
+    input.get(..len)
+         .and_then(|bytes| str::from_utf8(bytes).ok())
+         .and_then(|s| s.chars().next())
+}
+
+/// Check whether the UTF-8 input starts with a letter or underscore.
+///
+/// Letters are not restricted to ASCII, so identifiers can be written in any
+/// language. Keywords are matched exactly, so they remain ASCII.
+fn starts_with_ident_char(input: &[u8]) -> bool {
+    match decode_char(input) {
+        Some(c) => c == '_' || c.is_alphabetic(),
+        None => false,
+    }
+}
+
 /// Check whether a byte of UTF-8 is an ASCII digit.
 fn is_digit(byte: u8) -> bool {
     b'0' <= byte && byte <= b'9'
@@ -565,10 +760,18 @@ fn is_hexadecimal(byte: u8) -> bool {
 /// Returns either a keyword if one matches, or an identifier token otherwise.
 fn make_keyword_or_ident(ident: &str) -> Token {
     match ident {
+        "else" => Token::KwElse,
         "function" => Token::KwFunction,
         "import" => Token::KwImport,
+        "infix" => Token::KwInfix,
+        "match" => Token::KwMatch,
+        "or" => Token::KwOr,
+        "pris_version" => Token::KwPrisVersion,
         "put" => Token::KwPut,
+        "repeat" => Token::KwRepeat,
         "return" => Token::KwReturn,
+        "section" => Token::KwSection,
+        "with" => Token::KwWith,
         _ => Token::Ident(ident),
     }
 }
@@ -615,27 +818,24 @@ fn make_parse_error(at: usize, input: &[u8]) -> Error {
             format!("Unexpected character '{}'.", char::from(x))
         }
         x => {
-            // If we find a non-ASCII byte, try to decode the next few bytes as
-            // UTF-8. If that succeeds, complain about non-ASCII identifiers.
-            // Otherwise complain about the encoding. Note that the unwrap will
-            // succeed, as we have at least one byte in the input.
-            let to = if input.len() < 8 { input.len() } else { 8 };
-            match String::from_utf8_lossy(&input[..to]).chars().next().unwrap() {
-                '\u{fffd}' => {
-                    // U+FFFD is generated when decoding UTF-8 fails.
+            // If we find a non-ASCII byte, try to decode it as UTF-8. If that
+            // succeeds, it is a valid character, but not a letter, because a
+            // letter would have started an identifier. Otherwise complain
+            // about the encoding.
+            match decode_char(input) {
+                Some(c) => {
+                    let message = format!("Unexpected character '{}'. ", c) +
+                        "Note that identifiers must start with a letter or underscore.";
+                    return Error::parse(at, at + c.len_utf8(), message)
+                }
+                None => {
                     format!("Unexpected byte 0x{:x}. ", x) +
                     "Note that Pris expects UTF-8 encoded files."
                 }
-                c => {
-                    format!("Unexpected character '{}'. ", c) +
-                    "Note that identifiers must be ASCII."
-                }
             }
         }
     };
 
-    // The end index is not entirely correct for the non-ASCII but valid UTF-8
-    // case, but meh.
     Error::parse(at, at + 1, message)
 }
 
@@ -684,6 +884,73 @@ fn lex_strips_a_comment() {
     assert_eq!(tokens[1], (Token::Ident("bar"), Span::new(23, 26)));
 }
 
+#[test]
+fn lex_with_comments_keeps_comments_and_whitespace() {
+    let input = b"foo\n// This is comment\nbar // Trailing";
+    let tokens = lex_with_comments(input).unwrap();
+    assert_eq!(tokens, vec![
+        (Token::Ident("foo"), Span::new(0, 3)),
+        (Token::Space("\n"), Span::new(3, 4)),
+        (Token::Comment("// This is comment"), Span::new(4, 22)),
+        (Token::Space("\n"), Span::new(22, 23)),
+        (Token::Ident("bar"), Span::new(23, 26)),
+        (Token::Space(" "), Span::new(26, 27)),
+        (Token::Comment("// Trailing"), Span::new(27, 38)),
+    ]);
+}
+
+#[test]
+fn lex_keeps_doc_comments() {
+    let input = b"/// Doc\n//// Not doc\nfoo";
+    let tokens = lex(input).unwrap();
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(tokens[0], (Token::DocComment("/// Doc"), Span::new(0, 7)));
+    assert_eq!(tokens[1], (Token::Ident("foo"), Span::new(21, 24)));
+}
+
+#[test]
+fn lex_strips_a_nested_block_comment() {
+    let input = b"foo /* a /* b */\n c */bar";
+    let tokens = lex(input).unwrap();
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(tokens[0], (Token::Ident("foo"), Span::new(0, 3)));
+    assert_eq!(tokens[1], (Token::Ident("bar"), Span::new(22, 25)));
+}
+
+#[test]
+fn lex_with_comments_keeps_block_comments() {
+    let input = b"/* a */ foo/**/";
+    let tokens = lex_with_comments(input).unwrap();
+    assert_eq!(tokens, vec![
+        (Token::Comment("/* a */"), Span::new(0, 7)),
+        (Token::Space(" "), Span::new(7, 8)),
+        (Token::Ident("foo"), Span::new(8, 11)),
+        (Token::Comment("/**/"), Span::new(11, 15)),
+    ]);
+}
+
+#[test]
+fn lex_rejects_an_unclosed_block_comment() {
+    let input = b"foo /* a /* b */ c";
+    match lex(input) {
+        Err(Error::Parse(ref pe)) => assert_eq!((pe.start, pe.end), (4, 6)),
+        _ => panic!("Expected a parse error."),
+    }
+}
+
+#[test]
+fn lex_with_comments_covers_the_entire_input() {
+    let input = b"  x = 2em + 3w // c\n\n{\n  put t(---raw---) at (1h, #ffffff)\n}\n";
+    let tokens = lex_with_comments(input).unwrap();
+    let mut end = 0;
+    for &(_, span) in &tokens {
+        assert_eq!(span.start, end);
+        assert!(span.end > span.start);
+        end = span.end;
+    }
+    assert_eq!(end, input.len());
+}
+
 #[test]
 fn lex_handles_a_color() {
     let input = b"#f8f8f8 #cfcfcf";
@@ -693,11 +960,23 @@ fn lex_handles_a_color() {
     assert_eq!(tokens[1], (Token::Color("#cfcfcf"), Span::new(8, 15)));
 }
 
+#[test]
+fn lex_handles_a_color_with_alpha() {
+    let input = b"#f8f8f880 #cfcfcf";
+    let tokens = lex(input).unwrap();
+    assert_eq!(tokens[0], (Token::Color("#f8f8f880"), Span::new(0, 9)));
+    assert_eq!(tokens[1], (Token::Color("#cfcfcf"), Span::new(10, 17)));
+    assert_eq!(lex(b"#f8f8f88").unwrap_err().message(), "Expected six or eight hexadecimal digits, found seven.");
+    assert_eq!(lex(b"#f8f8f88 ").unwrap_err().message(), "Expected six or eight hexadecimal digits, found seven.");
+    assert_eq!(lex(b"#f8f8f8801").unwrap_err().message(), "Expected at most eight hexadecimal digits, found one more.");
+    assert_eq!(lex(b"#f8f8f8x").unwrap_err().message(), "Expected six hexadecimal digits, found extra 'x'.");
+}
+
 #[test]
 fn lex_handles_numbers() {
-    let input = b"31 31.0 2w 2h 2em 2pt 17";
+    let input = b"31 31.0 2w 2h 2em 2pt 17 5pw 5ph";
     let tokens = lex(input).unwrap();
-    assert_eq!(tokens.len(), 11);
+    assert_eq!(tokens.len(), 15);
     assert_eq!(tokens[0], (Token::Number("31"), Span::new(0, 2)));
     assert_eq!(tokens[1], (Token::Number("31.0"), Span::new(3, 7)));
     assert_eq!(tokens[2], (Token::Number("2"), Span::new(8, 9)));
@@ -709,6 +988,8 @@ fn lex_handles_numbers() {
     assert_eq!(tokens[8], (Token::Number("2"), Span::new(18, 19)));
     assert_eq!(tokens[9], (Token::UnitPt, Span::new(19, 21)));
     assert_eq!(tokens[10], (Token::Number("17"), Span::new(22, 24)));
+    assert_eq!(tokens[12], (Token::UnitPw, Span::new(26, 28)));
+    assert_eq!(tokens[14], (Token::UnitPh, Span::new(30, 32)));
 }
 
 #[test]
@@ -745,11 +1026,36 @@ fn lex_handles_punctuation() {
     assert_eq!(tokens[8], (Token::RParen, Span::new(14, 15)));
 }
 
+#[test]
+fn lex_handles_match() {
+    let input = b"match k { \"a\"->1, else -> -1 }";
+    let tokens = lex(input).unwrap();
+    assert_eq!(tokens.len(), 12);
+    assert_eq!(tokens[0], (Token::KwMatch, Span::new(0, 5)));
+    assert_eq!(tokens[4], (Token::Arrow, Span::new(13, 15)));
+    assert_eq!(tokens[7], (Token::KwElse, Span::new(18, 22)));
+    assert_eq!(tokens[8], (Token::Arrow, Span::new(23, 25)));
+    assert_eq!(tokens[9], (Token::Minus, Span::new(26, 27)));
+}
+
+#[test]
+fn lex_handles_update_operators() {
+    let input = b"x+=1 y -= -2 z*=w";
+    let tokens = lex(input).unwrap();
+    assert_eq!(tokens.len(), 10);
+    assert_eq!(tokens[1], (Token::PlusEquals, Span::new(1, 3)));
+    assert_eq!(tokens[2], (Token::Number("1"), Span::new(3, 4)));
+    assert_eq!(tokens[4], (Token::MinusEquals, Span::new(7, 9)));
+    assert_eq!(tokens[5], (Token::Minus, Span::new(10, 11)));
+    assert_eq!(tokens[8], (Token::StarEquals, Span::new(14, 16)));
+    assert_eq!(tokens[9], (Token::Ident("w"), Span::new(16, 17)));
+}
+
 #[test]
 fn lex_handles_keywords() {
-    let input = b"return the function put at the import";
+    let input = b"return the function put at the import infix section pris_version repeat or with";
     let tokens = lex(input).unwrap();
-    assert_eq!(tokens.len(), 7);
+    assert_eq!(tokens.len(), 13);
     assert_eq!(tokens[0], (Token::KwReturn, Span::new(0, 6)));
     assert_eq!(tokens[1], (Token::Ident("the"), Span::new(7, 10)));
     assert_eq!(tokens[2], (Token::KwFunction, Span::new(11, 19)));
@@ -757,6 +1063,35 @@ fn lex_handles_keywords() {
     assert_eq!(tokens[4], (Token::Ident("at"), Span::new(24, 26)));
     assert_eq!(tokens[5], (Token::Ident("the"), Span::new(27, 30)));
     assert_eq!(tokens[6], (Token::KwImport, Span::new(31, 37)));
+    assert_eq!(tokens[7], (Token::KwInfix, Span::new(38, 43)));
+    assert_eq!(tokens[8], (Token::KwSection, Span::new(44, 51)));
+    assert_eq!(tokens[9], (Token::KwPrisVersion, Span::new(52, 64)));
+    assert_eq!(tokens[10], (Token::KwRepeat, Span::new(65, 71)));
+    assert_eq!(tokens[11], (Token::KwOr, Span::new(72, 74)));
+    assert_eq!(tokens[12], (Token::KwWith, Span::new(75, 79)));
+}
+
+#[test]
+fn lex_handles_non_ascii_identifiers() {
+    let input = "größe=één _π2 функция".as_bytes();
+    let tokens = lex(input).unwrap();
+    assert_eq!(tokens.len(), 5);
+    assert_eq!(tokens[0], (Token::Ident("größe"), Span::new(0, 7)));
+    assert_eq!(tokens[1], (Token::Equals, Span::new(7, 8)));
+    assert_eq!(tokens[2], (Token::Ident("één"), Span::new(8, 13)));
+    assert_eq!(tokens[3], (Token::Ident("_π2"), Span::new(14, 18)));
+    assert_eq!(tokens[4], (Token::Ident("функция"), Span::new(19, 33)));
+}
+
+#[test]
+fn lex_rejects_non_letter_characters_outside_strings() {
+    let input = "x → y".as_bytes();
+    let err = lex(input).unwrap_err();
+    assert!(err.message().contains("'→'"));
+    match err {
+        Error::Parse(ref pe) => assert_eq!((pe.start, pe.end), (2, 5)),
+        _ => panic!("Expected a parse error."),
+    }
 }
 
 #[test]
@@ -5,31 +5,66 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
+#[cfg(feature = "native")]
 extern crate freetype;
 
 mod builtins;
 mod elements;
+#[cfg(feature = "native")]
 mod fontconfig;
+#[cfg(feature = "native")]
+mod glyph_lookup;
+#[cfg(feature = "shaping")]
 mod harfbuzz;
+mod inflate;
+#[cfg(feature = "native")]
+mod json;
 mod names;
 mod parser_utils;
+mod pdf_object;
 mod png;
+#[cfg(feature = "svg")]
 mod rsvg;
 mod types;
+mod uri;
 
 #[macro_use]
 pub mod pretty;
 
 pub mod ast;
+pub mod cancel;
+#[cfg(feature = "native")]
+pub mod compile;
+#[cfg(feature = "native")]
 pub mod cairo;
+pub mod diff;
+#[cfg(feature = "native")]
 pub mod driver;
 pub mod error;
+pub mod external;
 pub mod interpreter;
 pub mod lexer;
+pub mod limits;
+pub mod lint;
+pub mod logger;
+pub mod module;
+pub mod output;
 pub mod parser;
+pub mod pdf;
+pub mod profile;
 pub mod runtime;
+#[cfg(feature = "native")]
+pub mod scene;
+pub mod simulation;
+pub mod spellcheck;
+pub mod symbol;
+pub mod timing;
+pub mod toc;
+pub mod todo;
+#[cfg(feature = "native")]
+pub mod transcript;
 
-pub use elements::Vec2;
+pub use elements::{Color, Vec2};
 
 // This is the compiler entry point for the library, which is used by the
 // command-line program. The source for that program is in bin/pris.rs.
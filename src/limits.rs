@@ -0,0 +1,221 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module enforces limits on the resources that evaluation may use.
+//!
+//! A document can recurse without end, or generate an unbounded number of
+//! elements with `tile` and friends. When rendering documents that are not
+//! trusted, such as in a service, limits turn that into an error, rather than
+//! a renderer that never finishes or runs out of memory.
+
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::mem;
+
+use elements::{Element, Glyph, PlacedElement, TextCluster, Vec2};
+use error::{Error, Result};
+use runtime::Frame;
+use symbol::Symbol;
+
+/// Limits on evaluation, where `None` means unlimited.
+#[derive(Clone, Default)]
+pub struct Limits {
+    /// The number of statements to execute, in the entire document.
+    pub max_statements: Option<u64>,
+    /// The number of elements on one slide, summed over its steps.
+    pub max_elements: Option<u64>,
+    /// The estimated memory in bytes of the elements of all slides.
+    pub max_memory: Option<u64>,
+}
+
+/// Resources used so far, checked against the limits.
+pub struct Budget {
+    limits: Limits,
+    statements: Cell<u64>,
+    memory: Cell<u64>,
+    /// The number of the slide being evaluated, starting at 1.
+    slide: Cell<usize>,
+    /// Whether evaluation is currently inside a top-level block.
+    in_slide: Cell<bool>,
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Budget {
+        Budget {
+            limits: limits,
+            statements: Cell::new(0),
+            memory: Cell::new(0),
+            slide: Cell::new(0),
+            in_slide: Cell::new(false),
+        }
+    }
+
+    pub fn unlimited() -> Budget {
+        Budget::new(Limits::default())
+    }
+
+    /// Record that evaluation of the next slide starts.
+    pub fn begin_slide(&self) {
+        self.slide.set(self.slide.get() + 1);
+        self.in_slide.set(true);
+    }
+
+    /// Record that a slide is done, and count its memory towards the limit.
+    pub fn end_slide(&self, frame: &Frame) -> Result<()> {
+        self.in_slide.set(false);
+        let memory = self.memory.get() + estimate_frame_size(frame);
+        self.memory.set(memory);
+        match self.limits.max_memory {
+            Some(max) if memory > max => {
+                let msg = format!(
+                    "Slide {} exceeds the memory limit: the slides so far take \
+                     an estimated {} bytes, but the limit is {} bytes.",
+                    self.slide.get(), memory, max
+                );
+                Err(Error::limit(msg))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count one executed statement.
+    pub fn count_statement(&self) -> Result<()> {
+        let statements = self.statements.get() + 1;
+        self.statements.set(statements);
+        match self.limits.max_statements {
+            Some(max) if statements > max => {
+                let msg = format!(
+                    "Evaluation exceeds the limit of {} statements, {}. \
+                     Note: the document may contain unbounded recursion.",
+                    max, self.location()
+                );
+                Err(Error::limit(msg))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the number of elements of a frame that is being built.
+    pub fn check_elements(&self, frame: &Frame) -> Result<()> {
+        let max = match self.limits.max_elements {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        let elements = count_frame_elements(frame);
+        if elements > max {
+            let msg = format!(
+                "A frame has {} elements, but the limit is {}, {}.",
+                elements, max, self.location()
+            );
+            return Err(Error::limit(msg))
+        }
+        Ok(())
+    }
+
+    /// Check that a builtin may place `count` new elements, before it
+    /// allocates them.
+    pub fn check_new_elements(&self, fn_name: Symbol, count: u64) -> Result<()> {
+        match self.limits.max_elements {
+            Some(max) if count > max => {
+                let msg = format!(
+                    "'{}' would place {} elements, but the limit is {}, {}.",
+                    fn_name, count, max, self.location()
+                );
+                Err(Error::limit(msg))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Return the number of the slide being evaluated, if inside of one.
+    pub fn slide(&self) -> Option<usize> {
+        if self.in_slide.get() { Some(self.slide.get()) } else { None }
+    }
+
+    /// Describe where evaluation is, for use in error messages.
+    fn location(&self) -> String {
+        match (self.in_slide.get(), self.slide.get()) {
+            (true, n) => format!("in slide {}", n),
+            (false, 0) => String::from("before the first slide"),
+            (false, n) => format!("after slide {}", n),
+        }
+    }
+}
+
+/// Count elements, including the elements nested inside of them.
+///
+/// Groups and symbols share their elements between placements, so their
+/// contents are counted once, no matter how often they are placed. The
+/// addresses of the shared contents that were counted are in `shared`.
+fn count_elements(elements: &[PlacedElement], shared: &mut HashSet<usize>) -> u64 {
+    elements.iter().map(|pe| 1 + match pe.element {
+        Element::Transformed(ref inner, _) => count_elements(inner, shared),
+        Element::Affine(ref inner, _) => count_elements(inner, shared),
+        Element::Opacity(ref inner, _) => count_elements(inner, shared),
+        Element::Clipped(ref inner, _, _) => count_elements(inner, shared),
+        Element::Group(ref inner) => {
+            let inner: &[PlacedElement] = &inner[..];
+            count_shared(inner, shared)
+        }
+        Element::Symbol(ref symbol) => count_shared(&symbol.elements, shared),
+        _ => 0,
+    }).sum()
+}
+
+fn count_shared(elements: &[PlacedElement], shared: &mut HashSet<usize>) -> u64 {
+    if shared.insert(elements.as_ptr() as usize) {
+        count_elements(elements, shared)
+    } else {
+        0
+    }
+}
+
+/// Count the elements of all steps of a frame.
+pub fn count_frame_elements(frame: &Frame) -> u64 {
+    let mut shared = HashSet::new();
+    frame.get_subframes().iter().map(|sf| count_elements(sf.get_elements(), &mut shared)).sum()
+}
+
+/// Estimate the size in bytes of elements and the data they own.
+fn estimate_size(elements: &[PlacedElement]) -> u64 {
+    elements.iter().map(|pe| {
+        let owned = match pe.element {
+            Element::FillPolygon(ref p) => p.vertices.len() * mem::size_of::<Vec2>(),
+            Element::StrokePolygon(ref p) => p.vertices.len() * mem::size_of::<Vec2>(),
+            Element::Text(ref text) => {
+                text.glyphs.len() * mem::size_of::<Glyph>()
+                    + text.clusters.len() * mem::size_of::<TextCluster>()
+                    + text.text.len()
+            }
+            Element::Transformed(ref inner, _) => estimate_size(inner) as usize,
+            Element::Affine(ref inner, _) => estimate_size(inner) as usize,
+            Element::Opacity(ref inner, _) => estimate_size(inner) as usize,
+            Element::Clipped(ref inner, _, _) => estimate_size(inner) as usize,
+            // Shared and external data is not owned by a single placement.
+            Element::Group(..) | Element::Symbol(..) => 0,
+            Element::Png(..) | Element::Svg(..) | Element::Hyperlink(..) => 0,
+        };
+        (mem::size_of::<PlacedElement>() + owned) as u64
+    }).sum()
+}
+
+fn estimate_frame_size(frame: &Frame) -> u64 {
+    frame.get_subframes().iter().map(|sf| estimate_size(sf.get_elements())).sum()
+}
+
+#[test]
+fn count_statement_fails_after_the_limit() {
+    let limits = Limits { max_statements: Some(2), ..Limits::default() };
+    let budget = Budget::new(limits);
+    assert!(budget.count_statement().is_ok());
+    budget.begin_slide();
+    assert!(budget.count_statement().is_ok());
+    match budget.count_statement() {
+        Err(Error::Limit(..)) => {}
+        _ => panic!("Expected a limit error."),
+    }
+}
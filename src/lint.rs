@@ -0,0 +1,315 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module checks documents for likely mistakes, without evaluating them.
+//!
+//! Pris is dynamically scoped, so whether a variable is in scope depends on
+//! the call site of the function that refers to it. Rather than tracking
+//! scopes, the checks here consider the document as a whole: a variable counts
+//! as assigned if it is assigned anywhere, and as used if it is referred to
+//! anywhere. This does not catch every mistake, but it does not flag correct
+//! documents either.
+
+use ast::{BinOp, Block, Document, Idents, Stmt, Term};
+use lexer::{Span, Token};
+use names;
+use runtime::{Env, Val};
+use symbol::Symbol;
+
+/// Variables that the runtime reads, but that have no default value.
+const RUNTIME_VARIABLES: &'static [Symbol] = &[
+    names::background,
+    names::background_color,
+    names::container_size,
+    names::minutes,
+    names::notes,
+    names::slide_title,
+];
+
+/// Names bound and referred to in a document, in order of occurrence.
+struct Names {
+    /// Targets of assignments.
+    assigned: Vec<Symbol>,
+    /// Assignment targets and function parameters.
+    bound: Vec<Symbol>,
+    /// Variables that are referred to, the first part of a path.
+    referenced: Vec<Symbol>,
+    /// All parts of paths, fields of frames are variables too.
+    used: Vec<Symbol>,
+}
+
+impl Names {
+    fn new() -> Names {
+        Names {
+            assigned: Vec::new(),
+            bound: Vec::new(),
+            referenced: Vec::new(),
+            used: Vec::new(),
+        }
+    }
+
+    fn visit_statements<'a>(&mut self, statements: &[Stmt<'a>]) {
+        for statement in statements {
+            match *statement {
+                Stmt::Assign(ref assign) => {
+                    self.assigned.push(assign.0);
+                    self.bound.push(assign.0);
+                    self.visit_term(&assign.1);
+                }
+                // Reading the old value does not count as a use, the new
+                // value must still be used.
+                Stmt::Update(ref update) => {
+                    self.referenced.push(update.0);
+                    self.assigned.push(update.0);
+                    self.visit_term(&update.2);
+                }
+                Stmt::Return(ref ret) => self.visit_term(&ret.0),
+                Stmt::Block(ref block) => self.visit_block(block),
+                Stmt::Put(ref put) => self.visit_term(&put.0),
+                Stmt::Repeat(ref repeat) => {
+                    self.bound.push(names::repeat_index);
+                    self.visit_term(&repeat.0);
+                    self.visit_block(&repeat.1);
+                }
+                Stmt::With(ref with) => {
+                    self.visit_block(&with.0);
+                    self.visit_block(&with.1);
+                }
+                Stmt::Import(..) => {}
+                Stmt::Infix(..) => {}
+                Stmt::Section(..) => {}
+                Stmt::Version(..) => {}
+                Stmt::Comment(..) => {}
+            }
+        }
+    }
+
+    fn visit_block<'a>(&mut self, block: &Block<'a>) {
+        self.visit_statements(&block.0);
+    }
+
+    fn visit_idents(&mut self, idents: &Idents) {
+        self.referenced.push(idents.0[0]);
+        self.used.extend(idents.0.iter().cloned());
+    }
+
+    fn visit_term<'a>(&mut self, term: &Term<'a>) {
+        match *term {
+            Term::Idents(ref idents) => self.visit_idents(idents),
+            Term::Coord(ref coord) => {
+                self.visit_term(&coord.0);
+                self.visit_term(&coord.1);
+            }
+            Term::BinOp(ref bin_term) => {
+                match (&bin_term.0, &bin_term.1) {
+                    // The variable before 'or' may be unset, that is what the
+                    // default is for, so it is used but not referenced.
+                    (&Term::Idents(ref idents), &BinOp::Or) => self.used.extend(idents.0.iter().cloned()),
+                    _ => self.visit_term(&bin_term.0),
+                }
+                if let BinOp::Infix(ref op) = bin_term.1 {
+                    self.visit_idents(op);
+                }
+                self.visit_term(&bin_term.2);
+            }
+            Term::UnOp(ref un_term) => self.visit_term(&un_term.1),
+            Term::FnCall(ref call) => {
+                self.visit_term(&call.0);
+                for arg in &call.1 {
+                    self.visit_term(arg);
+                }
+            }
+            Term::FnDef(ref fn_def) => {
+                self.bound.extend(fn_def.0.iter().cloned());
+                self.visit_block(&fn_def.1);
+            }
+            Term::Block(ref block) => self.visit_block(block),
+            Term::Match(ref m) => {
+                self.visit_term(&m.0);
+                for arm in &m.1 {
+                    self.visit_term(&arm.1);
+                }
+                if let Some(ref default) = m.2 {
+                    self.visit_term(default);
+                }
+            }
+            Term::List(ref list) => {
+                for elem in &list.0 {
+                    self.visit_term(elem);
+                }
+            }
+            Term::String(..) => {}
+            Term::Number(..) => {}
+            Term::Color(..) => {}
+        }
+    }
+}
+
+/// Return whether `name` is bound to a builtin function by default.
+fn is_builtin_fn(builtins: &Env, name: Symbol) -> bool {
+    match builtins.get(name) {
+        Some(val) => match **val {
+            Val::FnIntrin(..) => true,
+            _ => false,
+        },
+        None => false,
+    }
+}
+
+/// Return whether `name` has a value by default.
+fn is_builtin(builtins: &Env, name: Symbol) -> bool {
+    builtins.get(name).is_some()
+}
+
+/// Remove all but the first occurrence of every name.
+fn dedup(names: Vec<Symbol>) -> Vec<Symbol> {
+    let mut result = Vec::new();
+    for name in names {
+        if !result.contains(&name) {
+            result.push(name);
+        }
+    }
+    result
+}
+
+/// Return the names of builtin functions that are assigned to, or used as
+/// function parameter, in order of occurrence.
+///
+/// Such variables shadow the builtin for the remainder of the scope, which is
+/// usually a mistake, e.g. naming a variable `t` and then calling `t("Hi")`.
+pub fn shadowed_builtins(doc: &Document) -> Vec<Symbol> {
+    let builtins = Env::new();
+    let mut names = Names::new();
+    names.visit_statements(&doc.0);
+    names.bound.into_iter().filter(|&name| is_builtin_fn(&builtins, name)).collect()
+}
+
+/// Return the variables that are assigned to, but never used.
+///
+/// Assignments to variables that the runtime reads, such as `font_size`, are
+/// not reported.
+pub fn unused_variables(doc: &Document) -> Vec<Symbol> {
+    let builtins = Env::new();
+    let mut names = Names::new();
+    names.visit_statements(&doc.0);
+    let unused = names.assigned.iter().cloned().filter(|name| {
+        !names.used.contains(name)
+            && !is_builtin(&builtins, *name)
+            && !RUNTIME_VARIABLES.contains(name)
+    }).collect();
+    dedup(unused)
+}
+
+/// Return the variables that are referred to, but never assigned.
+///
+/// Variables in `defined` are assumed to be assigned before evaluation, as
+/// with the `--define` command-line flag.
+pub fn undefined_variables(doc: &Document, defined: &[Symbol]) -> Vec<Symbol> {
+    let builtins = Env::new();
+    let mut names = Names::new();
+    names.visit_statements(&doc.0);
+    let undefined = names.referenced.iter().cloned().filter(|name| {
+        !names.bound.contains(name)
+            && !is_builtin(&builtins, *name)
+            && !RUNTIME_VARIABLES.contains(name)
+            && !defined.contains(name)
+    }).collect();
+    dedup(undefined)
+}
+
+/// Return the list separators that differ from the first one in the document.
+///
+/// Lists accept both `;` and `,` as separator, but mixing them in one document
+/// is confusing to read.
+pub fn inconsistent_list_separators<'a>(tokens: &[(Token<'a>, Span)]) -> Vec<Span> {
+    let mut open = Vec::new();
+    let mut first = None;
+    let mut result = Vec::new();
+    for &(token, span) in tokens {
+        match token {
+            Token::LParen | Token::LBrace | Token::LBracket => open.push(token),
+            Token::RParen | Token::RBrace | Token::RBracket => { open.pop(); }
+            Token::Comma | Token::Semicolon if open.last() == Some(&Token::LBracket) => {
+                match first {
+                    None => first = Some(token),
+                    Some(sep) if sep != token => result.push(span),
+                    Some(..) => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+fn parse_document(input: &[u8]) -> Document {
+    use lexer::lex;
+    use parser::parse;
+    let tokens = lex(input).unwrap();
+    // The document borrows from the input only, not from the tokens.
+    parse(&tokens).unwrap()
+}
+
+#[cfg(test)]
+fn names_of(symbols: Vec<Symbol>) -> Vec<String> {
+    symbols.iter().map(|symbol| symbol.to_string()).collect()
+}
+
+#[test]
+fn shadowed_builtins_finds_assignments_and_parameters() {
+    let doc = parse_document(b"t = 1 f = function(image, x) { line = 2 y = 3 } put { str = 4 }");
+    assert_eq!(names_of(shadowed_builtins(&doc)), vec!["t", "image", "line", "str"]);
+}
+
+#[test]
+fn unused_variables_ignores_fields_and_runtime_variables() {
+    let doc = parse_document(br#"
+        font_size = 1em
+        unused = 1
+        label = { caption = t("Hi") }
+        w = label.caption.width
+        twice = function(x) { return x * 2 }
+        result = twice(3)
+    "#);
+    assert_eq!(names_of(unused_variables(&doc)), vec!["unused", "w", "result"]);
+}
+
+#[test]
+fn undefined_variables_allows_dynamic_scope() {
+    let doc = parse_document(br#"
+        title = function() { put t(title_text) at (margn, margin) }
+        title_text = "Hello"
+        margin = 1em
+        put title() at position
+    "#);
+    assert_eq!(names_of(undefined_variables(&doc, &[])), vec!["margn", "position"]);
+    assert_eq!(names_of(undefined_variables(&doc, &[Symbol::intern("margn"), Symbol::intern("position")])), Vec::<String>::new());
+}
+
+#[test]
+fn inconsistent_list_separators_ignores_call_arguments() {
+    use lexer::lex;
+    let tokens = lex(b"xs = [f(1, 2); 3] ys = [(1, 2), (3, 4)] zs = [[1; 2]; [3, 4]]").unwrap();
+    let spans: Vec<usize> = inconsistent_list_separators(&tokens).iter().map(|s| s.start).collect();
+    assert_eq!(spans, vec![30, 56]);
+}
+
+#[test]
+fn unused_variables_requires_use_after_update() {
+    let doc = parse_document(b"y = 0 y += 1 z = 0 z *= 2 put t(z)");
+    assert_eq!(names_of(unused_variables(&doc)), vec!["y"]);
+    let doc = parse_document(b"w += 1");
+    assert_eq!(names_of(undefined_variables(&doc, &[])), vec!["w"]);
+}
+
+#[test]
+fn undefined_variables_allows_unset_variable_before_or() {
+    let doc = parse_document(b"accent = 0 put t(subtitle or title) size = accent or 1 ");
+    assert_eq!(names_of(undefined_variables(&doc, &[])), vec!["title"]);
+    assert_eq!(names_of(unused_variables(&doc)), vec!["size"]);
+}
@@ -0,0 +1,74 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module routes warnings and other messages to the host.
+//!
+//! The command-line program prints messages, but a program that embeds Pris
+//! may want to capture them instead, to filter them or show them in its own
+//! interface. It can do so by implementing `Logger`.
+
+use std::cell::RefCell;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Level {
+    /// Progress and other information that is not a problem.
+    Info,
+    /// Something that is likely a mistake, but does not stop compilation.
+    Warning,
+}
+
+pub trait Logger {
+    fn log(&self, level: Level, message: &str);
+}
+
+/// Prints messages to stdout, with warnings prefixed.
+pub struct StdoutLogger;
+
+impl Logger for StdoutLogger {
+    fn log(&self, level: Level, message: &str) {
+        match level {
+            Level::Info => println!("{}", message),
+            Level::Warning => println!("Warning: {}", message),
+        }
+    }
+}
+
+/// Prints messages to stderr, for when stdout is used for output.
+pub struct StderrLogger;
+
+impl Logger for StderrLogger {
+    fn log(&self, level: Level, message: &str) {
+        match level {
+            Level::Info => eprintln!("{}", message),
+            Level::Warning => eprintln!("Warning: {}", message),
+        }
+    }
+}
+
+/// Stores messages, so the host can inspect them afterwards.
+pub struct MemoryLogger {
+    messages: RefCell<Vec<(Level, String)>>,
+}
+
+impl MemoryLogger {
+    pub fn new() -> MemoryLogger {
+        MemoryLogger {
+            messages: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Return the messages logged so far, and forget them.
+    pub fn take(&self) -> Vec<(Level, String)> {
+        self.messages.replace(Vec::new())
+    }
+}
+
+impl Logger for MemoryLogger {
+    fn log(&self, level: Level, message: &str) {
+        self.messages.borrow_mut().push((level, message.to_string()));
+    }
+}
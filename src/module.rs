@@ -0,0 +1,432 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module finds and loads the modules that a document imports.
+//!
+//! `import theme.colors` loads `theme/colors.pris`, relative to the directory
+//! of the document. If it is not there, the first part of the path names a
+//! package: a directory with a `pris.package` manifest, in one of the
+//! directories of the search path. `import theme` alone loads the `main.pris`
+//! module of the package. Finally, the `std` package is the standard library,
+//! which is part of the binary, so `import std.colors` works everywhere.
+//!
+//! The documents that the interpreter evaluates borrow from their source, so
+//! all sources are loaded before evaluation starts, and parsed afterwards.
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::result;
+
+use ast::{Block, Document, Stmt, Term};
+use error::{Error, Result};
+use lexer;
+use parser;
+use symbol::Symbol;
+
+/// The file name of the manifest that makes a directory a package.
+pub const MANIFEST: &'static str = "pris.package";
+
+/// The module of a package that `import <package>` loads.
+const MAIN_MODULE: &'static str = "main";
+
+/// The name of the standard library package.
+const STD_PACKAGE: &'static str = "std";
+
+/// The modules of the standard library, by name, in alphabetical order.
+const STD_MODULES: &'static [(&'static str, &'static [u8])] = &[
+    ("arrows", include_bytes!("../std/arrows.pris")),
+    ("colors", include_bytes!("../std/colors.pris")),
+    ("layout", include_bytes!("../std/layout.pris")),
+];
+
+/// Where the source of a module is.
+#[derive(Debug, PartialEq)]
+pub enum Location {
+    /// A file next to the document, or in a package.
+    File(PathBuf),
+    /// A module of the standard library.
+    Std(&'static [u8]),
+}
+
+/// The metadata of a package.
+#[derive(Debug, PartialEq)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+}
+
+impl Manifest {
+    /// Parse a manifest, which consists of lines of the form `key = "value"`.
+    ///
+    /// Empty lines and lines that start with `#` are ignored. The `name` and
+    /// `version` keys are required.
+    pub fn parse(source: &str) -> result::Result<Manifest, String> {
+        let mut name = None;
+        let mut version = None;
+        let mut description = None;
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let mut parts = line.splitn(2, '=').map(|part| part.trim());
+            let key = parts.next().unwrap_or("");
+            let value = match parts.next() {
+                Some(v) if v.len() >= 2 && v.starts_with('"') && v.ends_with('"') => &v[1..v.len() - 1],
+                _ => return Err(format!("Line {} is not of the form 'key = \"value\"'.", i + 1)),
+            };
+            match key {
+                "name" => name = Some(value.to_string()),
+                "version" => version = Some(value.to_string()),
+                "description" => description = Some(value.to_string()),
+                _ => return Err(format!("Line {} has unknown key '{}'.", i + 1, key)),
+            }
+        }
+        match (name, version) {
+            (Some(name), Some(version)) => Ok(Manifest {
+                name: name,
+                version: version,
+                description: description,
+            }),
+            (None, _) => Err("The manifest has no 'name'.".to_string()),
+            (_, None) => Err("The manifest has no 'version'.".to_string()),
+        }
+    }
+}
+
+/// The directories to look for packages in, in order of preference.
+pub struct SearchPath {
+    dirs: Vec<PathBuf>,
+}
+
+impl SearchPath {
+    pub fn new(dirs: Vec<PathBuf>) -> SearchPath {
+        SearchPath { dirs: dirs }
+    }
+
+    /// Search `dirs` first, and then the directories in `PRIS_PATH`.
+    pub fn with_env(mut dirs: Vec<PathBuf>) -> SearchPath {
+        if let Some(paths) = env::var_os("PRIS_PATH") {
+            dirs.extend(env::split_paths(&paths).filter(|p| !p.as_os_str().is_empty()));
+        }
+        SearchPath::new(dirs)
+    }
+
+    /// Return the file of a module, relative to the document in `base_dir`,
+    /// or in a package, or the module of the standard library.
+    ///
+    /// Packages in the search path take precedence over the standard library,
+    /// so a newer version of it can be installed.
+    pub fn resolve(&self, base_dir: &Path, name: &[&str]) -> Result<Location> {
+        let mut local = base_dir.to_path_buf();
+        local.extend(name);
+        local.set_extension("pris");
+        if local.is_file() {
+            return Ok(Location::File(local))
+        }
+
+        for dir in &self.dirs {
+            let package_dir = dir.join(name[0]);
+            let manifest_path = package_dir.join(MANIFEST);
+            if !manifest_path.is_file() {
+                continue
+            }
+            let manifest = read_manifest(&manifest_path)?;
+            if manifest.name != name[0] {
+                let msg = format!(
+                    "The package in '{}' is named '{}', but it must be named after its directory.",
+                    package_dir.display(), manifest.name,
+                );
+                return Err(Error::value(msg))
+            }
+            let mut path = package_dir;
+            if name.len() == 1 {
+                path.push(MAIN_MODULE);
+            } else {
+                path.extend(&name[1..]);
+            }
+            path.set_extension("pris");
+            if path.is_file() {
+                return Ok(Location::File(path))
+            }
+            let msg = format!(
+                "The package '{}' in '{}' has no module '{}'.",
+                name[0], dir.display(), name.join("."),
+            );
+            return Err(Error::Other(msg))
+        }
+
+        if name[0] == STD_PACKAGE {
+            let module = if name.len() == 2 { name[1] } else { "" };
+            if let Some(&(_, bytes)) = STD_MODULES.iter().find(|m| m.0 == module) {
+                return Ok(Location::Std(bytes))
+            }
+            let modules: Vec<String> = STD_MODULES.iter().map(|m| format!("std.{}", m.0)).collect();
+            let msg = format!(
+                "The standard library has no module '{}'. Its modules are {}.",
+                name.join("."), modules.join(", "),
+            );
+            return Err(Error::Other(msg))
+        }
+
+        let msg = format!(
+            "Cannot find the module '{}'. It is not in '{}', and there is no package \
+             '{}' in the search path. Note: Add directories with packages to the search \
+             path with --path or PRIS_PATH.",
+            name.join("."), local.display(), name[0],
+        );
+        Err(Error::Other(msg))
+    }
+}
+
+fn read_manifest(path: &Path) -> Result<Manifest> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| Error::io(path.display().to_string(), e.to_string()))?;
+    Manifest::parse(&source).map_err(|msg| {
+        Error::Other(format!("The package manifest '{}' is invalid. {}", path.display(), msg))
+    })
+}
+
+struct Collector {
+    imports: Vec<String>,
+}
+
+impl Collector {
+    fn visit_statements<'a>(&mut self, statements: &[Stmt<'a>]) {
+        for statement in statements {
+            match *statement {
+                Stmt::Import(ref import) => self.imports.push(import.0.to_string()),
+                Stmt::Assign(ref assign) => self.visit_term(&assign.1),
+                Stmt::Update(ref update) => self.visit_term(&update.2),
+                Stmt::Return(ref ret) => self.visit_term(&ret.0),
+                Stmt::Block(ref block) => self.visit_block(block),
+                Stmt::Put(ref put) => self.visit_term(&put.0),
+                Stmt::Repeat(ref repeat) => {
+                    self.visit_term(&repeat.0);
+                    self.visit_block(&repeat.1);
+                }
+                Stmt::With(ref with) => {
+                    self.visit_block(&with.0);
+                    self.visit_block(&with.1);
+                }
+                Stmt::Infix(..) | Stmt::Section(..) | Stmt::Version(..) | Stmt::Comment(..) => {}
+            }
+        }
+    }
+
+    fn visit_block<'a>(&mut self, block: &Block<'a>) {
+        self.visit_statements(&block.0);
+    }
+
+    fn visit_term<'a>(&mut self, term: &Term<'a>) {
+        // Only blocks contain statements, but blocks can be nested in any term.
+        match *term {
+            Term::FnDef(ref def) => self.visit_block(&def.1),
+            Term::Block(ref block) => self.visit_block(block),
+            Term::FnCall(ref call) => {
+                self.visit_term(&call.0);
+                for arg in &call.1 {
+                    self.visit_term(arg);
+                }
+            }
+            Term::Coord(ref coord) => {
+                self.visit_term(&coord.0);
+                self.visit_term(&coord.1);
+            }
+            Term::BinOp(ref bin) => {
+                self.visit_term(&bin.0);
+                self.visit_term(&bin.2);
+            }
+            Term::UnOp(ref un) => self.visit_term(&un.1),
+            Term::Match(ref m) => {
+                self.visit_term(&m.0);
+                for arm in &m.1 {
+                    self.visit_term(&arm.1);
+                }
+                if let Some(ref default) = m.2 {
+                    self.visit_term(default);
+                }
+            }
+            Term::List(ref list) => for t in &list.0 { self.visit_term(t) },
+            Term::String(..) | Term::Number(..) | Term::Color(..) | Term::Idents(..) => {}
+        }
+    }
+}
+
+/// Return the modules that a document imports, as dotted paths.
+pub fn imports<'a>(doc: &Document<'a>) -> Vec<String> {
+    let mut collector = Collector { imports: Vec::new() };
+    collector.visit_statements(&doc.0);
+    collector.imports
+}
+
+/// The source of a module, before it is parsed.
+pub struct Source {
+    /// The dotted path that the module is imported as.
+    pub name: String,
+    /// The file of the module, none for modules of the standard library.
+    pub path: Option<PathBuf>,
+    pub bytes: Vec<u8>,
+}
+
+/// Parse a module, and describe parse errors with the file they occur in.
+fn parse_module<'a>(source: &'a Source) -> Result<Document<'a>> {
+    lexer::lex(&source.bytes)
+        .and_then(|tokens| parser::parse(&tokens))
+        .map_err(|err| match err {
+            Error::Parse(ref pe) => {
+                let line = 1 + source.bytes[..pe.start].iter().filter(|&&b| b == b'\n').count();
+                let file = match source.path {
+                    Some(ref path) => format!("'{}'", path.display()),
+                    None => "the standard library".to_string(),
+                };
+                let msg = format!(
+                    "Failed to parse module '{}' in {}, at line {}: {}",
+                    source.name, file, line, err.message(),
+                );
+                Error::Other(msg)
+            }
+            other => other,
+        })
+}
+
+/// Load the modules that a document imports, and the modules that they import.
+///
+/// Every module is loaded once, even if it is imported multiple times. Modules
+/// are resolved relative to `base_dir`, the directory of the document, also
+/// when a module in a package imports another one.
+pub fn load_sources<'a>(doc: &Document<'a>, base_dir: &Path, search_path: &SearchPath) -> Result<Vec<Source>> {
+    let mut sources: Vec<Source> = Vec::new();
+    let mut pending = imports(doc);
+    while let Some(name) = pending.pop() {
+        if sources.iter().any(|s| s.name == name) {
+            continue
+        }
+        let location = {
+            let parts: Vec<&str> = name.split('.').collect();
+            search_path.resolve(base_dir, &parts)?
+        };
+        let (path, bytes) = match location {
+            Location::File(path) => {
+                let bytes = fs::read(&path).map_err(|e| Error::io(path.display().to_string(), e.to_string()))?;
+                (Some(path), bytes)
+            }
+            Location::Std(bytes) => (None, bytes.to_vec()),
+        };
+        let source = Source {
+            name: name,
+            path: path,
+            bytes: bytes,
+        };
+        pending.extend(imports(&parse_module(&source)?));
+        sources.push(source);
+    }
+    Ok(sources)
+}
+
+/// Parsed modules, by the name they are imported as.
+pub struct Modules<'a> {
+    modules: Vec<(&'a str, Document<'a>)>,
+    /// The modules that are being imported, to detect import cycles.
+    active: RefCell<Vec<String>>,
+}
+
+impl<'a> Modules<'a> {
+    pub fn new() -> Modules<'a> {
+        Modules {
+            modules: Vec::new(),
+            active: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Parse the sources that `load_sources` returned.
+    pub fn parse(sources: &'a [Source]) -> Result<Modules<'a>> {
+        let mut modules = Modules::new();
+        for source in sources {
+            modules.modules.push((&source.name[..], parse_module(source)?));
+        }
+        Ok(modules)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Document<'a>> {
+        self.modules.iter().find(|m| m.0 == name).map(|m| &m.1)
+    }
+
+    /// Return the variables that modules assign at their top level.
+    pub fn assigned_names(&self) -> Vec<Symbol> {
+        let mut names = Vec::new();
+        for &(_, ref doc) in &self.modules {
+            for statement in &doc.0 {
+                if let Stmt::Assign(ref assign) = *statement {
+                    names.push(assign.0);
+                }
+            }
+        }
+        names
+    }
+
+    /// Mark the module as being imported, fail if it is being imported already.
+    pub fn enter(&self, name: &str) -> Result<()> {
+        let mut active = self.active.borrow_mut();
+        if let Some(i) = active.iter().position(|m| m == name) {
+            let msg = format!(
+                "The module '{}' imports itself, through the cycle '{} -> {}'.",
+                name, active[i..].join(" -> "), name,
+            );
+            return Err(Error::Other(msg))
+        }
+        active.push(name.to_string());
+        Ok(())
+    }
+
+    pub fn leave(&self) {
+        self.active.borrow_mut().pop();
+    }
+}
+
+#[test]
+fn manifest_parse_reads_keys() {
+    let source = "# A theme.\nname = \"serif\"\nversion = \"1.0.0\"\n\ndescription = \"Classic\"\n";
+    assert_eq!(Manifest::parse(source), Ok(Manifest {
+        name: "serif".to_string(),
+        version: "1.0.0".to_string(),
+        description: Some("Classic".to_string()),
+    }));
+    assert!(Manifest::parse("name = \"serif\"").is_err());
+    assert!(Manifest::parse("name = serif\nversion = \"1\"").is_err());
+}
+
+#[test]
+fn imports_finds_imports_in_blocks() {
+    let source = b"import base\nf = function() { import theme.colors\n return 1 }\n{ import base }";
+    let tokens = lexer::lex(source).unwrap();
+    let doc = parser::parse(&tokens).unwrap();
+    assert_eq!(imports(&doc), vec!["base", "theme.colors", "base"]);
+}
+
+#[test]
+fn std_modules_parse() {
+    for &(name, bytes) in STD_MODULES {
+        let tokens = lexer::lex(bytes).unwrap_or_else(|_| panic!("Failed to lex std.{}.", name));
+        assert!(parser::parse(&tokens).is_ok(), "Failed to parse std.{}.", name);
+    }
+}
+
+#[test]
+fn resolve_finds_std_modules() {
+    let search_path = SearchPath::new(Vec::new());
+    let base_dir = Path::new("/nonexistent");
+    match search_path.resolve(base_dir, &["std", "colors"]) {
+        Ok(location) => assert_eq!(location, Location::Std(STD_MODULES[1].1)),
+        Err(..) => panic!("Expected std.colors to resolve."),
+    }
+    assert!(search_path.resolve(base_dir, &["std", "missing"]).is_err());
+    assert!(search_path.resolve(base_dir, &["std"]).is_err());
+}
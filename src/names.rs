@@ -5,38 +5,134 @@
 //!
 //! Using constants rather than inlining string literals protects against
 //! typos. Furthermore, it gives an overview of all magic strings in one place.
+//!
+//! The names are symbols, which the interner reserves up front in the order
+//! of this list, so the interpreter can look up builtins without interning.
 
 // Shouting at the reader is impolite. Furthermore, these names are easier to
 // grep for if they map 1:1.
 #![allow(non_upper_case_globals)]
 
-pub const at: &'static str = "at";
-pub const canvas_size: &'static str = "canvas_size";
-pub const color: &'static str = "color";
-pub const fill_circle: &'static str = "fill_circle";
-pub const fill_curve: &'static str = "fill_curve";
-pub const fill_polygon: &'static str = "fill_polygon";
-pub const fill_rectangle: &'static str = "fill_rectangle";
-pub const fit: &'static str = "fit";
-pub const font_family: &'static str = "font_family";
-pub const font_features: &'static str = "font_features";
-pub const font_size: &'static str = "font_size";
-pub const font_style: &'static str = "font_style";
-pub const glyph: &'static str = "glyph";
-pub const height: &'static str = "height";
-pub const hyperlink: &'static str = "hyperlink";
-pub const image: &'static str = "image";
-pub const line: &'static str = "line";
-pub const line_height: &'static str = "line_height";
-pub const line_width: &'static str = "line_width";
-pub const offset: &'static str = "offset";
-pub const size: &'static str = "size";
-pub const str: &'static str = "str";
-pub const sqrt: &'static str = "sqrt";
-pub const stroke_circle: &'static str = "stroke_circle";
-pub const stroke_curve: &'static str = "stroke_curve";
-pub const stroke_polygon: &'static str = "stroke_polygon";
-pub const stroke_rectangle: &'static str = "stroke_rectangle";
-pub const t: &'static str = "t";
-pub const text_align: &'static str = "text_align";
-pub const width: &'static str = "width";
+use symbol::Symbol;
+
+macro_rules! names {
+    ($($(#[$attr:meta])* $name:ident,)*) => {
+        /// The position of every name in the list, which is its symbol.
+        #[allow(non_camel_case_types, dead_code)]
+        enum Index { $($(#[$attr])* $name,)* }
+
+        $($(#[$attr])* pub const $name: Symbol = Symbol(Index::$name as u32);)*
+
+        /// Return all names, in the order of their symbols.
+        pub fn all() -> Vec<&'static str> {
+            let mut all = Vec::new();
+            $($(#[$attr])* all.push(stringify!($name));)*
+            all
+        }
+    }
+}
+
+names! {
+    above,
+    adjoin_all,
+    adjoin_parallel,
+    adjoin_sequential,
+    as_len,
+    as_num,
+    #[cfg(feature = "native")]
+    ascent,
+    at,
+    auto_toc,
+    background,
+    background_color,
+    background_image,
+    baseline_grid,
+    baseline_grid_overlay,
+    below,
+    bleed,
+    boxed,
+    canvas_size,
+    char_glyph,
+    clip_or_warn,
+    color,
+    container_size,
+    crop_marks,
+    current_section,
+    decimals,
+    definition_list,
+    #[cfg(feature = "native")]
+    descent,
+    external_svg,
+    fill_circle,
+    fill_curve,
+    fill_polygon,
+    fill_rectangle,
+    fit,
+    fits,
+    font_family,
+    font_features,
+    font_size,
+    font_style,
+    gap,
+    getenv,
+    glyph,
+    glyph_by_name,
+    height,
+    highlight,
+    hyperlink,
+    image,
+    left_of,
+    line,
+    line_height,
+    line_width,
+    linear_gradient,
+    link,
+    measure,
+    minutes,
+    none,
+    notes,
+    offset,
+    on_step,
+    opacity,
+    pad,
+    pad_bottom,
+    pad_left,
+    pad_right,
+    pad_top,
+    place_all,
+    place_glyphs,
+    polar,
+    radial_gradient,
+    repeat_index,
+    right_of,
+    rotate,
+    rotate_coord,
+    scale,
+    scene,
+    section_count,
+    section_index,
+    size,
+    slide_title,
+    smooth_curve,
+    snap_to_grid,
+    str,
+    str_len,
+    sqrt,
+    stroke_circle,
+    stroke_curve,
+    stroke_polygon,
+    stroke_rectangle,
+    symbol,
+    t,
+    tapered_line,
+    text_align,
+    text_language,
+    text_script,
+    tile,
+    todo,
+    transform,
+    translate,
+    width,
+    x,
+    y,
+}
@@ -0,0 +1,141 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module names the files for output with one file per page.
+//!
+//! A pattern such as `slides/{deck}-{:03}.png` contains placeholders:
+//!
+//!  * `{deck}` is replaced with the name of the input file, without extension.
+//!  * `{}` is replaced with the page number, starting at 1.
+//!  * `{:0N}` is replaced with the page number, padded with zeros to N digits.
+//!
+//! Literal braces can be written as `{{` and `}}`.
+
+use std::path::Path;
+
+use error::{Error, Result};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Svg,
+}
+
+enum Part<'a> {
+    Literal(&'a str),
+    Deck,
+    Page { width: usize },
+}
+
+/// A validated output file name pattern.
+pub struct OutputPattern<'a> {
+    parts: Vec<Part<'a>>,
+    format: ImageFormat,
+}
+
+fn pattern_error(pattern: &str, message: &str) -> Error {
+    Error::value(format!("Invalid output pattern '{}': {}", pattern, message))
+}
+
+impl<'a> OutputPattern<'a> {
+    pub fn parse(pattern: &'a str) -> Result<OutputPattern<'a>> {
+        let format = match Path::new(pattern).extension().and_then(|e| e.to_str()) {
+            Some("png") => ImageFormat::Png,
+            Some("svg") => ImageFormat::Svg,
+            _ => return Err(pattern_error(pattern, "the extension must be '.png' or '.svg'.")),
+        };
+
+        let mut parts = Vec::new();
+        let mut has_page = false;
+        let mut rest = pattern;
+        while rest.len() > 0 {
+            if rest.starts_with("{{") {
+                parts.push(Part::Literal("{"));
+                rest = &rest[2..];
+            } else if rest.starts_with("}}") {
+                parts.push(Part::Literal("}"));
+                rest = &rest[2..];
+            } else if rest.starts_with("{") {
+                let end = match rest.find('}') {
+                    Some(i) => i,
+                    None => return Err(pattern_error(pattern, "a '{' is not closed.")),
+                };
+                let part = match &rest[1..end] {
+                    "deck" => Part::Deck,
+                    "" => Part::Page { width: 0 },
+                    spec if spec.starts_with(":0") => match spec[2..].parse() {
+                        Ok(width) => Part::Page { width: width },
+                        Err(..) => return Err(pattern_error(pattern, "expected '{:0N}' with a number N.")),
+                    },
+                    _ => return Err(pattern_error(pattern, "expected '{deck}', '{}', or '{:0N}'.")),
+                };
+                if let Part::Page { .. } = part { has_page = true; }
+                parts.push(part);
+                rest = &rest[end + 1..];
+            } else {
+                let end = rest.find(|c: char| c == '{' || c == '}').unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(pattern_error(pattern, "a '}' must be written as '}}'."));
+                }
+                parts.push(Part::Literal(&rest[..end]));
+                rest = &rest[end..];
+            }
+        }
+
+        // Without a page number, every page would overwrite the previous one.
+        if !has_page {
+            return Err(pattern_error(pattern, "it must contain '{}' or '{:0N}' for the page number."));
+        }
+
+        let output_pattern = OutputPattern {
+            parts: parts,
+            format: format,
+        };
+        Ok(output_pattern)
+    }
+
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    /// Return the file name for the given page, numbered from 1.
+    pub fn file_name(&self, deck: &str, page: usize) -> String {
+        let mut name = String::new();
+        for part in &self.parts {
+            match *part {
+                Part::Literal(s) => name.push_str(s),
+                Part::Deck => name.push_str(deck),
+                Part::Page { width } => name.push_str(&format!("{:01$}", page, width)),
+            }
+        }
+        name
+    }
+}
+
+#[test]
+fn output_pattern_substitutes_deck_and_padded_page() {
+    let pattern = OutputPattern::parse("slides/{deck}-{:03}.png").unwrap();
+    assert_eq!(pattern.format(), ImageFormat::Png);
+    assert_eq!(pattern.file_name("talk", 7), "slides/talk-007.png");
+    assert_eq!(pattern.file_name("talk", 1234), "slides/talk-1234.png");
+}
+
+#[test]
+fn output_pattern_supports_escaped_braces() {
+    let pattern = OutputPattern::parse("{{{}}}.svg").unwrap();
+    assert_eq!(pattern.format(), ImageFormat::Svg);
+    assert_eq!(pattern.file_name("talk", 2), "{2}.svg");
+}
+
+#[test]
+fn output_pattern_rejects_invalid_patterns() {
+    assert!(OutputPattern::parse("{deck}.png").is_err());
+    assert!(OutputPattern::parse("{}.pdf").is_err());
+    assert!(OutputPattern::parse("{page}.png").is_err());
+    assert!(OutputPattern::parse("{.png").is_err());
+    assert!(OutputPattern::parse("}{}.png").is_err());
+}
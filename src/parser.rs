@@ -20,17 +20,72 @@
 
 use std::result;
 
-use ast::{Assign, BinOp, BinTerm, Block, Coord, Document, FnCall, FnDef};
-use ast::{Idents, Import, Num, List, Put, Return, Stmt, Term, UnOp, UnTerm, Unit};
+use ast::{Assign, BinOp, BinTerm, Block, Comment, Coord, Doc, Document, FnCall, FnDef};
+use ast::{Idents, Import, InfixDecl, Num, List, Put, Return, Section, Stmt, Term, UnOp, UnTerm, Unit};
+use ast::{Match, MatchArm, Repeat, Update, Version, With};
 use error::{Error, Result};
 use lexer::{Span, Token};
+use symbol::Symbol;
 
 /// Parse a token stream into a document.
+///
+/// Doc comments are attached to the assignment that directly follows them.
+/// Doc comments in other places are ignored.
 pub fn parse<'a>(tokens: &[(Token<'a>, Span)]) -> Result<Document<'a>> {
+    parse_split(tokens, false)
+}
+
+/// Parse a token stream that includes comments and whitespace into a document.
+///
+/// This is the counterpart of `lexer::lex_with_comments`. Comments become
+/// `Stmt::Comment` statements, so printing the document re-emits them. A
+/// comment inside an expression is moved after the statement that contains it.
+/// Doc comments that do not precede an assignment become regular comments.
+pub fn parse_with_comments<'a>(tokens: &[(Token<'a>, Span)]) -> Result<Document<'a>> {
+    parse_split(tokens, true)
+}
+
+/// Separate comments from the other tokens, and parse the other tokens.
+fn parse_split<'a>(tokens: &[(Token<'a>, Span)], keep_comments: bool) -> Result<Document<'a>> {
+    let mut code = Vec::with_capacity(tokens.len());
+    let mut comments = Vec::new();
+
+    // A comment is trailing if it is on the same line as the preceding token.
+    let mut on_new_line = true;
+
+    for &(token, span) in tokens {
+        match token {
+            Token::Space(space) => {
+                on_new_line = on_new_line || space.contains('\n');
+            }
+            Token::Comment(text) | Token::DocComment(text) => {
+                let comment = PendingComment {
+                    before: code.len(),
+                    comment: Comment { text: text, trailing: !on_new_line },
+                    is_doc: token == Token::DocComment(text),
+                };
+                comments.push(comment);
+            }
+            _ => {
+                code.push((token, span));
+                on_new_line = false;
+            }
+        }
+    }
+
+    let mut parser = Parser::new(&code);
+    comments.reverse();
+    parser.comments = comments;
+    parser.keep_comments = keep_comments;
+    parse_tokens(parser)
+}
+
+fn parse_tokens<'t, 'a>(mut parser: Parser<'t, 'a>) -> Result<Document<'a>> {
+    let tokens = parser.tokens;
     // TODO: Have a pre-pass that checks for balanced parens and brackets.
     // That will produce more helpful error messages than "unexpected token"
     // at the mismatched closing bracket.
-    match Parser::new(tokens).parse_document() {
+    match parser.parse_document() {
         Ok(doc) => Ok(doc),
         Err(perr) => {
             assert!(perr.token_index <= tokens.len());
@@ -49,9 +104,32 @@ pub fn parse<'a>(tokens: &[(Token<'a>, Span)]) -> Result<Document<'a>> {
     }
 }
 
+const VERSION_NOT_FIRST: &'static str =
+    "Parse error: 'pris_version' must be the first statement of the document.";
+
 struct Parser<'t, 'a: 't> {
     tokens: &'t [(Token<'a>, Span)],
     cursor: usize,
+
+    /// Comments that have not yet been turned into statements, in reverse
+    /// order, so the next one can be popped off.
+    comments: Vec<PendingComment<'a>>,
+
+    /// Whether to turn comments into statements. Doc comments are tracked
+    /// regardless, so they can be attached to assignments.
+    keep_comments: bool,
+
+    /// Precedences declared with `infix` statements that are in scope.
+    infix_precedences: Vec<(Idents, u32)>,
+}
+
+/// A comment that was removed from the token stream before parsing.
+struct PendingComment<'a> {
+    /// Index of the first token after the comment.
+    before: usize,
+    comment: Comment<'a>,
+    /// Whether the comment is a doc comment, starting with "///".
+    is_doc: bool,
 }
 
 /// An intermediate parse error.
@@ -90,37 +168,88 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
         Parser {
             tokens: tokens,
             cursor: 0,
+            comments: Vec::new(),
+            keep_comments: false,
+            infix_precedences: Vec::new(),
         }
     }
 
     /// Run the parser on the full input and return the resulting document.
     fn parse_document(&mut self) -> PResult<Document<'a>> {
         let mut statements = Vec::new();
-        while self.cursor < self.tokens.len() {
+        loop {
+            self.take_comments(&mut statements);
+            if self.cursor >= self.tokens.len() {
+                break
+            }
+            // The version determines how the rest of the document is read, so
+            // it must come before anything else.
+            let is_first = statements.iter().all(|stmt| match *stmt {
+                Stmt::Comment(..) => true,
+                _ => false,
+            });
+            if self.peek() == Some(Token::KwPrisVersion) && !is_first {
+                return self.error(VERSION_NOT_FIRST)
+            }
             statements.push(self.parse_statement()?);
         }
 
         Ok(Document(statements))
     }
 
+    /// Append the comments before the token at the cursor as statements.
+    ///
+    /// A comment that directly follows the previous statement on the same
+    /// line stays a trailing comment. Comments that occurred inside the
+    /// previous statement are placed on a line of their own after it. Doc
+    /// comments directly before an assignment are left for `parse_assign`.
+    fn take_comments(&mut self, statements: &mut Vec<Stmt<'a>>) {
+        while self.comments.last().map_or(false, |c| c.before <= self.cursor) {
+            if self.has_doc_comment() {
+                if let Some(Token::Ident(..)) = self.peek() {
+                    break
+                }
+            }
+            let pending = self.comments.pop().unwrap();
+            if !self.keep_comments {
+                continue
+            }
+            let mut comment = pending.comment;
+            comment.trailing = comment.trailing
+                && pending.before == self.cursor
+                && !statements.is_empty();
+            statements.push(Stmt::Comment(comment));
+        }
+    }
+
     fn parse_statement(&mut self) -> PResult<Stmt<'a>> {
         debug_assert!(self.cursor < self.tokens.len());
 
         match self.tokens[self.cursor].0 {
             Token::KwImport => self.parse_import().map(Stmt::Import),
-            Token::Ident(..) => self.parse_assign().map(Stmt::Assign),
+            Token::KwInfix => self.parse_infix_decl().map(Stmt::Infix),
+            Token::Ident(..) => match self.peek_next() {
+                Some(Token::PlusEquals) | Some(Token::MinusEquals) | Some(Token::StarEquals) => {
+                    self.parse_update().map(Stmt::Update)
+                }
+                _ => self.parse_assign().map(Stmt::Assign),
+            },
             Token::KwReturn => self.parse_return().map(Stmt::Return),
             Token::LBrace => self.parse_block().map(Stmt::Block),
             Token::KwPut => self.parse_put().map(Stmt::Put),
+            Token::KwRepeat => self.parse_repeat().map(Stmt::Repeat),
+            Token::KwWith => self.parse_with().map(Stmt::With),
+            Token::KwSection => self.parse_section().map(Stmt::Section),
+            Token::KwPrisVersion => self.parse_version().map(Stmt::Version),
             _ => {
-                let msg = "Parse error in statement: expected import, return, \
-                           assignment, block, or put.";
+                let msg = "Parse error in statement: expected import, infix, \
+                           return, assignment, block, put, repeat, with, or section.";
                 self.error(msg)
             }
         }
     }
 
-    fn parse_idents(&mut self) -> PResult<Idents<'a>> {
+    fn parse_idents(&mut self) -> PResult<Idents> {
         let mut idents = Vec::new();
 
         // Take one identifier. If it is followed by a dot, repeat. There is at
@@ -137,32 +266,127 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
         Ok(Idents(idents))
     }
 
-    fn parse_ident(&mut self) -> PResult<&'a str> {
+    fn parse_ident(&mut self) -> PResult<Symbol> {
         if let Some(Token::Ident(ident)) = self.peek() {
             self.consume();
-            Ok(ident)
+            Ok(Symbol::intern(ident))
         } else {
             self.error("Parse error: expected identifier.")
         }
     }
 
-    fn parse_import(&mut self) -> PResult<Import<'a>> {
+    fn parse_import(&mut self) -> PResult<Import> {
         assert!(self.take() == Some(Token::KwImport));
 
         let msg = "Parse error in import: expected path like 'std.colors'.";
         self.parse_idents().map(Import).replace_error(msg)
     }
 
+    fn parse_infix_decl(&mut self) -> PResult<InfixDecl> {
+        assert!(self.take() == Some(Token::KwInfix));
+
+        let precedence = match self.peek() {
+            Some(Token::Number(digit)) if digit.len() == 1 => {
+                (digit.as_bytes()[0] - b'0') as u32
+            }
+            _ => {
+                let msg = "Parse error in infix declaration: \
+                           expected precedence from 0 to 9.";
+                return self.error(msg)
+            }
+        };
+        self.consume();
+
+        let msg = "Parse error in infix declaration: expected function name.";
+        let op = self.parse_idents().replace_error(msg)?;
+
+        // The declaration takes effect immediately, for the remainder of the
+        // enclosing block.
+        self.infix_precedences.push((Idents(op.0.clone()), precedence));
+
+        Ok(InfixDecl(precedence, op))
+    }
+
+    fn parse_section(&mut self) -> PResult<Section> {
+        use parser_utils::unescape_string_literal;
+        use parser_utils::unescape_raw_string_literal;
+
+        assert!(self.take() == Some(Token::KwSection));
+
+        let name = match self.peek() {
+            // TODO: Return the right kind of parse error there, or make the
+            // type an enum.
+            Some(Token::String(s)) => unescape_string_literal(s).unwrap(),
+            Some(Token::RawString(s)) => unescape_raw_string_literal(s),
+            _ => {
+                let msg = "Parse error in section: expected section name as string.";
+                return self.error(msg)
+            }
+        };
+        self.consume();
+
+        Ok(Section(name))
+    }
+
+    fn parse_version(&mut self) -> PResult<Version> {
+        assert!(self.take() == Some(Token::KwPrisVersion));
+
+        let version = match self.peek() {
+            Some(Token::String(s)) => Version::parse(&s[1..s.len() - 1]),
+            _ => None,
+        };
+        match version {
+            Some(v) => {
+                self.consume();
+                Ok(v)
+            }
+            None => self.error("Parse error in version: expected version like \"0.2\"."),
+        }
+    }
+
+    /// Return the declared precedence of an infix function, or 0 if none.
+    fn infix_precedence(&self, op: &Idents) -> u32 {
+        self.infix_precedences
+            .iter()
+            .rev()
+            .find(|&&(ref name, _)| name == op)
+            .map_or(0, |&(_, precedence)| precedence)
+    }
+
     fn parse_assign(&mut self) -> PResult<Assign<'a>> {
         // TODO: Add hints to these messages. It is possible to explain here
         // that nested assignments are not allowed.
         let msg = "Parse error: expected '='.";
 
+        let mut doc = Vec::new();
+        while self.has_doc_comment() {
+            doc.push(self.comments.pop().unwrap().comment.text);
+        }
+
         let ident = self.parse_ident()?;
         self.expect_consume(Token::Equals, msg)?;
         let expr = self.parse_expr()?;
 
-        Ok(Assign(ident, expr))
+        Ok(Assign(ident, expr, Doc(doc)))
+    }
+
+    fn parse_update(&mut self) -> PResult<Update<'a>> {
+        let ident = self.parse_ident()?;
+        let op = match self.take() {
+            Some(Token::PlusEquals) => BinOp::Add,
+            Some(Token::MinusEquals) => BinOp::Sub,
+            Some(Token::StarEquals) => BinOp::Mul,
+            _ => unreachable!("Only called with an update operator after the identifier."),
+        };
+        let expr = self.parse_expr()?;
+
+        Ok(Update(ident, op, expr))
+    }
+
+    /// Return whether the next pending comment is a doc comment right before
+    /// the token at the cursor.
+    fn has_doc_comment(&self) -> bool {
+        self.comments.last().map_or(false, |c| c.is_doc && c.before == self.cursor)
     }
 
     fn parse_return(&mut self) -> PResult<Return<'a>> {
@@ -182,7 +406,12 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
 
         let mut statements = Vec::new();
 
+        // Infix declarations inside the block are not visible outside of it.
+        let num_infix_precedences = self.infix_precedences.len();
+
         loop {
+            self.take_comments(&mut statements);
+
             // A closing brace marks the end of the block.
             match self.peek() {
                 Some(Token::RBrace) => {
@@ -194,6 +423,7 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
                     let msg = "Parse error in block: expected closing '}'.";
                     return self.error(msg)
                 }
+                Some(Token::KwPrisVersion) => return self.error(VERSION_NOT_FIRST),
                 Some(..) => {}
             }
 
@@ -203,9 +433,58 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
             // Unlike idents, there are no separators for statements.
         }
 
+        self.infix_precedences.truncate(num_infix_precedences);
+
         Ok(Block(statements))
     }
 
+    fn parse_repeat(&mut self) -> PResult<Repeat<'a>> {
+        debug_assert!(self.peek() == Some(Token::KwRepeat));
+
+        // Step over the 'repeat' keyword.
+        self.consume();
+
+        let count = self.parse_expr()?;
+        self.expect_peek(Token::LBrace, "Parse error in repeat: expected '{' after the count.")?;
+        let body = self.parse_block()?;
+
+        Ok(Repeat(count, body))
+    }
+
+    fn parse_with(&mut self) -> PResult<With<'a>> {
+        debug_assert!(self.peek() == Some(Token::KwWith));
+
+        // Step over the 'with' keyword.
+        self.consume();
+
+        // The overrides are a block of assignments. Comments are kept with
+        // them, like in a regular block.
+        self.expect_consume(Token::LBrace, "Parse error in with: expected '{' after 'with'.")?;
+        let mut overrides = Vec::new();
+        loop {
+            self.take_comments(&mut overrides);
+            match (self.peek(), self.peek_next()) {
+                (Some(Token::RBrace), _) => {
+                    self.consume();
+                    break
+                }
+                (Some(Token::Ident(..)), Some(Token::Equals)) => {
+                    overrides.push(Stmt::Assign(self.parse_assign()?));
+                }
+                _ => {
+                    let msg = "Parse error in with: expected an assignment or '}'. \
+                               Note: the first block of 'with' can only assign variables.";
+                    return self.error(msg)
+                }
+            }
+        }
+
+        self.expect_peek(Token::LBrace, "Parse error in with: expected '{' after the assignments.")?;
+        let body = self.parse_block()?;
+
+        Ok(With(Block(overrides), body))
+    }
+
     fn parse_put(&mut self) -> PResult<Put<'a>> {
         debug_assert!(self.peek() == Some(Token::KwPut));
 
@@ -218,10 +497,35 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
     fn parse_expr(&mut self) -> PResult<Term<'a>> {
         // Note: `parse_expr` is just a synonym for readability. There are
         // multiple levels of expressions to handle precedence.
-        self.parse_expr_infix()
+        self.parse_expr_or()
+    }
+
+    /// Parse an expression with a default, `x or y`.
+    ///
+    /// The default binds less tightly than anything else, and associates to
+    /// the right, so in `a or b or c`, a missing `b` falls through to `c`.
+    fn parse_expr_or(&mut self) -> PResult<Term<'a>> {
+        let term = self.parse_expr_infix()?;
+        match self.peek() {
+            Some(Token::KwOr) => {
+                self.consume();
+                let rhs = self.parse_expr_or()?;
+                Ok(Term::bin_op(BinTerm(term, BinOp::Or, rhs)))
+            }
+            _ => Ok(term),
+        }
     }
 
     fn parse_expr_infix(&mut self) -> PResult<Term<'a>> {
+        self.parse_expr_infix_from(0)
+    }
+
+    /// Parse an expression with infix calls of at least the given precedence.
+    ///
+    /// Infix calls always bind less tightly than arithmetic. Among each other,
+    /// the one with the highest declared precedence binds most tightly, and
+    /// calls of equal precedence associate to the left.
+    fn parse_expr_infix_from(&mut self, min_precedence: u32) -> PResult<Term<'a>> {
         let mut term = self.parse_expr_add()?;
 
         loop {
@@ -230,15 +534,26 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
             // might be an infix call. NOTE: This is the only place in the
             // parser where we need more than one token lookahead.
             match (self.peek(), self.peek_next()) {
-                // If there is a '=' after the identifier, then the identifier
-                // is not an infix call, but the target of an assignment.
+                // If there is a '=' or an update operator such as '+=' after
+                // the identifier, then the identifier is not an infix call,
+                // but the target of an assignment.
                 (Some(Token::Ident(..)), Some(Token::Equals)) => break,
+                (Some(Token::Ident(..)), Some(Token::PlusEquals)) => break,
+                (Some(Token::Ident(..)), Some(Token::MinusEquals)) => break,
+                (Some(Token::Ident(..)), Some(Token::StarEquals)) => break,
                 (Some(Token::Ident(..)), _) => {
                     // If there is no '=', then we expect identifiers for the
-                    // function to call.
-                    let infix = self.parse_idents().map(BinOp::Infix)?;
-                    let rhs = self.parse_expr_add()?;
-                    term = Term::bin_op(BinTerm(term, infix, rhs));
+                    // function to call. If it binds less tightly than the
+                    // call we are in, it is up to the caller to parse it.
+                    let start = self.cursor;
+                    let infix = self.parse_idents()?;
+                    let precedence = self.infix_precedence(&infix);
+                    if precedence < min_precedence {
+                        self.cursor = start;
+                        break
+                    }
+                    let rhs = self.parse_expr_infix_from(precedence + 1)?;
+                    term = Term::bin_op(BinTerm(term, BinOp::Infix(infix), rhs));
                 }
                 _ => break,
             }
@@ -337,12 +652,18 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
         loop {
             args.push(self.parse_expr()?);
 
-            match self.peek() {
-                Some(Token::Comma) => {
+            // A trailing comma before the closing paren is allowed.
+            match (self.peek(), self.peek_next()) {
+                (Some(Token::Comma), Some(Token::RParen)) => {
+                    self.consume();
+                    self.consume();
+                    break
+                }
+                (Some(Token::Comma), _) => {
                     self.consume();
                     continue
                 }
-                Some(Token::RParen) => {
+                (Some(Token::RParen), _) => {
                     self.consume();
                     break
                 }
@@ -403,6 +724,7 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
             Token::KwFunction => self.parse_fn_def().map(Term::FnDef),
             Token::LBrace => self.parse_block().map(Term::Block),
             Token::LBracket => self.parse_list().map(Term::List),
+            Token::KwMatch => self.parse_match().map(|m| Term::Match(Box::new(m))),
             // Only in the case of an opening paren, it is ambiguous what to
             // parse: it could become a coord or an expression between parens.
             Token::LParen => self.parse_coord_or_parens(),
@@ -410,6 +732,57 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
         }
     }
 
+    fn parse_match(&mut self) -> PResult<Match<'a>> {
+        use parser_utils::unescape_string_literal;
+        use parser_utils::unescape_raw_string_literal;
+
+        debug_assert!(self.peek() == Some(Token::KwMatch));
+
+        // Step over the 'match' keyword.
+        self.consume();
+
+        let value = self.parse_expr()?;
+        self.expect_consume(Token::LBrace, "Parse error in match: expected '{' after the value.")?;
+
+        let mut arms = Vec::new();
+        let mut default = None;
+
+        loop {
+            let pattern = match self.take() {
+                Some(Token::RBrace) => break,
+                Some(Token::String(s)) => Some(unescape_string_literal(s).unwrap()),
+                Some(Token::RawString(s)) => Some(unescape_raw_string_literal(s)),
+                Some(Token::KwElse) => None,
+                _ => {
+                    self.cursor -= 1;
+                    return self.error("Parse error in match: expected string, 'else', or '}'.")
+                }
+            };
+            self.expect_consume(Token::Arrow, "Parse error in match: expected '->'.")?;
+            let result = self.parse_expr()?;
+
+            // Arms are separated by commas, and a trailing comma is allowed.
+            match self.peek() {
+                Some(Token::Comma) => self.consume(),
+                Some(Token::RBrace) => {}
+                _ => return self.error("Parse error in match: expected ',' or '}'."),
+            }
+
+            match pattern {
+                Some(p) => arms.push(MatchArm(p, result)),
+                None => {
+                    default = Some(result);
+                    let msg = "Parse error in match: expected '}' after the 'else' arm, \
+                               it must be the last arm.";
+                    self.expect_consume(Token::RBrace, msg)?;
+                    break
+                }
+            }
+        }
+
+        Ok(Match(value, arms, default))
+    }
+
     fn parse_number(&mut self) -> PResult<Num> {
         use std::str::FromStr;
 
@@ -425,6 +798,8 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
         let unit = match self.peek() {
             Some(Token::UnitEm) => Some(Unit::Em),
             Some(Token::UnitPt) => Some(Unit::Pt),
+            Some(Token::UnitPw) => Some(Unit::Pw),
+            Some(Token::UnitPh) => Some(Unit::Ph),
             Some(Token::UnitW) => Some(Unit::W),
             Some(Token::UnitH) => Some(Unit::H),
             _ => None,
@@ -454,7 +829,7 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
     }
 
     /// Parse arguments between parentheses, like "()" or "(a, b, c)".
-    fn parse_fn_def_args(&mut self) -> PResult<Vec<&'a str>> {
+    fn parse_fn_def_args(&mut self) -> PResult<Vec<Symbol>> {
         let msg = "Parse error in function definition: expected '('.";
         self.expect_consume(Token::LParen, msg)?;
 
@@ -470,15 +845,24 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
         // a closing paren instead, we are done.
         loop {
             match self.peek() {
-                Some(Token::Ident(ident)) => args.push(ident),
+                Some(Token::Ident(ident)) => args.push(Symbol::intern(ident)),
                 _ => return self.error("Parse error in function definition: expected argument name or ')'."),
             }
 
             self.consume();
 
-            match self.take() {
-                Some(Token::Comma) => continue,
-                Some(Token::RParen) => break,
+            // A trailing comma before the closing paren is allowed.
+            match (self.peek(), self.peek_next()) {
+                (Some(Token::Comma), Some(Token::RParen)) => {
+                    self.consume();
+                    self.consume();
+                    break
+                }
+                (Some(Token::Comma), _) => self.consume(),
+                (Some(Token::RParen), _) => {
+                    self.consume();
+                    break
+                }
                 _ => return self.error("Parse error in function definition: expected ',' or ')'."),
             }
         }
@@ -534,28 +918,20 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
                     self.consume();
                     return Ok(List(elements));
                 }
-                Some(Token::Semicolon) => {
+                // Both are accepted, because a comma is the separator in many
+                // other languages. The linter reports documents that mix them.
+                Some(Token::Semicolon) | Some(Token::Comma) => {
                     self.consume();
                     continue;
                 }
-                Some(Token::Comma) => {
-                    // Trying to use a comma as list separator is likely going
-                    // to be a common mistake, as it is common from other
-                    // languages. (Perhaps I should use comma after all for that
-                    // reason.) Add a hint about that.
-                    let msg = "Parse error in list: expected ';' or ']'. \
-                               Note: use ';' to separate elements.";
-                    return self.error(msg)
-               }
                 _ => {
-                    let msg = "Parse error in list: expected ';' or ']'.";
+                    let msg = "Parse error in list: expected ';', ',', or ']'.";
                     return self.error(msg)
                 }
             }
         }
     }
 
-
     /// Return the token under the cursor, if there is one.
     fn peek(&self) -> Option<Token<'a>> {
         self.tokens.get(self.cursor).map(|t| t.0)
@@ -616,17 +992,25 @@ impl<'t, 'a: 't> Parser<'t, 'a> {
 
 #[cfg(test)]
 mod test {
-    use parser::Parser;
-    use lexer::lex;
-    use ast::{Assign, BinOp, BinTerm, Coord, Color, FnCall};
-    use ast::{Idents, List, Num, Put, Stmt, Term, UnOp, UnTerm, Unit};
+    use parser::{Parser, VERSION_NOT_FIRST, parse, parse_with_comments};
+    use lexer::{lex, lex_with_comments};
+    use pretty::print;
+    use ast::{Assign, BinOp, BinTerm, Block, Comment, Coord, Color, Doc, FnCall};
+    use ast::{Idents, InfixDecl, List, Num, Put, Section, Stmt, Term, UnOp, UnTerm, Unit};
+    use ast::Version;
+    use symbol::Symbol;
+
+    /// Intern a path of names, for comparing against parsed identifiers.
+    fn symbols(names: &[&str]) -> Vec<Symbol> {
+        names.iter().map(|name| Symbol::intern(name)).collect()
+    }
 
     #[test]
     fn parse_parses_import() {
         let tokens = lex(b"import foo.bar").unwrap();
         let mut parser = Parser::new(&tokens);
         let import = parser.parse_import().unwrap();
-        assert_eq!(&(import.0).0[..], ["foo", "bar"]);
+        assert_eq!((import.0).0, symbols(&["foo", "bar"]));
         assert_eq!(parser.cursor, 4);
     }
 
@@ -635,7 +1019,7 @@ mod test {
         let tokens = lex(b"foo 22").unwrap();
         let mut parser = Parser::new(&tokens);
         let idents = parser.parse_idents().unwrap();
-        assert_eq!(&idents.0[..], ["foo"]);
+        assert_eq!(idents.0, symbols(&["foo"]));
         assert_eq!(parser.cursor, 1);
     }
 
@@ -644,7 +1028,7 @@ mod test {
         let tokens = lex(b"foo.bar.baz").unwrap();
         let mut parser = Parser::new(&tokens);
         let idents = parser.parse_idents().unwrap();
-        assert_eq!(&idents.0[..], ["foo", "bar", "baz"]);
+        assert_eq!(idents.0, symbols(&["foo", "bar", "baz"]));
         assert_eq!(parser.cursor, 5);
     }
 
@@ -687,8 +1071,13 @@ mod test {
         let tokens = lex(b"#c0ffee").unwrap();
         let mut parser = Parser::new(&tokens);
         let color = parser.parse_term().unwrap();
-        assert_preq!(color, Term::Color(Color(0xc0, 0xff, 0xee)));
+        assert_preq!(color, Term::Color(Color(0xc0, 0xff, 0xee, 0xff)));
         assert_eq!(parser.cursor, 1);
+
+        let tokens = lex(b"#c0ffee80").unwrap();
+        let mut parser = Parser::new(&tokens);
+        let color = parser.parse_term().unwrap();
+        assert_preq!(color, Term::Color(Color(0xc0, 0xff, 0xee, 0x80)));
     }
 
     #[test]
@@ -697,7 +1086,7 @@ mod test {
         let mut parser = Parser::new(&tokens);
         let term = parser.parse_term().unwrap();
         match term {
-            Term::Idents(idents) => assert_eq!(&idents.0[..], ["foo", "bar", "baz"]),
+            Term::Idents(idents) => assert_eq!(idents.0, symbols(&["foo", "bar", "baz"])),
             _ => panic!("expected idents"),
         }
         assert_eq!(parser.cursor, 5);
@@ -735,8 +1124,7 @@ mod test {
         let tokens = lex(b"()").unwrap();
         let mut parser = Parser::new(&tokens);
         let args = parser.parse_fn_def_args().unwrap();
-        let bogus = ["foobar"]; // Required to help type inference.
-        assert_eq!(&args[..], &bogus[..0]);
+        assert!(args.is_empty());
         assert_eq!(parser.cursor, 2);
     }
 
@@ -745,7 +1133,7 @@ mod test {
         let tokens = lex(b"(x)").unwrap();
         let mut parser = Parser::new(&tokens);
         let args = parser.parse_fn_def_args().unwrap();
-        assert_eq!(&args[..], &["x"]);
+        assert_eq!(args, symbols(&["x"]));
         assert_eq!(parser.cursor, 3);
     }
 
@@ -754,16 +1142,35 @@ mod test {
         let tokens = lex(b"(x, y)").unwrap();
         let mut parser = Parser::new(&tokens);
         let args = parser.parse_fn_def_args().unwrap();
-        assert_eq!(&args[..], &["x", "y"]);
+        assert_eq!(args, symbols(&["x", "y"]));
         assert_eq!(parser.cursor, 5);
     }
 
     #[test]
-    fn parse_fails_fn_def_args_trailing_comma() {
+    fn parse_parses_fn_def_args_trailing_comma() {
         let tokens = lex(b"(a,)").unwrap();
         let mut parser = Parser::new(&tokens);
-        let err = parser.parse_fn_def_args().err().unwrap();
-        assert_eq!(err.token_index, 3);
+        let args = parser.parse_fn_def_args().unwrap();
+        assert_eq!(args, symbols(&["a"]));
+        assert_eq!(parser.cursor, 4);
+    }
+
+    #[test]
+    fn parse_fails_fn_def_args_double_comma() {
+        for &input in &[&b"(a,,)"[..], b"(,)"] {
+            let tokens = lex(input).unwrap();
+            let mut parser = Parser::new(&tokens);
+            assert!(parser.parse_fn_def_args().is_err());
+        }
+    }
+
+    #[test]
+    fn parse_parses_fn_call_args_trailing_comma() {
+        let tokens = lex(b"f(1, 2,)").unwrap();
+        let mut parser = Parser::new(&tokens);
+        let term = parser.parse_expr().unwrap();
+        assert_eq!(print(&term), "f(1, 2)");
+        assert_eq!(parser.cursor, 7);
     }
 
     #[test]
@@ -804,7 +1211,7 @@ mod test {
         let put = parser.parse_put().unwrap();
         let one = Term::Number(Num(1.0, None));
         let two = Term::Number(Num(2.0, None));
-        let at = BinTerm(one, BinOp::Infix(Idents(vec!["at"])), two);
+        let at = BinTerm(one, BinOp::Infix(Idents(symbols(&["at"]))), two);
         assert_preq!(put.0, Term::bin_op(at));
         assert_eq!(parser.cursor, 4);
     }
@@ -822,7 +1229,7 @@ mod test {
         let tokens = lex(b"at 1 place 2").unwrap();
         let mut parser = Parser::new(&tokens);
         let result = parser.parse_expr().unwrap();
-        assert_preq!(result, Term::Idents(Idents(vec!["at"])));
+        assert_preq!(result, Term::Idents(Idents(symbols(&["at"]))));
         assert_eq!(parser.cursor, 1);
     }
 
@@ -838,7 +1245,7 @@ mod test {
               let one = Term::Number(Num(1.0, None));
               let two = Term::Number(Num(2.0, None));
               assert_preq!(binterm.0, one);
-              assert_preq!(binterm.1, BinOp::Infix(Idents(vec!["on"])));
+              assert_preq!(binterm.1, BinOp::Infix(Idents(symbols(&["on"]))));
               assert_preq!(binterm.2, two);
           }
           _ => panic!("Unexpected parse result."),
@@ -876,11 +1283,190 @@ mod test {
         let exp = parser.parse_expr().unwrap();
         let one = Term::Number(Num(1.0, None));
         let two = Term::Number(Num(2.0, None));
-        let bt = BinTerm(one, BinOp::Infix(Idents(vec!["at"])), two);
+        let bt = BinTerm(one, BinOp::Infix(Idents(symbols(&["at"]))), two);
         assert_preq!(exp, Term::bin_op(bt));
         assert_eq!(parser.cursor, 3);
     }
 
+    #[test]
+    fn parse_parses_binop_infix_looser_than_add() {
+        let tokens = lex(b"1 at 2 + 3").unwrap();
+        let mut parser = Parser::new(&tokens);
+        let exp = parser.parse_expr().unwrap();
+        let one = Term::Number(Num(1.0, None));
+        let two = Term::Number(Num(2.0, None));
+        let three = Term::Number(Num(3.0, None));
+        let add = Term::bin_op(BinTerm(two, BinOp::Add, three));
+        let bt = BinTerm(one, BinOp::Infix(Idents(symbols(&["at"]))), add);
+        assert_preq!(exp, Term::bin_op(bt));
+        assert_eq!(parser.cursor, 5);
+    }
+
+    #[test]
+    fn parse_parses_binop_infix_left_associative() {
+        let tokens = lex(b"1 at 2 my.on 3").unwrap();
+        let mut parser = Parser::new(&tokens);
+        let exp = parser.parse_expr().unwrap();
+        let one = Term::Number(Num(1.0, None));
+        let two = Term::Number(Num(2.0, None));
+        let three = Term::Number(Num(3.0, None));
+        let at = Term::bin_op(BinTerm(one, BinOp::Infix(Idents(symbols(&["at"]))), two));
+        let bt = BinTerm(at, BinOp::Infix(Idents(symbols(&["my", "on"]))), three);
+        assert_preq!(exp, Term::bin_op(bt));
+        assert_eq!(parser.cursor, 7);
+    }
+
+    #[test]
+    fn parse_parses_infix_decl() {
+        let tokens = lex(b"infix 5 my.above").unwrap();
+        let mut parser = Parser::new(&tokens);
+        let stmt = parser.parse_statement().unwrap();
+        let decl = InfixDecl(5, Idents(symbols(&["my", "above"])));
+        assert_preq!(stmt, Stmt::Infix(decl));
+        assert_eq!(parser.cursor, 5);
+    }
+
+    #[test]
+    fn parse_fails_infix_decl_with_invalid_precedence() {
+        for &input in &[&b"infix 10 above"[..], b"infix 1.5 above", b"infix above"] {
+            let tokens = lex(input).unwrap();
+            let mut parser = Parser::new(&tokens);
+            let result = parser.parse_statement();
+            assert_eq!(result.err().unwrap().token_index, 1);
+        }
+    }
+
+    #[test]
+    fn parse_parses_section() {
+        let tokens = lex(br#"section "Results \"so far\"" x = 1"#).unwrap();
+        let mut parser = Parser::new(&tokens);
+        let stmt = parser.parse_statement().unwrap();
+        assert_preq!(stmt, Stmt::Section(Section("Results \"so far\"".into())));
+        assert_eq!(parser.cursor, 2);
+    }
+
+    #[test]
+    fn parse_fails_section_without_name() {
+        let tokens = lex(b"section results").unwrap();
+        let mut parser = Parser::new(&tokens);
+        let result = parser.parse_statement();
+        assert_eq!(result.err().unwrap().token_index, 1);
+    }
+
+    #[test]
+    fn parse_parses_match() {
+        let tokens = lex(b"match kind { \"title\" -> 1, \"end\" -> 2 at 3, else -> 4, }").unwrap();
+        let mut parser = Parser::new(&tokens);
+        let term = parser.parse_expr().unwrap();
+        assert_eq!(print(&term), "match kind { \"title\" -> 1, \"end\" -> (2 at 3), else -> 4 }");
+        assert_eq!(parser.cursor, tokens.len());
+
+        let tokens = lex(b"match kind { else -> 1, \"title\" -> 2 }").unwrap();
+        let mut parser = Parser::new(&tokens);
+        assert_eq!(parser.parse_expr().err().unwrap().token_index, 7);
+
+        let tokens = lex(b"match kind { title -> 1 }").unwrap();
+        let mut parser = Parser::new(&tokens);
+        assert_eq!(parser.parse_expr().err().unwrap().token_index, 3);
+    }
+
+    #[test]
+    fn parse_parses_or() {
+        let tokens = lex(b"title or subtitle at 2 or 1 + 2").unwrap();
+        let mut parser = Parser::new(&tokens);
+        let term = parser.parse_expr().unwrap();
+        assert_eq!(print(&term), "(title or ((subtitle at 2) or (1 + 2)))");
+        assert_eq!(parser.cursor, tokens.len());
+    }
+
+    #[test]
+    fn parse_parses_with() {
+        let tokens = lex(b"with { accent = 2 font_size = 2em } { put t(\"x\") }").unwrap();
+        let doc = parse(&tokens).unwrap();
+        assert_eq!(doc.0.len(), 1);
+        assert_eq!(
+            print(&doc.0[0]),
+            "with\n{\n  accent = 2\n  font_size = 2em\n}\n{\n  put t(x)\n}",
+        );
+
+        let tokens = lex(b"with { put x } { }").unwrap();
+        let mut parser = Parser::new(&tokens);
+        assert_eq!(parser.parse_statement().err().unwrap().token_index, 2);
+
+        let tokens = lex(b"with { x = 1 } put x").unwrap();
+        let mut parser = Parser::new(&tokens);
+        assert_eq!(parser.parse_statement().err().unwrap().token_index, 6);
+    }
+
+    #[test]
+    fn parse_parses_repeat() {
+        let tokens = lex(b"repeat columns * 2 { put x }").unwrap();
+        let doc = parse(&tokens).unwrap();
+        assert_eq!(doc.0.len(), 1);
+        assert_eq!(print(&doc.0[0]), "repeat (columns * 2)\n{\n  put x\n}");
+
+        let tokens = lex(b"repeat 3 put t(\"x\")").unwrap();
+        let mut parser = Parser::new(&tokens);
+        assert_eq!(parser.parse_statement().err().unwrap().token_index, 2);
+    }
+
+    #[test]
+    fn parse_parses_update_after_infix_call() {
+        let tokens = lex(b"x = 1 at 2 y -= x * 2").unwrap();
+        let doc = parse(&tokens).unwrap();
+        assert_eq!(doc.0.len(), 2);
+        assert_eq!(print(&doc.0[1]), "y -= (x * 2)");
+    }
+
+    #[test]
+    fn parse_parses_version_only_first() {
+        let tokens = lex(b"// Talk.\npris_version \"0.2\" x = 1").unwrap();
+        let doc = Parser::new(&tokens).parse_document().unwrap();
+        assert_eq!(doc.version(), Some(Version(0, 2)));
+
+        for &input in &[&b"x = 1 pris_version \"0.2\""[..], b"x = { pris_version \"0.2\" }"] {
+            let tokens = lex(input).unwrap();
+            let result = Parser::new(&tokens).parse_document();
+            assert_eq!(result.err().unwrap().message, VERSION_NOT_FIRST);
+        }
+
+        for &input in &[&b"pris_version 2"[..], b"pris_version \"2\"", b"pris_version \"0.2.1\""] {
+            let tokens = lex(input).unwrap();
+            let result = Parser::new(&tokens).parse_document();
+            assert_eq!(result.err().unwrap().token_index, 1);
+        }
+    }
+
+    #[test]
+    fn parse_parses_binop_infix_with_declared_precedence() {
+        let tokens = lex(b"infix 5 above x = 1 at 2 above 3 at 4").unwrap();
+        let mut parser = Parser::new(&tokens);
+        let doc = parser.parse_document().unwrap();
+        let one = Term::Number(Num(1.0, None));
+        let two = Term::Number(Num(2.0, None));
+        let three = Term::Number(Num(3.0, None));
+        let four = Term::Number(Num(4.0, None));
+        let at = || BinOp::Infix(Idents(symbols(&["at"])));
+        let above = Term::bin_op(BinTerm(two, BinOp::Infix(Idents(symbols(&["above"]))), three));
+        let lhs = Term::bin_op(BinTerm(one, at(), above));
+        let bt = BinTerm(lhs, at(), four);
+        assert_eq!(doc.0.len(), 2);
+        assert_preq!(doc.0[1], Stmt::Assign(Assign(Symbol::intern("x"), Term::bin_op(bt), Doc::none())));
+    }
+
+    #[test]
+    fn parse_scopes_infix_decl_to_block() {
+        let tokens = lex(b"{ infix 5 above } x = 1 at 2 above 3").unwrap();
+        let mut parser = Parser::new(&tokens);
+        let doc = parser.parse_document().unwrap();
+        let one = Term::Number(Num(1.0, None));
+        let two = Term::Number(Num(2.0, None));
+        let three = Term::Number(Num(3.0, None));
+        let at = Term::bin_op(BinTerm(one, BinOp::Infix(Idents(symbols(&["at"]))), two));
+        let bt = BinTerm(at, BinOp::Infix(Idents(symbols(&["above"]))), three);
+        assert_preq!(doc.0[1], Stmt::Assign(Assign(Symbol::intern("x"), Term::bin_op(bt), Doc::none())));
+    }
+
     #[test]
     fn parse_parses_fn_call() {
         let tokens = lex(b"1(2, 6)").unwrap();
@@ -1059,7 +1645,7 @@ mod test {
         let block = parser.parse_block().unwrap();
         let one = Term::Number(Num(1.0, None));
         assert_eq!(block.0.len(), 1);
-        assert_preq!(block.0[0], Stmt::Assign(Assign("x", one)));
+        assert_preq!(block.0[0], Stmt::Assign(Assign(Symbol::intern("x"), one, Doc::none())));
         assert_eq!(parser.cursor, 5);
     }
 
@@ -1071,8 +1657,8 @@ mod test {
         let one = Term::Number(Num(1.0, None));
         let two = Term::Number(Num(2.0, None));
         assert_eq!(block.0.len(), 2);
-        assert_preq!(block.0[0], Stmt::Assign(Assign("x", one)));
-        assert_preq!(block.0[1], Stmt::Assign(Assign("y", two)));
+        assert_preq!(block.0[0], Stmt::Assign(Assign(Symbol::intern("x"), one, Doc::none())));
+        assert_preq!(block.0[1], Stmt::Assign(Assign(Symbol::intern("y"), two, Doc::none())));
         assert_eq!(parser.cursor, 8);
     }
 
@@ -1102,8 +1688,8 @@ mod test {
         let one = Term::Number(Num(1.0, None));
         let two = Term::Number(Num(2.0, None));
         assert_eq!(doc.0.len(), 2);
-        assert_preq!(doc.0[0], Stmt::Assign(Assign("x", one)));
-        assert_preq!(doc.0[1], Stmt::Assign(Assign("y", two)));
+        assert_preq!(doc.0[0], Stmt::Assign(Assign(Symbol::intern("x"), one, Doc::none())));
+        assert_preq!(doc.0[1], Stmt::Assign(Assign(Symbol::intern("y"), two, Doc::none())));
         assert_eq!(parser.cursor, 6);
     }
 
@@ -1188,6 +1774,20 @@ mod test {
         assert_eq!(parser.cursor, 6);
     }
 
+    #[test]
+    fn parse_parses_list_with_commas() {
+        let one = Term::Number(Num(1.0, None));
+        let two = Term::Number(Num(2.0, None));
+        let list = Term::List(List(vec![one, two]));
+
+        for &input in &[&b"[1, 2]"[..], b"[1, 2,]"] {
+            let tokens = lex(input).unwrap();
+            let mut parser = Parser::new(&tokens);
+            let term = parser.parse_term().unwrap();
+            assert_preq!(term, list);
+        }
+    }
+
     #[test]
     fn parse_fails_on_empty_list_with_semicolon() {
         let tokens = lex(b"[;]").unwrap();
@@ -1195,4 +1795,57 @@ mod test {
         let result = parser.parse_term();
         assert_eq!(result.err().unwrap().token_index, 1);
     }
+
+    #[test]
+    fn parse_with_comments_keeps_leading_and_trailing_comments() {
+        let input = b"// Leading\nx = 1 // Trailing\n{\n  // In block\n}\n";
+        let tokens = lex_with_comments(input).unwrap();
+        let doc = parse_with_comments(&tokens).unwrap();
+        let leading = Comment { text: "// Leading", trailing: false };
+        let trailing = Comment { text: "// Trailing", trailing: true };
+        let in_block = Comment { text: "// In block", trailing: false };
+        assert_eq!(doc.0.len(), 4);
+        assert_preq!(doc.0[0], Stmt::Comment(leading));
+        assert_preq!(doc.0[1], Stmt::Assign(Assign(Symbol::intern("x"), Term::Number(Num(1.0, None)), Doc::none())));
+        assert_preq!(doc.0[2], Stmt::Comment(trailing));
+        assert_preq!(doc.0[3], Stmt::Block(Block(vec![Stmt::Comment(in_block)])));
+    }
+
+    #[test]
+    fn parse_with_comments_moves_comments_out_of_expressions() {
+        let input = b"x = [1; // One\n2]\ny = 3";
+        let tokens = lex_with_comments(input).unwrap();
+        let doc = parse_with_comments(&tokens).unwrap();
+        let one = Comment { text: "// One", trailing: false };
+        assert_eq!(doc.0.len(), 3);
+        assert_preq!(doc.0[1], Stmt::Comment(one));
+    }
+
+    #[test]
+    fn parse_with_comments_round_trips_through_print() {
+        let input = "// Leading\nx = 1  // Trailing\n\n{\n  y = 2\n  // Last\n}\n";
+        let tokens = lex_with_comments(input.as_bytes()).unwrap();
+        let doc = parse_with_comments(&tokens).unwrap();
+        assert_eq!(print(&doc), input);
+    }
+
+    #[test]
+    fn parse_attaches_doc_comments_to_assignments() {
+        let input = b"/// Draw a title.\n/// Centered.\ntitle = 1\n/// Stray\nput 2 x = [/// Inner\n3]";
+        let tokens = lex(input).unwrap();
+        let doc = parse(&tokens).unwrap();
+        assert_eq!(doc.0.len(), 3);
+        let title_doc = Doc(vec!["/// Draw a title.", "/// Centered."]);
+        assert_preq!(doc.0[0], Stmt::Assign(Assign(Symbol::intern("title"), Term::Number(Num(1.0, None)), title_doc)));
+        assert_eq!(doc.documented().len(), 1);
+        assert_eq!(doc.documented()[0].2.text(), "Draw a title.\nCentered.");
+    }
+
+    #[test]
+    fn parse_with_comments_round_trips_doc_comments() {
+        let input = "// Leading\n/// Doc\nx = 1\n\n{\n  /// Inner\n  y = 2\n  /// Stray\n}\n";
+        let tokens = lex_with_comments(input.as_bytes()).unwrap();
+        let doc = parse_with_comments(&tokens).unwrap();
+        assert_eq!(print(&doc), input);
+    }
 }
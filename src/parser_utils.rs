@@ -147,7 +147,7 @@ fn char_from_codepoint<'a>(codepoint: u32) -> Result<char, String> {
     }
 }
 
-/// Parse a color string like `#aabbcc` into a `Color`.
+/// Parse a color string like `#aabbcc` or `#aabbccdd` into a `Color`.
 ///
 /// This assumes that the string is of the expected format; it panics if it is
 /// not. It is the task of the lexer and parser to ensure that only strings of
@@ -157,7 +157,11 @@ pub fn parse_color(color: &str) -> ast::Color {
     let r = u8::from_str_radix(&color[1..3], 16).unwrap();
     let g = u8::from_str_radix(&color[3..5], 16).unwrap();
     let b = u8::from_str_radix(&color[5..7], 16).unwrap();
-    ast::Color(r, g, b)
+    let a = match color.len() {
+        9 => u8::from_str_radix(&color[7..9], 16).unwrap(),
+        _ => 0xff,
+    };
+    ast::Color(r, g, b, a)
 }
 
 #[test]
@@ -0,0 +1,753 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module post-processes the PDF files that Cairo writes.
+//!
+//! Cairo has no way to set the trim box and bleed box of a page, which
+//! printers need to know where to cut. We add them with an incremental
+//! update: the page objects are appended to the file again with the boxes
+//! added, followed by a cross-reference section that points at the new
+//! versions. The bytes that Cairo wrote are left untouched. The objects are
+//! read with `pdf_object`, which understands both the cross-reference tables
+//! of older Cairo versions, and the compressed cross-reference and object
+//! streams that Cairo writes since 1.17.6.
+//!
+//! Archival PDF (PDF/A-2b) is produced the same way: an update adds the
+//! metadata and color profile that the standard requires to the catalog.
+//!
+//! Encryption rewrites the entire file, so rather than implementing the
+//! ciphers here, it is delegated to the `qpdf` program, behind the
+//! `encryption` feature.
+
+#[cfg(feature = "encryption")]
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+#[cfg(feature = "encryption")]
+use std::process::{Command, Stdio};
+
+use elements::ContentHasher;
+use error::{Error, Result};
+use pdf_object::{Dict, Object, PdfFile, Update};
+
+/// Page boxes as `[x0, y0, x1, y1]` in PDF user space.
+pub struct PageBoxes {
+    pub trim: [f64; 4],
+    pub bleed: [f64; 4],
+}
+
+fn box_object(b: &[f64; 4]) -> Object {
+    Object::Array(b.iter().map(|&x| Object::Real(x)).collect())
+}
+
+/// Build an incremental update that adds the boxes to every page of `pdf`.
+///
+/// Returns `None` if the page tree of the file cannot be read.
+fn page_boxes_update(pdf: &[u8], boxes: &PageBoxes) -> Option<Vec<u8>> {
+    let file = PdfFile::parse(pdf)?;
+    let pages = file.pages()?;
+    if pages.is_empty() {
+        return None
+    }
+
+    let mut update = Update::new(&file);
+    for (number, mut page) in pages {
+        page.set(b"TrimBox", box_object(&boxes.trim));
+        page.set(b"BleedBox", box_object(&boxes.bleed));
+        update.set_object(number, &Object::Dict(page));
+    }
+    Some(update.finish(Dict::new()))
+}
+
+const PAGE_BOXES_ERROR: &'static str = "the page tree could not be read to set the trim box.";
+
+/// Set the trim box and bleed box of every page in a PDF held in memory.
+pub fn append_page_boxes(pdf: &mut Vec<u8>, boxes: &PageBoxes) -> Result<()> {
+    match page_boxes_update(pdf, boxes) {
+        Some(update) => {
+            pdf.extend_from_slice(&update);
+            Ok(())
+        }
+        None => Err(Error::io("pdf output".to_string(), PAGE_BOXES_ERROR.to_string())),
+    }
+}
+
+/// Set the trim box and bleed box of every page in the PDF file at `path`.
+pub fn add_page_boxes(path: &Path, boxes: &PageBoxes) -> Result<()> {
+    append_update(path, |pdf| page_boxes_update(pdf, boxes), PAGE_BOXES_ERROR)
+}
+
+/// Append the incremental update that `make_update` builds to the file at `path`.
+fn append_update<F>(path: &Path, make_update: F, error: &str) -> Result<()>
+where F: FnOnce(&[u8]) -> Option<Vec<u8>> {
+    let io_error = |e: ::std::io::Error| {
+        Error::io(path.display().to_string(), e.to_string())
+    };
+
+    let mut pdf = Vec::new();
+    File::open(path).and_then(|mut f| f.read_to_end(&mut pdf)).map_err(&io_error)?;
+
+    match make_update(&pdf) {
+        Some(update) => {
+            OpenOptions::new()
+                .append(true)
+                .open(path)
+                .and_then(|mut f| f.write_all(&update))
+                .map_err(&io_error)
+        }
+        None => Err(Error::io(path.display().to_string(), error.to_string())),
+    }
+}
+
+/// Append `value` as a big-endian number of `bytes` bytes.
+fn push_be(out: &mut Vec<u8>, bytes: usize, value: u32) {
+    for i in (0..bytes).rev() {
+        out.push((value >> (8 * i)) as u8);
+    }
+}
+
+fn push_s15_fixed16(out: &mut Vec<u8>, x: f64) {
+    push_be(out, 4, (x * 65536.0).round() as i32 as u32);
+}
+
+fn push_xyz_tag(out: &mut Vec<u8>, x: f64, y: f64, z: f64) {
+    out.extend_from_slice(b"XYZ \0\0\0\0");
+    push_s15_fixed16(out, x);
+    push_s15_fixed16(out, y);
+    push_s15_fixed16(out, z);
+}
+
+/// Build an ICC version 2 display profile for the sRGB color space.
+///
+/// Cairo draws in device RGB, which PDF/A requires to be tied to a color space
+/// with an output intent. The profile is small enough to generate instead of
+/// shipping a file. The primaries are adapted to the D50 white point of the
+/// profile connection space, and the tone curve is sampled from the sRGB
+/// transfer function.
+fn srgb_icc_profile() -> Vec<u8> {
+    let description = b"sRGB IEC61966-2.1";
+
+    let mut desc = Vec::new();
+    desc.extend_from_slice(b"desc\0\0\0\0");
+    push_be(&mut desc, 4, description.len() as u32 + 1);
+    desc.extend_from_slice(description);
+    desc.push(0);
+    // No Unicode or ScriptCode description: language, count, code, count,
+    // and 67 bytes of ScriptCode data.
+    desc.extend_from_slice(&[0; 4 + 4 + 2 + 1 + 67]);
+
+    let mut cprt = Vec::new();
+    cprt.extend_from_slice(b"text\0\0\0\0No copyright, use freely\0");
+
+    let mut wtpt = Vec::new();
+    push_xyz_tag(&mut wtpt, 0.9642, 1.0, 0.8249);
+    let mut rxyz = Vec::new();
+    push_xyz_tag(&mut rxyz, 0.4361, 0.2225, 0.0139);
+    let mut gxyz = Vec::new();
+    push_xyz_tag(&mut gxyz, 0.3851, 0.7169, 0.0971);
+    let mut bxyz = Vec::new();
+    push_xyz_tag(&mut bxyz, 0.1431, 0.0606, 0.7141);
+
+    let mut trc = Vec::new();
+    trc.extend_from_slice(b"curv\0\0\0\0");
+    push_be(&mut trc, 4, 256);
+    for i in 0..256 {
+        let v = i as f64 / 255.0;
+        let linear = if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) };
+        push_be(&mut trc, 2, (linear * 65535.0).round() as u32);
+    }
+
+    // The three tone curves are the same, so their tags share the data.
+    let tags: [(&[u8; 4], &[u8]); 6] = [
+        (b"desc", &desc),
+        (b"cprt", &cprt),
+        (b"wtpt", &wtpt),
+        (b"rXYZ", &rxyz),
+        (b"gXYZ", &gxyz),
+        (b"bXYZ", &bxyz),
+    ];
+    let num_tags = tags.len() + 3;
+    let mut data = Vec::new();
+    let mut table = Vec::new();
+    let data_start = 128 + 4 + 12 * num_tags;
+    for &(signature, tag) in &tags {
+        table.extend_from_slice(signature);
+        push_be(&mut table, 4, (data_start + data.len()) as u32);
+        push_be(&mut table, 4, tag.len() as u32);
+        data.extend_from_slice(tag);
+        // Tag data starts on a 4-byte boundary.
+        while data.len() % 4 != 0 { data.push(0) }
+    }
+    for signature in &[b"rTRC", b"gTRC", b"bTRC"] {
+        table.extend_from_slice(&signature[..]);
+        push_be(&mut table, 4, (data_start + data.len()) as u32);
+        push_be(&mut table, 4, trc.len() as u32);
+    }
+    data.extend_from_slice(&trc);
+
+    let mut profile = Vec::new();
+    push_be(&mut profile, 4, (data_start + data.len()) as u32);
+    profile.extend_from_slice(b"\0\0\0\0"); // Preferred CMM.
+    profile.extend_from_slice(&[2, 0x10, 0, 0]); // Version 2.1.
+    profile.extend_from_slice(b"mntrRGB XYZ ");
+    profile.extend_from_slice(&[0; 12]); // Creation date, not set.
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 4 + 4 + 4 + 4 + 8]); // Platform to attributes.
+    push_be(&mut profile, 4, 0); // Perceptual rendering intent.
+    push_s15_fixed16(&mut profile, 0.9642);
+    push_s15_fixed16(&mut profile, 1.0);
+    push_s15_fixed16(&mut profile, 0.8249);
+    profile.extend_from_slice(&[0; 4 + 16 + 28]); // Creator, id, reserved.
+    debug_assert_eq!(profile.len(), 128);
+
+    push_be(&mut profile, 4, num_tags as u32);
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    profile
+}
+
+#[test]
+fn srgb_icc_profile_is_well_formed() {
+    let profile = srgb_icc_profile();
+    assert_eq!(parse_be(&profile[0..4]), profile.len());
+    assert_eq!(&profile[12..24], b"mntrRGB XYZ ");
+    assert_eq!(&profile[36..40], b"acsp");
+
+    // Every tag must lie within the profile and start on a 4-byte boundary.
+    let num_tags = parse_be(&profile[128..132]);
+    assert_eq!(num_tags, 9);
+    for i in 0..num_tags {
+        let entry = &profile[132 + 12 * i..144 + 12 * i];
+        let (offset, size) = (parse_be(&entry[4..8]), parse_be(&entry[8..12]));
+        assert_eq!(offset % 4, 0);
+        assert!(offset + size <= profile.len());
+    }
+}
+
+#[cfg(test)]
+fn parse_be(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |acc, &b| (acc << 8) | b as usize)
+}
+
+/// Characters of PDFDocEncoding that differ from Latin-1, from 0x18 to 0x1f.
+const PDF_DOC_LOW: [char; 8] = ['\u{2d8}', '\u{2c7}', '\u{2c6}', '\u{2d9}', '\u{2dd}', '\u{2db}', '\u{2da}', '\u{2dc}'];
+
+/// Characters of PDFDocEncoding that differ from Latin-1, from 0x80 to 0xa0.
+const PDF_DOC_HIGH: [char; 33] = [
+    '\u{2022}', '\u{2020}', '\u{2021}', '\u{2026}', '\u{2014}', '\u{2013}', '\u{192}', '\u{2044}',
+    '\u{2039}', '\u{203a}', '\u{2212}', '\u{2030}', '\u{201e}', '\u{201c}', '\u{201d}', '\u{2018}',
+    '\u{2019}', '\u{201a}', '\u{2122}', '\u{fb01}', '\u{fb02}', '\u{141}', '\u{152}', '\u{160}',
+    '\u{178}', '\u{17d}', '\u{131}', '\u{142}', '\u{153}', '\u{161}', '\u{17e}', '\u{fffd}',
+    '\u{20ac}',
+];
+
+/// Decode a PDF text string, which is UTF-16 with a byte order mark, UTF-8
+/// with a byte order mark, or PDFDocEncoding.
+fn decode_text(bytes: &[u8]) -> String {
+    if bytes.starts_with(&[0xfe, 0xff]) {
+        let units: Vec<u16> = bytes[2..]
+            .chunks(2)
+            .map(|pair| (pair[0] as u16) << 8 | pair.get(1).cloned().unwrap_or(0) as u16)
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+        String::from_utf8_lossy(&bytes[3..]).into_owned()
+    } else {
+        bytes.iter().map(|&b| match b {
+            0x18...0x1f => PDF_DOC_LOW[b as usize - 0x18],
+            0x80...0xa0 => PDF_DOC_HIGH[b as usize - 0x80],
+            _ => b as char,
+        }).collect()
+    }
+}
+
+/// Escape text for XML, and drop the control characters that XML forbids.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(ch),
+            _ if ch < ' ' => {}
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Convert a PDF date, `D:YYYYMMDDHHmmSSOHH'mm'`, to the ISO 8601 form of XMP.
+///
+/// Fields after the year are optional in PDF, the defaults are filled in.
+fn xmp_date(date: &str) -> Option<String> {
+    let date = date.trim_left_matches("D:");
+    let digits = date.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if digits < 4 || digits > 14 || digits % 2 != 0 {
+        return None
+    }
+    let mut fields = "0101000000".to_string();
+    fields.replace_range(..digits - 4, &date[4..digits]);
+    let f = |i: usize| &fields[i..i + 2];
+    let mut iso = format!(
+        "{}-{}-{}T{}:{}:{}",
+        &date[..4], f(0), f(2), f(4), f(6), f(8),
+    );
+
+    // Without an offset, the time is local, which XMP expresses the same way.
+    let zone: Vec<char> = date[digits..].chars().filter(|&c| c != '\'').collect();
+    match zone.first() {
+        None => {}
+        Some(&'Z') => iso.push('Z'),
+        Some(&sign) if sign == '+' || sign == '-' => {
+            let hours: String = zone[1..].iter().take(2).collect();
+            let minutes: String = zone[1..].iter().skip(2).take(2).collect();
+            if hours.len() != 2 || !hours.bytes().all(|b| b.is_ascii_digit()) {
+                return None
+            }
+            let minutes = if minutes.is_empty() { "00".to_string() } else { minutes };
+            iso.push_str(&format!("{}{}:{}", sign, hours, minutes));
+        }
+        Some(..) => return None,
+    }
+    Some(iso)
+}
+
+/// XMP metadata that identifies the file as PDF/A-2b.
+///
+/// PDF/A requires the entries of the document information to be repeated in
+/// the metadata, so they are copied from `info`.
+fn pdfa_xmp_metadata(info: &Dict) -> String {
+    let text = |key: &[u8]| -> Option<String> {
+        info.get(key).and_then(|v| v.as_string()).map(|v| xml_escape(&decode_text(v)))
+    };
+
+    let mut properties = String::new();
+    properties.push_str("<dc:format>application/pdf</dc:format>\n");
+    if let Some(title) = text(b"Title") {
+        properties.push_str(&format!(
+            "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>\n",
+            title,
+        ));
+    }
+    if let Some(author) = text(b"Author") {
+        properties.push_str(&format!(
+            "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+            author,
+        ));
+    }
+    if let Some(subject) = text(b"Subject") {
+        properties.push_str(&format!(
+            "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+            subject,
+        ));
+    }
+    if let Some(keywords) = text(b"Keywords") {
+        properties.push_str(&format!("<pdf:Keywords>{}</pdf:Keywords>\n", keywords));
+    }
+    if let Some(producer) = text(b"Producer") {
+        properties.push_str(&format!("<pdf:Producer>{}</pdf:Producer>\n", producer));
+    }
+    if let Some(creator) = text(b"Creator") {
+        properties.push_str(&format!("<xmp:CreatorTool>{}</xmp:CreatorTool>\n", creator));
+    }
+    let dates = [(&b"CreationDate"[..], "xmp:CreateDate"), (&b"ModDate"[..], "xmp:ModifyDate")];
+    for &(key, property) in &dates {
+        if let Some(date) = text(key).and_then(|d| xmp_date(&d)) {
+            properties.push_str(&format!("<{}>{}</{}>\n", property, date, property));
+        }
+    }
+
+    // The packet header contains a byte order mark, and the id is fixed by
+    // the XMP specification.
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+         <rdf:Description rdf:about=\"\"\n\
+         \x20   xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n\
+         \x20   xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"\n\
+         \x20   xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n\
+         \x20   xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\">\n\
+         {}\
+         <pdfaid:part>2</pdfaid:part>\n\
+         <pdfaid:conformance>B</pdfaid:conformance>\n\
+         </rdf:Description>\n\
+         </rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"w\"?>\n",
+        properties,
+    )
+}
+
+fn name(name: &str) -> Object {
+    Object::Name(name.as_bytes().to_vec())
+}
+
+/// Build an incremental update that makes `pdf` conform to PDF/A-2b.
+///
+/// It adds XMP metadata, which repeats the document information, and an sRGB
+/// output intent to the catalog, and gives the file an identifier.
+///
+/// Returns `None` if the catalog of the file cannot be read.
+fn pdfa_update(pdf: &[u8]) -> Option<Vec<u8>> {
+    let file = PdfFile::parse(pdf)?;
+    let (catalog_number, mut catalog) = file.catalog()?;
+    let info = match file.trailer.get(b"Info") {
+        Some(info) => file.resolve(info)?.into_dict()?,
+        None => Dict::new(),
+    };
+
+    let mut update = Update::new(&file);
+    let (metadata, profile, intent) = (update.new_object(), update.new_object(), update.new_object());
+
+    let mut metadata_dict = Dict::new();
+    metadata_dict.set(b"Type", name("Metadata"));
+    metadata_dict.set(b"Subtype", name("XML"));
+    update.set_stream(metadata, metadata_dict, pdfa_xmp_metadata(&info).as_bytes());
+
+    let mut profile_dict = Dict::new();
+    profile_dict.set(b"N", Object::Int(3));
+    update.set_stream(profile, profile_dict, &srgb_icc_profile());
+
+    let mut intent_dict = Dict::new();
+    intent_dict.set(b"Type", name("OutputIntent"));
+    intent_dict.set(b"S", name("GTS_PDFA1"));
+    intent_dict.set(b"OutputConditionIdentifier", Object::String(b"sRGB IEC61966-2.1".to_vec()));
+    intent_dict.set(b"DestOutputProfile", Object::Ref(profile, 0));
+    update.set_object(intent, &Object::Dict(intent_dict));
+
+    // The catalog keeps its object number, the root in the trailer must not
+    // change.
+    catalog.set(b"Metadata", Object::Ref(metadata, 0));
+    catalog.set(b"OutputIntents", Object::Array(vec![Object::Ref(intent, 0)]));
+    update.set_object(catalog_number, &Object::Dict(catalog));
+
+    // The identifier only needs to be unique, derive it from the contents so
+    // the output stays reproducible. The first half identifies the original
+    // file, so it is kept if there is one.
+    let mut hasher = ContentHasher::new();
+    hasher.write(pdf);
+    let id_low = hasher.finish();
+    hasher.write(b"pris");
+    let mut id = Vec::with_capacity(16);
+    for &half in &[id_low, hasher.finish()] {
+        for i in (0..8).rev() {
+            id.push((half >> (8 * i)) as u8);
+        }
+    }
+    let original_id = file.trailer
+        .get(b"ID")
+        .and_then(|ids| ids.as_array())
+        .and_then(|ids| ids.first().cloned())
+        .unwrap_or(Object::String(id.clone()));
+
+    let mut trailer = Dict::new();
+    trailer.set(b"ID", Object::Array(vec![original_id, Object::String(id)]));
+    Some(update.finish(trailer))
+}
+
+const PDFA_ERROR: &'static str = "the document catalog could not be located to make the file PDF/A.";
+
+/// Make a PDF held in memory conform to PDF/A-2b.
+pub fn append_pdfa(pdf: &mut Vec<u8>) -> Result<()> {
+    match pdfa_update(pdf) {
+        Some(update) => {
+            pdf.extend_from_slice(&update);
+            Ok(())
+        }
+        None => Err(Error::io("pdf output".to_string(), PDFA_ERROR.to_string())),
+    }
+}
+
+/// Make the PDF file at `path` conform to PDF/A-2b.
+pub fn add_pdfa(path: &Path) -> Result<()> {
+    append_update(path, pdfa_update, PDFA_ERROR)
+}
+
+/// Passwords and permissions for an encrypted PDF.
+pub struct Encryption {
+    /// Password needed to open the file, may be empty.
+    pub user_password: String,
+    /// Password needed to lift the restrictions below.
+    pub owner_password: String,
+    pub allow_print: bool,
+    pub allow_copy: bool,
+}
+
+/// Read the user and owner password from the contents of a password file.
+///
+/// The first line holds the user password, and the second line the owner
+/// password. A missing or empty line means that there is no such password.
+pub fn parse_password_file(contents: &str) -> (Option<String>, Option<String>) {
+    let mut lines = contents.lines();
+    let mut next = || lines.next().filter(|pw| !pw.is_empty()).map(|pw| pw.to_string());
+    let user_password = next();
+    let owner_password = next();
+    (user_password, owner_password)
+}
+
+#[test]
+fn parse_password_file_reads_one_password_per_line() {
+    let some = |pw: &str| Some(pw.to_string());
+    assert_eq!(parse_password_file("open sesame\nhunter2\n"), (some("open sesame"), some("hunter2")));
+    assert_eq!(parse_password_file("open sesame\r\n"), (some("open sesame"), None));
+    assert_eq!(parse_password_file("\nhunter2"), (None, some("hunter2")));
+    assert_eq!(parse_password_file(""), (None, None));
+}
+
+/// Return the arguments for qpdf to encrypt `input` into `output`.
+///
+/// The arguments are passed on stdin rather than on the command line, so the
+/// passwords do not show up in the process list. Every line is one argument.
+#[cfg(feature = "encryption")]
+fn qpdf_encrypt_args(encryption: &Encryption, input: &Path, output: &Path) -> Option<String> {
+    let mut args = vec![
+        "--encrypt".to_string(),
+        encryption.user_password.clone(),
+        encryption.owner_password.clone(),
+        // 256-bit AES, the strongest method that PDF supports.
+        "256".to_string(),
+        format!("--print={}", if encryption.allow_print { "full" } else { "none" }),
+        format!("--extract={}", if encryption.allow_copy { "y" } else { "n" }),
+        "--".to_string(),
+        input.to_str()?.to_string(),
+        output.to_str()?.to_string(),
+    ];
+    if args.iter().any(|arg| arg.contains('\n')) {
+        return None
+    }
+    args.push(String::new());
+    Some(args.join("\n"))
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn qpdf_encrypt_args_puts_one_argument_per_line() {
+    let mut encryption = Encryption {
+        user_password: "".to_string(),
+        owner_password: "secret pass".to_string(),
+        allow_print: true,
+        allow_copy: false,
+    };
+    let args = qpdf_encrypt_args(&encryption, Path::new("a.pdf"), Path::new("b.pdf"));
+    assert_eq!(
+        args.unwrap(),
+        "--encrypt\n\nsecret pass\n256\n--print=full\n--extract=n\n--\na.pdf\nb.pdf\n"
+    );
+
+    encryption.user_password = "two\nlines".to_string();
+    assert!(qpdf_encrypt_args(&encryption, Path::new("a.pdf"), Path::new("b.pdf")).is_none());
+}
+
+/// Encrypt the PDF file at `path` in place, using the `qpdf` program.
+#[cfg(feature = "encryption")]
+pub fn encrypt(path: &Path, encryption: &Encryption) -> Result<()> {
+    let io_error = |message: String| Error::io(path.display().to_string(), message);
+
+    let mut tmp_name = path.as_os_str().to_os_string();
+    tmp_name.push(".encrypted");
+    let tmp_path = Path::new(&tmp_name);
+
+    let args = match qpdf_encrypt_args(encryption, path, tmp_path) {
+        Some(args) => args,
+        None => return Err(io_error("passwords must not contain newlines, and the \
+                                     path must be valid UTF-8.".to_string())),
+    };
+
+    let mut child = Command::new("qpdf")
+        .arg("@-")
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| io_error(format!("failed to run qpdf for encryption: {}.", e)))?;
+    let written = child.stdin.take().unwrap().write_all(args.as_bytes());
+    let status = child.wait().map_err(|e| io_error(e.to_string()))?;
+    written.map_err(|e| io_error(e.to_string()))?;
+
+    // Qpdf exits with 3 when it succeeded with warnings.
+    match status.code() {
+        Some(0) | Some(3) => {
+            fs::rename(tmp_path, path).map_err(|e| io_error(e.to_string()))
+        }
+        _ => {
+            fs::remove_file(tmp_path).ok();
+            Err(io_error(format!("qpdf failed to encrypt the file, {}.", status)))
+        }
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+pub fn encrypt(path: &Path, _encryption: &Encryption) -> Result<()> {
+    let msg = "not encrypted, Pris was compiled without encryption support.";
+    Err(Error::io(path.display().to_string(), msg.to_string()))
+}
+
+#[cfg(test)]
+const TEST_BOXES: PageBoxes = PageBoxes {
+    trim: [10.0, 10.0, 110.0, 60.0],
+    bleed: [5.0, 5.5, 115.0, 65.0],
+};
+
+/// Return the numbers in a box array, integers are written without a point.
+#[cfg(test)]
+fn box_values(array: &Object) -> Vec<f64> {
+    array.as_array().unwrap().iter().map(|x| match *x {
+        Object::Int(x) => x as f64,
+        Object::Real(x) => x,
+        _ => panic!("box coordinates must be numbers"),
+    }).collect()
+}
+
+/// Check that every page of `pdf` has the boxes of `TEST_BOXES`.
+#[cfg(test)]
+fn assert_page_boxes(pdf: &[u8], num_pages: usize) {
+    let file = PdfFile::parse(pdf).unwrap();
+    let pages = file.pages().unwrap();
+    assert_eq!(pages.len(), num_pages);
+    for (_, page) in pages {
+        assert_eq!(box_values(page.get(b"TrimBox").unwrap()), TEST_BOXES.trim.to_vec());
+        assert_eq!(box_values(page.get(b"BleedBox").unwrap()), TEST_BOXES.bleed.to_vec());
+        assert_eq!(page.get(b"Type"), Some(&Object::Name(b"Page".to_vec())));
+    }
+}
+
+#[test]
+fn page_boxes_update_appends_pages_with_boxes() {
+    let objects = [
+        "<< /Type /Pages /Kids [ 2 0 R 3 0 R ] /Count 2 >>",
+        "<< /Type /Page % 1\n /Parent 1 0 R >>",
+        "<< /Type /Page % 2\n /Parent 1 0 R >>",
+        "<< /Type /Catalog /Pages 1 0 R >>",
+        "<< /Producer (cairo) >>",
+    ];
+    let trailer = "/Root 4 0 R /Info 5 0 R";
+    let fixtures = [
+        ::pdf_object::classic_fixture(&objects, trailer),
+        ::pdf_object::xref_stream_fixture(&objects, trailer),
+    ];
+    for pdf in &fixtures {
+        let mut updated = pdf.clone();
+        append_page_boxes(&mut updated, &TEST_BOXES).unwrap();
+        assert!(updated.starts_with(pdf));
+        assert_page_boxes(&updated, 2);
+
+        let file = PdfFile::parse(&updated).unwrap();
+        assert_eq!(file.trailer.get(b"Info"), Some(&Object::Ref(5, 0)));
+        assert_eq!(file.trailer.get(b"Prev"), Some(&Object::Int(PdfFile::parse(pdf).unwrap().last_xref as i64)));
+    }
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn pdf_updates_handle_cairo_output() {
+    use cairo::{Cairo, SharedBuffer, Surface};
+
+    let buffer = SharedBuffer::new();
+    {
+        let surf = Surface::new_pdf_for_stream(buffer.clone(), 120.0, 70.0).unwrap();
+        let mut cr = Cairo::new(surf.clone()).unwrap();
+        for &x in &[10.0, 20.0] {
+            cr.set_source_rgb(0.2, 0.4, 0.6);
+            cr.rectangle(x, 10.0, 50.0, 30.0);
+            cr.fill();
+            cr.show_page();
+        }
+        surf.finish().unwrap();
+    }
+    let mut pdf = buffer.take();
+    append_page_boxes(&mut pdf, &TEST_BOXES).unwrap();
+    assert_page_boxes(&pdf, 2);
+
+    // The update of the page boxes must in turn be readable for PDF/A.
+    append_pdfa(&mut pdf).unwrap();
+    assert_page_boxes(&pdf, 2);
+    let file = PdfFile::parse(&pdf).unwrap();
+    let info = file.resolve(file.trailer.get(b"Info").unwrap()).unwrap();
+    assert!(info.as_dict().unwrap().get(b"Producer").is_some());
+    assert!(file.catalog().unwrap().1.get(b"Metadata").is_some());
+}
+
+#[test]
+fn page_boxes_update_rejects_unknown_files() {
+    let boxes = PageBoxes { trim: [0.0; 4], bleed: [0.0; 4] };
+    assert!(page_boxes_update(b"not a pdf", &boxes).is_none());
+}
+
+#[test]
+fn pdfa_update_adds_metadata_and_output_intent_to_catalog() {
+    let objects = [
+        "<< /Type /Pages /Kids [ 2 0 R ] /Count 1 >>",
+        "<< /Type /Page /Parent 1 0 R >>",
+        "<< /Producer (cairo) /Title (Talk) >>",
+        "<< /Type /Catalog /Pages 1 0 R >>",
+    ];
+    let trailer = "/Root 4 0 R /Info 3 0 R";
+    let fixtures = [
+        ::pdf_object::classic_fixture(&objects, trailer),
+        ::pdf_object::xref_stream_fixture(&objects, trailer),
+    ];
+    for pdf in &fixtures {
+        let mut updated = pdf.clone();
+        append_pdfa(&mut updated).unwrap();
+        assert!(updated.starts_with(pdf));
+
+        let file = PdfFile::parse(&updated).unwrap();
+        let (number, catalog) = file.catalog().unwrap();
+        assert_eq!(number, 4);
+        assert_eq!(catalog.get(b"Pages"), Some(&Object::Ref(1, 0)));
+        let metadata = file.resolve(catalog.get(b"Metadata").unwrap()).unwrap();
+        assert_eq!(metadata.as_dict().unwrap().get(b"Subtype"), Some(&name("XML")));
+        let intents = catalog.get(b"OutputIntents").unwrap().as_array().unwrap();
+        let intent = file.resolve(&intents[0]).unwrap().into_dict().unwrap();
+        assert_eq!(intent.get(b"S"), Some(&name("GTS_PDFA1")));
+        let profile = file.resolve(intent.get(b"DestOutputProfile").unwrap()).unwrap();
+        assert_eq!(profile.as_dict().unwrap().get(b"N"), Some(&Object::Int(3)));
+
+        // The document information is kept as it was.
+        assert_eq!(file.trailer.get(b"Info"), Some(&Object::Ref(3, 0)));
+        let info = file.object(3).unwrap().into_dict().unwrap();
+        assert_eq!(info.get(b"Producer"), Some(&Object::String(b"cairo".to_vec())));
+
+        let ids = file.trailer.get(b"ID").unwrap().as_array().unwrap();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.iter().all(|id| id.as_string().map(|id| id.len()) == Some(16)));
+
+        // The identifier depends only on the contents.
+        let mut again = pdf.clone();
+        append_pdfa(&mut again).unwrap();
+        assert_eq!(again, updated);
+    }
+}
+
+#[test]
+fn pdfa_xmp_metadata_repeats_document_information() {
+    let mut info = Dict::new();
+    info.set(b"Title", Object::String(b"\xfe\xff\x00a\x22\x60\x00b".to_vec()));
+    info.set(b"Author", Object::String(b"Ann \x84 Bob".to_vec()));
+    info.set(b"Producer", Object::String(b"cairo <1.17> & co".to_vec()));
+    info.set(b"CreationDate", Object::String(b"D:20260101120000+01'00".to_vec()));
+    let xmp = pdfa_xmp_metadata(&info);
+
+    assert!(xmp.contains("<rdf:li xml:lang=\"x-default\">a\u{2260}b</rdf:li>"));
+    assert!(xmp.contains("<rdf:Seq><rdf:li>Ann \u{2014} Bob</rdf:li></rdf:Seq>"));
+    assert!(xmp.contains("<pdf:Producer>cairo &lt;1.17&gt; &amp; co</pdf:Producer>"));
+    assert!(xmp.contains("<xmp:CreateDate>2026-01-01T12:00:00+01:00</xmp:CreateDate>"));
+    assert!(!xmp.contains("ModifyDate"));
+    assert!(xmp.contains("<pdfaid:part>2</pdfaid:part>"));
+}
+
+#[test]
+fn xmp_date_converts_pdf_dates() {
+    assert_eq!(xmp_date("D:20260101120000Z").unwrap(), "2026-01-01T12:00:00Z");
+    assert_eq!(xmp_date("D:20261017093005-07'30'").unwrap(), "2026-10-17T09:30:05-07:30");
+    assert_eq!(xmp_date("D:2026").unwrap(), "2026-01-01T00:00:00");
+    assert_eq!(xmp_date("D:202610").unwrap(), "2026-10-01T00:00:00");
+    assert_eq!(xmp_date("D:20261"), None);
+    assert_eq!(xmp_date("yesterday"), None);
+}
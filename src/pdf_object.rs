@@ -0,0 +1,1095 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module reads the objects of a PDF file, as far as needed to update it.
+//!
+//! A PDF file is a collection of numbered objects, followed by a
+//! cross-reference section that records where every object is stored. Cairo
+//! before 1.17.6 writes the cross-reference as a plain-text table, and every
+//! object as plain text. Later versions write the cross-reference as a
+//! compressed stream, and store most objects in compressed object streams.
+//! Both layouts are read here, so `pdf` can change objects of either kind in
+//! an incremental update: the new versions of the objects are appended to the
+//! file, followed by a cross-reference section that points at them.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::str;
+
+use inflate;
+
+/// A PDF object. Streams are represented by their dictionary only.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Object {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Real(f64),
+    Name(Vec<u8>),
+    String(Vec<u8>),
+    Array(Vec<Object>),
+    Dict(Dict),
+    /// A reference to an indirect object, by number and generation.
+    Ref(u32, u16),
+}
+
+/// A dictionary, which keeps its keys in the order of the file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dict(pub Vec<(Vec<u8>, Object)>);
+
+impl Dict {
+    pub fn new() -> Dict {
+        Dict(Vec::new())
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&Object> {
+        self.0.iter().find(|entry| entry.0 == key).map(|entry| &entry.1)
+    }
+
+    /// Set the value of `key`, replacing the existing value if there is one.
+    pub fn set(&mut self, key: &[u8], value: Object) {
+        match self.0.iter().position(|entry| entry.0 == key) {
+            Some(i) => self.0[i].1 = value,
+            None => self.0.push((key.to_vec(), value)),
+        }
+    }
+}
+
+impl Object {
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            Object::Int(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_name(&self) -> Option<&[u8]> {
+        match *self {
+            Object::Name(ref name) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&[u8]> {
+        match *self {
+            Object::String(ref string) => Some(string),
+            _ => None,
+        }
+    }
+
+
+    pub fn as_array(&self) -> Option<&[Object]> {
+        match *self {
+            Object::Array(ref elements) => Some(elements),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<&Dict> {
+        match *self {
+            Object::Dict(ref dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    pub fn into_dict(self) -> Option<Dict> {
+        match self {
+            Object::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    /// Append the object in PDF syntax to `out`.
+    pub fn write(&self, out: &mut Vec<u8>) {
+        match *self {
+            Object::Null => out.extend_from_slice(b"null"),
+            Object::Bool(b) => write!(out, "{}", b).unwrap(),
+            Object::Int(x) => write!(out, "{}", x).unwrap(),
+            // Rust never formats a float with an exponent, which PDF lacks.
+            Object::Real(x) if x.is_finite() => write!(out, "{}", x).unwrap(),
+            Object::Real(..) => out.push(b'0'),
+            Object::Name(ref name) => {
+                out.push(b'/');
+                for &b in name {
+                    if b > b' ' && b < 0x7f && b != b'#' && !is_delimiter(b) {
+                        out.push(b);
+                    } else {
+                        write!(out, "#{:02x}", b).unwrap();
+                    }
+                }
+            }
+            Object::String(ref string) => {
+                out.push(b'<');
+                for &b in string {
+                    write!(out, "{:02x}", b).unwrap();
+                }
+                out.push(b'>');
+            }
+            Object::Array(ref elements) => {
+                out.push(b'[');
+                for element in elements {
+                    out.push(b' ');
+                    element.write(out);
+                }
+                out.extend_from_slice(b" ]");
+            }
+            Object::Dict(ref dict) => {
+                out.extend_from_slice(b"<<");
+                for &(ref key, ref value) in &dict.0 {
+                    out.extend_from_slice(b"\n   ");
+                    Object::Name(key.clone()).write(out);
+                    out.push(b' ');
+                    value.write(out);
+                }
+                out.extend_from_slice(b"\n>>");
+            }
+            Object::Ref(number, generation) => write!(out, "{} {} R", number, generation).unwrap(),
+        }
+    }
+}
+
+fn is_whitespace(b: u8) -> bool {
+    b"\0\t\n\x0c\r ".contains(&b)
+}
+
+fn is_delimiter(b: u8) -> bool {
+    b"()<>[]{}/%".contains(&b)
+}
+
+fn is_regular(b: u8) -> bool {
+    !is_whitespace(b) && !is_delimiter(b)
+}
+
+/// Nested arrays and dictionaries deeper than this are rejected.
+const MAX_DEPTH: u32 = 64;
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Parser<'a> {
+        Parser { data: data, pos: pos }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).cloned()
+    }
+
+    /// Skip whitespace and comments.
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.peek() {
+            if b == b'%' {
+                while self.peek().map_or(false, |b| b != b'\n' && b != b'\r') {
+                    self.pos += 1;
+                }
+            } else if is_whitespace(b) {
+                self.pos += 1;
+            } else {
+                break
+            }
+        }
+    }
+
+    /// Take a run of regular characters, such as a keyword or a number.
+    fn token(&mut self) -> &'a [u8] {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.peek().map_or(false, is_regular) {
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    fn expect_keyword(&mut self, keyword: &[u8]) -> Option<()> {
+        if self.token() == keyword { Some(()) } else { None }
+    }
+
+    fn parse_uint(&mut self) -> Option<u64> {
+        let token = self.token();
+        if token.is_empty() || !token.iter().all(|b| b.is_ascii_digit()) {
+            return None
+        }
+        str::from_utf8(token).ok()?.parse().ok()
+    }
+
+    fn parse_object(&mut self, depth: u32) -> Option<Object> {
+        if depth > MAX_DEPTH { return None }
+        self.skip_whitespace();
+        match self.peek()? {
+            b'/' => {
+                self.pos += 1;
+                self.parse_name().map(Object::Name)
+            }
+            b'(' => {
+                self.pos += 1;
+                self.parse_literal_string().map(Object::String)
+            }
+            b'<' if self.data.get(self.pos + 1) == Some(&b'<') => {
+                self.pos += 2;
+                self.parse_dict(depth).map(Object::Dict)
+            }
+            b'<' => {
+                self.pos += 1;
+                self.parse_hex_string().map(Object::String)
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut elements = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.peek()? == b']' {
+                        self.pos += 1;
+                        return Some(Object::Array(elements))
+                    }
+                    elements.push(self.parse_object(depth + 1)?);
+                }
+            }
+            _ => {
+                let token = self.token();
+                match token {
+                    b"null" => Some(Object::Null),
+                    b"true" => Some(Object::Bool(true)),
+                    b"false" => Some(Object::Bool(false)),
+                    _ => self.parse_number_or_ref(token),
+                }
+            }
+        }
+    }
+
+    fn parse_number_or_ref(&mut self, token: &[u8]) -> Option<Object> {
+        let text = str::from_utf8(token).ok()?;
+        if token.is_empty() || !token.iter().all(|b| b.is_ascii_digit()) {
+            // A signed integer, or a real, possibly without digits before the point.
+            return text.parse().ok().map(Object::Int)
+                .or_else(|| text.parse().ok().map(Object::Real))
+        }
+
+        // An unsigned integer may be the start of a reference "12 0 R".
+        let number: i64 = text.parse().ok()?;
+        let after_number = self.pos;
+        if let Some(generation) = self.parse_uint() {
+            if self.token() == b"R" && number <= u32::max_value() as i64 && generation <= 0xffff {
+                return Some(Object::Ref(number as u32, generation as u16))
+            }
+        }
+        self.pos = after_number;
+        Some(Object::Int(number))
+    }
+
+    fn parse_name(&mut self) -> Option<Vec<u8>> {
+        let mut name = Vec::new();
+        while let Some(b) = self.peek().filter(|&b| is_regular(b)) {
+            self.pos += 1;
+            if b == b'#' {
+                let hex = self.data.get(self.pos..self.pos + 2)?;
+                let byte = u8::from_str_radix(str::from_utf8(hex).ok()?, 16).ok()?;
+                name.push(byte);
+                self.pos += 2;
+            } else {
+                name.push(b);
+            }
+        }
+        Some(name)
+    }
+
+    fn parse_literal_string(&mut self) -> Option<Vec<u8>> {
+        let mut string = Vec::new();
+        let mut open = 0;
+        loop {
+            let b = self.peek()?;
+            self.pos += 1;
+            match b {
+                b'(' => {
+                    open += 1;
+                    string.push(b);
+                }
+                b')' if open == 0 => return Some(string),
+                b')' => {
+                    open -= 1;
+                    string.push(b);
+                }
+                b'\\' => {
+                    let escaped = self.peek()?;
+                    self.pos += 1;
+                    match escaped {
+                        b'n' => string.push(b'\n'),
+                        b'r' => string.push(b'\r'),
+                        b't' => string.push(b'\t'),
+                        b'b' => string.push(0x08),
+                        b'f' => string.push(0x0c),
+                        b'0'...b'7' => {
+                            let mut value = (escaped - b'0') as u32;
+                            for _ in 0..2 {
+                                match self.peek() {
+                                    Some(d @ b'0'...b'7') => {
+                                        value = value * 8 + (d - b'0') as u32;
+                                        self.pos += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            string.push(value as u8);
+                        }
+                        // A backslash at the end of a line continues the string.
+                        b'\r' => if self.peek() == Some(b'\n') { self.pos += 1 },
+                        b'\n' => {}
+                        other => string.push(other),
+                    }
+                }
+                _ => string.push(b),
+            }
+        }
+    }
+
+    fn parse_hex_string(&mut self) -> Option<Vec<u8>> {
+        let mut digits = Vec::new();
+        loop {
+            let b = self.peek()?;
+            self.pos += 1;
+            match b {
+                b'>' => break,
+                b'0'...b'9' => digits.push(b - b'0'),
+                b'a'...b'f' => digits.push(b - b'a' + 10),
+                b'A'...b'F' => digits.push(b - b'A' + 10),
+                _ if is_whitespace(b) => {}
+                _ => return None,
+            }
+        }
+        // A missing final digit is taken to be zero.
+        if digits.len() % 2 == 1 {
+            digits.push(0);
+        }
+        Some(digits.chunks(2).map(|pair| pair[0] * 16 + pair[1]).collect())
+    }
+
+    fn parse_dict(&mut self, depth: u32) -> Option<Dict> {
+        let mut dict = Dict::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek()? {
+                b'>' if self.data.get(self.pos + 1) == Some(&b'>') => {
+                    self.pos += 2;
+                    return Some(dict)
+                }
+                b'/' => {
+                    self.pos += 1;
+                    let key = self.parse_name()?;
+                    let value = self.parse_object(depth + 1)?;
+                    dict.0.push((key, value));
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Where the most recent version of an object is stored.
+#[derive(Copy, Clone, Debug)]
+enum Location {
+    /// At a byte offset in the file, with a generation number.
+    Offset(usize, u16),
+    /// In the object stream with the given number, at an index.
+    Compressed(u32, usize),
+}
+
+/// A PDF file held in memory, with its cross-reference sections read.
+pub struct PdfFile<'a> {
+    data: &'a [u8],
+    locations: HashMap<u32, Location>,
+    /// The trailer of the most recent cross-reference section.
+    pub trailer: Dict,
+    /// The offset of the most recent cross-reference section.
+    pub last_xref: usize,
+    /// Whether the most recent cross-reference section is a stream.
+    pub has_xref_stream: bool,
+}
+
+/// Undo a PNG predictor, which Cairo uses for cross-reference streams.
+fn unpredict_png(data: &[u8], columns: usize) -> Option<Vec<u8>> {
+    let row_len = columns + 1;
+    if columns == 0 || data.len() % row_len != 0 { return None }
+    let mut out: Vec<u8> = Vec::with_capacity(data.len() / row_len * columns);
+    let mut prev = vec![0u8; columns];
+    for row in data.chunks(row_len) {
+        let (filter, row) = (row[0], &row[1..]);
+        let mut current = vec![0u8; columns];
+        for i in 0..columns {
+            let left = if i > 0 { current[i - 1] } else { 0 };
+            let up = prev[i];
+            let up_left = if i > 0 { prev[i - 1] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => {
+                    let p = left as i16 + up as i16 - up_left as i16;
+                    let (pa, pb, pc) = ((p - left as i16).abs(), (p - up as i16).abs(), (p - up_left as i16).abs());
+                    if pa <= pb && pa <= pc { left } else if pb <= pc { up } else { up_left }
+                }
+                _ => return None,
+            };
+            current[i] = row[i].wrapping_add(predicted);
+        }
+        out.extend_from_slice(&current);
+        prev = current;
+    }
+    Some(out)
+}
+
+/// Apply the filters of a stream to its raw data.
+fn decode_stream(dict: &Dict, raw: &[u8]) -> Option<Vec<u8>> {
+    let filter = match dict.get(b"Filter") {
+        None => None,
+        Some(&Object::Name(ref name)) => Some(&name[..]),
+        Some(&Object::Array(ref filters)) if filters.is_empty() => None,
+        Some(&Object::Array(ref filters)) if filters.len() == 1 => Some(filters[0].as_name()?),
+        Some(..) => return None,
+    };
+    let data = match filter {
+        None => raw.to_vec(),
+        Some(b"FlateDecode") => inflate::zlib_decompress(raw)?,
+        Some(..) => return None,
+    };
+
+    let params = match dict.get(b"DecodeParms") {
+        Some(&Object::Array(ref params)) => params.get(0).and_then(|p| p.as_dict()),
+        Some(params) => params.as_dict(),
+        None => None,
+    };
+    let predictor = params.and_then(|p| p.get(b"Predictor")).and_then(|p| p.as_int()).unwrap_or(1);
+    match predictor {
+        1 => Some(data),
+        10...15 => {
+            let columns = params?.get(b"Columns").and_then(|c| c.as_int()).unwrap_or(1);
+            // Only one byte per pixel is supported, which is what PDF
+            // writers use for cross-reference and object streams.
+            let colors = params?.get(b"Colors").and_then(|c| c.as_int()).unwrap_or(1);
+            let bits = params?.get(b"BitsPerComponent").and_then(|c| c.as_int()).unwrap_or(8);
+            if colors != 1 || bits != 8 || columns < 1 { return None }
+            unpredict_png(&data, columns as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Read a big-endian number from the bytes of a cross-reference stream entry.
+fn read_field(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+impl<'a> PdfFile<'a> {
+    /// Read the cross-reference sections of a file.
+    ///
+    /// Returns `None` if the file is not a PDF file, or if it uses features
+    /// that this reader does not support.
+    pub fn parse(data: &'a [u8]) -> Option<PdfFile<'a>> {
+        let tail_start = data.len().saturating_sub(1024);
+        let startxref = data[tail_start..]
+            .windows(9)
+            .rposition(|w| w == b"startxref")
+            .map(|i| tail_start + i + 9)?;
+        let last_xref = Parser::new(data, startxref).parse_uint()? as usize;
+
+        let mut file = PdfFile {
+            data: data,
+            locations: HashMap::new(),
+            trailer: Dict::new(),
+            last_xref: last_xref,
+            has_xref_stream: false,
+        };
+
+        // Sections are read from new to old, so the first location of an
+        // object that is found is the most recent one.
+        let mut pending = vec![last_xref];
+        let mut visited = HashSet::new();
+        while let Some(offset) = pending.pop() {
+            if !visited.insert(offset) || offset >= data.len() {
+                continue
+            }
+            let mut parser = Parser::new(data, offset);
+            let trailer = if parser.token() == b"xref" {
+                file.read_xref_table(&mut parser)?
+            } else {
+                let trailer = file.read_xref_stream(offset)?;
+                if offset == last_xref {
+                    file.has_xref_stream = true;
+                }
+                trailer
+            };
+
+            // The /Prev section is older than the stream of a hybrid file,
+            // so it goes on the stack first.
+            if let Some(prev) = trailer.get(b"Prev").and_then(|p| p.as_int()) {
+                pending.push(prev as usize);
+            }
+            if let Some(stream) = trailer.get(b"XRefStm").and_then(|p| p.as_int()) {
+                pending.push(stream as usize);
+            }
+            if offset == last_xref {
+                file.trailer = trailer;
+            }
+        }
+
+        Some(file)
+    }
+
+    /// Read a cross-reference table, after the "xref" keyword, and its trailer.
+    fn read_xref_table(&mut self, parser: &mut Parser) -> Option<Dict> {
+        loop {
+            parser.skip_whitespace();
+            if parser.data[parser.pos..].starts_with(b"trailer") {
+                parser.expect_keyword(b"trailer")?;
+                return parser.parse_object(0)?.into_dict()
+            }
+            let first = parser.parse_uint()? as u32;
+            let count = parser.parse_uint()? as u32;
+            for i in 0..count {
+                let offset = parser.parse_uint()? as usize;
+                let generation = parser.parse_uint()? as u16;
+                match parser.token() {
+                    // Free entries are skipped rather than recorded, so that
+                    // the entries of the stream of a hybrid file are used.
+                    b"f" => {}
+                    b"n" => {
+                        self.locations
+                            .entry(first + i)
+                            .or_insert(Location::Offset(offset, generation));
+                    }
+                    _ => return None,
+                }
+            }
+        }
+    }
+
+    /// Read a cross-reference stream, and return its dictionary.
+    fn read_xref_stream(&mut self, offset: usize) -> Option<Dict> {
+        let (_, _, dict, data) = self.stream_at(offset)?;
+        if dict.get(b"Type").and_then(|t| t.as_name()) != Some(b"XRef") {
+            return None
+        }
+        let widths: Vec<usize> = dict
+            .get(b"W")?
+            .as_array()?
+            .iter()
+            .map(|w| w.as_int().map(|w| w as usize))
+            .collect::<Option<_>>()?;
+        if widths.len() != 3 || widths.iter().any(|&w| w > 8) {
+            return None
+        }
+        let entry_len = widths[0] + widths[1] + widths[2];
+        let size = dict.get(b"Size")?.as_int()?;
+        let index = match dict.get(b"Index") {
+            Some(index) => index
+                .as_array()?
+                .iter()
+                .map(|i| i.as_int())
+                .collect::<Option<Vec<i64>>>()?,
+            None => vec![0, size],
+        };
+
+        let mut entries = data.chunks(entry_len);
+        for range in index.chunks(2) {
+            if range.len() != 2 { return None }
+            for number in range[0]..range[0] + range[1] {
+                let entry = entries.next()?;
+                let (kind, rest) = entry.split_at(widths[0]);
+                let (field1, field2) = rest.split_at(widths[1]);
+                // Without a type field, the type is 1, an uncompressed object.
+                let kind = if widths[0] == 0 { 1 } else { read_field(kind) };
+                let location = match kind {
+                    1 => Location::Offset(read_field(field1) as usize, read_field(field2) as u16),
+                    2 => Location::Compressed(read_field(field1) as u32, read_field(field2) as usize),
+                    _ => continue,
+                };
+                self.locations.entry(number as u32).or_insert(location);
+            }
+        }
+        Some(dict)
+    }
+
+    /// Parse the indirect object at `offset`, which must be a stream.
+    ///
+    /// Returns the object number and generation, the dictionary, and the
+    /// decoded data.
+    fn stream_at(&self, offset: usize) -> Option<(u32, u16, Dict, Vec<u8>)> {
+        let mut parser = Parser::new(self.data, offset);
+        let number = parser.parse_uint()? as u32;
+        let generation = parser.parse_uint()? as u16;
+        parser.expect_keyword(b"obj")?;
+        let dict = parser.parse_object(0)?.into_dict()?;
+        parser.expect_keyword(b"stream")?;
+
+        // The data starts after the end of the line of the keyword.
+        let mut start = parser.pos;
+        if self.data[start..].starts_with(b"\r\n") {
+            start += 2;
+        } else if self.data[start..].starts_with(b"\n") {
+            start += 1;
+        }
+        let length = dict
+            .get(b"Length")
+            .and_then(|length| self.resolve(length))
+            .and_then(|length| length.as_int())
+            .map(|length| length as usize);
+        let end = match length {
+            Some(length) if start + length <= self.data.len() => start + length,
+            // Without a usable length, the data ends at the keyword.
+            _ => {
+                let len = self.data[start..].windows(9).position(|w| w == b"endstream")?;
+                start + len
+            }
+        };
+
+        let data = decode_stream(&dict, &self.data[start..end])?;
+        Some((number, generation, dict, data))
+    }
+
+    /// Return the most recent version of an object, `None` if it is missing.
+    pub fn object(&self, number: u32) -> Option<Object> {
+        match *self.locations.get(&number)? {
+            Location::Offset(offset, _) => {
+                let mut parser = Parser::new(self.data, offset);
+                if parser.parse_uint()? != number as u64 {
+                    return None
+                }
+                parser.parse_uint()?;
+                parser.expect_keyword(b"obj")?;
+                parser.parse_object(0)
+            }
+            Location::Compressed(stream, index) => {
+                let offset = match *self.locations.get(&stream)? {
+                    Location::Offset(offset, _) => offset,
+                    Location::Compressed(..) => return None,
+                };
+                let (_, _, dict, data) = self.stream_at(offset)?;
+                let count = dict.get(b"N")?.as_int()? as usize;
+                let first = dict.get(b"First")?.as_int()? as usize;
+
+                // The stream starts with pairs of object number and offset.
+                let mut parser = Parser::new(&data, 0);
+                let mut found = None;
+                for i in 0..count {
+                    let (n, offset) = (parser.parse_uint()?, parser.parse_uint()? as usize);
+                    if n == number as u64 && (i == index || found.is_none()) {
+                        found = Some(offset);
+                    }
+                }
+                let mut parser = Parser::new(&data, first + found?);
+                parser.parse_object(0)
+            }
+        }
+    }
+
+    /// Return the generation number of the most recent version of an object.
+    pub fn generation(&self, number: u32) -> u16 {
+        match self.locations.get(&number) {
+            Some(&Location::Offset(_, generation)) => generation,
+            _ => 0,
+        }
+    }
+
+    /// Follow a reference, or return a copy of a direct object.
+    pub fn resolve(&self, object: &Object) -> Option<Object> {
+        match *object {
+            Object::Ref(number, _) => self.object(number),
+            ref direct => Some(direct.clone()),
+        }
+    }
+
+    /// Return the number of the catalog, and the catalog itself.
+    pub fn catalog(&self) -> Option<(u32, Dict)> {
+        match *self.trailer.get(b"Root")? {
+            Object::Ref(number, _) => Some((number, self.object(number)?.into_dict()?)),
+            _ => None,
+        }
+    }
+
+    /// Return the object numbers and dictionaries of all pages, in order.
+    pub fn pages(&self) -> Option<Vec<(u32, Dict)>> {
+        let (_, catalog) = self.catalog()?;
+        let mut pages = Vec::new();
+        let mut visited = HashSet::new();
+        match *catalog.get(b"Pages")? {
+            Object::Ref(number, _) => self.collect_pages(number, 0, &mut visited, &mut pages)?,
+            _ => return None,
+        }
+        Some(pages)
+    }
+
+    fn collect_pages(&self,
+                     number: u32,
+                     depth: u32,
+                     visited: &mut HashSet<u32>,
+                     pages: &mut Vec<(u32, Dict)>)
+                     -> Option<()> {
+        if depth > MAX_DEPTH || !visited.insert(number) {
+            return None
+        }
+        let node = self.object(number)?.into_dict()?;
+        match node.get(b"Type").and_then(|t| t.as_name()) {
+            Some(b"Page") => pages.push((number, node)),
+            Some(b"Pages") => {
+                let kids = self.resolve(node.get(b"Kids")?)?;
+                for kid in kids.as_array()? {
+                    match *kid {
+                        Object::Ref(kid, _) => self.collect_pages(kid, depth + 1, visited, pages)?,
+                        _ => return None,
+                    }
+                }
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+/// New versions of objects to append to a file as an incremental update.
+pub struct Update<'p, 'a: 'p> {
+    file: &'p PdfFile<'a>,
+    /// Object number, generation, and the object in PDF syntax.
+    objects: Vec<(u32, u16, Vec<u8>)>,
+    /// The number of the next new object.
+    size: u32,
+}
+
+impl<'p, 'a> Update<'p, 'a> {
+    pub fn new(file: &'p PdfFile<'a>) -> Update<'p, 'a> {
+        let size = file.trailer.get(b"Size").and_then(|s| s.as_int()).unwrap_or(0);
+        Update {
+            file: file,
+            objects: Vec::new(),
+            size: size as u32,
+        }
+    }
+
+    /// Reserve the number of a new object.
+    pub fn new_object(&mut self) -> u32 {
+        self.size += 1;
+        self.size - 1
+    }
+
+    /// Replace the object with the given number, or set a new object.
+    pub fn set_object(&mut self, number: u32, object: &Object) {
+        let mut body = Vec::new();
+        object.write(&mut body);
+        self.set_body(number, body);
+    }
+
+    /// Set a stream object, the length is added to its dictionary.
+    pub fn set_stream(&mut self, number: u32, mut dict: Dict, data: &[u8]) {
+        dict.set(b"Length", Object::Int(data.len() as i64));
+        let mut body = Vec::new();
+        Object::Dict(dict).write(&mut body);
+        body.extend_from_slice(b"\nstream\n");
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\nendstream");
+        self.set_body(number, body);
+    }
+
+    fn set_body(&mut self, number: u32, body: Vec<u8>) {
+        let generation = self.file.generation(number);
+        self.objects.retain(|object| object.0 != number);
+        self.objects.push((number, generation, body));
+    }
+
+    /// Return the bytes to append to the file.
+    ///
+    /// The trailer of the update has the /Root, /Info and /ID of the file,
+    /// overridden by the entries of `trailer`. The cross-reference section
+    /// has the same form as the most recent one of the file.
+    pub fn finish(mut self, trailer: Dict) -> Vec<u8> {
+        let base = self.file.data.len();
+        let mut update = Vec::new();
+        if !self.file.data.ends_with(b"\n") && !self.file.data.ends_with(b"\r") {
+            update.push(b'\n');
+        }
+
+        self.objects.sort_by_key(|object| object.0);
+        let mut offsets = Vec::with_capacity(self.objects.len() + 1);
+        for &(number, generation, ref body) in &self.objects {
+            offsets.push((number, generation, base + update.len()));
+            write!(update, "{} {} obj\n", number, generation).unwrap();
+            update.extend_from_slice(body);
+            update.extend_from_slice(b"\nendobj\n");
+        }
+
+        let mut full_trailer = Dict::new();
+        for key in &[&b"Root"[..], &b"Info"[..], &b"ID"[..]] {
+            if let Some(value) = self.file.trailer.get(key) {
+                full_trailer.set(key, value.clone());
+            }
+        }
+        for (key, value) in trailer.0 {
+            full_trailer.set(&key, value);
+        }
+        full_trailer.set(b"Prev", Object::Int(self.file.last_xref as i64));
+
+        let xref_pos = base + update.len();
+        if self.file.has_xref_stream {
+            // The stream is an object itself, so it needs an entry too.
+            let number = self.new_object();
+            offsets.push((number, 0, xref_pos));
+            let mut dict = Dict::new();
+            dict.set(b"Type", Object::Name(b"XRef".to_vec()));
+            dict.set(b"Size", Object::Int(self.size as i64));
+            dict.set(b"Index", Object::Array(index_ranges(&offsets)));
+            dict.set(b"W", Object::Array(vec![Object::Int(1), Object::Int(8), Object::Int(2)]));
+            for (key, value) in full_trailer.0 {
+                dict.set(&key, value);
+            }
+
+            let mut data = Vec::with_capacity(offsets.len() * 11);
+            for &(_, generation, offset) in &offsets {
+                data.push(1);
+                for i in (0..8).rev() {
+                    data.push((offset as u64 >> (8 * i)) as u8);
+                }
+                data.push((generation >> 8) as u8);
+                data.push(generation as u8);
+            }
+            dict.set(b"Length", Object::Int(data.len() as i64));
+
+            write!(update, "{} 0 obj\n", number).unwrap();
+            Object::Dict(dict).write(&mut update);
+            update.extend_from_slice(b"\nstream\n");
+            update.extend_from_slice(&data);
+            update.extend_from_slice(b"\nendstream\nendobj\n");
+        } else {
+            update.extend_from_slice(b"xref\n");
+            let mut i = 0;
+            while i < offsets.len() {
+                let run = consecutive_run(&offsets[i..]);
+                write!(update, "{} {}\n", offsets[i].0, run).unwrap();
+                for &(_, generation, offset) in &offsets[i..i + run] {
+                    // Entries are exactly 20 bytes, including the two-byte line ending.
+                    write!(update, "{:010} {:05} n \n", offset, generation).unwrap();
+                }
+                i += run;
+            }
+            full_trailer.set(b"Size", Object::Int(self.size as i64));
+            update.extend_from_slice(b"trailer\n");
+            Object::Dict(full_trailer).write(&mut update);
+            update.push(b'\n');
+        }
+        write!(update, "startxref\n{}\n%%EOF\n", xref_pos).unwrap();
+        update
+    }
+}
+
+/// Return the length of the run of consecutive object numbers at the start.
+fn consecutive_run(offsets: &[(u32, u16, usize)]) -> usize {
+    let mut run = 1;
+    while run < offsets.len() && offsets[run].0 == offsets[0].0 + run as u32 {
+        run += 1;
+    }
+    run
+}
+
+/// Return the /Index of a cross-reference stream, pairs of first and count.
+fn index_ranges(offsets: &[(u32, u16, usize)]) -> Vec<Object> {
+    let mut index = Vec::new();
+    let mut i = 0;
+    while i < offsets.len() {
+        let run = consecutive_run(&offsets[i..]);
+        index.push(Object::Int(offsets[i].0 as i64));
+        index.push(Object::Int(run as i64));
+        i += run;
+    }
+    index
+}
+
+#[test]
+fn parser_reads_nested_objects() {
+    let data = b"<< /Type /Page /Kids [ 3 0 R 4 0 R ] /Name (a \\(b\\) \\101) \
+                 /Hex <48 69> /N -1.5 /I 42 /Esc /A#20B /Null null >>";
+    let object = Parser::new(data, 0).parse_object(0).unwrap();
+    let dict = object.as_dict().unwrap();
+    assert_eq!(dict.get(b"Type"), Some(&Object::Name(b"Page".to_vec())));
+    assert_eq!(dict.get(b"Kids"), Some(&Object::Array(vec![Object::Ref(3, 0), Object::Ref(4, 0)])));
+    assert_eq!(dict.get(b"Name"), Some(&Object::String(b"a (b) A".to_vec())));
+    assert_eq!(dict.get(b"Hex"), Some(&Object::String(b"Hi".to_vec())));
+    assert_eq!(dict.get(b"N"), Some(&Object::Real(-1.5)));
+    assert_eq!(dict.get(b"I"), Some(&Object::Int(42)));
+    assert_eq!(dict.get(b"Esc"), Some(&Object::Name(b"A B".to_vec())));
+    assert_eq!(dict.get(b"Null"), Some(&Object::Null));
+}
+
+#[test]
+fn object_write_round_trips() {
+    let mut dict = Dict::new();
+    dict.set(b"Type", Object::Name(b"A B".to_vec()));
+    dict.set(b"Box", Object::Array(vec![Object::Real(0.5), Object::Int(-2), Object::Ref(7, 0)]));
+    dict.set(b"Text", Object::String(b"(\xfe)".to_vec()));
+    let object = Object::Dict(dict);
+    let mut out = Vec::new();
+    object.write(&mut out);
+    assert_eq!(Parser::new(&out, 0).parse_object(0).unwrap(), object);
+}
+
+#[test]
+fn unpredict_png_undoes_up_filter() {
+    // Two rows of two columns, the second row relative to the first.
+    let data = [2, 1, 200, 2, 1, 100];
+    assert_eq!(unpredict_png(&data, 2).unwrap(), vec![1, 200, 2, 44]);
+}
+
+/// Compress `data` as a single stored block, which is valid zlib data.
+#[cfg(test)]
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let len = data.len() as u16;
+    let mut out = vec![0x78, 0x01, 0x01, len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8];
+    out.extend_from_slice(data);
+    let (mut a, mut b) = (1u32, 0u32);
+    for &x in data {
+        a = (a + x as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&[(b >> 8) as u8, b as u8, (a >> 8) as u8, a as u8]);
+    out
+}
+
+/// Build a file with a cross-reference table, the way Cairo before 1.17.6
+/// writes them. Objects are numbered from 1.
+#[cfg(test)]
+pub fn classic_fixture(objects: &[&str], trailer: &str) -> Vec<u8> {
+    let mut pdf = b"%PDF-1.5\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        write!(pdf, "{} 0 obj\n{}\nendobj\n", i + 1, object).unwrap();
+    }
+    let xref = pdf.len();
+    write!(pdf, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).unwrap();
+    for offset in offsets {
+        write!(pdf, "{:010} 00000 n \n", offset).unwrap();
+    }
+    write!(pdf, "trailer\n<< /Size {} {} >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, trailer, xref).unwrap();
+    pdf
+}
+
+/// Build a file with the objects in a compressed object stream, and a
+/// compressed cross-reference stream with a PNG predictor, the way Cairo
+/// 1.17.6 and later writes them. Objects are numbered from 1, the object
+/// stream and cross-reference stream follow them.
+#[cfg(test)]
+pub fn xref_stream_fixture(objects: &[&str], trailer: &str) -> Vec<u8> {
+    let n = objects.len();
+    let (objstm, xref_number) = (n as u32 + 1, n as u32 + 2);
+
+    let mut header = Vec::new();
+    let mut body = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        write!(header, "{} {} ", i + 1, body.len()).unwrap();
+        body.extend_from_slice(object.as_bytes());
+        body.push(b' ');
+    }
+    let first = header.len();
+    header.extend_from_slice(&body);
+    let data = zlib_stored(&header);
+
+    let mut pdf = b"%PDF-1.5\n".to_vec();
+    let objstm_offset = pdf.len();
+    write!(
+        pdf,
+        "{} 0 obj\n<< /Type /ObjStm /N {} /First {} /Filter /FlateDecode /Length {} >>\nstream\n",
+        objstm, n, first, data.len(),
+    ).unwrap();
+    pdf.extend_from_slice(&data);
+    pdf.extend_from_slice(b"\nendstream\nendobj\n");
+
+    // Entries of type, offset or stream, and generation or index, with
+    // field widths 1, 2, 1.
+    let xref_offset = pdf.len();
+    let mut rows: Vec<[u8; 4]> = vec![[0, 0, 0, 255]];
+    for i in 0..n {
+        rows.push([2, (objstm >> 8) as u8, objstm as u8, i as u8]);
+    }
+    rows.push([1, (objstm_offset >> 8) as u8, objstm_offset as u8, 0]);
+    rows.push([1, (xref_offset >> 8) as u8, xref_offset as u8, 0]);
+
+    // Every row is encoded with the Up predictor, relative to the row above.
+    let mut predicted = Vec::new();
+    let mut prev = [0u8; 4];
+    for row in &rows {
+        predicted.push(2);
+        for i in 0..4 {
+            predicted.push(row[i].wrapping_sub(prev[i]));
+        }
+        prev = *row;
+    }
+    let data = zlib_stored(&predicted);
+    write!(
+        pdf,
+        "{} 0 obj\n<< /Type /XRef /Size {} /W [ 1 2 1 ] {} /Filter /FlateDecode \
+         /DecodeParms << /Predictor 12 /Columns 4 >> /Length {} >>\nstream\n",
+        xref_number, xref_number + 1, trailer, data.len(),
+    ).unwrap();
+    pdf.extend_from_slice(&data);
+    write!(pdf, "\nendstream\nendobj\nstartxref\n{}\n%%EOF\n", xref_offset).unwrap();
+    pdf
+}
+
+#[cfg(test)]
+const FIXTURE_OBJECTS: [&'static str; 4] = [
+    "<< /Type /Pages /Kids [ 2 0 R 3 0 R ] /Count 2 >>",
+    "<< /Type /Page /Parent 1 0 R >>",
+    "<< /Type /Page /Parent 1 0 R /Title (two) >>",
+    "<< /Type /Catalog /Pages 1 0 R >>",
+];
+
+#[test]
+fn pdf_file_reads_cross_reference_tables() {
+    let pdf = classic_fixture(&FIXTURE_OBJECTS, "/Root 4 0 R");
+    let file = PdfFile::parse(&pdf).unwrap();
+    assert!(!file.has_xref_stream);
+    let pages = file.pages().unwrap();
+    assert_eq!(pages.iter().map(|p| p.0).collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!(pages[1].1.get(b"Title"), Some(&Object::String(b"two".to_vec())));
+}
+
+#[test]
+fn pdf_file_reads_cross_reference_and_object_streams() {
+    let pdf = xref_stream_fixture(&FIXTURE_OBJECTS, "/Root 4 0 R");
+    let file = PdfFile::parse(&pdf).unwrap();
+    assert!(file.has_xref_stream);
+    let pages = file.pages().unwrap();
+    assert_eq!(pages.iter().map(|p| p.0).collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!(pages[1].1.get(b"Title"), Some(&Object::String(b"two".to_vec())));
+}
+
+#[test]
+fn update_appends_section_of_the_same_kind() {
+    for &streams in &[false, true] {
+        let pdf = if streams {
+            xref_stream_fixture(&FIXTURE_OBJECTS, "/Root 4 0 R")
+        } else {
+            classic_fixture(&FIXTURE_OBJECTS, "/Root 4 0 R")
+        };
+        let updated = {
+            let file = PdfFile::parse(&pdf).unwrap();
+            let mut update = Update::new(&file);
+            let mut page = file.object(3).unwrap().into_dict().unwrap();
+            page.set(b"Title", Object::String(b"new".to_vec()));
+            update.set_object(3, &Object::Dict(page));
+            let extra = update.new_object();
+            update.set_stream(extra, Dict::new(), b"data");
+            [&pdf[..], &update.finish(Dict::new())[..]].concat()
+        };
+
+        let file = PdfFile::parse(&updated).unwrap();
+        assert_eq!(file.has_xref_stream, streams);
+        assert_eq!(file.trailer.get(b"Root"), Some(&Object::Ref(4, 0)));
+        let pages = file.pages().unwrap();
+        assert_eq!(pages[0].1.get(b"Title"), None);
+        assert_eq!(pages[1].1.get(b"Title"), Some(&Object::String(b"new".to_vec())));
+        let size = file.trailer.get(b"Size").unwrap().as_int().unwrap() as u32;
+        assert!(file.object(size - 1).is_some());
+    }
+}
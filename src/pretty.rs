@@ -14,6 +14,8 @@
 use std::fmt::Write;
 use std::rc::Rc;
 
+use symbol::Symbol;
+
 // The compiler is wrong, this function *is* used, from the macro at the end of
 // this file. And that macro itself is also used, in tests.
 #[allow(dead_code)]
@@ -56,6 +58,12 @@ impl<'a> Print for &'a str {
     }
 }
 
+impl Print for Symbol {
+    fn print(&self, f: &mut Formatter) {
+        f.target.push_str(&self.as_str());
+    }
+}
+
 impl Print for i32 {
     fn print(&self, f: &mut Formatter) {
         write!(&mut f.target, "{}", self).unwrap();
@@ -0,0 +1,107 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module measures where evaluation spends its time.
+//!
+//! Most documents evaluate quickly, but a single slide with a long code
+//! listing or a large image can dominate the build. A profile records the time
+//! spent in top-level statements and in builtin functions, to find such slides.
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+/// The number of times something ran, and the total time it took.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Timing {
+    pub count: u64,
+    pub time: Duration,
+}
+
+/// Time spent in evaluation, collected while evaluating.
+pub struct Profile {
+    enabled: bool,
+    statements: RefCell<Vec<(String, Duration)>>,
+    builtins: RefCell<Vec<(String, Timing)>>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            enabled: true,
+            statements: RefCell::new(Vec::new()),
+            builtins: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Return a profile that records nothing, so that timing can be skipped.
+    pub fn disabled() -> Profile {
+        Profile {
+            enabled: false,
+            ..Profile::new()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Record the time that a top-level statement took to evaluate.
+    pub fn record_statement(&self, label: String, time: Duration) {
+        if self.enabled {
+            self.statements.borrow_mut().push((label, time));
+        }
+    }
+
+    /// Record a call to the builtin function `name`.
+    ///
+    /// The time includes the time spent in functions that the builtin calls.
+    pub fn record_builtin(&self, name: &str, time: Duration) {
+        if !self.enabled {
+            return
+        }
+        let mut builtins = self.builtins.borrow_mut();
+        if let Some(&mut (_, ref mut timing)) = builtins.iter_mut().find(|b| b.0 == name) {
+            timing.count += 1;
+            timing.time += time;
+            return
+        }
+        builtins.push((name.to_string(), Timing { count: 1, time: time }));
+    }
+
+    /// Return the top-level statements in order of evaluation.
+    pub fn statements(&self) -> Vec<(String, Duration)> {
+        self.statements.borrow().clone()
+    }
+
+    /// Return the time per builtin, the most expensive one first.
+    pub fn builtins(&self) -> Vec<(String, Timing)> {
+        let mut builtins = self.builtins.borrow().clone();
+        builtins.sort_by(|a, b| b.1.time.cmp(&a.1.time));
+        builtins
+    }
+}
+
+#[test]
+fn profile_sums_calls_per_builtin() {
+    let profile = Profile::new();
+    profile.record_builtin("t", Duration::from_millis(3));
+    profile.record_builtin("image", Duration::from_millis(10));
+    profile.record_builtin("t", Duration::from_millis(4));
+    let builtins = profile.builtins();
+    assert_eq!(builtins[0].0, "image");
+    assert_eq!(builtins[1].0, "t");
+    assert_eq!(builtins[1].1, Timing { count: 2, time: Duration::from_millis(7) });
+}
+
+#[test]
+fn profile_disabled_records_nothing() {
+    let profile = Profile::disabled();
+    profile.record_statement("slide 1".to_string(), Duration::from_millis(1));
+    profile.record_builtin("t", Duration::from_millis(1));
+    assert!(profile.statements().is_empty());
+    assert!(profile.builtins().is_empty());
+}
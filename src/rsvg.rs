@@ -8,7 +8,6 @@
 use cairo::{Cairo, cairo_t};
 use std::fs;
 use std::io::{BufRead, BufReader};
-use std::mem;
 use std::os::raw::{c_void, c_uchar, c_int, c_ulong};
 use std::path::Path;
 use std::ptr;
@@ -116,7 +115,12 @@ impl Svg {
 
     pub fn size(&self) -> (u32, u32) {
         unsafe {
-            let mut dims: RsvgDimensionData = mem::uninitialized();
+            let mut dims = RsvgDimensionData {
+                width: 0,
+                height: 0,
+                em: 0.0,
+                ex: 0.0,
+            };
             rsvg_handle_get_dimensions(self.handle, &mut dims);
             (dims.width as u32, dims.height as u32)
         }
@@ -125,7 +129,7 @@ impl Svg {
 
 impl Drop for Svg {
     fn drop(&mut self) {
-        unsafe { g_object_unref(mem::transmute(self.handle)) }
+        unsafe { g_object_unref(self.handle as *mut c_void) }
     }
 }
 
@@ -136,7 +140,7 @@ impl Clone for Svg {
         // can produce two mutable pointers to the same RsvgHandle in this way,
         // but this is fine because we do not mutate the object after
         // construction. (This assumes that drawing does not mutate.)
-        unsafe { g_object_ref(mem::transmute(self.handle)) }
+        unsafe { g_object_ref(self.handle as *mut c_void) }
         Svg {
             handle: self.handle,
         }
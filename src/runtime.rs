@@ -5,18 +5,25 @@
 // it under the terms of the GNU General Public License version 3. A copy
 // of the License is available in the root of the repository.
 
+#[cfg(feature = "native")]
 use freetype;
+use std::cmp;
+use std::fmt;
 use std::collections::HashMap;
+#[cfg(feature = "native")]
 use std::collections::hash_map::Entry;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use ast::{FnDef, Idents};
 use builtins;
-use elements::{Color, Element, PlacedElement, Vec2};
+use elements::{Affine, Color, Element, Gradient, GradientKind, Paint, PlacedElement, Vec2};
 use error::{Error, Result};
+#[cfg(feature = "native")]
 use fontconfig;
 use names;
 use pretty::{Formatter, Print};
+use symbol::Symbol;
 use types::{LenDim, ValType};
 
 // TODO: Put that somewhere else.
@@ -32,6 +39,10 @@ pub enum Val<'a> {
     Frame(Rc<Frame<'a>>),
     FnExtrin(&'a FnDef<'a>),
     FnIntrin(Builtin),
+    Transform(Affine),
+    Gradient(Gradient),
+    /// The absence of a value, `none`.
+    None,
 }
 
 #[derive(Clone)]
@@ -53,7 +64,10 @@ pub struct Frame<'a> {
 
 #[derive(Clone)]
 pub struct Env<'a> {
-    bindings: HashMap<&'a str, Val<'a>>,
+    /// The bindings of this scope, keyed by the symbol of the variable name.
+    /// Values are reference counted, so cloning an environment (which happens
+    /// for every block and function call) does not clone the values.
+    bindings: HashMap<Symbol, Rc<Val<'a>>>,
 }
 
 #[derive(Clone)]
@@ -64,9 +78,13 @@ pub struct BoundingBox {
     pub height: f64,
 }
 
+/// The elements visible at one step of a frame.
+///
+/// The elements are shared between clones, and copied only when a clone is
+/// modified, so a subframe can be reused in a transformed frame cheaply.
 #[derive(Clone)]
 pub struct Subframe {
-    elements: Vec<PlacedElement>,
+    elements: Arc<Vec<PlacedElement>>,
 }
 
 /// A "builtin" function is a function that takes an environment and a vector of
@@ -75,11 +93,16 @@ pub struct Subframe {
 pub struct Builtin(pub for<'i, 'a> fn(&mut ExprInterpreter<'i, 'a>, Vec<Val<'a>>) -> Result<Val<'a>>);
 
 /// Keeps track of loaded Freetype fonts, indexed by (family name, style) pairs.
+#[cfg(feature = "native")]
 pub struct FontMap {
     freetype: freetype::Library,
     fonts: HashMap<(String, String), freetype::Face>,
 }
 
+/// Without the native feature there is no font backend, so there are no fonts.
+#[cfg(not(feature = "native"))]
+pub struct FontMap;
+
 impl<'a> Val<'a> {
     pub fn get_type(&self) -> ValType {
         match *self {
@@ -91,28 +114,35 @@ impl<'a> Val<'a> {
             Val::Frame(..) => ValType::Frame,
             Val::FnExtrin(..) => ValType::Fn,
             Val::FnIntrin(..) => ValType::Fn,
+            Val::Transform(..) => ValType::Transform,
+            Val::Gradient(..) => ValType::Gradient,
+            Val::None => ValType::None,
         }
     }
 
     /// Look up a possibly nested field.
-    pub fn lookup(&self, idents: &Idents<'a>) -> Result<Val<'a>> {
-        if idents.0.is_empty() { return Ok(self.clone()) }
-
-        match (self, idents.0[0]) {
-            (&Val::Frame(ref frame), _) => frame.lookup(idents),
-            (&Val::Coord(x, _, d), "x") => Ok(Val::Num(x, d)),
-            (&Val::Coord(_, y, d), "y") => Ok(Val::Num(y, d)),
+    ///
+    /// The path is a slice rather than `Idents`, so nested lookups can recurse
+    /// on the tail of the path without allocating a new vector at every level.
+    /// Fields that are variables of a frame are returned without a copy.
+    pub fn lookup(&self, path: &[Symbol]) -> Result<Rc<Val<'a>>> {
+        if path.is_empty() { return Ok(Rc::new(self.clone())) }
+
+        match (self, path[0]) {
+            (&Val::Frame(ref frame), _) => frame.lookup(path),
+            (&Val::Coord(x, _, d), names::x) => Val::Num(x, d).lookup(&path[1..]),
+            (&Val::Coord(_, y, d), names::y) => Val::Num(y, d).lookup(&path[1..]),
             // TODO: Could add string length, num dimension, etc. But I could
             // also provide builtin functions for those. What is the best way to
             // go there?
             _ => {
                 let mut f = Formatter::new();
                 f.print("Type error while reading field '");
-                f.print(idents);
+                f.print(&Idents(path.to_vec()));
                 f.print("'. '");
                 f.print(self);
                 f.print("' does not have a field '");
-                f.print(idents.0[0]);
+                f.print(path[0]);
                 f.print("'.");
                 Err(Error::Other(f.into_string()))
             }
@@ -149,11 +179,11 @@ impl<'a> Frame<'a> {
     /// frame's environment. However, there are a few fields (such as `width`)
     /// which are provided by the runtime, and this function computes those.
     /// Variables with the same names cannot be assigned to to prevent
-    /// unexpected shadowing. (TODO: Enforce that in assignment.)
-    pub fn lookup(&self, idents: &Idents<'a>) -> Result<Val<'a>> {
-        assert!(idents.0.len() > 0);
+    /// unexpected shadowing.
+    pub fn lookup(&self, path: &[Symbol]) -> Result<Rc<Val<'a>>> {
+        assert!(path.len() > 0);
 
-        let value = match idents.0[0] {
+        let value = match path[0] {
             // When the identifier matches one of the read-only fields, we
             // compute it here on the fly.
             names::width => Val::Num(self.bounding_box.width, 1),
@@ -162,19 +192,23 @@ impl<'a> Frame<'a> {
             names::offset => Val::Coord(self.bounding_box.x, self.bounding_box.y, 1),
             // If the identifier does not refer to a generated read-only field,
             // look up in the environment.
-            _ => return self.env.lookup(idents),
+            _ => return self.env.lookup_path(path),
         };
 
-        if idents.0.len() > 1 {
-            let tail = Idents(idents.0.iter().cloned().skip(1).collect());
-            value.lookup(&tail)
-        } else {
-            Ok(value)
-        }
+        value.lookup(&path[1..])
+    }
+
+    pub fn put_in_env(&mut self, symbol: Symbol, val: Val<'a>) {
+        self.env.put(symbol, val);
     }
 
-    pub fn put_in_env(&mut self, ident: &'a str, val: Val<'a>) {
-        self.env.put(ident, val);
+    pub fn remove_from_env(&mut self, symbol: Symbol) {
+        self.env.remove(symbol);
+    }
+
+    /// Replace the environment, for example to undo assignments.
+    pub fn set_env(&mut self, env: Env<'a>) {
+        self.env = env;
     }
 
     pub fn get_subframes(&self) -> &[Subframe] {
@@ -189,6 +223,46 @@ impl<'a> Frame<'a> {
         self.subframes.push(subframe);
     }
 
+    /// Place the subframes of `content` onto this frame, starting at subframe
+    /// `first`, and offset by `offset`.
+    ///
+    /// A subframe contains everything that is visible at that step, and after
+    /// its last step, a frame stays in its final state. Therefore subframes
+    /// that need to be added start out as a copy of the last one, and the
+    /// last subframe of `content` is placed on all remaining subframes.
+    pub fn place_subframes(&mut self, first: usize, content: &Frame, offset: Vec2) {
+        let content_sfs = content.get_subframes();
+        if content_sfs.len() == 0 {
+            return
+        }
+
+        while self.subframes.len() < first + content_sfs.len() {
+            let subframe = self.subframes.last().cloned().unwrap_or_else(Subframe::new);
+            self.subframes.push(subframe);
+        }
+
+        for (i, dest_sf) in self.subframes.iter_mut().enumerate().skip(first) {
+            let src_sf = &content_sfs[cmp::min(i - first, content_sfs.len() - 1)];
+            for pe in src_sf.get_elements() {
+                dest_sf.place_element(offset + pe.position, pe.element.clone());
+            }
+        }
+    }
+
+    /// Return a copy of this frame with `other` adjoined at the anchor.
+    ///
+    /// The subframes of `other` are placed starting at subframe `first`. At 0,
+    /// the steps of the two frames are aligned. At the number of subframes of
+    /// this frame, the steps of `other` follow after the steps of this frame.
+    pub fn adjoin(&self, other: &Frame<'a>, first: usize) -> Frame<'a> {
+        let mut frame = self.clone();
+        let anchor = self.anchor;
+        frame.place_subframes(first, other, anchor);
+        frame.anchor = anchor + other.anchor;
+        frame.union_bounding_box(&other.bounding_box.offset(anchor));
+        frame
+    }
+
     pub fn place_element_on_last_subframe(&mut self, position: Vec2, elem: Element) {
         if self.subframes.len() == 0 {
             self.subframes.push(Subframe::new());
@@ -221,107 +295,306 @@ impl<'a> Frame<'a> {
 
 impl<'a> Env<'a> {
     pub fn new() -> Env<'a> {
-        let mut bindings = HashMap::new();
+        let mut env = Env { bindings: HashMap::new() };
         // Default font size is 0.1h.
-        bindings.insert(names::font_size, Val::Num(108.0, 1));
+        env.put(names::font_size, Val::Num(108.0, 1));
         // The default font is "sans roman", which is usually DejaVu Sans Book.
-        bindings.insert(names::font_family, Val::Str("sans".to_string()));
-        bindings.insert(names::font_style, Val::Str("roman".to_string()));
-        bindings.insert(names::font_features, Val::List(Vec::new()));
-        bindings.insert(names::fill_circle, Val::FnIntrin(Builtin(builtins::fill_circle)));
-        bindings.insert(names::fill_curve, Val::FnIntrin(Builtin(builtins::fill_curve)));
-        bindings.insert(names::fill_polygon, Val::FnIntrin(Builtin(builtins::fill_polygon)));
-        bindings.insert(names::fill_rectangle, Val::FnIntrin(Builtin(builtins::fill_rectangle)));
-        bindings.insert(names::stroke_circle, Val::FnIntrin(Builtin(builtins::stroke_circle)));
-        bindings.insert(names::stroke_curve, Val::FnIntrin(Builtin(builtins::stroke_curve)));
-        bindings.insert(names::stroke_polygon, Val::FnIntrin(Builtin(builtins::stroke_polygon)));
-        bindings.insert(names::stroke_rectangle, Val::FnIntrin(Builtin(builtins::stroke_rectangle)));
-        bindings.insert(names::text_align, Val::Str("left".to_string()));
-        bindings.insert(names::line_height, Val::Num(128.0, 1));
-        bindings.insert(names::line_width, Val::Num(10.8, 1));
-        bindings.insert(names::canvas_size, Val::Coord(1920.0, 1080.0, 0));
-        bindings.insert(names::color, Val::Col(Color::new(0.0, 0.0, 0.0)));
-        bindings.insert(names::at, Val::FnIntrin(Builtin(builtins::at)));
-        bindings.insert(names::fit, Val::FnIntrin(Builtin(builtins::fit)));
-        bindings.insert(names::glyph, Val::FnIntrin(Builtin(builtins::glyph)));
-        bindings.insert(names::hyperlink, Val::FnIntrin(Builtin(builtins::hyperlink)));
-        bindings.insert(names::image, Val::FnIntrin(Builtin(builtins::image)));
-        bindings.insert(names::line, Val::FnIntrin(Builtin(builtins::line)));
-        bindings.insert(names::sqrt, Val::FnIntrin(Builtin(builtins::sqrt)));
-        bindings.insert(names::str, Val::FnIntrin(Builtin(builtins::str)));
-        bindings.insert(names::t, Val::FnIntrin(Builtin(builtins::t)));
-        Env { bindings: bindings }
-    }
-
-    pub fn lookup(&self, idents: &Idents<'a>) -> Result<Val<'a>> {
+        env.put(names::font_family, Val::Str("sans".to_string()));
+        env.put(names::font_style, Val::Str("roman".to_string()));
+        env.put(names::font_features, Val::List(Vec::new()));
+        env.put(names::fill_circle, Val::FnIntrin(Builtin(builtins::fill_circle)));
+        env.put(names::fill_curve, Val::FnIntrin(Builtin(builtins::fill_curve)));
+        env.put(names::fill_polygon, Val::FnIntrin(Builtin(builtins::fill_polygon)));
+        env.put(names::fill_rectangle, Val::FnIntrin(Builtin(builtins::fill_rectangle)));
+        env.put(names::stroke_circle, Val::FnIntrin(Builtin(builtins::stroke_circle)));
+        env.put(names::stroke_curve, Val::FnIntrin(Builtin(builtins::stroke_curve)));
+        env.put(names::smooth_curve, Val::FnIntrin(Builtin(builtins::smooth_curve)));
+        env.put(names::stroke_polygon, Val::FnIntrin(Builtin(builtins::stroke_polygon)));
+        env.put(names::stroke_rectangle, Val::FnIntrin(Builtin(builtins::stroke_rectangle)));
+        env.put(names::linear_gradient, Val::FnIntrin(Builtin(builtins::linear_gradient)));
+        env.put(names::radial_gradient, Val::FnIntrin(Builtin(builtins::radial_gradient)));
+        env.put(names::text_align, Val::Str("left".to_string()));
+        env.put(names::text_language, Val::Str(String::new()));
+        env.put(names::text_script, Val::Str(String::new()));
+        env.put(names::line_height, Val::Num(128.0, 1));
+        env.put(names::line_width, Val::Num(10.8, 1));
+        env.put(names::background_image, Val::FnIntrin(Builtin(builtins::background_image)));
+        env.put(names::canvas_size, Val::Coord(1920.0, 1080.0, 0));
+        env.put(names::bleed, Val::Num(0.0, 1));
+        env.put(names::baseline_grid, Val::Num(0.0, 1));
+        env.put(names::crop_marks, Val::Num(0.0, 1));
+        env.put(names::current_section, Val::Str(String::new()));
+        env.put(names::section_index, Val::Num(0.0, 0));
+        env.put(names::section_count, Val::Num(0.0, 0));
+        env.put(names::color, Val::Col(Color::new(0.0, 0.0, 0.0)));
+        env.put(names::at, Val::FnIntrin(Builtin(builtins::at)));
+        env.put(names::fit, Val::FnIntrin(Builtin(builtins::fit)));
+        env.put(names::fits, Val::FnIntrin(Builtin(builtins::fits)));
+        env.put(names::clip_or_warn, Val::FnIntrin(Builtin(builtins::clip_or_warn)));
+        env.put(names::gap, Val::Num(0.0, 1));
+        env.put(names::above, Val::FnIntrin(Builtin(builtins::above)));
+        env.put(names::below, Val::FnIntrin(Builtin(builtins::below)));
+        env.put(names::left_of, Val::FnIntrin(Builtin(builtins::left_of)));
+        env.put(names::right_of, Val::FnIntrin(Builtin(builtins::right_of)));
+        env.put(names::adjoin_parallel, Val::FnIntrin(Builtin(builtins::adjoin_parallel)));
+        env.put(names::adjoin_sequential, Val::FnIntrin(Builtin(builtins::adjoin_sequential)));
+        env.put(names::adjoin_all, Val::FnIntrin(Builtin(builtins::adjoin_all)));
+        env.put(names::place_all, Val::FnIntrin(Builtin(builtins::place_all)));
+        env.put(names::pad, Val::FnIntrin(Builtin(builtins::pad)));
+        env.put(names::pad_left, Val::FnIntrin(Builtin(builtins::pad_left)));
+        env.put(names::pad_right, Val::FnIntrin(Builtin(builtins::pad_right)));
+        env.put(names::pad_top, Val::FnIntrin(Builtin(builtins::pad_top)));
+        env.put(names::pad_bottom, Val::FnIntrin(Builtin(builtins::pad_bottom)));
+        env.put(names::opacity, Val::FnIntrin(Builtin(builtins::opacity)));
+        env.put(names::boxed, Val::FnIntrin(Builtin(builtins::boxed)));
+        env.put(names::todo, Val::FnIntrin(Builtin(builtins::todo)));
+        env.put(names::highlight, Val::FnIntrin(Builtin(builtins::highlight)));
+        env.put(names::symbol, Val::FnIntrin(Builtin(builtins::symbol)));
+        env.put(names::tile, Val::FnIntrin(Builtin(builtins::tile)));
+        env.put(names::glyph, Val::FnIntrin(Builtin(builtins::glyph)));
+        env.put(names::glyph_by_name, Val::FnIntrin(Builtin(builtins::glyph_by_name)));
+        env.put(names::char_glyph, Val::FnIntrin(Builtin(builtins::char_glyph)));
+        env.put(names::place_glyphs, Val::FnIntrin(Builtin(builtins::place_glyphs)));
+        env.put(names::hyperlink, Val::FnIntrin(Builtin(builtins::hyperlink)));
+        env.put(names::link, Val::FnIntrin(Builtin(builtins::link)));
+        env.put(names::auto_toc, Val::FnIntrin(Builtin(builtins::auto_toc)));
+        env.put(names::definition_list, Val::FnIntrin(Builtin(builtins::definition_list)));
+        env.put(names::image, Val::FnIntrin(Builtin(builtins::image)));
+        env.put(names::scene, Val::FnIntrin(Builtin(builtins::scene)));
+        env.put(names::external_svg, Val::FnIntrin(Builtin(builtins::external_svg)));
+        env.put(names::line, Val::FnIntrin(Builtin(builtins::line)));
+        env.put(names::tapered_line, Val::FnIntrin(Builtin(builtins::tapered_line)));
+        env.put(names::snap_to_grid, Val::FnIntrin(Builtin(builtins::snap_to_grid)));
+        env.put(names::baseline_grid_overlay, Val::FnIntrin(Builtin(builtins::baseline_grid_overlay)));
+        env.put(names::sqrt, Val::FnIntrin(Builtin(builtins::sqrt)));
+        env.put(names::as_len, Val::FnIntrin(Builtin(builtins::as_len)));
+        env.put(names::as_num, Val::FnIntrin(Builtin(builtins::as_num)));
+        env.put(names::polar, Val::FnIntrin(Builtin(builtins::polar)));
+        env.put(names::rotate_coord, Val::FnIntrin(Builtin(builtins::rotate_coord)));
+        env.put(names::rotate, Val::FnIntrin(Builtin(builtins::rotate)));
+        env.put(names::scale, Val::FnIntrin(Builtin(builtins::scale)));
+        env.put(names::translate, Val::FnIntrin(Builtin(builtins::translate)));
+        env.put(names::transform, Val::FnIntrin(Builtin(builtins::transform)));
+        env.put(names::on_step, Val::FnIntrin(Builtin(builtins::on_step)));
+        env.put(names::str, Val::FnIntrin(Builtin(builtins::str)));
+        env.put(names::str_len, Val::FnIntrin(Builtin(builtins::str_len)));
+        env.put(names::decimals, Val::Num(2.0, 0));
+        env.put(names::none, Val::None);
+        env.put(names::getenv, Val::FnIntrin(Builtin(builtins::getenv_disabled)));
+        env.put(names::t, Val::FnIntrin(Builtin(builtins::t)));
+        env.put(names::measure, Val::FnIntrin(Builtin(builtins::measure)));
+        env
+    }
+
+    pub fn lookup(&self, idents: &Idents) -> Result<Rc<Val<'a>>> {
+        self.lookup_path(&idents.0)
+    }
+
+    /// Look up a variable, and possibly fields of it, by a path of names.
+    pub fn lookup_path(&self, path: &[Symbol]) -> Result<Rc<Val<'a>>> {
+        assert!(path.len() > 0);
+        match self.get(path[0]) {
+            Some(val) if path.len() == 1 => Ok(val.clone()),
+            Some(val) => val.lookup(&path[1..]),
+            None => Err(Error::Other(format!("Variable '{}' does not exist.", path[0]))),
+        }
+    }
+
+    /// Return the value bound to a variable, without copying it.
+    pub fn get(&self, symbol: Symbol) -> Option<&Rc<Val<'a>>> {
+        self.bindings.get(&symbol)
+    }
+
+    /// Look up a variable like `lookup`, but return `None` rather than an
+    /// error when the variable does not exist, and also when it is `none`.
+    ///
+    /// Only the variable itself may be missing, a missing field of a value
+    /// that does exist is still an error.
+    pub fn try_lookup(&self, idents: &Idents) -> Result<Option<Rc<Val<'a>>>> {
         assert!(idents.0.len() > 0);
-        match self.bindings.get(idents.0[0]) {
-            Some(val) => {
-                if idents.0.len() == 1 {
-                    Ok(val.clone())
-                } else {
-                    let tail = Idents(idents.0.iter().cloned().skip(1).collect());
-                    val.lookup(&tail)
+        match self.get(idents.0[0]) {
+            Some(val) => match **val {
+                Val::None => Ok(None),
+                _ => {
+                    let field = self.lookup_path(&idents.0)?;
+                    match *field {
+                        Val::None => return Ok(None),
+                        _ => {}
+                    }
+                    Ok(Some(field))
                 }
-            }
-            None => Err(Error::Other(format!("Variable '{}' does not exist.", idents.0[0]))),
+            },
+            None => Ok(None),
         }
     }
 
-    pub fn lookup_num(&self, idents: &Idents<'a>) -> Result<f64> {
-        match self.lookup(idents)? {
+    pub fn lookup_num(&self, idents: &Idents) -> Result<f64> {
+        match *self.lookup(idents)? {
             Val::Num(x, 0) => Ok(x),
-            other => Err(Error::var_type(idents, ValType::Num(0), other.get_type())),
+            ref other => Err(Error::var_type(idents, ValType::Num(0), other.get_type())),
+        }
+    }
+
+    pub fn try_lookup_num(&self, idents: &Idents) -> Result<Option<f64>> {
+        match self.try_lookup(idents)?.as_ref().map(|val| &**val) {
+            Some(&Val::Num(x, 0)) => Ok(Some(x)),
+            Some(other) => Err(Error::var_type(idents, ValType::Num(0), other.get_type())),
+            None => Ok(None),
         }
     }
 
-    pub fn lookup_len(&self, idents: &Idents<'a>) -> Result<f64> {
-        match self.lookup(idents)? {
+    pub fn lookup_len(&self, idents: &Idents) -> Result<f64> {
+        match *self.lookup(idents)? {
             Val::Num(x, 1) => Ok(x),
-            other => Err(Error::var_type(idents, ValType::Num(1), other.get_type())),
+            ref other => Err(Error::var_type(idents, ValType::Num(1), other.get_type())),
+        }
+    }
+
+    pub fn try_lookup_len(&self, idents: &Idents) -> Result<Option<f64>> {
+        match self.try_lookup(idents)?.as_ref().map(|val| &**val) {
+            Some(&Val::Num(x, 1)) => Ok(Some(x)),
+            Some(other) => Err(Error::var_type(idents, ValType::Num(1), other.get_type())),
+            None => Ok(None),
         }
     }
 
-    pub fn lookup_coord_num(&self, idents: &Idents<'a>) -> Result<Vec2> {
-        match self.lookup(idents)? {
+    pub fn lookup_coord_num(&self, idents: &Idents) -> Result<Vec2> {
+        match *self.lookup(idents)? {
             Val::Coord(x, y, 0) => Ok(Vec2::new(x, y)),
-            other => Err(Error::var_type(idents, ValType::Coord(0), other.get_type())),
+            ref other => Err(Error::var_type(idents, ValType::Coord(0), other.get_type())),
         }
     }
 
-    pub fn lookup_coord_len(&self, idents: &Idents<'a>) -> Result<Vec2> {
-        match self.lookup(idents)? {
+    pub fn try_lookup_coord_num(&self, idents: &Idents) -> Result<Option<Vec2>> {
+        match self.try_lookup(idents)?.as_ref().map(|val| &**val) {
+            Some(&Val::Coord(x, y, 0)) => Ok(Some(Vec2::new(x, y))),
+            Some(other) => Err(Error::var_type(idents, ValType::Coord(0), other.get_type())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn lookup_coord_len(&self, idents: &Idents) -> Result<Vec2> {
+        match *self.lookup(idents)? {
             Val::Coord(x, y, 1) => Ok(Vec2::new(x, y)),
-            other => Err(Error::var_type(idents, ValType::Coord(1), other.get_type())),
+            ref other => Err(Error::var_type(idents, ValType::Coord(1), other.get_type())),
         }
     }
 
-    pub fn lookup_color(&self, idents: &Idents<'a>) -> Result<Color> {
-        match self.lookup(idents)? {
+    pub fn try_lookup_coord_len(&self, idents: &Idents) -> Result<Option<Vec2>> {
+        match self.try_lookup(idents)?.as_ref().map(|val| &**val) {
+            Some(&Val::Coord(x, y, 1)) => Ok(Some(Vec2::new(x, y))),
+            Some(other) => Err(Error::var_type(idents, ValType::Coord(1), other.get_type())),
+            None => Ok(None),
+        }
+    }
+
+    /// Return the size that `pw` and `ph` are relative to.
+    ///
+    /// This is `container_size` when it is set, and the canvas size otherwise.
+    pub fn lookup_container_size(&self) -> Result<Vec2> {
+        match self.try_lookup_coord_len(&Idents(vec![names::container_size]))? {
+            Some(size) => Ok(size),
+            None => self.lookup_coord_num(&Idents(vec![names::canvas_size])),
+        }
+    }
+
+    pub fn lookup_color(&self, idents: &Idents) -> Result<Color> {
+        match *self.lookup(idents)? {
             Val::Col(col) => Ok(col),
-            other => Err(Error::var_type(idents, ValType::Color, other.get_type())),
+            ref other => Err(Error::var_type(idents, ValType::Color, other.get_type())),
         }
     }
 
-    pub fn lookup_str(&self, idents: &Idents<'a>) -> Result<String> {
-        match self.lookup(idents)? {
-            Val::Str(s) => Ok(s),
-            other => Err(Error::var_type(idents, ValType::Str, other.get_type())),
+    /// Look up a color or a gradient to fill shapes with.
+    pub fn lookup_paint(&self, idents: &Idents) -> Result<Paint> {
+        match *self.lookup(idents)? {
+            Val::Col(col) => Ok(Paint::Solid(col)),
+            Val::Gradient(ref gradient) => Ok(Paint::Gradient(gradient.clone())),
+            ref other => Err(Error::var_type(idents, ValType::Color, other.get_type())),
         }
     }
 
-    pub fn lookup_list(&self, idents: &Idents<'a>) -> Result<Vec<Val<'a>>> {
-        match self.lookup(idents)? {
-            Val::List(elems) => Ok(elems),
-            other => Err(Error::var_type(idents, ValType::List, other.get_type())),
+    pub fn try_lookup_color(&self, idents: &Idents) -> Result<Option<Color>> {
+        match self.try_lookup(idents)?.as_ref().map(|val| &**val) {
+            Some(&Val::Col(col)) => Ok(Some(col)),
+            Some(other) => Err(Error::var_type(idents, ValType::Color, other.get_type())),
+            None => Ok(None),
         }
     }
 
-    pub fn put(&mut self, ident: &'a str, val: Val<'a>) {
+    pub fn lookup_str(&self, idents: &Idents) -> Result<String> {
+        match *self.lookup(idents)? {
+            Val::Str(ref s) => Ok(s.clone()),
+            ref other => Err(Error::var_type(idents, ValType::Str, other.get_type())),
+        }
+    }
+
+    pub fn try_lookup_str(&self, idents: &Idents) -> Result<Option<String>> {
+        match self.try_lookup(idents)?.as_ref().map(|val| &**val) {
+            Some(&Val::Str(ref s)) => Ok(Some(s.clone())),
+            Some(other) => Err(Error::var_type(idents, ValType::Str, other.get_type())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn lookup_frame(&self, idents: &Idents) -> Result<Rc<Frame<'a>>> {
+        match *self.lookup(idents)? {
+            Val::Frame(ref frame) => Ok(frame.clone()),
+            ref other => Err(Error::var_type(idents, ValType::Frame, other.get_type())),
+        }
+    }
+
+    pub fn try_lookup_frame(&self, idents: &Idents) -> Result<Option<Rc<Frame<'a>>>> {
+        match self.try_lookup(idents)?.as_ref().map(|val| &**val) {
+            Some(&Val::Frame(ref frame)) => Ok(Some(frame.clone())),
+            Some(other) => Err(Error::var_type(idents, ValType::Frame, other.get_type())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn lookup_list(&self, idents: &Idents) -> Result<Vec<Val<'a>>> {
+        match *self.lookup(idents)? {
+            Val::List(ref elems) => Ok(elems.clone()),
+            ref other => Err(Error::var_type(idents, ValType::List, other.get_type())),
+        }
+    }
+
+    pub fn try_lookup_list(&self, idents: &Idents) -> Result<Option<Vec<Val<'a>>>> {
+        match self.try_lookup(idents)?.as_ref().map(|val| &**val) {
+            Some(&Val::List(ref elems)) => Ok(Some(elems.clone())),
+            Some(other) => Err(Error::var_type(idents, ValType::List, other.get_type())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put(&mut self, symbol: Symbol, val: Val<'a>) {
         // TODO: Validate types for known variables, disallow assigning to
         // constants.
-        self.bindings.insert(ident, val);
+        self.bindings.insert(symbol, Rc::new(val));
+    }
+
+    pub fn remove(&mut self, symbol: Symbol) {
+        self.bindings.remove(&symbol);
     }
+
+    /// Iterate over the bound variables and their values.
+    pub fn iter<'e>(&'e self) -> impl Iterator<Item = (Symbol, &'e Val<'a>)> + 'e {
+        self.bindings.iter().map(|(&symbol, val)| (symbol, &**val))
+    }
+}
+
+#[test]
+fn env_try_lookup_treats_none_as_unset() {
+    let mut env = Env::new();
+    let label = Symbol::intern("label");
+    let missing = Symbol::intern("missing");
+    env.put(names::gap, Val::Num(2.0, 1));
+    env.put(label, Val::None);
+    assert_eq!(env.try_lookup_len(&Idents(vec![names::gap])).unwrap(), Some(2.0));
+    assert_eq!(env.try_lookup_len(&Idents(vec![label])).unwrap(), None);
+    assert_eq!(env.try_lookup_len(&Idents(vec![missing])).unwrap(), None);
+    assert!(env.try_lookup_str(&Idents(vec![names::gap])).is_err());
+    assert!(env.lookup_len(&Idents(vec![label])).is_err());
 }
 
 impl BoundingBox {
@@ -389,7 +662,7 @@ impl BoundingBox {
 impl Subframe {
     pub fn new() -> Subframe {
         Subframe {
-            elements: Vec::new(),
+            elements: Arc::new(Vec::new()),
         }
     }
 
@@ -397,15 +670,36 @@ impl Subframe {
         &self.elements
     }
 
+    /// Return the elements of this subframe without copying them.
+    pub fn share_elements(&self) -> Arc<Vec<PlacedElement>> {
+        self.elements.clone()
+    }
+
     pub fn place_element(&mut self, position: Vec2, elem: Element) {
         let placed = PlacedElement {
             position: position,
             element: elem,
         };
-        self.elements.push(placed);
+        Arc::make_mut(&mut self.elements).push(placed);
     }
 }
 
+#[test]
+fn subframe_clone_shares_elements_until_modified() {
+    use elements::{Hyperlink, LinkTarget};
+    let link = || Element::Hyperlink(Hyperlink {
+        size: Vec2::zero(),
+        target: LinkTarget::Slide(1),
+    });
+    let mut original = Subframe::new();
+    original.place_element(Vec2::zero(), link());
+    let mut copy = original.clone();
+    assert!(Arc::ptr_eq(&original.share_elements(), &copy.share_elements()));
+    copy.place_element(Vec2::zero(), link());
+    assert_eq!(original.get_elements().len(), 1);
+    assert_eq!(copy.get_elements().len(), 2);
+}
+
 impl Clone for Builtin {
     fn clone(&self) -> Builtin {
         let Builtin(x) = *self;
@@ -413,6 +707,14 @@ impl Clone for Builtin {
     }
 }
 
+#[cfg(not(feature = "native"))]
+impl FontMap {
+    pub fn new() -> FontMap {
+        FontMap
+    }
+}
+
+#[cfg(feature = "native")]
 impl FontMap {
     pub fn new() -> FontMap {
         FontMap {
@@ -457,6 +759,65 @@ impl FontMap {
 
 // Pretty printers for values and interpreter data structures.
 
+/// Compare values structurally.
+///
+/// Frames and functions have no structural equality; they are equal only when
+/// they are the same frame or function. Values from different evaluations can
+/// be compared, so a test can compare against a value it constructed itself.
+impl<'a, 'b> PartialEq<Val<'b>> for Val<'a> {
+    fn eq(&self, other: &Val<'b>) -> bool {
+        match (self, other) {
+            (&Val::Num(x, d), &Val::Num(y, e)) => x == y && d == e,
+            (&Val::Str(ref x), &Val::Str(ref y)) => x == y,
+            (&Val::Col(x), &Val::Col(y)) => x == y,
+            (&Val::Coord(x0, y0, d), &Val::Coord(x1, y1, e)) => x0 == x1 && y0 == y1 && d == e,
+            (&Val::List(ref xs), &Val::List(ref ys)) => {
+                xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| x == y)
+            }
+            (&Val::Frame(ref x), &Val::Frame(ref y)) => {
+                &**x as *const Frame as usize == &**y as *const Frame as usize
+            }
+            (&Val::FnExtrin(x), &Val::FnExtrin(y)) => {
+                x as *const FnDef as usize == y as *const FnDef as usize
+            }
+            (&Val::FnIntrin(ref x), &Val::FnIntrin(ref y)) => x.0 as usize == y.0 as usize,
+            (&Val::Transform(x), &Val::Transform(y)) => x == y,
+            (&Val::Gradient(x), &Val::Gradient(y)) => x == y,
+            (&Val::None, &Val::None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Val<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Val::Num(x, d) => write!(f, "Num({:?}, {})", x, d),
+            Val::Str(ref s) => write!(f, "Str({:?})", s),
+            Val::Col(c) => write!(f, "Col({:?}, {:?}, {:?}, {:?})", c.r, c.g, c.b, c.a),
+            Val::Coord(x, y, d) => write!(f, "Coord({:?}, {:?}, {})", x, y, d),
+            Val::List(ref xs) => write!(f, "List({:?})", xs),
+            Val::Frame(ref frame) => write!(f, "Frame(<{} subframes>)", frame.subframes.len()),
+            Val::FnExtrin(..) => write!(f, "FnExtrin(..)"),
+            Val::FnIntrin(..) => write!(f, "FnIntrin(..)"),
+            Val::Transform(m) => write!(f, "Transform({:?})", m),
+            Val::Gradient(..) => write!(f, "Gradient(..)"),
+            Val::None => write!(f, "None"),
+        }
+    }
+}
+
+#[test]
+fn val_eq_compares_structurally() {
+    let list = Val::List(vec![Val::Num(1.0, 0), Val::Coord(2.0, 3.0, 1)]);
+    assert_eq!(list, list.clone());
+    assert_ne!(list, Val::List(vec![Val::Num(1.0, 0)]));
+    assert_ne!(Val::Num(1.0, 0), Val::Num(1.0, 1));
+    assert_ne!(Val::Num(1.0, 0), Val::Str("1".to_string()));
+    assert_eq!(Val::Col(Color::new(1.0, 0.0, 0.0)), Val::Col(Color::new(1.0, 0.0, 0.0)));
+    assert_eq!(format!("{:?}", list), "List([Num(1.0, 0), Coord(2.0, 3.0, 1)])");
+}
+
 impl<'a> Print for Val<'a> {
     fn print(&self, f: &mut Formatter) {
         match *self {
@@ -470,14 +831,9 @@ impl<'a> Print for Val<'a> {
                 f.print(&s[..]); // TODO: Escaping.
                 f.print("\"");
             }
-            Val::Col(ref col) => {
-                f.print("(");
-                f.print(col.r);
-                f.print(", ");
-                f.print(col.g);
-                f.print(", ");
-                f.print(col.b);
-                f.print(") : color");
+            Val::Col(col) => {
+                print_color(f, col);
+                f.print(" : color");
             }
             Val::List(ref elements) => {
                 f.print("[");
@@ -504,6 +860,40 @@ impl<'a> Print for Val<'a> {
             Val::FnIntrin(..) => {
                 f.print("function(...) { <built-in> }");
             }
+            Val::Transform(m) => {
+                // Print the top two rows of the matrix, the bottom row of an
+                // affine transform is always (0, 0, 1).
+                f.print("[");
+                f.print(m.xx);
+                f.print(", ");
+                f.print(m.xy);
+                f.print(", ");
+                f.print(m.x0);
+                f.print("; ");
+                f.print(m.yx);
+                f.print(", ");
+                f.print(m.yy);
+                f.print(", ");
+                f.print(m.y0);
+                f.print("] : transform");
+            }
+            Val::Gradient(ref gradient) => {
+                match gradient.kind {
+                    GradientKind::Linear(..) => f.print("linear_gradient("),
+                    GradientKind::Radial => f.print("radial_gradient("),
+                }
+                print_color(f, gradient.from);
+                f.print(", ");
+                print_color(f, gradient.to);
+                if let GradientKind::Linear(angle) = gradient.kind {
+                    f.print(", ");
+                    f.print(angle);
+                }
+                f.print(") : gradient");
+            }
+            Val::None => {
+                f.print("none");
+            }
         }
     }
 }
@@ -518,10 +908,28 @@ impl Print for ValType {
             ValType::List => f.print("list"),
             ValType::Frame => f.print("frame"),
             ValType::Fn => f.print("function"),
+            ValType::Transform => f.print("transform"),
+            ValType::Gradient => f.print("gradient"),
+            ValType::None => f.print("none"),
         }
     }
 }
 
+/// Print the components of a color, and its alpha if it is not opaque.
+fn print_color(f: &mut Formatter, col: Color) {
+    f.print("(");
+    f.print(col.r);
+    f.print(", ");
+    f.print(col.g);
+    f.print(", ");
+    f.print(col.b);
+    if !col.is_opaque() {
+        f.print(", ");
+        f.print(col.a);
+    }
+    f.print(")");
+}
+
 fn print_unit(f: &mut Formatter, d: LenDim) {
     match d {
         -3 => f.print("len⁻³"),
@@ -537,7 +945,7 @@ fn print_unit(f: &mut Formatter, d: LenDim) {
 
 // Print implementation for variable bindings when printing env. Prints of the
 // form "name = value".
-impl<'a> Print for (&'a &'a str, &'a Val<'a>) {
+impl<'b> Print for (Symbol, &'b Val<'b>) {
     fn print(&self, f: &mut Formatter) {
         f.print(self.0);
         f.print(" = ");
@@ -558,7 +966,7 @@ impl<'a> Print for Env<'a> {
     fn print(&self, f: &mut Formatter) {
         f.println("{\n");
         f.indent_more();
-        for binding in self.bindings.iter() {
+        for binding in self.iter() {
             f.println(binding);
             f.print("\n");
         }
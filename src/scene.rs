@@ -0,0 +1,686 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module writes the evaluated slides as a JSON scene graph.
+//!
+//! The scene graph contains the elements that Pris would draw, after layout,
+//! so other programs such as custom renderers or animation pipelines can use
+//! Pris layouts without linking against Pris. Positions are relative to the
+//! enclosing element, and lengths are in canvas units, like `1w` and `1h`.
+//!
+//! Conversely, a list of elements in the same format can be read back, so
+//! other programs can produce geometry for Pris to compose into slides.
+
+use std::io;
+use std::io::Write;
+use std::sync::Arc;
+
+use driver::{PageSetup, Slide};
+use elements::{Affine, Color, Element, FillPolygon, Glyph, Gradient, GradientKind, Hyperlink, LinkTarget, Paint};
+use elements::{PlacedElement, PolygonKind, StrokePolygon, Text, TextCluster, Vec2};
+use error::{Error, Result};
+use json;
+use json::Json;
+use runtime::BoundingBox;
+use uri;
+
+/// The version of the scene format, incremented on incompatible changes.
+const VERSION: u32 = 1;
+
+/// Write a string as a JSON string literal.
+fn write_str<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    write!(out, "\"")?;
+    for ch in s.chars() {
+        match ch {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    write!(out, "\"")
+}
+
+/// Write a number, JSON has no representation for infinity and NaN.
+fn write_num<W: Write>(out: &mut W, x: f64) -> io::Result<()> {
+    if x.is_finite() {
+        write!(out, "{}", x)
+    } else {
+        write!(out, "null")
+    }
+}
+
+fn write_vec2<W: Write>(out: &mut W, v: Vec2) -> io::Result<()> {
+    write!(out, "[")?;
+    write_num(out, v.x)?;
+    write!(out, ", ")?;
+    write_num(out, v.y)?;
+    write!(out, "]")
+}
+
+/// Write a color as a `#rrggbb` string, the precision that pdf output has.
+///
+/// Colors that are not opaque are written as `#rrggbbaa`.
+fn write_color<W: Write>(out: &mut W, color: Color) -> io::Result<()> {
+    let byte = |x: f64| (x.max(0.0).min(1.0) * 255.0).round() as u8;
+    write!(out, "\"#{:02x}{:02x}{:02x}", byte(color.r), byte(color.g), byte(color.b))?;
+    if !color.is_opaque() {
+        write!(out, "{:02x}", byte(color.a))?;
+    }
+    write!(out, "\"")
+}
+
+fn write_vertices<W: Write>(out: &mut W, vertices: &[Vec2], kind: PolygonKind) -> io::Result<()> {
+    let kind = match kind {
+        PolygonKind::Lines => "lines",
+        PolygonKind::Curves => "curves",
+    };
+    write!(out, ", \"kind\": \"{}\", \"vertices\": [", kind)?;
+    for (i, &v) in vertices.iter().enumerate() {
+        if i > 0 {
+            write!(out, ", ")?;
+        }
+        write_vec2(out, v)?;
+    }
+    write!(out, "]")
+}
+
+fn write_elements<W: Write>(out: &mut W, elements: &[PlacedElement], indent: usize) -> io::Result<()> {
+    if elements.is_empty() {
+        return write!(out, "[]")
+    }
+    writeln!(out, "[")?;
+    for (i, pe) in elements.iter().enumerate() {
+        write!(out, "{:1$}", "", indent + 2)?;
+        write_element(out, pe, indent + 2)?;
+        writeln!(out, "{}", if i + 1 < elements.len() { "," } else { "" })?;
+    }
+    write!(out, "{:1$}]", "", indent)
+}
+
+fn write_element<W: Write>(out: &mut W, pe: &PlacedElement, indent: usize) -> io::Result<()> {
+    let kind = match pe.element {
+        Element::Clipped(..) => "clipped",
+        Element::FillPolygon(..) => "fill_polygon",
+        Element::Group(..) | Element::Symbol(..) => "group",
+        Element::Png(..) => "png",
+        Element::StrokePolygon(..) => "stroke_polygon",
+        Element::Svg(..) => "svg",
+        Element::Text(..) => "text",
+        Element::Transformed(..) => "scaled",
+        Element::Affine(..) => "transformed",
+        Element::Opacity(..) => "translucent",
+        Element::Hyperlink(..) => "hyperlink",
+    };
+    write!(out, "{{\"type\": \"{}\", \"position\": ", kind)?;
+    write_vec2(out, pe.position)?;
+    match pe.element {
+        Element::Clipped(ref inner, offset, size) => {
+            write!(out, ", \"clip_offset\": ")?;
+            write_vec2(out, offset)?;
+            write!(out, ", \"clip_size\": ")?;
+            write_vec2(out, size)?;
+            write!(out, ", \"elements\": ")?;
+            write_elements(out, inner, indent)?;
+        }
+        Element::FillPolygon(ref polygon) => {
+            match polygon.paint {
+                Paint::Solid(color) => {
+                    write!(out, ", \"color\": ")?;
+                    write_color(out, color)?;
+                }
+                Paint::Gradient(gradient) => {
+                    match gradient.kind {
+                        GradientKind::Linear(angle) => {
+                            write!(out, ", \"gradient\": {{\"kind\": \"linear\", \"angle\": ")?;
+                            write_num(out, angle)?;
+                        }
+                        GradientKind::Radial => write!(out, ", \"gradient\": {{\"kind\": \"radial\"")?,
+                    }
+                    write!(out, ", \"from\": ")?;
+                    write_color(out, gradient.from)?;
+                    write!(out, ", \"to\": ")?;
+                    write_color(out, gradient.to)?;
+                    write!(out, "}}")?;
+                }
+            }
+            write_vertices(out, &polygon.vertices, polygon.kind)?;
+        }
+        Element::StrokePolygon(ref polygon) => {
+            write!(out, ", \"color\": ")?;
+            write_color(out, polygon.color)?;
+            write!(out, ", \"line_width\": ")?;
+            write_num(out, polygon.line_width)?;
+            write!(out, ", \"close\": {}", polygon.close)?;
+            write_vertices(out, &polygon.vertices, polygon.kind)?;
+        }
+        Element::Group(ref inner) => {
+            write!(out, ", \"elements\": ")?;
+            write_elements(out, inner, indent)?;
+        }
+        Element::Symbol(ref symbol) => {
+            write!(out, ", \"elements\": ")?;
+            write_elements(out, &symbol.elements, indent)?;
+        }
+        Element::Png(ref path) | Element::Svg(ref path) => {
+            write!(out, ", \"path\": ")?;
+            write_str(out, &path.to_string_lossy())?;
+        }
+        Element::Text(ref text) => {
+            write!(out, ", \"color\": ")?;
+            write_color(out, text.color)?;
+            write!(out, ", \"font_family\": ")?;
+            write_str(out, &text.font_family)?;
+            write!(out, ", \"font_style\": ")?;
+            write_str(out, &text.font_style)?;
+            write!(out, ", \"font_size\": ")?;
+            write_num(out, text.font_size)?;
+            write!(out, ", \"text\": ")?;
+            write_str(out, &text.text)?;
+            write!(out, ", \"glyphs\": [")?;
+            for (i, glyph) in text.glyphs.iter().enumerate() {
+                if i > 0 {
+                    write!(out, ", ")?;
+                }
+                write!(out, "[{}, ", glyph.index())?;
+                write_num(out, glyph.position().x)?;
+                write!(out, ", ")?;
+                write_num(out, glyph.position().y)?;
+                write!(out, "]")?;
+            }
+            write!(out, "]")?;
+        }
+        Element::Transformed(ref inner, scale) => {
+            write!(out, ", \"scale\": ")?;
+            write_num(out, scale)?;
+            write!(out, ", \"elements\": ")?;
+            write_elements(out, inner, indent)?;
+        }
+        Element::Affine(ref inner, ref m) => {
+            write!(out, ", \"matrix\": [")?;
+            for (i, &x) in [m.xx, m.yx, m.xy, m.yy, m.x0, m.y0].iter().enumerate() {
+                if i > 0 {
+                    write!(out, ", ")?;
+                }
+                write_num(out, x)?;
+            }
+            write!(out, "], \"elements\": ")?;
+            write_elements(out, inner, indent)?;
+        }
+        Element::Opacity(ref inner, opacity) => {
+            write!(out, ", \"opacity\": ")?;
+            write_num(out, opacity)?;
+            write!(out, ", \"elements\": ")?;
+            write_elements(out, inner, indent)?;
+        }
+        Element::Hyperlink(ref link) => {
+            write!(out, ", \"size\": ")?;
+            write_vec2(out, link.size)?;
+            match link.target {
+                LinkTarget::Uri(ref uri) => {
+                    write!(out, ", \"uri\": ")?;
+                    write_str(out, uri)?;
+                }
+                LinkTarget::Slide(n) => write!(out, ", \"slide\": {}", n)?,
+            }
+        }
+    }
+    write!(out, "}}")
+}
+
+fn write_steps<W: Write>(out: &mut W, steps: &[Vec<PlacedElement>], indent: usize) -> io::Result<()> {
+    if steps.is_empty() {
+        return write!(out, "[]")
+    }
+    writeln!(out, "[")?;
+    for (i, step) in steps.iter().enumerate() {
+        write!(out, "{:1$}", "", indent + 2)?;
+        write_elements(out, step, indent + 2)?;
+        writeln!(out, "{}", if i + 1 < steps.len() { "," } else { "" })?;
+    }
+    write!(out, "{:1$}]", "", indent)
+}
+
+fn write_slide<W: Write>(out: &mut W, slide: &Slide) -> io::Result<()> {
+    writeln!(out, "    {{")?;
+    write!(out, "      \"background_color\": ")?;
+    match slide.background_color() {
+        Some(color) => write_color(out, color)?,
+        None => write!(out, "null")?,
+    }
+    write!(out, ",\n      \"notes\": ")?;
+    write_str(out, slide.notes())?;
+    write!(out, ",\n      \"background\": ")?;
+    write_steps(out, slide.background(), 6)?;
+    write!(out, ",\n      \"steps\": ")?;
+    write_steps(out, slide.steps(), 6)?;
+    write!(out, "\n    }}")
+}
+
+/// Write the scene graph of all slides.
+///
+/// Every slide has a list of steps, and every step is the list of elements
+/// that its page shows. The background frame has steps of its own.
+pub fn write<W: Write>(out: &mut W, slides: &[Slide], page: &PageSetup) -> io::Result<()> {
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"version\": {},", VERSION)?;
+    write!(out, "  \"canvas_size\": ")?;
+    write_vec2(out, page.canvas_size)?;
+    writeln!(out, ",")?;
+    write!(out, "  \"slides\": [")?;
+    for (i, slide) in slides.iter().enumerate() {
+        writeln!(out, "{}", if i > 0 { "," } else { "" })?;
+        write_slide(out, slide)?;
+    }
+    if !slides.is_empty() {
+        write!(out, "\n  ")?;
+    }
+    writeln!(out, "]")?;
+    writeln!(out, "}}")
+}
+
+/// Elements read from a scene file.
+pub struct Fragment {
+    pub elements: Vec<PlacedElement>,
+    /// The bounds of the elements. Text covers the origins of its glyphs.
+    pub bounding_box: BoundingBox,
+    /// The family and style of the fonts that text elements use.
+    pub fonts: Vec<(String, String)>,
+}
+
+/// Return the smallest bounding box that contains the points.
+fn bounds(points: &[Vec2]) -> BoundingBox {
+    let mut min = points.first().cloned().unwrap_or(Vec2::zero());
+    let mut max = min;
+    for p in points {
+        min = Vec2::new(min.x.min(p.x), min.y.min(p.y));
+        max = Vec2::new(max.x.max(p.x), max.y.max(p.y));
+    }
+    BoundingBox::new(min, Vec2::new(max.x - min.x, max.y - min.y))
+}
+
+struct Reader<'p, F> {
+    path: &'p str,
+    load_image: F,
+    fonts: Vec<(String, String)>,
+}
+
+impl<'p, F: FnMut(String) -> Result<(f64, f64, Element)>> Reader<'p, F> {
+    fn invalid<T>(&self, msg: String) -> Result<T> {
+        Err(Error::value(format!("The scene '{}' is invalid. {}", self.path, msg)))
+    }
+
+    fn field<'j>(&self, json: &'j Json, key: &str) -> Result<&'j Json> {
+        match json.get(key) {
+            Some(value) => Ok(value),
+            None => self.invalid(format!("An element has no '{}'.", key)),
+        }
+    }
+
+    fn num(&self, json: &Json, key: &str) -> Result<f64> {
+        match *self.field(json, key)? {
+            Json::Num(x) => Ok(x),
+            _ => self.invalid(format!("The '{}' of an element must be a number.", key)),
+        }
+    }
+
+    fn string(&self, json: &Json, key: &str) -> Result<String> {
+        match *self.field(json, key)? {
+            Json::Str(ref s) => Ok(s.clone()),
+            _ => self.invalid(format!("The '{}' of an element must be a string.", key)),
+        }
+    }
+
+    fn vec2(&self, json: &Json, key: &str) -> Result<Vec2> {
+        match *self.field(json, key)? {
+            Json::Array(ref xs) if xs.len() == 2 => match (&xs[0], &xs[1]) {
+                (&Json::Num(x), &Json::Num(y)) => return Ok(Vec2::new(x, y)),
+                _ => {}
+            },
+            _ => {}
+        }
+        self.invalid(format!("The '{}' of an element must be a list of two numbers.", key))
+    }
+
+    fn vertices(&self, json: &Json) -> Result<(Vec<Vec2>, PolygonKind)> {
+        let kind = match &self.string(json, "kind")?[..] {
+            "lines" => PolygonKind::Lines,
+            "curves" => PolygonKind::Curves,
+            other => return self.invalid(format!("Unknown polygon kind '{}'.", other)),
+        };
+        let points = match *self.field(json, "vertices")? {
+            Json::Array(ref points) => points,
+            _ => return self.invalid("The 'vertices' of a polygon must be a list.".to_string()),
+        };
+        let mut vertices = Vec::with_capacity(points.len());
+        for point in points {
+            match *point {
+                Json::Array(ref xs) if xs.len() == 2 => match (&xs[0], &xs[1]) {
+                    (&Json::Num(x), &Json::Num(y)) => vertices.push(Vec2::new(x, y)),
+                    _ => return self.invalid("A vertex must be a list of two numbers.".to_string()),
+                },
+                _ => return self.invalid("A vertex must be a list of two numbers.".to_string()),
+            }
+        }
+        Ok((vertices, kind))
+    }
+
+    fn color(&self, json: &Json) -> Result<Color> {
+        self.color_field(json, "color")
+    }
+
+    fn color_field(&self, json: &Json, key: &str) -> Result<Color> {
+        let hex = self.string(json, key)?;
+        let is_hex = hex.bytes().skip(1).all(|b| (b as char).is_digit(16));
+        if (hex.len() == 7 || hex.len() == 9) && hex.starts_with('#') && is_hex {
+            let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap() as f64 / 255.0;
+            let alpha = if hex.len() == 9 { byte(7) } else { 1.0 };
+            return Ok(Color::new_rgba(byte(1), byte(3), byte(5), alpha))
+        }
+        self.invalid(format!("The color '{}' is not of the form '#rrggbb' or '#rrggbbaa'.", hex))
+    }
+
+    /// Read the `gradient` of a filled polygon if it has one, or its `color`.
+    fn paint(&self, json: &Json) -> Result<Paint> {
+        let gradient = match json.get("gradient") {
+            Some(gradient) => gradient,
+            None => return self.color(json).map(Paint::Solid),
+        };
+        let kind = match &self.string(gradient, "kind")?[..] {
+            "linear" => GradientKind::Linear(self.num(gradient, "angle")?),
+            "radial" => GradientKind::Radial,
+            other => return self.invalid(format!("Unknown gradient kind '{}'.", other)),
+        };
+        let gradient = Gradient {
+            kind: kind,
+            from: self.color_field(gradient, "from")?,
+            to: self.color_field(gradient, "to")?,
+        };
+        Ok(Paint::Gradient(gradient))
+    }
+
+    fn elements(&mut self, json: &Json) -> Result<(Vec<PlacedElement>, BoundingBox)> {
+        let items = match *json {
+            Json::Array(ref items) => items,
+            _ => return self.invalid("Expected a list of elements.".to_string()),
+        };
+        let mut elements = Vec::with_capacity(items.len());
+        let mut bounding_box = BoundingBox::empty();
+        for item in items {
+            let (pe, bb) = self.element(item)?;
+            elements.push(pe);
+            bounding_box = bounding_box.union(&bb);
+        }
+        Ok((elements, bounding_box))
+    }
+
+    /// Read the elements that a group, or a transformed or clipped element contains.
+    fn children(&mut self, json: &Json) -> Result<(Vec<PlacedElement>, BoundingBox)> {
+        let elements = self.field(json, "elements")?;
+        self.elements(elements)
+    }
+
+    /// Read an element, and return it with its bounds around its position.
+    fn element(&mut self, json: &Json) -> Result<(PlacedElement, BoundingBox)> {
+        let position = self.vec2(json, "position")?;
+        let (element, bb) = match &self.string(json, "type")?[..] {
+            "fill_polygon" => {
+                let (vertices, kind) = self.vertices(json)?;
+                let bb = bounds(&vertices);
+                let polygon = FillPolygon {
+                    paint: self.paint(json)?,
+                    vertices: vertices,
+                    kind: kind,
+                };
+                (Element::FillPolygon(polygon), bb)
+            }
+            "stroke_polygon" => {
+                let (vertices, kind) = self.vertices(json)?;
+                let line_width = self.num(json, "line_width")?;
+                let close = match json.get("close") {
+                    Some(&Json::Bool(close)) => close,
+                    None => false,
+                    Some(..) => return self.invalid("The 'close' of a polygon must be true or false.".to_string()),
+                };
+                let bb = bounds(&vertices);
+                let w = line_width * 0.5;
+                let bb = BoundingBox::new(
+                    Vec2::new(bb.x - w, bb.y - w),
+                    Vec2::new(bb.width + line_width, bb.height + line_width),
+                );
+                let polygon = StrokePolygon {
+                    color: self.color(json)?,
+                    line_width: line_width,
+                    close: close,
+                    vertices: vertices,
+                    kind: kind,
+                };
+                (Element::StrokePolygon(polygon), bb)
+            }
+            "group" => {
+                let (elements, bb) = self.children(json)?;
+                (Element::Group(Arc::new(elements)), bb)
+            }
+            "scaled" => {
+                let scale = self.num(json, "scale")?;
+                if !(scale > 0.0) {
+                    return self.invalid("The 'scale' of an element must be positive.".to_string())
+                }
+                let (elements, bb) = self.children(json)?;
+                (Element::Transformed(Arc::new(elements), scale), bb.scale(scale))
+            }
+            "transformed" => {
+                let m = match *self.field(json, "matrix")? {
+                    Json::Array(ref xs) if xs.len() == 6 => {
+                        let mut m = [0.0; 6];
+                        for (i, x) in xs.iter().enumerate() {
+                            match *x {
+                                Json::Num(x) => m[i] = x,
+                                _ => return self.invalid("A matrix must consist of numbers.".to_string()),
+                            }
+                        }
+                        Affine { xx: m[0], yx: m[1], xy: m[2], yy: m[3], x0: m[4], y0: m[5] }
+                    }
+                    _ => return self.invalid("A matrix must be a list of six numbers.".to_string()),
+                };
+                if m.determinant() == 0.0 {
+                    return self.invalid("A matrix must not flatten its elements.".to_string())
+                }
+                let (elements, bb) = self.children(json)?;
+                let corners = [
+                    m.apply(Vec2::new(bb.x, bb.y)),
+                    m.apply(Vec2::new(bb.x + bb.width, bb.y)),
+                    m.apply(Vec2::new(bb.x, bb.y + bb.height)),
+                    m.apply(Vec2::new(bb.x + bb.width, bb.y + bb.height)),
+                ];
+                (Element::Affine(Arc::new(elements), m), bounds(&corners))
+            }
+            "translucent" => {
+                let opacity = self.num(json, "opacity")?;
+                if !(opacity >= 0.0 && opacity <= 1.0) {
+                    return self.invalid("The 'opacity' of an element must be between 0 and 1.".to_string())
+                }
+                let (elements, bb) = self.children(json)?;
+                (Element::Opacity(Arc::new(elements), opacity), bb)
+            }
+            "clipped" => {
+                let offset = self.vec2(json, "clip_offset")?;
+                let size = self.vec2(json, "clip_size")?;
+                let (elements, _) = self.children(json)?;
+                (Element::Clipped(Arc::new(elements), offset, size), BoundingBox::new(offset, size))
+            }
+            kind @ "png" | kind @ "svg" => {
+                let path = self.string(json, "path")?;
+                if !path.ends_with(&format!(".{}", kind)) {
+                    return self.invalid(format!("The path '{}' of a {} image must end in '.{}'.", path, kind, kind))
+                }
+                let (width, height, element) = (self.load_image)(path)?;
+                (element, BoundingBox::sized(width, height))
+            }
+            "text" => {
+                let font_family = self.string(json, "font_family")?;
+                let font_style = self.string(json, "font_style")?;
+                let mut glyphs = Vec::new();
+                if let Json::Array(ref items) = *self.field(json, "glyphs")? {
+                    for item in items {
+                        match *item {
+                            Json::Array(ref xs) if xs.len() == 3 => match (&xs[0], &xs[1], &xs[2]) {
+                                (&Json::Num(i), &Json::Num(x), &Json::Num(y)) if i >= 0.0 && i.fract() == 0.0 => {
+                                    glyphs.push(Glyph::new(i as u64, x, y));
+                                }
+                                _ => return self.invalid("A glyph must be a list of an index and a position.".to_string()),
+                            },
+                            _ => return self.invalid("A glyph must be a list of an index and a position.".to_string()),
+                        }
+                    }
+                }
+                let text = match json.get("text") {
+                    Some(&Json::Str(ref text)) => text.clone(),
+                    _ => String::new(),
+                };
+                // The scene does not say which glyphs represent which
+                // characters, so the text is a single cluster.
+                let clusters = if text.is_empty() || glyphs.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![TextCluster::new(text.len(), glyphs.len())]
+                };
+                let origins: Vec<Vec2> = glyphs.iter().map(|g| g.position()).collect();
+                let font = (font_family.clone(), font_style.clone());
+                if !self.fonts.contains(&font) {
+                    self.fonts.push(font);
+                }
+                let text = Text {
+                    color: self.color(json)?,
+                    font_family: font_family,
+                    font_style: font_style,
+                    font_size: self.num(json, "font_size")?,
+                    glyphs: glyphs,
+                    text: if clusters.is_empty() { String::new() } else { text },
+                    clusters: clusters,
+                };
+                (Element::Text(text), bounds(&origins))
+            }
+            "hyperlink" => {
+                let size = self.vec2(json, "size")?;
+                let target = match (json.get("uri"), json.get("slide")) {
+                    (Some(&Json::Str(ref target)), None) => LinkTarget::Uri(uri::check(target)?.uri),
+                    (None, Some(&Json::Num(n))) if n >= 1.0 && n.fract() == 0.0 => LinkTarget::Slide(n as usize),
+                    _ => return self.invalid("A hyperlink must have a 'uri' or a 'slide' number.".to_string()),
+                };
+                let link = Hyperlink {
+                    size: size,
+                    target: target,
+                };
+                (Element::Hyperlink(link), BoundingBox::new(Vec2::zero(), size))
+            }
+            other => return self.invalid(format!("Unknown element type '{}'.", other)),
+        };
+        let pe = PlacedElement {
+            position: position,
+            element: element,
+        };
+        Ok((pe, bb.offset(position)))
+    }
+}
+
+/// Read a list of elements in the format that `write` writes them in.
+///
+/// Images are loaded with `load_image`, which returns their size and element.
+pub fn read<F>(path: &str, source: &[u8], load_image: F) -> Result<Fragment>
+where F: FnMut(String) -> Result<(f64, f64, Element)> {
+    let json = json::parse(source)
+        .map_err(|msg| Error::value(format!("The scene '{}' is not valid JSON. {}", path, msg)))?;
+    let mut reader = Reader {
+        path: path,
+        load_image: load_image,
+        fonts: Vec::new(),
+    };
+    let (elements, bounding_box) = reader.elements(&json)?;
+    Ok(Fragment {
+        elements: elements,
+        bounding_box: bounding_box,
+        fonts: reader.fonts,
+    })
+}
+
+#[test]
+fn write_str_escapes_json() {
+    let mut out = Vec::new();
+    write_str(&mut out, "a \"b\"\\\n\u{1}").unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "\"a \\\"b\\\"\\\\\\n\\u0001\"");
+}
+
+#[test]
+fn write_element_writes_nested_elements() {
+    use std::sync::Arc;
+    use elements::FillPolygon;
+    let polygon = PlacedElement {
+        position: Vec2::new(1.0, 2.0),
+        element: Element::FillPolygon(FillPolygon {
+            paint: Paint::Solid(Color::new(1.0, 0.0, 0.0)),
+            vertices: vec![Vec2::zero(), Vec2::new(3.0, 0.5)],
+            kind: PolygonKind::Lines,
+        }),
+    };
+    let group = PlacedElement {
+        position: Vec2::new(10.0, 0.0),
+        element: Element::Transformed(Arc::new(vec![polygon]), 2.0),
+    };
+    let mut out = Vec::new();
+    write_element(&mut out, &group, 0).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{\"type\": \"scaled\", \"position\": [10, 0], \"scale\": 2, \"elements\": [\n  \
+         {\"type\": \"fill_polygon\", \"position\": [1, 2], \"color\": \"#ff0000\", \
+         \"kind\": \"lines\", \"vertices\": [[0, 0], [3, 0.5]]}\n]}"
+    );
+}
+
+#[test]
+fn read_reads_written_elements() {
+    let source = br##"[
+      {"type": "scaled", "position": [10, 0], "scale": 2, "elements": [
+        {"type": "fill_polygon", "position": [1, 2], "color": "#ff0000", "kind": "lines", "vertices": [[0, 0], [3, 0.5]]}
+      ]},
+      {"type": "hyperlink", "position": [0, 0], "size": [1, 1], "slide": 2},
+      {"type": "translucent", "position": [0, 0], "opacity": 0.5, "elements": [
+        {"type": "fill_polygon", "position": [0, 0], "color": "#00ff0080", "kind": "lines", "vertices": [[0, 0], [1, 1]]}
+      ]}
+    ]"##;
+    let fragment = read("plot.json", source, |_| panic!("There are no images.")).unwrap();
+    let mut out = Vec::new();
+    write_element(&mut out, &fragment.elements[0], 0).unwrap();
+    assert!(String::from_utf8(out).unwrap().contains("\"color\": \"#ff0000\""));
+    let mut out = Vec::new();
+    write_element(&mut out, &fragment.elements[2], 0).unwrap();
+    let translucent = String::from_utf8(out).unwrap();
+    assert!(translucent.contains("\"opacity\": 0.5"), "{}", translucent);
+    assert!(translucent.contains("\"color\": \"#00ff0080\""), "{}", translucent);
+    let bb = fragment.bounding_box;
+    assert_eq!((bb.x, bb.y, bb.width, bb.height), (0.0, 0.0, 18.0, 5.0));
+}
+
+#[test]
+fn read_rejects_invalid_elements() {
+    let no_images = |_| panic!("There are no images.");
+    for &source in &[
+        &b"{}"[..],
+        b"[{\"type\": \"circle\", \"position\": [0, 0]}]",
+        b"[{\"type\": \"group\", \"elements\": []}]",
+        b"[{\"type\": \"fill_polygon\", \"position\": [0, 0], \"color\": \"red\", \"kind\": \"lines\", \"vertices\": []}]",
+        b"[{\"type\": \"translucent\", \"position\": [0, 0], \"opacity\": 2, \"elements\": []}]",
+    ] {
+        match read("plot.json", source, no_images) {
+            Err(Error::Value(..)) => {}
+            _ => panic!("Expected a value error for {:?}.", String::from_utf8_lossy(source)),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module simulates how slides look to other eyes and on other devices.
+//!
+//! The simulation transforms the colors of rendered pixels, so authors can
+//! check that their slides remain legible for a colorblind audience, or when
+//! printed in grayscale.
+
+/// A color transform applied to raster output.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Simulation {
+    /// Missing red cones.
+    Protanopia,
+    /// Missing green cones, the most common form of colorblindness.
+    Deuteranopia,
+    /// Missing blue cones.
+    Tritanopia,
+    /// Only luminance remains, as on a grayscale printer.
+    Grayscale,
+}
+
+impl Simulation {
+    /// Parse a simulation such as `colorblind=deuteranopia` or `grayscale`.
+    pub fn from_name(name: &str) -> Option<Simulation> {
+        match name {
+            "colorblind=protanopia" => Some(Simulation::Protanopia),
+            "colorblind=deuteranopia" => Some(Simulation::Deuteranopia),
+            "colorblind=tritanopia" => Some(Simulation::Tritanopia),
+            "grayscale" => Some(Simulation::Grayscale),
+            _ => None,
+        }
+    }
+
+    /// Return the matrix that transforms linear RGB, row by row.
+    ///
+    /// The colorblindness matrices are those of Machado, Oliveira, and
+    /// Fernandes (2009) for full severity. Grayscale uses the Rec. 709
+    /// luminance coefficients, which sRGB shares.
+    fn matrix(&self) -> [[f64; 3]; 3] {
+        match *self {
+            Simulation::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            Simulation::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            Simulation::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+            Simulation::Grayscale => [
+                [0.2126, 0.7152, 0.0722],
+                [0.2126, 0.7152, 0.0722],
+                [0.2126, 0.7152, 0.0722],
+            ],
+        }
+    }
+
+    /// Transform an sRGB color with components in the range 0 to 1.
+    pub fn apply(&self, rgb: [f64; 3]) -> [f64; 3] {
+        let m = self.matrix();
+        let lin = [srgb_to_linear(rgb[0]), srgb_to_linear(rgb[1]), srgb_to_linear(rgb[2])];
+        let mut out = [0.0; 3];
+        for i in 0..3 {
+            let x = m[i][0] * lin[0] + m[i][1] * lin[1] + m[i][2] * lin[2];
+            out[i] = linear_to_srgb(x.max(0.0).min(1.0));
+        }
+        out
+    }
+
+    /// Transform pixels in Cairo's ARGB32 format in place.
+    ///
+    /// The pixels are 32-bit words in native byte order, with premultiplied
+    /// alpha. Alpha itself is not changed.
+    pub fn apply_argb32(&self, data: &mut [u8]) {
+        // Byte offsets of the red, green, blue, and alpha channels.
+        let (r, g, b, a) = if cfg!(target_endian = "little") { (2, 1, 0, 3) } else { (1, 2, 3, 0) };
+        for pixel in data.chunks_mut(4) {
+            let alpha = pixel[a] as f64;
+            if alpha == 0.0 {
+                continue
+            }
+            let rgb = [pixel[r] as f64 / alpha, pixel[g] as f64 / alpha, pixel[b] as f64 / alpha];
+            let out = self.apply(rgb);
+            pixel[r] = (out[0] * alpha).round() as u8;
+            pixel[g] = (out[1] * alpha).round() as u8;
+            pixel[b] = (out[2] * alpha).round() as u8;
+        }
+    }
+}
+
+fn srgb_to_linear(x: f64) -> f64 {
+    if x <= 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(x: f64) -> f64 {
+    if x <= 0.0031308 { x * 12.92 } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 }
+}
+
+#[test]
+fn simulation_preserves_white_and_black() {
+    for &sim in &[Simulation::Protanopia, Simulation::Deuteranopia, Simulation::Tritanopia, Simulation::Grayscale] {
+        for &x in &[0.0, 1.0] {
+            let out = sim.apply([x, x, x]);
+            for i in 0..3 {
+                assert!((out[i] - x).abs() < 1e-3, "{:?} maps {} to {:?}", sim, x, out);
+            }
+        }
+    }
+}
+
+#[test]
+fn simulation_deuteranopia_confuses_red_and_green() {
+    let red = Simulation::Deuteranopia.apply([1.0, 0.0, 0.0]);
+    let green = Simulation::Deuteranopia.apply([0.0, 1.0, 0.0]);
+    // Both become a shade of yellowish brown, with hardly any blue.
+    assert!((red[0] - red[1]).abs() < 0.1);
+    assert!((green[0] - green[1]).abs() < 0.1);
+}
+
+#[test]
+fn simulation_apply_argb32_respects_premultiplied_alpha() {
+    // Half-transparent pure red, premultiplied, in native byte order.
+    let pixel: u32 = 0x80_80_00_00;
+    let mut data = [0u8; 8];
+    for (i, &word) in [pixel, 0].iter().enumerate() {
+        for j in 0..4 {
+            let shift = if cfg!(target_endian = "little") { 8 * j } else { 24 - 8 * j };
+            data[4 * i + j] = (word >> shift) as u8;
+        }
+    }
+    Simulation::Grayscale.apply_argb32(&mut data);
+    let (r, g, b, a) = if cfg!(target_endian = "little") { (2, 1, 0, 3) } else { (1, 2, 3, 0) };
+    assert_eq!(data[a], 0x80);
+    assert_eq!(data[r], data[g]);
+    assert_eq!(data[g], data[b]);
+    // Red has a luminance of 0.2126, which is about 0.5 in sRGB.
+    assert!(data[r] > 0x30 && data[r] < 0x50);
+    // A fully transparent pixel stays as it is.
+    assert_eq!(&data[4..], &[0, 0, 0, 0]);
+}
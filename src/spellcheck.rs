@@ -0,0 +1,319 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module checks the spelling of text on slides with Hunspell.
+//!
+//! Only string literals that are passed to `t()` directly are checked: text
+//! that is computed while evaluating cannot be traced back to the source. The
+//! literals are found without evaluating the document.
+
+use ast::{Block, Document, FnCall, Idents, Stmt, Term};
+use error::{Error, Result};
+use lexer::{self, Token};
+use names;
+
+#[cfg(feature = "spellcheck")]
+use std::io::Write;
+#[cfg(feature = "spellcheck")]
+use std::process::{Command, Stdio};
+#[cfg(feature = "spellcheck")]
+use std::thread;
+
+/// A string literal that is typeset on a slide.
+pub struct TextLiteral<'d> {
+    pub text: &'d str,
+    /// Byte offset of the call to `t()` in the source.
+    pub offset: usize,
+    /// The slide that the literal is on, starting at 1, if it is in a slide
+    /// rather than in a function defined outside of one.
+    pub slide: Option<usize>,
+}
+
+/// A word that the dictionary does not know.
+#[derive(Debug, PartialEq)]
+pub struct Misspelling {
+    pub word: String,
+    /// Similar words that the dictionary does know, most likely first.
+    pub suggestions: Vec<String>,
+}
+
+struct Collector<'d> {
+    /// Byte offsets of the calls to `t()` in the source, in order.
+    calls: Vec<usize>,
+    /// The number of calls to `t()` visited so far.
+    num_calls: usize,
+    slide: Option<usize>,
+    literals: Vec<TextLiteral<'d>>,
+}
+
+impl<'d> Collector<'d> {
+    fn visit_statement<'a>(&mut self, statement: &'d Stmt<'a>) {
+        match *statement {
+            Stmt::Assign(ref assign) => self.visit_term(&assign.1),
+            Stmt::Update(ref update) => self.visit_term(&update.2),
+            Stmt::Return(ref ret) => self.visit_term(&ret.0),
+            Stmt::Block(ref block) => self.visit_block(block),
+            Stmt::Put(ref put) => self.visit_term(&put.0),
+            Stmt::Repeat(ref repeat) => {
+                self.visit_term(&repeat.0);
+                self.visit_block(&repeat.1);
+            }
+            Stmt::With(ref with) => {
+                self.visit_block(&with.0);
+                self.visit_block(&with.1);
+            }
+            Stmt::Import(..) | Stmt::Infix(..) | Stmt::Section(..) => {}
+            Stmt::Version(..) | Stmt::Comment(..) => {}
+        }
+    }
+
+    fn visit_block<'a>(&mut self, block: &'d Block<'a>) {
+        for statement in &block.0 {
+            self.visit_statement(statement);
+        }
+    }
+
+    fn visit_term<'a>(&mut self, term: &'d Term<'a>) {
+        match *term {
+            Term::FnCall(ref call) => self.visit_call(call),
+            Term::Coord(ref coord) => {
+                self.visit_term(&coord.0);
+                self.visit_term(&coord.1);
+            }
+            Term::BinOp(ref bin) => {
+                self.visit_term(&bin.0);
+                self.visit_term(&bin.2);
+            }
+            Term::UnOp(ref un) => self.visit_term(&un.1),
+            Term::FnDef(ref def) => self.visit_block(&def.1),
+            Term::Block(ref block) => self.visit_block(block),
+            Term::Match(ref m) => {
+                self.visit_term(&m.0);
+                for arm in &m.1 {
+                    self.visit_term(&arm.1);
+                }
+                if let Some(ref default) = m.2 {
+                    self.visit_term(default);
+                }
+            }
+            Term::List(ref list) => for t in &list.0 { self.visit_term(t) },
+            Term::String(..) | Term::Number(..) | Term::Color(..) | Term::Idents(..) => {}
+        }
+    }
+
+    fn visit_call<'a>(&mut self, call: &'d FnCall<'a>) {
+        if let Term::Idents(Idents(ref path)) = call.0 {
+            if path.len() == 1 && path[0] == names::t {
+                let offset = self.calls.get(self.num_calls).cloned().unwrap_or(0);
+                self.num_calls += 1;
+                for arg in &call.1 {
+                    self.collect_strings(arg, offset);
+                }
+            }
+        }
+        self.visit_term(&call.0);
+        for arg in &call.1 {
+            self.visit_term(arg);
+        }
+    }
+
+    /// Collect the literals in an argument of `t()`, also when concatenated.
+    fn collect_strings<'a>(&mut self, term: &'d Term<'a>, offset: usize) {
+        match *term {
+            Term::String(ref text) => self.literals.push(TextLiteral {
+                text: text,
+                offset: offset,
+                slide: self.slide,
+            }),
+            Term::BinOp(ref bin) => {
+                self.collect_strings(&bin.0, offset);
+                self.collect_strings(&bin.2, offset);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Return the byte offsets of the calls to `t()` in the source, in order.
+///
+/// Identifiers in the document do not know where they came from, so the
+/// calls are found in the tokens instead. The document is visited in source
+/// order, so the n-th call visited is the n-th call found here.
+fn call_offsets(source: &[u8]) -> Vec<usize> {
+    let tokens = match lexer::lex(source) {
+        Ok(tokens) => tokens,
+        Err(..) => return Vec::new(),
+    };
+    let mut offsets = Vec::new();
+    for (i, window) in tokens.windows(2).enumerate() {
+        let after_dot = i > 0 && tokens[i - 1].0 == Token::Dot;
+        match (window[0].0, window[1].0) {
+            (Token::Ident("t"), Token::LParen) if !after_dot => offsets.push(window[0].1.start),
+            _ => {}
+        }
+    }
+    offsets
+}
+
+/// Return the string literals that are passed to `t()`, in source order.
+///
+/// The document must have been parsed from `source`.
+pub fn text_literals<'d, 'a>(doc: &'d Document<'a>, source: &'a [u8]) -> Vec<TextLiteral<'d>> {
+    let mut collector = Collector {
+        calls: call_offsets(source),
+        num_calls: 0,
+        slide: None,
+        literals: Vec::new(),
+    };
+    let mut num_slides = 0;
+    for statement in &doc.0 {
+        // Top-level blocks are slides.
+        if let Stmt::Block(ref block) = *statement {
+            num_slides += 1;
+            collector.slide = Some(num_slides);
+            collector.visit_block(block);
+            collector.slide = None;
+        } else {
+            collector.visit_statement(statement);
+        }
+    }
+    collector.literals
+}
+
+/// Parse the output of `hunspell -a` for `num_lines` lines of input.
+///
+/// Hunspell prints a line per word, and an empty line after every input line.
+/// Only lines for unknown words, which start with `&` or `#`, are of interest.
+#[cfg(feature = "spellcheck")]
+fn parse_hunspell_output(output: &str, num_lines: usize) -> Vec<Vec<Misspelling>> {
+    let mut result = Vec::with_capacity(num_lines);
+    let mut current = Vec::new();
+    // The first line identifies the version.
+    for line in output.lines().skip(1) {
+        if line.is_empty() {
+            result.push(current);
+            current = Vec::new();
+            continue
+        }
+        let mut parts = line.splitn(2, ": ");
+        let mut head = parts.next().unwrap_or("").split(' ');
+        let suggestions = parts.next().map_or(Vec::new(), |s| s.split(", ").map(String::from).collect());
+        match (head.next(), head.next()) {
+            (Some("&"), Some(word)) | (Some("#"), Some(word)) => current.push(Misspelling {
+                word: word.to_string(),
+                suggestions: suggestions,
+            }),
+            _ => {}
+        }
+    }
+    // Should Hunspell stop early, the remaining lines are not checked.
+    while result.len() < num_lines {
+        result.push(Vec::new());
+    }
+    result.truncate(num_lines);
+    result
+}
+
+/// Check the spelling of `texts` with the Hunspell dictionary `dictionary`.
+///
+/// Returns the misspelled words of every text, in order of occurrence.
+#[cfg(feature = "spellcheck")]
+pub fn check(dictionary: &str, texts: &[&str]) -> Result<Vec<Vec<Misspelling>>> {
+    // In pipe mode, a line that starts with "^" is checked as it is, even if
+    // it would otherwise be interpreted as a command.
+    let mut input = String::new();
+    let mut num_lines = Vec::with_capacity(texts.len());
+    for text in texts {
+        let lines: Vec<&str> = text.split('\n').collect();
+        for line in &lines {
+            input.push('^');
+            input.push_str(line);
+            input.push('\n');
+        }
+        num_lines.push(lines.len());
+    }
+
+    let mut child = Command::new("hunspell")
+        .args(&["-a", "-d", dictionary])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Other(format!("Failed to run hunspell for spell checking: {}.", e)))?;
+
+    // Write from a different thread, so Hunspell does not block on a full
+    // stdout pipe while we block on a full stdin pipe.
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().map_err(|e| Error::Other(e.to_string()))?;
+    writer.join().unwrap().map_err(|e| Error::Other(e.to_string()))?;
+
+    if !output.status.success() {
+        let msg = format!(
+            "Hunspell failed to check spelling with dictionary '{}': {}",
+            dictionary, String::from_utf8_lossy(&output.stderr).trim(),
+        );
+        return Err(Error::Other(msg))
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = parse_hunspell_output(&stdout, num_lines.iter().sum()).into_iter();
+    let result = num_lines.iter().map(|&n| {
+        lines.by_ref().take(n).flat_map(|line| line).collect()
+    }).collect();
+    Ok(result)
+}
+
+#[cfg(not(feature = "spellcheck"))]
+pub fn check(_dictionary: &str, _texts: &[&str]) -> Result<Vec<Vec<Misspelling>>> {
+    let msg = "Pris was compiled without spell checking support, \
+               enable the 'spellcheck' feature to check spelling.";
+    Err(Error::Other(msg.to_string()))
+}
+
+#[cfg(test)]
+fn parse_document(input: &[u8]) -> Document {
+    use lexer::lex;
+    use parser::parse;
+    let tokens = lex(input).unwrap();
+    parse(&tokens).unwrap()
+}
+
+#[test]
+fn text_literals_finds_arguments_of_t_with_their_slide() {
+    let source = b"caption = function(s) { put t(s + \"!\") }\n\
+                   { put t(\"Helo\") }\n\
+                   { x = \"unchecked\"\n  put t(\"a\" + \"b\") }";
+    let doc = parse_document(source);
+    let literals = text_literals(&doc, source);
+    let summary: Vec<_> = literals.iter().map(|l| (l.text, l.slide)).collect();
+    assert_eq!(summary, vec![("!", None), ("Helo", Some(1)), ("a", Some(2)), ("b", Some(2))]);
+    assert!(source[literals[1].offset..].starts_with(b"t(\"Helo\")"));
+    assert!(source[literals[3].offset..].starts_with(b"t(\"a\" + \"b\")"));
+}
+
+#[test]
+#[cfg(feature = "spellcheck")]
+fn parse_hunspell_output_groups_words_per_line() {
+    let output = "@(#) International Ispell Version 3.2.06 (but really Hunspell 1.7.0)\n\
+                  *\n\
+                  & Helo 3 0: Hello, Help, Halo\n\
+                  \n\
+                  \n\
+                  # Prisss 4\n\
+                  *\n\
+                  \n";
+    let result = parse_hunspell_output(output, 3);
+    assert_eq!(result, vec![
+        vec![Misspelling {
+            word: "Helo".to_string(),
+            suggestions: vec!["Hello".to_string(), "Help".to_string(), "Halo".to_string()],
+        }],
+        vec![],
+        vec![Misspelling { word: "Prisss".to_string(), suggestions: vec![] }],
+    ]);
+}
@@ -0,0 +1,100 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module implements interning of identifiers.
+//!
+//! The parser interns every identifier once, after which it is represented by
+//! a small integer, the symbol. Symbols can be compared and hashed in constant
+//! time, which is how the environment looks up bindings. The names of builtins
+//! are interned before anything else, see `names`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use names;
+
+/// An interned identifier.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Symbol(pub(crate) u32);
+
+struct Interner {
+    names: Vec<Rc<str>>,
+    symbols: HashMap<Rc<str>, Symbol>,
+}
+
+impl Interner {
+    /// Return an interner that holds the names of builtins, in order.
+    fn with_builtins() -> Interner {
+        let mut interner = Interner {
+            names: Vec::new(),
+            symbols: HashMap::new(),
+        };
+        for name in names::all() {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol
+        }
+        let name: Rc<str> = Rc::from(name);
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.clone());
+        self.symbols.insert(name, symbol);
+        symbol
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::with_builtins());
+}
+
+impl Symbol {
+    /// Return the symbol for the identifier, interning it if needed.
+    pub fn intern(name: &str) -> Symbol {
+        INTERNER.with(|interner| interner.borrow_mut().intern(name))
+    }
+
+    /// Return the identifier that this symbol represents.
+    pub fn as_str(self) -> Rc<str> {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize].clone())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({:?})", &*self.as_str())
+    }
+}
+
+#[test]
+fn intern_returns_the_same_symbol_for_the_same_name() {
+    let a = Symbol::intern("font_size");
+    let b = Symbol::intern("font_size");
+    let c = Symbol::intern("font_family");
+    assert_eq!(a, b);
+    assert!(a != c);
+    assert_eq!(&*a.as_str(), "font_size");
+    assert_eq!(&*c.as_str(), "font_family");
+}
+
+#[test]
+fn intern_returns_the_constants_for_builtin_names() {
+    assert_eq!(Symbol::intern("font_size"), names::font_size);
+    assert_eq!(Symbol::intern("width"), names::width);
+    assert_eq!(&*names::canvas_size.as_str(), "canvas_size");
+}
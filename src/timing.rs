@@ -0,0 +1,120 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module estimates how long it takes to present a document.
+//!
+//! Slides are timed with the `minutes` variable. Like `slide_title`, it can be
+//! assigned inside a slide, or in the global scope to time all slides that
+//! follow. Consecutive slides in the same section are summed, so a speaker can
+//! see which part of a talk to shorten.
+
+use std::rc::Rc;
+
+use ast::Idents;
+use error::{Error, Result};
+use names;
+use runtime::{Frame, Val};
+use types::ValType;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SectionTime {
+    /// The section, or `None` for slides before the first section.
+    pub section: Option<String>,
+    /// The number of slides in the section.
+    pub slides: usize,
+    /// The number of those slides that set `minutes`.
+    pub timed_slides: usize,
+    /// The sum of the estimated minutes of the timed slides.
+    pub minutes: f64,
+}
+
+/// Sum the estimated presentation time of the slides of a document per section.
+pub fn collect<'a>(slides: &[Rc<Frame<'a>>]) -> Result<Vec<SectionTime>> {
+    let ident_minutes = Idents(vec![names::minutes]);
+    let ident_section = Idents(vec![names::current_section]);
+    let mut sections: Vec<SectionTime> = Vec::new();
+
+    for (i, slide) in slides.iter().enumerate() {
+        let env = slide.get_env();
+        let minutes = match env.try_lookup(&ident_minutes)?.as_ref().map(|val| &**val) {
+            Some(&Val::Num(x, 0)) if x >= 0.0 => Some(x),
+            Some(&Val::Num(x, 0)) => {
+                let msg = format!("Slide {} is timed at {} minutes, but time cannot be negative.", i + 1, x);
+                return Err(Error::value(msg))
+            }
+            Some(other) => return Err(Error::var_type(&ident_minutes, ValType::Num(0), other.get_type())),
+            None => None,
+        };
+        let section = match env.lookup_str(&ident_section) {
+            Ok(ref section) if section.is_empty() => None,
+            Ok(section) => Some(section),
+            Err(..) => None,
+        };
+
+        let is_new_section = match sections.last() {
+            Some(last) => last.section != section,
+            None => true,
+        };
+        if is_new_section {
+            sections.push(SectionTime {
+                section: section,
+                slides: 0,
+                timed_slides: 0,
+                minutes: 0.0,
+            });
+        }
+
+        let current = sections.last_mut().unwrap();
+        current.slides += 1;
+        if let Some(x) = minutes {
+            current.timed_slides += 1;
+            current.minutes += x;
+        }
+    }
+
+    Ok(sections)
+}
+
+#[cfg(test)]
+fn make_slide<'a>(section: Option<&str>, minutes: Option<f64>) -> Rc<Frame<'a>> {
+    let mut frame = Frame::new();
+    if let Some(section) = section {
+        frame.put_in_env(names::current_section, Val::Str(section.into()));
+    }
+    if let Some(minutes) = minutes {
+        frame.put_in_env(names::minutes, Val::Num(minutes, 0));
+    }
+    Rc::new(frame)
+}
+
+#[test]
+fn collect_sums_minutes_per_section() {
+    let slides = vec![
+        make_slide(None, Some(0.5)),
+        make_slide(Some("Intro"), Some(2.0)),
+        make_slide(Some("Intro"), None),
+        make_slide(Some("Intro"), Some(1.5)),
+        make_slide(Some("Results"), Some(3.0)),
+    ];
+    let sections = collect(&slides).unwrap();
+    let summary: Vec<_> = sections.iter().map(|s| {
+        (s.section.as_ref().map(|s| &s[..]), s.slides, s.timed_slides, s.minutes)
+    }).collect();
+    assert_eq!(summary, vec![
+        (None, 1, 1, 0.5),
+        (Some("Intro"), 3, 2, 3.5),
+        (Some("Results"), 1, 1, 3.0),
+    ]);
+}
+
+#[test]
+fn collect_rejects_minutes_with_a_unit() {
+    let mut frame = Frame::new();
+    frame.put_in_env(names::minutes, Val::Num(2.0, 1));
+    assert!(collect(&[Rc::new(frame)]).is_err());
+    assert!(collect(&[make_slide(None, Some(-1.0))]).is_err());
+}
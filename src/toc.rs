@@ -0,0 +1,107 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module collects the table of contents of a document.
+//!
+//! Slides are titled with the `slide_title` variable, or otherwise by the
+//! section they are in. Consecutive slides with the same title form one entry
+//! in the table of contents. A table of contents
+//! usually appears before the slides it lists, so it can only be generated
+//! from the entries of a previous evaluation of the document.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use ast::Idents;
+use names;
+use runtime::Frame;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TocEntry {
+    pub title: String,
+    /// The number of the slide at which the entry starts, starting at 1.
+    pub slide: usize,
+}
+
+/// Table of contents entries from a previous evaluation.
+pub struct Toc {
+    entries: Vec<TocEntry>,
+    used: Cell<bool>,
+}
+
+impl Toc {
+    pub fn new(entries: Vec<TocEntry>) -> Toc {
+        Toc {
+            entries: entries,
+            used: Cell::new(false),
+        }
+    }
+
+    /// Return the entries, and record that the document depends on them.
+    pub fn entries(&self) -> &[TocEntry] {
+        self.used.set(true);
+        &self.entries
+    }
+
+    /// Return whether the entries were used, so a second pass is needed.
+    pub fn is_used(&self) -> bool {
+        self.used.get()
+    }
+
+    /// Return whether `entries` differ from the entries of this table.
+    pub fn differs_from(&self, entries: &[TocEntry]) -> bool {
+        &self.entries[..] != entries
+    }
+}
+
+/// Collect the table of contents from the slides of a document.
+pub fn collect<'a>(slides: &[Rc<Frame<'a>>]) -> Vec<TocEntry> {
+    let ident_title = Idents(vec![names::slide_title]);
+    let ident_section = Idents(vec![names::current_section]);
+    let mut entries = Vec::new();
+    let mut previous_title = None;
+
+    for (i, slide) in slides.iter().enumerate() {
+        // Slides without a string title are not listed, also when the title
+        // variable is used for something else, such as a frame.
+        let env = slide.get_env();
+        let title = match (env.lookup_str(&ident_title), env.lookup_str(&ident_section)) {
+            (Ok(title), _) => Some(title),
+            (_, Ok(ref section)) if section.is_empty() => None,
+            (_, Ok(section)) => Some(section),
+            _ => None,
+        };
+
+        if title.is_some() && title != previous_title {
+            entries.push(TocEntry {
+                title: title.clone().unwrap(),
+                slide: i + 1,
+            });
+        }
+
+        previous_title = title;
+    }
+
+    entries
+}
+
+#[test]
+fn collect_merges_consecutive_slides_with_the_same_title() {
+    use runtime::Val;
+    let titles = [Some("Intro"), Some("Intro"), None, Some("Results"), Some("Intro")];
+    let slides: Vec<_> = titles.iter().map(|title| {
+        let mut frame = Frame::new();
+        if let Some(title) = *title {
+            frame.put_in_env(names::slide_title, Val::Str(title.into()));
+        }
+        Rc::new(frame)
+    }).collect();
+
+    let entries = collect(&slides);
+    let summary: Vec<_> = entries.iter().map(|e| (&e.title[..], e.slide)).collect();
+    assert_eq!(summary, vec![("Intro", 1), ("Results", 4), ("Intro", 5)]);
+}
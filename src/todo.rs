@@ -0,0 +1,52 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module collects the placeholders that `todo()` leaves in a document.
+//!
+//! A placeholder is drawn as a box on the slide, so it is hard to miss while
+//! working on a talk. For a release build, the host checks that there are no
+//! placeholders left, and lists them otherwise.
+
+use std::cell::RefCell;
+
+/// A call to `todo()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Todo {
+    pub message: String,
+    /// The slide that the placeholder is on, starting at 1, or `None` when
+    /// `todo()` was called outside of a slide.
+    pub slide: Option<usize>,
+}
+
+/// The placeholders that evaluation encountered, in order of occurrence.
+pub struct Todos {
+    todos: RefCell<Vec<Todo>>,
+}
+
+impl Todos {
+    pub fn new() -> Todos {
+        Todos {
+            todos: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn record(&self, message: String, slide: Option<usize>) {
+        self.todos.borrow_mut().push(Todo {
+            message: message,
+            slide: slide,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.todos.borrow().is_empty()
+    }
+
+    /// Return the placeholders recorded so far.
+    pub fn list(&self) -> Vec<Todo> {
+        self.todos.borrow().clone()
+    }
+}
@@ -0,0 +1,100 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module writes the text of slides to a transcript.
+//!
+//! A transcript lists the text on every slide in reading order, which is
+//! useful to check what a screen reader would read, to hand a talk to a
+//! translator, or as a starting point for captions.
+
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use driver::Slide;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    /// Plain text, with a line with the slide number before every slide.
+    Text,
+    /// Markdown, with a heading for every slide and a paragraph per text.
+    Markdown,
+}
+
+impl Format {
+    /// Pick the format by the extension of the file to write.
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("md") | Some("markdown") => Format::Markdown,
+            _ => Format::Text,
+        }
+    }
+}
+
+/// Escape characters that Markdown would interpret as formatting.
+fn escape_markdown(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    for (i, ch) in line.chars().enumerate() {
+        let is_special = match ch {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' => true,
+            // These start a list item only at the start of a line.
+            '-' | '+' => i == 0,
+            _ => false,
+        };
+        if is_special {
+            result.push('\\');
+        }
+        result.push(ch);
+    }
+    result
+}
+
+/// Write the transcript of a single slide, numbered starting at 1.
+pub fn write_slide<W: Write>(out: &mut W, number: usize, texts: &[&str], format: Format) -> io::Result<()> {
+    match format {
+        Format::Text => {
+            writeln!(out, "[Slide {}]", number)?;
+            for text in texts {
+                writeln!(out, "{}", text)?;
+            }
+        }
+        Format::Markdown => {
+            writeln!(out, "## Slide {}", number)?;
+            for text in texts {
+                writeln!(out)?;
+                for line in text.lines() {
+                    writeln!(out, "{}", escape_markdown(line))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the text on all slides, separated by blank lines.
+pub fn write<W: Write>(out: &mut W, slides: &[Slide], format: Format) -> io::Result<()> {
+    for (i, slide) in slides.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        write_slide(out, i + 1, &slide.texts_in_reading_order(), format)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn write_slide_escapes_markdown() {
+    let mut out = Vec::new();
+    write_slide(&mut out, 2, &["Title", "- not_a list\n3 * 4"], Format::Markdown).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "## Slide 2\n\nTitle\n\n\\- not\\_a list\n3 \\* 4\n"
+    );
+    let mut out = Vec::new();
+    write_slide(&mut out, 2, &["Title", "- not_a list"], Format::Text).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "[Slide 2]\nTitle\n- not_a list\n");
+}
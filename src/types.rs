@@ -14,7 +14,10 @@ pub enum ValType {
     // TODO: Make list homogeneous, and carry an element type?
     List,
     Frame,
-    Fn
+    Fn,
+    Transform,
+    Gradient,
+    None,
 }
 
 /// Represents a number of length dimensions.
@@ -27,3 +30,16 @@ pub enum ValType {
 /// 3 means "volume".
 /// etc.
 pub type LenDim = i32;
+
+/// Describe a dimension in words, with an article, for error messages.
+pub fn describe_dimension(d: LenDim) -> String {
+    match d {
+        -2 => "an inverse area".to_string(),
+        -1 => "an inverse length".to_string(),
+        0 => "a number".to_string(),
+        1 => "a length".to_string(),
+        2 => "an area".to_string(),
+        3 => "a volume".to_string(),
+        n => format!("a length to the power {}", n),
+    }
+}
@@ -0,0 +1,304 @@
+// Pris -- A language for designing slides
+// Copyright 2018 Ruud van Asseldonk
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License version 3. A copy
+// of the License is available in the root of the repository.
+
+//! This module validates the URIs of hyperlinks.
+//!
+//! Broken links are otherwise only discovered when clicking them in a PDF
+//! viewer, so we check them when the document is evaluated instead.
+
+use std::path::{Component, Path, PathBuf};
+
+use error::{Error, Result};
+
+/// Schemes that PDF viewers are expected to open.
+const ALLOWED_SCHEMES: &'static [&'static str] = &["http", "https", "mailto", "ftp", "file", "tel"];
+
+/// A URI that passed validation.
+pub struct CheckedUri {
+    /// The URI, with characters that are not valid in a URI percent-encoded.
+    pub uri: String,
+    /// A description of a problem with the URI that does not prevent linking.
+    pub warning: Option<String>,
+}
+
+/// Return the scheme of the URI, if it has one.
+///
+/// A scheme of a single letter is not considered a scheme, to not mistake
+/// Windows drive letters for schemes.
+fn scheme(uri: &str) -> Option<&str> {
+    let end = uri.find(':')?;
+    let candidate = &uri[..end];
+    let mut chars = candidate.chars();
+    let first_ok = chars.next().map_or(false, |c| c.is_ascii_alphabetic());
+    let rest_ok = chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+    if first_ok && rest_ok && candidate.len() > 1 {
+        Some(candidate)
+    } else {
+        None
+    }
+}
+
+/// Percent-encode all bytes that may not occur in a URI.
+///
+/// Percent signs are left untouched, so URIs that are already encoded remain
+/// valid.
+fn escape(uri: &str) -> String {
+    let mut result = String::with_capacity(uri.len());
+    for &b in uri.as_bytes() {
+        let allowed = match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => true,
+            b'-' | b'.' | b'_' | b'~' | b'%' => true,
+            b':' | b'/' | b'?' | b'#' | b'[' | b']' | b'@' => true,
+            b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' => true,
+            _ => false,
+        };
+        if allowed {
+            result.push(b as char);
+        } else {
+            result.push_str(&format!("%{:02X}", b));
+        }
+    }
+    result
+}
+
+/// Check the part after the scheme for obvious mistakes.
+fn check_scheme_specific(scheme: &str, rest: &str) -> Option<String> {
+    match scheme {
+        "http" | "https" | "ftp" => {
+            let host = if rest.starts_with("//") {
+                rest[2..].split(|c| c == '/' || c == '?' || c == '#').next().unwrap_or("")
+            } else {
+                ""
+            };
+            if host.is_empty() {
+                Some(format!("a {} link should be of the form '{}://host/path'.", scheme, scheme))
+            } else {
+                None
+            }
+        }
+        "mailto" => {
+            let address = rest.split('?').next().unwrap_or("");
+            let mut parts = address.splitn(2, '@');
+            let local = parts.next().unwrap_or("");
+            let domain = parts.next().unwrap_or("");
+            if local.is_empty() || domain.is_empty() {
+                Some("a mailto link should be of the form 'mailto:user@example.com'.".into())
+            } else {
+                None
+            }
+        }
+        "file" if !rest.starts_with("//") => {
+            Some("a file link should be of the form 'file:///path'. \
+                  Note: relative paths do not need a scheme.".into())
+        }
+        _ => None,
+    }
+}
+
+/// Validate a hyperlink URI.
+///
+/// URIs with a scheme must use one of the allowed schemes. URIs without a
+/// scheme are relative links, see `LinkBase` for how they are resolved.
+pub fn check(uri: &str) -> Result<CheckedUri> {
+    if uri.is_empty() {
+        return Err(Error::value("A hyperlink URI must not be empty.".into()))
+    }
+
+    let warning = match scheme(uri) {
+        Some(scheme) => {
+            let scheme_lower = scheme.to_ascii_lowercase();
+            if !ALLOWED_SCHEMES.contains(&&scheme_lower[..]) {
+                let msg = format!(
+                    "The scheme '{}' of hyperlink '{}' is not supported. \
+                     Must be one of '{}'.",
+                    scheme, uri, ALLOWED_SCHEMES.join("', '")
+                );
+                return Err(Error::value(msg))
+            }
+            check_scheme_specific(&scheme_lower, &uri[scheme.len() + 1..])
+        }
+        None if uri.contains('\\') => {
+            Some("relative links should use forward slashes.".into())
+        }
+        None => None,
+    };
+
+    let checked = CheckedUri {
+        uri: escape(uri),
+        warning: warning.map(|w| format!("hyperlink '{}' looks malformed, {}", uri, w)),
+    };
+    Ok(checked)
+}
+
+/// Decode percent-encoded bytes, to turn a link back into a file path.
+fn unescape(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| ::std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(b) if bytes[i] == b'%' => { result.push(b); i += 3; }
+            _ => { result.push(bytes[i]); i += 1; }
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Return the relative path from directory `from` to directory `to`, with
+/// forward slashes and a trailing slash, or `None` if there is none.
+///
+/// Both directories must exist, because they are made absolute first.
+fn relative_dir(from: &Path, to: &Path) -> Option<String> {
+    let from = from.canonicalize().ok()?;
+    let to = to.canonicalize().ok()?;
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|&(a, b)| a == b).count();
+    // On Windows, paths on different drives have no relative path.
+    if common == 0 {
+        return None
+    }
+    let mut parts: Vec<String> = from[common..].iter().map(|_| "..".to_string()).collect();
+    for component in &to[common..] {
+        parts.push(component.as_os_str().to_str()?.to_string());
+    }
+    Some(parts.iter().map(|part| format!("{}/", part)).collect())
+}
+
+/// Where relative links of a document point.
+///
+/// A link without a scheme is a path relative to the document that contains
+/// it, but PDF viewers resolve it against the location of the PDF file. When
+/// the PDF is written to a different directory, relative links are rewritten
+/// to point at the same file from there. Links that point at a file that does
+/// not exist get a warning.
+pub struct LinkBase {
+    /// The directory of the document, `None` if it is not known.
+    document_dir: Option<PathBuf>,
+    /// The path from the directory of the PDF to the document directory.
+    prefix: String,
+}
+
+/// A relative link after resolving, with a warning if its target is missing.
+pub struct ResolvedUri {
+    pub uri: String,
+    pub warning: Option<String>,
+}
+
+impl LinkBase {
+    /// Leave relative links as they are, for documents that are not files.
+    pub fn none() -> LinkBase {
+        LinkBase { document_dir: None, prefix: String::new() }
+    }
+
+    /// Resolve links of a document in `document_dir`, for a PDF in
+    /// `output_dir`, or next to the document if `None`.
+    pub fn new(document_dir: &Path, output_dir: Option<&Path>) -> LinkBase {
+        // An empty path is the working directory, which canonicalize rejects.
+        let or_cwd = |dir: &Path| if dir == Path::new("") { PathBuf::from(".") } else { dir.to_path_buf() };
+        let prefix = output_dir
+            .and_then(|out| relative_dir(&or_cwd(out), &or_cwd(document_dir)))
+            .unwrap_or_default();
+        LinkBase { document_dir: Some(document_dir.to_path_buf()), prefix: prefix }
+    }
+
+    /// Rewrite a relative link to be relative to the PDF, and check its target.
+    ///
+    /// Links with a scheme, links to a fragment, and absolute paths are left
+    /// as they are.
+    pub fn resolve(&self, uri: &str) -> ResolvedUri {
+        let unchanged = ResolvedUri { uri: uri.to_string(), warning: None };
+        let document_dir = match self.document_dir {
+            Some(ref dir) => dir,
+            None => return unchanged,
+        };
+        if scheme(uri).is_some() {
+            return unchanged
+        }
+        let path_len = uri.find(|c| c == '?' || c == '#').unwrap_or(uri.len());
+        let path = unescape(&uri[..path_len]);
+        if path.is_empty() {
+            return unchanged
+        }
+
+        let is_absolute = Path::new(&path).is_absolute() || path.starts_with('/');
+        let target = if is_absolute { PathBuf::from(&path) } else { document_dir.join(&path) };
+        let warning = if target.exists() {
+            None
+        } else {
+            Some(format!(
+                "hyperlink '{}' points to '{}', which does not exist.",
+                uri, target.display()
+            ))
+        };
+        let uri = if is_absolute { uri.to_string() } else { format!("{}{}", self.prefix, uri) };
+        ResolvedUri { uri: uri, warning: warning }
+    }
+}
+
+#[test]
+fn check_accepts_common_links() {
+    for &uri in &["https://github.com/ruuda/pris", "mailto:user@example.com", "slides/next.pdf"] {
+        let checked = check(uri).unwrap();
+        assert_eq!(&checked.uri[..], uri);
+        assert!(checked.warning.is_none());
+    }
+}
+
+#[test]
+fn check_escapes_invalid_characters() {
+    let checked = check("docs/my notes ü.pdf").unwrap();
+    assert_eq!(checked.uri, "docs/my%20notes%20%C3%BC.pdf");
+}
+
+#[test]
+fn check_rejects_disallowed_schemes() {
+    assert!(check("javascript:alert(1)").is_err());
+    assert!(check("").is_err());
+}
+
+#[test]
+fn check_warns_about_malformed_links() {
+    assert!(check("https:/example.com").unwrap().warning.is_some());
+    assert!(check("mailto:example.com").unwrap().warning.is_some());
+    assert!(check("C:\\slides\\deck.pdf").unwrap().warning.is_some());
+}
+
+#[test]
+fn link_base_rewrites_relative_links_for_the_output_dir() {
+    use std::env;
+    use std::fs;
+
+    let root = env::temp_dir().join("pris-link-base");
+    let (talks, out) = (root.join("talks"), root.join("out"));
+    fs::create_dir_all(&talks).unwrap();
+    fs::create_dir_all(&out).unwrap();
+    fs::write(talks.join("notes.pdf"), b"").unwrap();
+
+    let base = LinkBase::new(&talks, Some(&out));
+    let resolved = base.resolve("notes.pdf#page=2");
+    assert_eq!(resolved.uri, "../talks/notes.pdf#page=2");
+    assert!(resolved.warning.is_none());
+
+    // Next to the document, links stay as they are, but are still checked.
+    let base = LinkBase::new(&talks, Some(&talks));
+    let resolved = base.resolve("missing%20file.pdf");
+    assert_eq!(resolved.uri, "missing%20file.pdf");
+    let warning = resolved.warning.unwrap();
+    assert!(warning.contains("missing file.pdf"), "{}", warning);
+
+    // Links with a scheme and fragments are not files.
+    for &uri in &["https://example.com/x", "#slide-2"] {
+        let resolved = base.resolve(uri);
+        assert_eq!(resolved.uri, uri);
+        assert!(resolved.warning.is_none());
+    }
+    assert!(LinkBase::none().resolve("missing.pdf").warning.is_none());
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -10,31 +10,140 @@
 
 extern crate pris;
 
+use std::env;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use pris::ast::Idents;
+#[cfg(feature = "native")]
+use pris::compile;
+#[cfg(feature = "native")]
+use pris::driver;
+use pris::error::Error;
 use pris::interpreter;
 use pris::lexer;
+use pris::limits::Limits;
+use pris::logger::{Level, MemoryLogger};
+use pris::module;
 use pris::parser;
+use pris::profile::Profile;
 use pris::runtime;
 use pris::pretty;
+use pris::symbol::Symbol;
+use pris::toc;
+#[cfg(not(feature = "native"))]
+use pris::todo::Todos;
 
-fn eval<'a>(input: &'a [u8]) -> String {
+/// Lex and parse a script, let `setup` configure an interpreter for it, and
+/// evaluate the statements until one fails. Return what `inspect` makes of the
+/// interpreter and the outcome: the frames of the slides, or the first error.
+fn eval_source<S, F, T>(input: &[u8], fm: &mut runtime::FontMap, setup: S, inspect: F) -> T
+where
+    S: for<'i, 'a> FnOnce(&mut interpreter::StmtInterpreter<'i, 'a>),
+    F: for<'i, 'a> FnOnce(
+        &interpreter::StmtInterpreter<'i, 'a>,
+        Result<Vec<Rc<runtime::Frame<'a>>>, Error>,
+    ) -> T,
+{
     let doc = lexer::lex(input)
         .and_then(|tokens| parser::parse(&tokens[..]))
         .expect("Test script contains syntax error.");
 
-    let mut fm = runtime::FontMap::new();
-    let mut stmt_interpreter = interpreter::StmtInterpreter::new(&mut fm);
+    let mut stmt_interpreter = interpreter::StmtInterpreter::new(fm);
+    stmt_interpreter.set_section_count(doc.count_sections());
+    setup(&mut stmt_interpreter);
+    let mut frames = Vec::new();
+    let mut outcome = Ok(());
     for statement in &doc.0 {
-        stmt_interpreter
-            .eval_statement(statement)
-            .expect("Test script failed with an error.");
+        match stmt_interpreter.eval_statement(statement) {
+            Ok(Some(frame)) => frames.push(frame),
+            Ok(None) => {}
+            Err(err) => {
+                outcome = Err(err);
+                break;
+            }
+        }
     }
-    let result = stmt_interpreter
+    let outcome = outcome.map(|()| frames);
+    inspect(&stmt_interpreter, outcome)
+}
+
+/// Return the value that a script bound to the 'result' variable.
+fn result_of<'i, 'a>(stmt_interpreter: &interpreter::StmtInterpreter<'i, 'a>) -> runtime::Val<'a> {
+    stmt_interpreter
         .env()
-        .lookup(&Idents(vec!["result"]))
-        .expect("Test script did not assign to 'result' variable.");
+        .lookup(&Idents(vec![Symbol::intern("result")]))
+        .map(|result| (*result).clone())
+        .expect("Test script did not assign to 'result' variable.")
+}
+
+/// Evaluate a script, and pretty-print the value of the 'result' variable.
+fn eval(input: &[u8]) -> String {
+    let mut fm = runtime::FontMap::new();
+    eval_source(input, &mut fm, |_| (), |stmt_interpreter, outcome| {
+        outcome.expect("Test script failed with an error.");
+        pretty::print(result_of(stmt_interpreter))
+    })
+}
+
+/// Evaluate a script, and assert that the 'result' variable equals `expected`.
+fn assert_eval(input: &[u8], expected: runtime::Val<'static>) {
+    let mut fm = runtime::FontMap::new();
+    eval_source(input, &mut fm, |_| (), |stmt_interpreter, outcome| {
+        outcome.expect("Test script failed with an error.");
+        assert_eq!(result_of(stmt_interpreter), expected);
+    })
+}
+
+/// Evaluate a script, and return the number of elements in every subframe of
+/// the frame bound to the 'result' variable.
+fn eval_steps(input: &[u8]) -> Vec<usize> {
+    let mut fm = runtime::FontMap::new();
+    eval_source(input, &mut fm, |_| (), |stmt_interpreter, outcome| {
+        outcome.expect("Test script failed with an error.");
+        match result_of(stmt_interpreter) {
+            runtime::Val::Frame(frame) => frame
+                .get_subframes()
+                .iter()
+                .map(|sf| sf.get_elements().len())
+                .collect(),
+            _ => panic!("Test script assigned a non-frame to 'result'."),
+        }
+    })
+}
+
+/// Evaluate a script with an interpreter that `setup` configured, and return
+/// the error that it fails with.
+fn eval_error_with<S>(input: &[u8], setup: S) -> Error
+where
+    S: for<'i, 'a> FnOnce(&mut interpreter::StmtInterpreter<'i, 'a>),
+{
+    let mut fm = runtime::FontMap::new();
+    eval_source(input, &mut fm, setup, |_, outcome| match outcome {
+        Err(err) => err,
+        Ok(..) => panic!("Expected the test script to fail."),
+    })
+}
+
+/// Evaluate a script, and return the message of the error it fails with.
+fn eval_error(input: &[u8]) -> String {
+    eval_error_with(input, |_| ()).message()
+}
 
-    pretty::print(result)
+/// Evaluate a script, and collect the slides it produces for rendering.
+#[cfg(feature = "native")]
+fn compile(input: &[u8], fm: &mut runtime::FontMap) -> driver::CompiledDoc {
+    eval_source(input, fm, |_| (), |stmt_interpreter, outcome| {
+        let slides = outcome
+            .expect("Test script failed with an error.")
+            .iter()
+            .map(|frame| driver::Slide::from_frame(frame).unwrap())
+            .collect();
+        let page = driver::PageSetup::from_env(stmt_interpreter.env()).unwrap();
+        driver::CompiledDoc::new(slides, page)
+    })
 }
 
 #[test]
@@ -59,3 +168,1080 @@ fn eval_coord_y() {
     "#;
     assert_eq!(eval(src), "3840 : len");
 }
+
+#[test]
+fn eval_nested_frame_field() {
+    let src = br#"
+    frame = { coord = (1w, 2w) }
+    result = frame.coord.y
+    "#;
+    assert_eq!(eval(src), "3840 : len");
+}
+
+#[test]
+fn eval_values_compare_structurally() {
+    use pris::runtime::Val;
+    assert_eval(b"result = 2w * 3", Val::Num(6.0 * 1920.0, 1));
+    assert_eval(b"result = (1, 2) + (3, 4)", Val::Coord(4.0, 6.0, 0));
+    assert_eval(b"result = [\"a\"; \"b\" + \"c\"]", Val::List(vec![
+        Val::Str("a".to_string()),
+        Val::Str("bc".to_string()),
+    ]));
+    assert_eval(b"result = #ff0000", Val::Col(pris::Color::new(1.0, 0.0, 0.0)));
+}
+
+#[test]
+fn eval_list_rejects_mixed_element_types() {
+    assert_eq!(
+        eval_error(b"result = [(1, 2); (3, 4); 5]"),
+        "Encountered elements of type 'coord of num' as well as 'num' in one list, \
+         at index 2, but all elements must have the same type.",
+    );
+    assert_eq!(
+        eval_error(b"result = [1; 1w]"),
+        "Encountered elements of type 'num' as well as 'len' in one list, \
+         at index 1, but all elements must have the same type.",
+    );
+}
+
+#[test]
+fn eval_list_rejects_mixed_nested_element_types() {
+    assert_eq!(
+        eval_error(b"result = [[1; 2]; [\"a\"]]"),
+        "Encountered elements of type 'list of num' as well as 'list of str' in one list, \
+         at index 1, but all elements must have the same type.",
+    );
+}
+
+#[test]
+fn eval_list_allows_empty_nested_lists() {
+    assert_eq!(eval(b"result = [[1]; []; [2]]"), "[[1 : num; ]; []; [2 : num; ]; ]");
+}
+
+#[test]
+fn eval_user_function_infix() {
+    let src = br#"
+    plus_twice = function(x, y) { return x + 2 * y }
+    result = 1 plus_twice 2 + 3
+    "#;
+    assert_eq!(eval(src), "11 : num");
+}
+
+#[test]
+fn eval_user_function_infix_with_precedence() {
+    let src = br#"
+    infix 1 times
+    times = function(x, y) { return x * y }
+    plus = function(x, y) { return x + y }
+    result = 2 plus 3 times 4
+    "#;
+    assert_eq!(eval(src), "14 : num");
+}
+
+#[test]
+fn eval_assign_to_frame_property_fails() {
+    for &src in &[&b"width = 1em"[..], b"{ size = (1em, 1em) }", b"offset = 0"] {
+        eval_error(src);
+    }
+}
+
+#[test]
+fn eval_update_operators_are_assignment_sugar() {
+    let result = eval(b"y = 1 y += 2 y *= 3 y -= 1 result = y");
+    assert_eq!(result, "8 : num");
+    let result = eval(b"label = \"Step\" label += \" 2\" result = label");
+    assert_eq!(result, "\"Step 2\"");
+
+    let message = eval_error(b"y = 1w y += 2");
+    assert!(message.contains("'y += 2'"), "{}", message);
+}
+
+#[test]
+fn eval_repeat_runs_body_in_enclosing_scope() {
+    let result = eval(b"x = 0 repeat 2 { repeat 3 { x += 1 } x += repeat_index * 10 } result = x");
+    assert_eq!(result, "16 : num");
+    let steps = eval_steps(b"result = { repeat 4 { put fill_rectangle((1em, 1em)) at (repeat_index * 2em, 0em) } }");
+    assert_eq!(steps, vec![4]);
+}
+
+#[test]
+fn eval_repeat_index_is_undefined_after_the_loop() {
+    let message = eval_error(b"repeat 2 { } result = repeat_index");
+    assert!(message.contains("'repeat_index' does not exist"), "{}", message);
+    let result = eval(b"x = 0 repeat 2 { repeat 3 { } x += repeat_index } result = x");
+    assert_eq!(result, "1 : num");
+}
+
+#[test]
+fn eval_match_selects_arm_by_string() {
+    let src = b"pick = function(kind) { return match kind { \"title\" -> 1, \"section\" -> 2, else -> 0 } } \
+                result = [pick(\"title\"); pick(\"section\"); pick(\"quote\")]";
+    assert_eq!(eval(src), "[1 : num; 2 : num; 0 : num; ]");
+    // Arms that are not selected are not evaluated.
+    assert_eq!(eval(b"result = match \"a\" { \"a\" -> 1, \"b\" -> 1 + \"x\" }"), "1 : num");
+    assert!(eval_error(b"result = match \"c\" { \"a\" -> 1 }").contains("Note: add an 'else' arm."));
+    assert!(eval_error(b"result = match 1 { \"a\" -> 1 }").contains("type 'str'"));
+}
+
+#[test]
+fn eval_or_defaults_unset_variables_and_none() {
+    let src = b"subtitle_text = none \
+                result = [title_text or \"a\"; subtitle_text or \"b\"; \"c\" or title_text]";
+    assert_eq!(eval(src), "[\"a\"; \"b\"; \"c\"; ]");
+    // The default is only evaluated when it is needed.
+    assert_eq!(eval(b"result = 1 or 1 + \"x\""), "1 : num");
+    assert_eq!(eval(b"result = none or none or 2"), "2 : num");
+    assert!(eval_error(b"result = title_text").contains("does not exist"));
+}
+
+#[test]
+fn eval_with_restores_overridden_variables() {
+    let src = b"side = 1em \
+                frame = { with { side = 3em } { put fill_rectangle((side, side)) seen = side } inner = seen or 0em } \
+                result = [frame.width / side; frame.inner / side]";
+    assert_eq!(eval(src), "[3 : num; 0 : num; ]");
+    // Overrides are evaluated in order, in the enclosing scope.
+    let src = b"gap = 1 f = function() { with { gap = gap * 2 gap = gap + 1 } { x = gap } return gap } result = f()";
+    assert_eq!(eval(src), "1 : num");
+    assert!(eval_error(b"with { gap = 1 } { { put t(\"x\") } }").contains("cannot be introduced in 'with'"));
+}
+
+#[test]
+fn eval_define_overrides_top_level_assignment() {
+    let mut fm = runtime::FontMap::new();
+    let result = eval_source(
+        b"variant = \"short\" result = variant + \" talk\"",
+        &mut fm,
+        |stmt_interpreter| stmt_interpreter.define("variant", runtime::Val::Str("long".into())),
+        |stmt_interpreter, outcome| {
+            outcome.unwrap();
+            pretty::print(result_of(stmt_interpreter))
+        },
+    );
+    assert_eq!(result, "\"long talk\"");
+}
+
+#[test]
+fn eval_getenv_requires_opt_in() {
+    let src = b"result = getenv(\"PRIS_TEST_VERSION\")";
+    env::set_var("PRIS_TEST_VERSION", "1.2.0");
+    eval_error(src);
+
+    let mut fm = runtime::FontMap::new();
+    let result = eval_source(src, &mut fm, |i| i.allow_getenv(), |stmt_interpreter, outcome| {
+        outcome.unwrap();
+        pretty::print(result_of(stmt_interpreter))
+    });
+    assert_eq!(result, "\"1.2.0\"");
+}
+
+#[test]
+fn eval_pris_version_must_be_supported() {
+    let mut fm = runtime::FontMap::new();
+    eval_source(b"pris_version \"0.2\" x = 1", &mut fm, |_| (), |_, outcome| {
+        outcome.unwrap();
+    });
+    for &input in &[&b"pris_version \"99.0\""[..], b"pris_version \"0.1\""] {
+        eval_error(input);
+    }
+}
+
+#[test]
+fn eval_import_evaluates_module_in_scope() {
+    let source = |name: &str, bytes: &[u8]| module::Source {
+        name: name.to_string(),
+        path: Some(PathBuf::from(format!("{}.pris", name))),
+        bytes: bytes.to_vec(),
+    };
+    let sources = vec![
+        source("theme.colors", b"import theme.base accent = base * 2"),
+        source("theme.base", b"base = 21"),
+        source("loop", b"import loop"),
+        source("slide", b"{ }"),
+    ];
+    let modules = module::Modules::parse(&sources).unwrap();
+    let doc = lexer::lex(b"import theme.colors result = accent import loop import slide")
+        .and_then(|tokens| parser::parse(&tokens[..]))
+        .unwrap();
+
+    let mut fm = runtime::FontMap::new();
+    let mut stmt_interpreter = interpreter::StmtInterpreter::new(&mut fm);
+    stmt_interpreter.set_modules(&modules);
+    stmt_interpreter.eval_statement(&doc.0[0]).unwrap();
+    stmt_interpreter.eval_statement(&doc.0[1]).unwrap();
+    let result = stmt_interpreter.env().lookup(&Idents(vec![Symbol::intern("result")])).unwrap();
+    assert_eq!(pretty::print(result), "42 : num");
+    match stmt_interpreter.eval_statement(&doc.0[2]) {
+        Err(Error::Other(msg)) => assert!(msg.contains("'loop -> loop'")),
+        _ => panic!("Expected import cycle to fail."),
+    }
+    assert!(stmt_interpreter.eval_statement(&doc.0[3]).is_err());
+}
+
+#[test]
+fn eval_import_loads_standard_library() {
+    let doc = lexer::lex(b"import std.colors import std.layout import std.arrows \
+                           result = blue expected = #2263a6 \
+                           card_frame = card({ put arrow_to((0em, 0em), (1em, 1em)) }) \
+                           centered = center(card_frame)")
+        .and_then(|tokens| parser::parse(&tokens[..]))
+        .unwrap();
+    let search_path = module::SearchPath::new(Vec::new());
+    let sources = module::load_sources(&doc, &env::temp_dir(), &search_path).unwrap();
+    assert!(sources.iter().all(|source| source.path.is_none()));
+    let modules = module::Modules::parse(&sources).unwrap();
+
+    let mut fm = runtime::FontMap::new();
+    let mut stmt_interpreter = interpreter::StmtInterpreter::new(&mut fm);
+    stmt_interpreter.set_modules(&modules);
+    for statement in &doc.0 {
+        stmt_interpreter.eval_statement(statement).unwrap();
+    }
+    let result = stmt_interpreter.env().lookup(&Idents(vec![Symbol::intern("result")])).unwrap();
+    let expected = stmt_interpreter.env().lookup(&Idents(vec![Symbol::intern("expected")])).unwrap();
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn eval_section_index_and_count() {
+    let src = br#"
+    section "Introduction"
+    { }
+    section "Results"
+    result = (section_index, section_count)
+    section "Conclusion"
+    "#;
+    assert_eq!(eval(src), "(2, 3) : coord of num");
+}
+
+#[test]
+fn eval_current_section() {
+    let src = br#"
+    section "Results"
+    result = current_section + "!"
+    "#;
+    assert_eq!(eval(src), "\"Results!\"");
+}
+
+#[test]
+fn eval_auto_toc_requests_a_second_pass() {
+    let toc = Rc::new(toc::Toc::new(Vec::new()));
+    let mut fm = runtime::FontMap::new();
+    let src = b"{ put auto_toc() } slide_title = \"Intro\" { }";
+    let entries = eval_source(src, &mut fm, |i| i.set_toc(toc.clone()), |_, outcome| {
+        toc::collect(&outcome.unwrap())
+    });
+    assert!(toc.is_used());
+    assert_eq!(entries.len(), 1);
+    assert_eq!((&entries[0].title[..], entries[0].slide), ("Intro", 2));
+}
+
+#[test]
+// With the native feature, the placeholder typesets its message, which needs
+// fonts. Without it, the placeholder is an empty box.
+#[cfg(not(feature = "native"))]
+fn eval_todo_records_placeholders_with_their_slide() {
+    let todos = Rc::new(Todos::new());
+    let mut fm = runtime::FontMap::new();
+    let src = b"note = todo(\"title\")\n{ put note }\n{ put todo(\"diagram\") }";
+    eval_source(src, &mut fm, |i| i.set_todos(todos.clone()), |_, outcome| {
+        outcome.unwrap();
+    });
+    let todos: Vec<_> = todos.list().into_iter().map(|t| (t.message, t.slide)).collect();
+    assert_eq!(todos, vec![("title".to_string(), None), ("diagram".to_string(), Some(2))]);
+}
+
+#[test]
+fn eval_units_derive_from_canvas_size() {
+    assert_eq!(eval(b"canvas_size = (720, 540) result = 12pt"), "6 : len");
+    assert_eq!(eval(b"canvas_size = (720, 540) result = (1w, 1h)"), "(720, 540) : coord of len");
+    // Functions resolve units when they are called, not when they are defined.
+    let middle = "middle = function() { return (0.5w, 0.5h) }\n";
+    assert_eq!(eval(format!("{}canvas_size = (842, 1191) result = middle()", middle).as_bytes()), "(421, 595.5) : coord of len");
+}
+
+#[test]
+fn eval_percent_units_are_relative_to_container() {
+    assert_eq!(eval(b"canvas_size = (800, 400) result = (50pw, 25ph)"), "(400, 100) : coord of len");
+    assert_eq!(eval(b"canvas_size = (1000, 1000) container_size = (0.2w, 0.1h) result = (50pw, 25ph)"), "(100, 25) : coord of len");
+    // Assigning the container in a function does not affect the caller.
+    let card = "card = function() { container_size = (0.5w, 0.5h) return 10pw }\n";
+    assert_eq!(eval(format!("{}result = card()", card).as_bytes()), "96 : len");
+    assert_eq!(eval(format!("{}x = card()\nresult = 10pw", card).as_bytes()), "192 : len");
+    assert_eq!(eval(b"canvas_size = (1000, 1000) container_size = (0.2w, 0.1h) result = str_len(50pw, \"ph\")"), "\"100ph\"");
+}
+
+#[test]
+fn eval_percent_units_inside_fit_and_pad_measure_their_container() {
+    // Half the width of the box to fit in is 192. Measured against the canvas
+    // it would be 960, and the rectangle would be scaled down to fit.
+    let src = b"frame = fit(fill_rectangle((50pw, 100ph)), (0.2w, 0.1h))\nresult = frame.size";
+    assert_eq!(eval(src), "(192, 108) : coord of len");
+    // Padding takes space from the container, so the padded frame fills it.
+    let src = b"frame = pad(fill_rectangle((100pw, 50ph)), 0.1w)\nresult = frame.size";
+    assert_eq!(eval(src), "(1920, 732) : coord of len");
+    let src = b"container_size = (0.5w, 0.5h)\nframe = pad_left(fill_rectangle((100pw, 100ph)), 0.1w)\nresult = frame.size";
+    assert_eq!(eval(src), "(960, 540) : coord of len");
+    // Outside of the call, the units measure the enclosing container again.
+    let src = b"frame = fit(fill_rectangle((50pw, 100ph)), (0.2w, 0.1h))\nresult = 50pw";
+    assert_eq!(eval(src), "960 : len");
+}
+
+#[test]
+fn eval_str_len_converts_units() {
+    assert_eq!(eval(b"result = str_len(0.1h, \"h\")"), "\"0.1h\"");
+    assert_eq!(eval(b"canvas_size = (720, 720) result = str_len(0.1h, \"cm\")"), "\"2.54 cm\"");
+    assert_eq!(eval(b"canvas_size = (720, 720) decimals = 0 result = str_len(0.1h, \"mm\")"), "\"25 mm\"");
+    assert_eq!(eval(b"result = str_len(1.5em, \"em\")"), "\"1.5em\"");
+}
+
+#[test]
+fn eval_as_len_and_as_num_convert_dimension() {
+    assert_eq!(eval(b"result = as_len(3)"), "3 : len");
+    assert_eq!(eval(b"canvas_size = (720, 540) result = as_num((0.5w, 1h))"), "(360, 540) : coord of num");
+    assert_eq!(eval(b"result = 1w + as_len(2)"), "1922 : len");
+}
+
+#[test]
+fn eval_dimension_errors_name_the_dimensions() {
+    let msg = eval_error(b"result = 2w - 1w * 1w");
+    assert!(msg.contains("found a length and an area, in '(2w - (1w * 1w))'"), "{}", msg);
+    let msg = eval_error(b"result = (1w, 2)");
+    assert!(msg.contains("found a length and a number, in '(1w, 2)'"), "{}", msg);
+    let msg = eval_error(b"result = sqrt(2w)");
+    assert!(msg.contains("but found a length"), "{}", msg);
+    let msg = eval_error(b"result = as_num(2)");
+    assert!(msg.contains("'as_num'"), "{}", msg);
+}
+
+#[test]
+fn eval_exp_raises_to_power() {
+    assert_eq!(eval(b"result = 2 ^ 10"), "1024 : num");
+    assert_eq!(eval(b"result = 4 ^ 0.5"), "2 : num");
+    assert_eq!(eval(b"result = 2 ^ (-1)"), "0.5 : num");
+    assert_eq!(eval(b"result = sqrt((0.1w) ^ 2)"), "192 : len");
+    assert_eq!(eval(b"result = 1w * (1w) ^ (-1)"), "1 : num");
+    let msg = eval_error(b"result = (1w) ^ 0.5");
+    assert!(msg.contains("Cannot raise a length to the power 0.5"), "{}", msg);
+    let msg = eval_error(b"result = 2 ^ 1w");
+    assert!(msg.contains("'^' expects operands of type 'num'"), "{}", msg);
+    let msg = eval_error(b"result = (-8) ^ 0.5");
+    assert!(msg.contains("not a real number"), "{}", msg);
+}
+
+#[test]
+fn eval_transforms_compose_right_to_left() {
+    assert_eq!(eval(b"result = translate((10w, 0w)) * scale(2)"), "[2, 0, 19200; 0, 2, 0] : transform");
+    assert_eq!(eval(b"result = translate((1w, 0w)) * rotate(90) * (1w, 0w)"), "(1920, 1920) : coord of len");
+    assert_eq!(eval(b"result = scale((2, 3)) * (1w, 1w)"), "(3840, 5760) : coord of len");
+}
+
+#[test]
+fn eval_transform_transforms_bounding_box() {
+    let src = b"frame = transform(line((0.1w, 0w)), translate((0w, 0.1w)) * rotate(90))\n";
+    assert_eq!(eval(&[&src[..], b"result = frame.size"].concat()), "(0, 384) : coord of len");
+    assert_eq!(eval(&[&src[..], b"result = frame.offset"].concat()), "(0, 0) : coord of len");
+    let msg = eval_error(b"result = transform(line((0.1w, 0w)), scale((1, 0)))");
+    assert!(msg.contains("flattens"), "{}", msg);
+}
+
+#[test]
+fn eval_rotate_rotates_frame_and_bounding_box() {
+    let src = b"frame = rotate(fill_rectangle((0.1w, 0.05w)), 90)\n";
+    assert_eq!(eval(&[&src[..], b"result = frame.size"].concat()), "(96, 192) : coord of len");
+    assert_eq!(eval(&[&src[..], b"result = frame.offset"].concat()), "(-96, 0) : coord of len");
+    let msg = eval_error(b"result = rotate(fill_rectangle((0.1w, 0.05w)), 90w)");
+    assert!(msg.contains("rotate"), "{}", msg);
+}
+
+#[test]
+fn eval_polar_and_rotate_coord() {
+    assert_eq!(eval(b"result = polar(2w, 90) + (0.5w, 0w)"), "(960, 3840) : coord of len");
+    assert_eq!(eval(b"result = rotate_coord((1, 0), 180) + (1, 0)"), "(0, 0) : coord of num");
+}
+
+#[test]
+fn eval_tile_size() {
+    let src = br#"
+    square = fill_rectangle((1w, 1w))
+    tiles = tile(square, 3, 2, 1w)
+    result = tiles.size
+    "#;
+    assert_eq!(eval(src), "(9600, 5760) : coord of len");
+}
+
+#[test]
+fn eval_above_size() {
+    let src = br#"
+    gap = 1w
+    stacked = fill_rectangle((1w, 2w)) above fill_rectangle((3w, 1w))
+    result = stacked.size
+    "#;
+    assert_eq!(eval(src), "(5760, 7680) : coord of len");
+}
+
+#[test]
+fn eval_right_of_offset() {
+    let src = br#"
+    row = fill_rectangle((1w, 1w)) right_of fill_rectangle((2w, 1w))
+    result = row.offset
+    "#;
+    assert_eq!(eval(src), "(-3840, 0) : coord of len");
+}
+
+#[test]
+fn eval_place_all_size() {
+    let src = br#"
+    square = fill_rectangle((1w, 1w))
+    placed = place_all([square; square], [(1w, 0w); (3w, 2w)])
+    result = placed.size
+    "#;
+    assert_eq!(eval(src), "(7680, 5760) : coord of len");
+}
+
+#[test]
+fn eval_place_all_requires_a_position_per_frame() {
+    let msg = eval_error(b"result = place_all([{}; {}], [(0w, 0w)])");
+    assert!(msg.contains("2 frames and 1 positions"), "{}", msg);
+}
+
+#[test]
+fn eval_adjoin_all_aligns_steps() {
+    let src = br#"
+    dot = fill_circle(0.1w)
+    revealed = { put dot put dot on_step 1 }
+    result = adjoin_all([dot; revealed; dot], (1w, 0w))
+    "#;
+    assert_eq!(eval_steps(src), vec![3, 4]);
+}
+
+#[test]
+fn eval_pad_size() {
+    let src = br#"
+    padded = pad_left(pad(fill_rectangle((1w, 2w)), 1w), 2w)
+    result = padded.size
+    "#;
+    assert_eq!(eval(src), "(9600, 7680) : coord of len");
+}
+
+#[test]
+fn eval_fits_compares_size_with_container() {
+    assert_eq!(eval(b"result = fits(line((0.2w, 0.1h)), (0.2w, 0.1h))"), "1 : num");
+    assert_eq!(eval(b"result = fits(line((0.2w, 0.1h)), (0.1w, 0.1h))"), "0 : num");
+}
+
+#[test]
+fn eval_clip_or_warn_warns_on_overflow() {
+    let logger = Rc::new(MemoryLogger::new());
+    let mut fm = runtime::FontMap::new();
+    let src = b"result = clip_or_warn(line((0.2w, 0.1h)), (0.1w, 0.1h))";
+    eval_source(src, &mut fm, |i| i.set_logger(logger.clone()), |_, outcome| {
+        outcome.unwrap();
+    });
+    let messages = logger.take();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].0, Level::Warning);
+    assert!(messages[0].1.contains("will be clipped"));
+}
+
+#[test]
+fn eval_definition_list_validates_pairs() {
+    let msg = eval_error(b"result = definition_list([[\"Ctrl+C\"; \"Copy\"; \"Paste\"]], 0.5w)");
+    assert!(msg.contains("entry 0 is not"), "{}", msg);
+    let msg = eval_error(b"result = definition_list([[\"Ctrl+C\"; \"Copy\"]; [\"Ctrl+V\"]], 0.5w)");
+    assert!(msg.contains("entry 1 is not"), "{}", msg);
+    let msg = eval_error(b"result = definition_list([[\"Ctrl+C\"; \"Copy\"]], 0.5)");
+    assert!(msg.contains("definition_list"), "{}", msg);
+}
+
+#[test]
+fn eval_tapered_line_size() {
+    let src = br#"
+    arrow = tapered_line((0w, 1w), 0.2w, 0w)
+    result = arrow.size
+    "#;
+    assert_eq!(eval(src), "(384, 1920) : coord of len");
+}
+
+#[test]
+fn eval_gradient_fills_shapes() {
+    assert_eq!(
+        eval(b"result = linear_gradient(#ff0000, #0000ff, 90)"),
+        "linear_gradient((1, 0, 0), (0, 0, 1), 90) : gradient",
+    );
+    assert_eq!(
+        eval(b"result = radial_gradient(#ffffff, #000000)"),
+        "radial_gradient((1, 1, 1), (0, 0, 0)) : gradient",
+    );
+    let src = b"color = radial_gradient(#ffffff, #000000)\n";
+    assert_eq!(eval(&[&src[..], b"frame = fill_circle(0.1w)\nresult = frame.size"].concat()), "(384, 384) : coord of len");
+    let msg = eval_error(b"result = { color = radial_gradient(#ffffff, #000000) put line((0.1w, 0w)) }");
+    assert!(msg.contains("gradient"), "{}", msg);
+    let msg = eval_error(b"result = linear_gradient(#ff0000, #0000ff)");
+    assert!(msg.contains("linear_gradient"), "{}", msg);
+}
+
+#[test]
+fn eval_color_with_alpha_and_opacity() {
+    assert_eq!(eval(b"result = #ff000000"), "(1, 0, 0, 0) : color");
+    assert_eq!(eval(b"result = #ff0000ff"), "(1, 0, 0) : color");
+    let src = b"frame = opacity(fill_rectangle((0.1w, 0.05w)), 0.5)\n";
+    assert_eq!(eval(&[&src[..], b"result = frame.size"].concat()), "(192, 96) : coord of len");
+    let msg = eval_error(b"result = opacity(fill_rectangle((0.1w, 0.05w)), 1.5)");
+    assert!(msg.contains("between 0 and 1"), "{}", msg);
+    let msg = eval_error(b"result = opacity(fill_rectangle((0.1w, 0.05w)), 0.5w)");
+    assert!(msg.contains("opacity"), "{}", msg);
+}
+
+#[test]
+fn eval_boxed_draws_border_on_every_step() {
+    let src = br#"
+    dot = fill_circle(1em)
+    result = boxed({ put dot put dot on_step 1 })
+    "#;
+    assert_eq!(eval_steps(src), vec![2, 3]);
+}
+
+#[test]
+fn eval_highlight_is_below_content_on_every_step() {
+    let src = br#"
+    dot = fill_circle(1em)
+    result = highlight({ put dot put dot on_step 1 }, #ffee00, 1w)
+    "#;
+    assert_eq!(eval_steps(src), vec![2, 3]);
+}
+
+#[test]
+fn eval_highlight_size() {
+    let src = br#"
+    highlighted = highlight(fill_rectangle((1w, 2w)), #ffee00, 1w)
+    result = highlighted.size
+    "#;
+    assert_eq!(eval(src), "(5760, 7680) : coord of len");
+}
+
+#[test]
+fn eval_symbol_preserves_size() {
+    let src = br#"
+    logo = symbol(fill_rectangle((1w, 2w)))
+    result = logo.size
+    "#;
+    assert_eq!(eval(src), "(1920, 3840) : coord of len");
+}
+
+#[test]
+fn eval_on_step_keeps_earlier_content_visible() {
+    let src = br#"
+    dot = fill_circle(1em)
+    result = {
+      put dot
+      put dot on_step 2
+      put dot on_step 1
+    }
+    "#;
+    assert_eq!(eval_steps(src), vec![1, 2, 3]);
+}
+
+#[test]
+fn eval_put_after_reveal_appears_on_every_step() {
+    let src = br#"
+    dot = fill_circle(1em)
+    result = {
+      put dot on_step 1
+      put dot
+    }
+    "#;
+    assert_eq!(eval_steps(src), vec![1, 2]);
+}
+
+#[test]
+fn eval_adjoin_with_reveal() {
+    let src = br#"
+    dot = fill_circle(1em)
+    revealed = dot ~ (dot on_step 1)
+    result = {
+      put revealed ~ dot
+    }
+    "#;
+    assert_eq!(eval_steps(src), vec![2, 3]);
+}
+
+#[test]
+fn eval_adjoin_parallel_aligns_steps() {
+    // Step i of the result shows step i of both frames. A frame with fewer
+    // steps stays in its final state, like the dot here.
+    let src = br#"
+    dot = fill_circle(1em)
+    revealed = { put dot put dot on_step 1 put dot on_step 2 }
+    result = adjoin_parallel(revealed, { put dot put dot on_step 1 })
+    "#;
+    assert_eq!(eval_steps(src), vec![2, 4, 5]);
+}
+
+#[test]
+fn eval_adjoin_parallel_is_adjoin_operator() {
+    let src_fn = br#"
+    dot = fill_circle(1em)
+    result = adjoin_parallel({ put dot put dot on_step 1 }, dot)
+    "#;
+    let src_op = br#"
+    dot = fill_circle(1em)
+    result = { put dot put dot on_step 1 } ~ dot
+    "#;
+    assert_eq!(eval_steps(src_fn), vec![2, 3]);
+    assert_eq!(eval_steps(src_op), vec![2, 3]);
+}
+
+#[test]
+fn eval_adjoin_sequential_concatenates_steps() {
+    // The steps of the second frame start after the last step of the first,
+    // which remains visible.
+    let src = br#"
+    dot = fill_circle(1em)
+    revealed = { put dot put dot on_step 1 }
+    result = adjoin_sequential(revealed, revealed)
+    "#;
+    assert_eq!(eval_steps(src), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn eval_adjoin_sequential_of_static_frames_adds_step() {
+    let src = br#"
+    dot = fill_circle(1em)
+    result = adjoin_sequential(dot, dot)
+    "#;
+    assert_eq!(eval_steps(src), vec![1, 2]);
+}
+
+#[test]
+fn eval_on_step_empty_frame_adds_step() {
+    let src = br#"
+    result = {
+      put fill_circle(1em)
+      put {} on_step 1
+    }
+    "#;
+    assert_eq!(eval_steps(src), vec![1, 1]);
+}
+
+#[test]
+fn eval_link_adds_link_to_every_step() {
+    let src = br#"
+    dot = fill_circle(1em)
+    result = link("https://example.com", { put dot put dot on_step 1 })
+    "#;
+    assert_eq!(eval_steps(src), vec![2, 3]);
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn eval_char_glyph_requires_a_single_character() {
+    for src in &[&b"result = char_glyph(\"\")"[..], b"result = char_glyph(\"ab\")"] {
+        assert!(eval_error(src).contains("single character"));
+    }
+}
+
+#[test]
+fn eval_measure_validates_arguments() {
+    for src in &[&b"result = measure()"[..], b"result = measure(\"a\", \"b\")"] {
+        match eval_error_with(src, |_| ()) {
+            Error::Arity(..) => {}
+            _ => panic!("Expected an arity error."),
+        }
+    }
+    let msg = eval_error(b"result = measure(12pt)");
+    assert!(msg.contains("first argument of 'measure'"), "{}", msg);
+}
+
+#[test]
+#[cfg(not(feature = "native"))]
+fn eval_measure_requires_native_feature() {
+    let msg = eval_error(b"result = measure(\"Hi\")");
+    assert!(msg.contains("'measure' requires the 'native' feature"), "{}", msg);
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn eval_measure_has_size_of_t_and_font_metrics() {
+    let src = b"text = \"Hello\\nworld\"\nm = measure(text)\nf = t(text)\nresult = m.size - f.size";
+    assert_eq!(eval(src), "(0, 0) : coord of len");
+
+    // The ascent and descent are properties of the font, so they scale with
+    // the font size, and do not depend on the text.
+    let metric = |src: &str| -> f64 {
+        let result = eval(src.as_bytes());
+        assert!(result.ends_with(" : len"), "{}", result);
+        result[..result.len() - 6].parse().unwrap()
+    };
+    let ascent = metric("m = measure(\"x\")\nresult = m.ascent");
+    let descent = metric("m = measure(\"x\")\nresult = m.descent");
+    assert!(ascent > 0.0 && descent > 0.0, "{} {}", ascent, descent);
+    assert_eq!(metric("m = measure(\"Hello\\nworld\")\nresult = m.ascent"), ascent);
+    assert_eq!(metric("font_size = 2 * font_size\nm = measure(\"x\")\nresult = m.ascent"), 2.0 * ascent);
+    assert_eq!(metric("font_size = 2 * font_size\nm = measure(\"x\")\nresult = m.descent"), 2.0 * descent);
+}
+
+#[test]
+fn eval_place_glyphs_requires_a_position_per_glyph() {
+    let msg = eval_error(b"result = place_glyphs([36; 37], [(0em, 0em)])");
+    assert!(msg.contains("2 glyphs and 1 positions"), "{}", msg);
+}
+
+#[test]
+fn eval_baseline_grid_overlay_covers_canvas() {
+    let steps = eval_steps(b"baseline_grid = 0.25h result = baseline_grid_overlay()");
+    assert_eq!(steps, vec![3]);
+}
+
+#[test]
+fn eval_snap_to_grid_requires_a_grid() {
+    let msg = eval_error(b"result = snap_to_grid({})");
+    assert!(msg.contains("positive 'baseline_grid'"), "{}", msg);
+}
+
+#[test]
+fn eval_background_image_has_canvas_size() {
+    let src = br#"
+    canvas_size = (4, 3)
+    background = background_image("examples/image.png", "cover")
+    result = background.size
+    "#;
+    assert_eq!(eval(src), "(4, 3) : coord of len");
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn slide_rejects_background_that_is_not_a_frame() {
+    let mut fm = runtime::FontMap::new();
+    let slide = eval_source(b"{ background = 1 }", &mut fm, |_| (), |_, outcome| {
+        driver::Slide::from_frame(&outcome.unwrap()[0]).map(|_| ())
+    });
+    match slide {
+        Err(Error::Type(..)) => {}
+        _ => panic!("Expected a type error for 'background'."),
+    }
+}
+
+#[test]
+#[cfg(all(feature = "native", not(feature = "svg")))]
+fn eval_image_svg_requires_svg_feature() {
+    assert!(eval_error(b"result = image(\"logo.svg\")").contains("'svg' feature"));
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn eval_scene_loads_elements_as_frame() {
+    // Include the process id, so concurrent test runs use different files.
+    let path = env::temp_dir().join(format!("pris-eval-scene-{}.json", std::process::id()));
+    let json = br##"[
+      {"type": "stroke_polygon", "position": [10, 20], "color": "#000000", "line_width": 2,
+       "kind": "lines", "vertices": [[0, 0], [100, 50]]}
+    ]"##;
+    std::fs::write(&path, &json[..]).unwrap();
+    let src = format!("frame = scene(\"{}\")\n", path.display());
+    // Like other frames, the bounding box includes the origin.
+    assert_eq!(eval(format!("{}result = frame.size", src).as_bytes()), "(111, 71) : coord of len");
+
+    std::fs::write(&path, &b"[{\"type\": \"circle\", \"position\": [0, 0]}]"[..]).unwrap();
+    let msg = eval_error(src.as_bytes());
+    assert!(msg.contains("Unknown element type 'circle'"), "{}", msg);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(feature = "svg")]
+fn eval_external_svg_embeds_tool_output() {
+    let src = br#"
+    frame = external_svg("echo", "<svg xmlns='http://www.w3.org/2000/svg' width='20' height='10'/>")
+    result = frame.size
+    "#;
+    let cache_dir = env::temp_dir().join("pris-eval-external-svg");
+    let mut tools = pris::external::Tools::new(cache_dir.clone());
+    tools.add("echo".to_string(), "cat".to_string());
+    let tools = Rc::new(tools);
+    let mut fm = runtime::FontMap::new();
+    let result = eval_source(src, &mut fm, |i| i.set_tools(tools), |stmt_interpreter, outcome| {
+        outcome.unwrap();
+        pretty::print(result_of(stmt_interpreter))
+    });
+    assert_eq!(result, "(20, 10) : coord of len");
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+
+    // Without configuring the tool, the document cannot run it.
+    let msg = eval_error(b"result = external_svg(\"echo\", \"<svg/>\")");
+    assert!(msg.contains("There is no external tool named 'echo'"), "{}", msg);
+}
+
+#[test]
+fn eval_statement_limit_stops_recursion() {
+    let src = br#"
+    loop = function(n) { x = n return loop(n + 1) }
+    result = loop(0)
+    "#;
+    let limits = Limits { max_statements: Some(100), ..Limits::default() };
+    match eval_error_with(src, |i| i.set_limits(limits)) {
+        Error::Limit(..) => {}
+        _ => panic!("Expected evaluation to exceed the statement limit."),
+    }
+}
+
+#[test]
+fn eval_element_limit_applies_per_slide() {
+    let limits = Limits { max_elements: Some(2), ..Limits::default() };
+    // The elements of earlier slides do not count towards the limit.
+    let src = b"dot = fill_circle(1em) { put dot put dot } { put dot put dot }";
+    let mut fm = runtime::FontMap::new();
+    eval_source(src, &mut fm, |i| i.set_limits(limits.clone()), |_, outcome| {
+        outcome.unwrap();
+    });
+    let src = b"dot = fill_circle(1em) { put dot put dot put dot }";
+    match eval_error_with(src, |i| i.set_limits(limits)) {
+        Error::Limit(..) => {}
+        _ => panic!("Expected the slide to exceed the element limit."),
+    }
+}
+
+#[test]
+fn eval_element_limit_counts_shared_elements_once() {
+    // Three copies of a group, plus the dot that they share.
+    let src = b"dot = fill_circle(1em) { put tile(dot, 3, 1, 0em) }";
+    let limits = Limits { max_elements: Some(4), ..Limits::default() };
+    let mut fm = runtime::FontMap::new();
+    eval_source(src, &mut fm, |i| i.set_limits(limits), |_, outcome| {
+        outcome.unwrap();
+    });
+    let limits = Limits { max_elements: Some(3), ..Limits::default() };
+    match eval_error_with(src, |i| i.set_limits(limits)) {
+        Error::Limit(..) => {}
+        _ => panic!("Expected the slide to exceed the element limit."),
+    }
+}
+
+#[test]
+fn eval_element_limit_applies_before_generating_elements() {
+    let limits = Limits { max_elements: Some(100), ..Limits::default() };
+    let sources: [(&[u8], &str); 3] = [
+        (b"result = tile(fill_circle(1em), 1000000000, 1000000000, 0em)", "'tile'"),
+        (b"baseline_grid = 0.0001h result = baseline_grid_overlay()", "'baseline_grid_overlay'"),
+        (b"dots = tile(fill_circle(1em), 10, 10, 0em) result = on_step(dots, 1)", "'on_step'"),
+    ];
+    for &(src, fn_name) in &sources {
+        let err = eval_error_with(src, |i| i.set_limits(limits.clone()));
+        let msg = err.message();
+        match err {
+            Error::Limit(..) => assert!(msg.contains(fn_name), "{}", msg),
+            _ => panic!("Expected {} to exceed the element limit.", fn_name),
+        }
+    }
+}
+
+#[test]
+fn eval_repeat_counts_towards_statement_limit() {
+    let limits = Limits { max_statements: Some(100), ..Limits::default() };
+    match eval_error_with(b"repeat 1000000000 { }", |i| i.set_limits(limits)) {
+        Error::Limit(..) => {}
+        _ => panic!("Expected evaluation to exceed the statement limit."),
+    }
+    eval_error(b"repeat -1 { }");
+    eval_error(b"repeat 2em { }");
+}
+
+#[test]
+fn eval_profile_records_builtin_calls() {
+    let profile = Rc::new(Profile::new());
+    let mut fm = runtime::FontMap::new();
+    let src = b"dot = fill_circle(1em)\nresult = dot at (1em, 1em)";
+    eval_source(src, &mut fm, |i| i.set_profile(profile.clone()), |_, outcome| {
+        outcome.unwrap();
+    });
+    let mut names: Vec<String> = profile.builtins().into_iter().map(|b| b.0).collect();
+    names.sort();
+    assert_eq!(names, vec!["at".to_string(), "fill_circle".to_string()]);
+}
+
+#[test]
+fn eval_warnings_go_to_logger() {
+    let logger = Rc::new(MemoryLogger::new());
+    let mut fm = runtime::FontMap::new();
+    let src = b"result = link(\"https:/example.com\", {})";
+    eval_source(src, &mut fm, |i| i.set_logger(logger.clone()), |_, outcome| {
+        outcome.unwrap();
+    });
+    let messages = logger.take();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].0, Level::Warning);
+}
+
+#[test]
+fn eval_stops_when_cancelled() {
+    let src = b"dot = fill_circle(1em)";
+    let flag = Arc::new(AtomicBool::new(false));
+    let mut fm = runtime::FontMap::new();
+    eval_source(src, &mut fm, |i| i.set_cancel(flag.clone()), |_, outcome| {
+        outcome.unwrap();
+    });
+    flag.store(true, Ordering::Relaxed);
+    match eval_error_with(src, |i| i.set_cancel(flag)) {
+        Error::Cancelled => {}
+        _ => panic!("Expected evaluation to be cancelled."),
+    }
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn render_in_memory_produces_pdf_and_png() {
+    let mut fm = runtime::FontMap::new();
+    let compiled = compile(b"{ put fill_rectangle((1em, 1em)) }\n{ }", &mut fm);
+    let logger = MemoryLogger::new();
+
+    let pdf = compiled.render_pdf_bytes(&mut fm, &logger).unwrap();
+    assert!(pdf.starts_with(b"%PDF-"));
+
+    let png = compiled.render_png_bytes(&mut fm, &logger, 0).unwrap();
+    assert!(png.starts_with(b"\x89PNG"));
+
+    // The second slide has no content, and there is no third slide.
+    assert!(compiled.render_png_bytes(&mut fm, &logger, 1).is_err());
+    assert!(compiled.render_png_bytes(&mut fm, &logger, 2).is_err());
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn compile_goes_from_source_to_slides() {
+    let mut fm = runtime::FontMap::new();
+    let mut options = compile::Options::new();
+    options.defines.push(("talk".to_string(), "Intro".to_string()));
+    let src = b"slide_title = talk\n{ put fill_rectangle((1em, 1em)) }\nslide_title = \"End\"\n{ }";
+    let compiled = compile::compile(&mut fm, src, None, &options).unwrap();
+    assert_eq!(compiled.slides.len(), 2);
+    assert_eq!(compiled.toc, vec![
+        toc::TocEntry { title: "Intro".to_string(), slide: 1 },
+        toc::TocEntry { title: "End".to_string(), slide: 2 },
+    ]);
+
+    match compile::compile(&mut fm, b"{ put", None, &options) {
+        Err(Error::Parse(..)) => {}
+        _ => panic!("Expected a parse error."),
+    }
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn content_hash_changes_only_with_changed_slides() {
+    let mut fm = runtime::FontMap::new();
+    let a = compile(b"{ put fill_rectangle((1em, 1em)) }\n{ put fill_circle(1em) }", &mut fm);
+    let b = compile(b"{ put fill_rectangle((1em, 2em)) }\n{ put fill_circle(1em) }", &mut fm);
+    let hashes = |doc: &driver::CompiledDoc| -> Vec<u64> {
+        doc.slides.iter().map(|slide| slide.content_hash()).collect()
+    };
+    let (ha, hb) = (hashes(&a), hashes(&b));
+    assert!(ha[0] != hb[0]);
+    assert_eq!(ha[1], hb[1]);
+    assert!(ha[0] != ha[1]);
+}
+
+#[test]
+#[cfg(all(feature = "native", feature = "hyperlink"))]
+fn render_pdf_merged_numbers_slides_across_documents() {
+    let mut fm = runtime::FontMap::new();
+    let logger = MemoryLogger::new();
+    let options = compile::Options::new();
+    let square = "{ put fill_rectangle((1em, 1em)) }\n";
+    let src_one = format!("{}{}", square, square);
+    let src_two = format!(
+        "slide_title = \"Contents\"\n{{ put auto_toc() }}\nslide_title = \"Details\"\n{}",
+        square,
+    );
+    let mut one = compile::compile(&mut fm, src_one.as_bytes(), None, &options).unwrap();
+    let mut two = compile::compile(&mut fm, src_two.as_bytes(), None, &options).unwrap();
+    one.name = "one".to_string();
+    two.name = "two".to_string();
+
+    let mut cache = driver::RenderCache::new();
+    let mut numbers = Vec::new();
+    let pdf = driver::render_pdf_merged(&mut fm, &mut cache, &logger, &[one, two], None, &mut |i, _| {
+        numbers.push(i)
+    }).unwrap();
+    assert_eq!(numbers, vec![1, 2, 3, 4]);
+
+    let contains = |needle: &[u8]| pdf.windows(needle.len()).any(|w| w == needle);
+    // The table of contents on the second deck links to its own slides, which
+    // come after the two slides of the first deck.
+    assert!(contains(b"/Dest (slide-3)"));
+    assert!(contains(b"/Dest (slide-4)"));
+    assert!(!contains(b"/Dest (slide-1)"));
+    assert!(!contains(b"/Dest (slide-2)"));
+
+    // The outline has an item for every deck, with its table of contents.
+    for title in &[&b"/Title (one)"[..], b"/Title (two)", b"/Title (Contents)", b"/Title (Details)"] {
+        assert!(contains(title), "{}", String::from_utf8_lossy(title));
+    }
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn render_pdf_reuses_recordings_of_unchanged_slides() {
+    let mut fm = runtime::FontMap::new();
+    let logger = MemoryLogger::new();
+    let mut slides = driver::SlideCache::new();
+    let a = compile(b"{ put fill_rectangle((1em, 1em)) }\n{ put fill_circle(1em) }", &mut fm);
+    let b = compile(b"{ put fill_rectangle((1em, 2em)) }\n{ put fill_circle(1em) }", &mut fm);
+    a.render_pdf_bytes_cached(&mut fm, &logger, &mut slides).unwrap();
+    assert_eq!((slides.hits(), slides.len()), (0, 2));
+    let pdf = b.render_pdf_bytes_cached(&mut fm, &logger, &mut slides).unwrap();
+    assert!(pdf.starts_with(b"%PDF"));
+    // Only the circle is unchanged, and the old rectangle was dropped.
+    assert_eq!((slides.hits(), slides.len()), (1, 2));
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn render_pdf_draws_slides_again_after_their_images_changed() {
+    let mut fm = runtime::FontMap::new();
+    let logger = MemoryLogger::new();
+    let mut slides = driver::SlideCache::new();
+    let doc = compile(b"{ put image(\"examples/image.png\") }\n{ put fill_circle(1em) }", &mut fm);
+    doc.render_pdf_bytes_cached(&mut fm, &logger, &mut slides).unwrap();
+    slides.forget_images(&[PathBuf::from("examples/image.png")]);
+    assert_eq!(slides.len(), 1);
+    doc.render_pdf_bytes_cached(&mut fm, &logger, &mut slides).unwrap();
+    assert_eq!((slides.hits(), slides.len()), (1, 2));
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn render_stops_when_cancelled() {
+    let mut fm = runtime::FontMap::new();
+    let mut compiled = compile(b"{ put fill_rectangle((1em, 1em)) }", &mut fm);
+    let logger = MemoryLogger::new();
+    let flag = Arc::new(AtomicBool::new(true));
+    compiled.set_cancel(flag);
+    match compiled.render_pdf_bytes(&mut fm, &logger) {
+        Err(Error::Cancelled) => {}
+        _ => panic!("Expected rendering to be cancelled."),
+    }
+}
+
+#[test]
+#[cfg(feature = "native")]
+fn render_pdf_stores_revealed_content_once() {
+    let one_step = br#"
+    { put tile(fill_circle(0.005w), 40, 20, 0.01w) }
+    "#;
+    let five_steps = br#"
+    {
+      dot = fill_circle(0.005w)
+      put tile(dot, 40, 20, 0.01w)
+      put dot at (0.1w, 0.9h) on_step 1
+      put dot at (0.2w, 0.9h) on_step 2
+      put dot at (0.3w, 0.9h) on_step 3
+      put dot at (0.4w, 0.9h) on_step 4
+    }
+    "#;
+    let mut fm = runtime::FontMap::new();
+    let logger = MemoryLogger::new();
+    let pdf_one = compile(one_step, &mut fm).render_pdf_bytes(&mut fm, &logger).unwrap();
+    let pdf_five = compile(five_steps, &mut fm).render_pdf_bytes(&mut fm, &logger).unwrap();
+    // If every page drew the pattern, the document would be five times larger.
+    assert!(pdf_five.len() < pdf_one.len() * 2);
+}